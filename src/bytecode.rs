@@ -0,0 +1,785 @@
+//! ## バイトコード
+//!
+//! 型付けに成功した式を、スタックマシン（[crate::vm]）で実行するバイトコードにコンパイルする。
+//!
+//! 関数、加法的ペアの要素、!型の中身はそれぞれ1つの関数にコンパイルし、
+//! 変数は関数ごとの局所変数の番号に解決する。
+//! 関数の局所変数は、キャプチャした変数、引数、関数内で束縛した変数の順に並ぶ。
+//! プログラム全体は0番の関数となり、局所変数の先頭には外部定義が並ぶ。
+//!
+//! コンパイルしたプログラムは、バージョンを含むヘッダを持つ`.linc`形式のバイト列に変換でき、
+//! [Program]のDisplayにより逆アセンブルした結果を表示できる。
+//!
+//! ```text
+//! <linc> := "LINC" <version: u16> <strings> <externs> <funcs>
+//! <strings> := <count: u32> (<len: u32> <utf8>)*
+//! <externs> := <count: u32> (<string: u32>)*
+//! <funcs> := <count: u32> (<var: u32> <captures: u32> (<slot: u32>)* <locals: u32> <len: u32> <op>*)*
+//! ```
+//!
+//! 整数はリトルエンディアンで、関数の引数名はなければ0xffffffffとする。
+//! チャネルとスレッドには対応していない。
+
+use crate::{externs::Externs, parser, subst::free_vars_in};
+use std::{collections::BTreeSet, fmt};
+
+/// `.linc`形式のバージョン
+pub const VERSION: u16 = 1;
+
+/// `.linc`形式の先頭のマジックナンバー
+const MAGIC: &[u8; 4] = b"LINC";
+
+/// 引数を持たない関数の引数名
+const NO_VAR: u32 = u32::MAX;
+
+/// 命令
+///
+/// オペランドスタックにはヒープ上のアドレスを積む
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Op {
+    PushBool(parser::Qual, bool),   // 真偽値を確保して積む
+    PushInt(parser::Qual, i64),     // 整数を確保して積む
+    PushStr(parser::Qual, u32),     // 文字列テーブルの文字列を確保して積む
+    MkPair(parser::Qual),           // 2つの値を降ろし、ペアを確保して積む
+    MkWith(parser::Qual, u32, u32), // 2つの関数の加法的ペアを確保して積む
+    MkClosure(parser::Qual, u32),   // 関数のクロージャを確保して積む
+    MkBang(u32),                    // 関数を中身とする!型の値を確保して積む
+    Load(u32),                      // 局所変数を積む
+    Force(u32),                     // 局所変数の!型の値の中身を評価する
+    Store(u32),                     // 値を降ろし、局所変数に束縛する
+    Borrow,                         // 値を降ろし、参照を積む
+    Free(u32),                      // 局所変数を解放する
+    App,                            // 引数と関数を降ろし、関数を呼び出す
+    Ret,                            // 呼び出し元に戻る
+    Fst,                            // 加法的ペアを降ろし、1つめの要素を評価する
+    Snd,                            // 加法的ペアを降ろし、2つめの要素を評価する
+    Split,                          // ペアを降ろし、2つの要素を積む
+    JmpIf(u32),                     // 条件を降ろし、真であれば分岐する
+    Jmp(u32),                       // 分岐する
+    NewRef,                         // 値を降ろし、可変な参照を積む
+    Swap,                           // 値と可変な参照を降ろし、参照と元の中身のペアを積む
+    CheckLen,                       // 積まれた整数が、配列の長さとして負でないかをチェックする
+    Alloc,                          // 初期値と長さを降ろし、配列を積む
+    CheckIndex,                     // 積まれた整数が、その下の配列の範囲内の位置かをチェックする
+    Get,                            // 位置と配列を降ろし、配列と要素のペアを積む
+    Set,                            // 値と位置と配列を降ろし、書き換えた配列を積む
+    RegionNew(u32),                 // 文字列テーブルの名前のリージョンを作成する
+    RegionFree,                     // 最も内側のリージョンを一括して解放する
+    InRegion(u32),                  // 積まれた値を、関数内で作成したリージョンに移す
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Op::PushBool(q, b) => write!(f, "PUSH_BOOL {} {}", q, b),
+            Op::PushInt(q, n) => write!(f, "PUSH_INT {} {}", q, n),
+            Op::PushStr(q, s) => write!(f, "PUSH_STR {} #{}", q, s),
+            Op::MkPair(q) => write!(f, "MK_PAIR {}", q),
+            Op::MkWith(q, f1, f2) => write!(f, "MK_WITH {} fn#{} fn#{}", q, f1, f2),
+            Op::MkClosure(q, func) => write!(f, "MK_CLOSURE {} fn#{}", q, func),
+            Op::MkBang(func) => write!(f, "MK_BANG fn#{}", func),
+            Op::Load(n) => write!(f, "LOAD {}", n),
+            Op::Force(n) => write!(f, "FORCE {}", n),
+            Op::Store(n) => write!(f, "STORE {}", n),
+            Op::Borrow => write!(f, "BORROW"),
+            Op::Free(n) => write!(f, "FREE {}", n),
+            Op::App => write!(f, "APP"),
+            Op::Ret => write!(f, "RET"),
+            Op::Fst => write!(f, "FST"),
+            Op::Snd => write!(f, "SND"),
+            Op::Split => write!(f, "SPLIT"),
+            Op::JmpIf(pc) => write!(f, "JMP_IF {:04}", pc),
+            Op::Jmp(pc) => write!(f, "JMP {:04}", pc),
+            Op::NewRef => write!(f, "NEW_REF"),
+            Op::Swap => write!(f, "SWAP"),
+            Op::CheckLen => write!(f, "CHECK_LEN"),
+            Op::Alloc => write!(f, "ALLOC"),
+            Op::CheckIndex => write!(f, "CHECK_INDEX"),
+            Op::Get => write!(f, "GET"),
+            Op::Set => write!(f, "SET"),
+            Op::RegionNew(s) => write!(f, "REGION_NEW #{}", s),
+            Op::RegionFree => write!(f, "REGION_FREE"),
+            Op::InRegion(n) => write!(f, "IN_REGION {}", n),
+        }
+    }
+}
+
+/// コンパイルした関数
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Func {
+    pub var: Option<String>, // 引数名。プログラム全体、加法的ペアの要素、!型の中身はNone
+    pub captures: Vec<u32>,  // キャプチャする、作成元の関数の局所変数の番号
+    pub num_locals: u32,     // 局所変数の数
+    pub code: Vec<Op>,
+}
+
+/// コンパイルしたプログラム
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Program {
+    pub strings: Vec<String>, // 文字列テーブル
+    pub externs: Vec<u32>,    // 0番の関数の局所変数の先頭に束縛する、外部定義の名前
+    pub funcs: Vec<Func>,
+}
+
+/// 局所変数の束縛
+#[derive(Debug, Clone)]
+struct Local {
+    name: String,
+    slot: u32,
+    bang: bool, // let !式で束縛された!型の値か
+}
+
+/// コンパイル中の関数
+#[derive(Debug, Default)]
+struct FuncBuilder {
+    code: Vec<Op>,
+    scope: Vec<Local>,    // 束縛された変数。後のものほど内側
+    num_locals: u32,      // 確保した局所変数の数
+    regions: Vec<String>, // 関数内で作成中のリージョン。後のものほど内側
+}
+
+impl FuncBuilder {
+    /// 命令を追加し、命令の位置を返す
+    fn emit(&mut self, op: Op) -> usize {
+        self.code.push(op);
+        self.code.len() - 1
+    }
+
+    /// 新しい局所変数に変数を束縛し、局所変数の番号を返す
+    fn bind(&mut self, name: &str, bang: bool) -> u32 {
+        let slot = self.num_locals;
+        self.num_locals += 1;
+        self.scope.push(Local {
+            name: name.to_string(),
+            slot,
+            bang,
+        });
+        slot
+    }
+
+    /// 変数を探す
+    fn lookup(&self, name: &str) -> Result<&Local, String> {
+        self.scope
+            .iter()
+            .rev()
+            .find(|l| l.name == name)
+            .ok_or_else(|| format!("\"{}\"という変数は定義されていない", name))
+    }
+}
+
+/// コンパイラ
+#[derive(Debug, Default)]
+struct Compiler {
+    strings: Vec<String>,
+    funcs: Vec<Func>,
+}
+
+type CResult = Result<(), String>;
+
+impl Compiler {
+    /// 文字列テーブルに文字列を追加し、番号を返す
+    fn string(&mut self, s: &str) -> u32 {
+        match self.strings.iter().position(|t| t == s) {
+            Some(i) => i as u32,
+            None => {
+                self.strings.push(s.to_string());
+                (self.strings.len() - 1) as u32
+            }
+        }
+    }
+
+    /// 変数varを引数とし、本体がexprsの関数をコンパイルし、関数の番号を返す
+    ///
+    /// 複数の式を受け取った場合は、同じ変数をキャプチャする関数を式ごとにコンパイルする
+    fn func(
+        &mut self,
+        var: Option<&str>,
+        exprs: &[&parser::Expr],
+        outer: &FuncBuilder,
+    ) -> Result<Vec<u32>, String> {
+        // 自由変数を、作成元の関数の局所変数からキャプチャ
+        let mut fv = BTreeSet::new();
+        for e in exprs {
+            free_vars_in(e, &mut var.iter().map(|v| v.to_string()).collect(), &mut fv);
+        }
+        let mut captures = Vec::new();
+        let mut scope = Vec::new();
+        for v in fv {
+            let l = outer.lookup(&v)?;
+            captures.push(l.slot);
+            scope.push((v, l.bang));
+        }
+
+        let mut ids = Vec::new();
+        for e in exprs {
+            let mut b = FuncBuilder::default();
+            for (v, bang) in scope.iter() {
+                b.bind(v, *bang);
+            }
+            if let Some(v) = var {
+                b.bind(v, false);
+            }
+            self.expr(e, &mut b)?;
+            b.emit(Op::Ret);
+
+            self.funcs.push(Func {
+                var: var.map(|v| v.to_string()),
+                captures: captures.clone(),
+                num_locals: b.num_locals,
+                code: b.code,
+            });
+            ids.push((self.funcs.len() - 1) as u32);
+        }
+        Ok(ids)
+    }
+
+    /// 式をコンパイルし、評価結果を積む命令をbに追加
+    fn expr(&mut self, expr: &parser::Expr, b: &mut FuncBuilder) -> CResult {
+        match expr {
+            parser::Expr::Let(e) => {
+                self.expr(&e.expr1, b)?;
+                let slot = b.bind(&e.var, false);
+                b.emit(Op::Store(slot));
+                self.scoped(&e.expr2, b)
+            }
+            parser::Expr::LetBang(e) => {
+                self.expr(&e.expr1, b)?;
+                let slot = b.bind(&e.var, true);
+                b.emit(Op::Store(slot));
+                self.scoped(&e.expr2, b)
+            }
+            parser::Expr::If(e) => {
+                // 条件が真であればthen部に分岐し、偽であればそのままelse部を評価
+                self.expr(&e.cond_expr, b)?;
+                let jmp_if = b.emit(Op::JmpIf(0));
+                self.expr(&e.else_expr, b)?;
+                let jmp = b.emit(Op::Jmp(0));
+                b.code[jmp_if] = Op::JmpIf(b.code.len() as u32);
+                self.expr(&e.then_expr, b)?;
+                b.code[jmp] = Op::Jmp(b.code.len() as u32);
+                Ok(())
+            }
+            parser::Expr::Split(e) => {
+                self.expr(&e.expr, b)?;
+                b.emit(Op::Split);
+                let n = b.scope.len();
+                let left = b.bind(&e.left, false);
+                let right = b.bind(&e.right, false);
+                b.emit(Op::Store(right));
+                b.emit(Op::Store(left));
+                self.expr(&e.body, b)?;
+                b.scope.truncate(n);
+                Ok(())
+            }
+            parser::Expr::Free(e) => {
+                let l = b.lookup(&e.var)?;
+                if l.bang {
+                    return Err(format!("変数\"{}\"をfreeできない", e.var));
+                }
+                let slot = l.slot;
+                b.emit(Op::Free(slot));
+                self.expr(&e.expr, b)
+            }
+            parser::Expr::App(e) => {
+                self.expr(&e.expr1, b)?;
+                self.expr(&e.expr2, b)?;
+                b.emit(Op::App);
+                Ok(())
+            }
+            parser::Expr::Proj(e) => {
+                self.expr(&e.expr, b)?;
+                b.emit(match e.proj {
+                    parser::Proj::Fst => Op::Fst,
+                    parser::Proj::Snd => Op::Snd,
+                });
+                Ok(())
+            }
+            parser::Expr::Promote(e) => {
+                let f = self.func(None, &[&e.expr], b)?;
+                b.emit(Op::MkBang(f[0]));
+                Ok(())
+            }
+            parser::Expr::Borrow(var) => {
+                self.var(var, b)?;
+                b.emit(Op::Borrow);
+                Ok(())
+            }
+            parser::Expr::NewRef(e) => {
+                self.expr(&e.expr, b)?;
+                b.emit(Op::NewRef);
+                Ok(())
+            }
+            parser::Expr::Swap(e) => {
+                self.expr(&e.cell, b)?;
+                self.expr(&e.expr, b)?;
+                b.emit(Op::Swap);
+                Ok(())
+            }
+            parser::Expr::LetRegion(e) => {
+                let name = self.string(&e.region);
+                b.emit(Op::RegionNew(name));
+                b.regions.push(e.region.clone());
+                self.expr(&e.expr, b)?;
+                b.regions.pop();
+                b.emit(Op::RegionFree);
+                Ok(())
+            }
+            parser::Expr::Alloc(e) => {
+                self.expr(&e.len, b)?;
+                b.emit(Op::CheckLen);
+                self.expr(&e.expr, b)?;
+                b.emit(Op::Alloc);
+                Ok(())
+            }
+            parser::Expr::Get(e) => {
+                self.expr(&e.array, b)?;
+                self.expr(&e.index, b)?;
+                b.emit(Op::CheckIndex);
+                b.emit(Op::Get);
+                Ok(())
+            }
+            parser::Expr::Set(e) => {
+                self.expr(&e.array, b)?;
+                self.expr(&e.index, b)?;
+                b.emit(Op::CheckIndex);
+                self.expr(&e.expr, b)?;
+                b.emit(Op::Set);
+                Ok(())
+            }
+            parser::Expr::Var(var) => self.var(var, b),
+            parser::Expr::QVal(e) => self.qval(e, b),
+            parser::Expr::New(_)
+            | parser::Expr::Send(_)
+            | parser::Expr::Recv(_)
+            | parser::Expr::Close(_)
+            | parser::Expr::Fork(_) => {
+                Err("チャネルとスレッドはバイトコードにコンパイルできない".to_string())
+            }
+        }
+    }
+
+    /// let式などの本体をコンパイルし、束縛した変数をスコープから外す
+    fn scoped(&mut self, expr: &parser::Expr, b: &mut FuncBuilder) -> CResult {
+        let n = b.scope.len() - 1;
+        self.expr(expr, b)?;
+        b.scope.truncate(n);
+        Ok(())
+    }
+
+    /// 変数のコンパイル
+    fn var(&mut self, var: &str, b: &mut FuncBuilder) -> CResult {
+        let l = b.lookup(var)?;
+        let op = if l.bang {
+            Op::Force(l.slot)
+        } else {
+            Op::Load(l.slot)
+        };
+        b.emit(op);
+        Ok(())
+    }
+
+    /// 修飾子付き値のコンパイル
+    fn qval(&mut self, expr: &parser::QValExpr, b: &mut FuncBuilder) -> CResult {
+        let q = expr.qual;
+        match &expr.val {
+            parser::ValExpr::Bool(v) => {
+                b.emit(Op::PushBool(q, *v));
+            }
+            parser::ValExpr::Int(n) => {
+                b.emit(Op::PushInt(q, *n));
+            }
+            parser::ValExpr::Str(s) => {
+                let s = self.string(s);
+                b.emit(Op::PushStr(q, s));
+            }
+            parser::ValExpr::Pair(e1, e2) => {
+                self.expr(e1, b)?;
+                self.expr(e2, b)?;
+                b.emit(Op::MkPair(q));
+            }
+            parser::ValExpr::With(e1, e2) => {
+                let f = self.func(None, &[e1, e2], b)?;
+                b.emit(Op::MkWith(q, f[0], f[1]));
+            }
+            parser::ValExpr::Fun(e) => {
+                let f = self.func(Some(&e.var), &[&e.expr], b)?;
+                b.emit(Op::MkClosure(q, f[0]));
+            }
+        }
+
+        // リージョンを指定した場合は、同じ名前のリージョンのうち最も内側のものに移す
+        if let Some(r) = &expr.region {
+            let n = b
+                .regions
+                .iter()
+                .rposition(|r2| r2 == r)
+                .ok_or_else(|| format!("リージョン\"{}\"は作成されていない", r))?;
+            b.emit(Op::InRegion(n as u32));
+        }
+        Ok(())
+    }
+}
+
+/// 外部定義を束縛した初期環境で式を評価する、バイトコードのプログラムにコンパイル
+///
+/// 型付けに成功した式を渡すこと
+pub fn compile(expr: &parser::Expr, externs: &Externs) -> Result<Program, String> {
+    let mut c = Compiler::default();
+
+    // 0番の関数の局所変数の先頭に、外部定義を束縛
+    let mut b = FuncBuilder::default();
+    let mut names = Vec::new();
+    for def in externs.defs.iter() {
+        b.bind(&def.name, false);
+        names.push(c.string(&def.name));
+    }
+
+    c.funcs.push(Func {
+        var: None,
+        captures: Vec::new(),
+        num_locals: 0,
+        code: Vec::new(),
+    });
+    c.expr(expr, &mut b)?;
+    b.emit(Op::Ret);
+    c.funcs[0].num_locals = b.num_locals;
+    c.funcs[0].code = b.code;
+
+    Ok(Program {
+        strings: c.strings,
+        externs: names,
+        funcs: c.funcs,
+    })
+}
+
+/// 修飾子の符号
+fn qual_code(q: parser::Qual) -> u8 {
+    match q {
+        parser::Qual::Ord => 0,
+        parser::Qual::Lin => 1,
+        parser::Qual::Aff => 2,
+        parser::Qual::Rel => 3,
+        parser::Qual::Un => 4,
+    }
+}
+
+/// バイト列への書き込み
+#[derive(Debug, Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, n: u8) {
+        self.buf.push(n);
+    }
+
+    fn u32(&mut self, n: u32) {
+        self.buf.extend_from_slice(&n.to_le_bytes());
+    }
+
+    fn op(&mut self, op: &Op) {
+        let (code, q) = match op {
+            Op::PushBool(q, _) => (0x01, Some(q)),
+            Op::PushInt(q, _) => (0x02, Some(q)),
+            Op::PushStr(q, _) => (0x03, Some(q)),
+            Op::MkPair(q) => (0x04, Some(q)),
+            Op::MkWith(q, _, _) => (0x05, Some(q)),
+            Op::MkClosure(q, _) => (0x06, Some(q)),
+            Op::MkBang(_) => (0x07, None),
+            Op::Load(_) => (0x10, None),
+            Op::Force(_) => (0x11, None),
+            Op::Store(_) => (0x12, None),
+            Op::Borrow => (0x13, None),
+            Op::Free(_) => (0x14, None),
+            Op::App => (0x20, None),
+            Op::Ret => (0x21, None),
+            Op::Fst => (0x22, None),
+            Op::Snd => (0x23, None),
+            Op::Split => (0x24, None),
+            Op::JmpIf(_) => (0x25, None),
+            Op::Jmp(_) => (0x26, None),
+            Op::NewRef => (0x30, None),
+            Op::Swap => (0x31, None),
+            Op::CheckLen => (0x32, None),
+            Op::Alloc => (0x33, None),
+            Op::CheckIndex => (0x34, None),
+            Op::Get => (0x35, None),
+            Op::Set => (0x36, None),
+            Op::RegionNew(_) => (0x40, None),
+            Op::RegionFree => (0x41, None),
+            Op::InRegion(_) => (0x42, None),
+        };
+        self.u8(code);
+        if let Some(q) = q {
+            self.u8(qual_code(*q));
+        }
+
+        match op {
+            Op::PushBool(_, b) => self.u8(*b as u8),
+            Op::PushInt(_, n) => self.buf.extend_from_slice(&n.to_le_bytes()),
+            Op::MkWith(_, f1, f2) => {
+                self.u32(*f1);
+                self.u32(*f2);
+            }
+            Op::PushStr(_, n)
+            | Op::MkClosure(_, n)
+            | Op::MkBang(n)
+            | Op::Load(n)
+            | Op::Force(n)
+            | Op::Store(n)
+            | Op::Free(n)
+            | Op::JmpIf(n)
+            | Op::Jmp(n)
+            | Op::RegionNew(n)
+            | Op::InRegion(n) => self.u32(*n),
+            _ => (),
+        }
+    }
+}
+
+/// バイト列からの読み出し
+#[derive(Debug)]
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let end = self.pos.checked_add(n).filter(|e| *e <= self.buf.len());
+        match end {
+            Some(end) => {
+                let b = &self.buf[self.pos..end];
+                self.pos = end;
+                Ok(b)
+            }
+            None => Err("lincファイルが途中で終わっている".to_string()),
+        }
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn qual(&mut self) -> Result<parser::Qual, String> {
+        match self.u8()? {
+            0 => Ok(parser::Qual::Ord),
+            1 => Ok(parser::Qual::Lin),
+            2 => Ok(parser::Qual::Aff),
+            3 => Ok(parser::Qual::Rel),
+            4 => Ok(parser::Qual::Un),
+            n => Err(format!("不正な修飾子{:#04x}", n)),
+        }
+    }
+
+    fn op(&mut self) -> Result<Op, String> {
+        Ok(match self.u8()? {
+            0x01 => {
+                let q = self.qual()?;
+                Op::PushBool(q, self.u8()? != 0)
+            }
+            0x02 => {
+                let q = self.qual()?;
+                Op::PushInt(q, i64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+            }
+            0x03 => Op::PushStr(self.qual()?, self.u32()?),
+            0x04 => Op::MkPair(self.qual()?),
+            0x05 => Op::MkWith(self.qual()?, self.u32()?, self.u32()?),
+            0x06 => Op::MkClosure(self.qual()?, self.u32()?),
+            0x07 => Op::MkBang(self.u32()?),
+            0x10 => Op::Load(self.u32()?),
+            0x11 => Op::Force(self.u32()?),
+            0x12 => Op::Store(self.u32()?),
+            0x13 => Op::Borrow,
+            0x14 => Op::Free(self.u32()?),
+            0x20 => Op::App,
+            0x21 => Op::Ret,
+            0x22 => Op::Fst,
+            0x23 => Op::Snd,
+            0x24 => Op::Split,
+            0x25 => Op::JmpIf(self.u32()?),
+            0x26 => Op::Jmp(self.u32()?),
+            0x30 => Op::NewRef,
+            0x31 => Op::Swap,
+            0x32 => Op::CheckLen,
+            0x33 => Op::Alloc,
+            0x34 => Op::CheckIndex,
+            0x35 => Op::Get,
+            0x36 => Op::Set,
+            0x40 => Op::RegionNew(self.u32()?),
+            0x41 => Op::RegionFree,
+            0x42 => Op::InRegion(self.u32()?),
+            n => return Err(format!("不正な命令{:#04x}", n)),
+        })
+    }
+}
+
+impl Program {
+    /// `.linc`形式のバイト列に変換
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.buf.extend_from_slice(MAGIC);
+        w.buf.extend_from_slice(&VERSION.to_le_bytes());
+
+        w.u32(self.strings.len() as u32);
+        for s in self.strings.iter() {
+            w.u32(s.len() as u32);
+            w.buf.extend_from_slice(s.as_bytes());
+        }
+
+        w.u32(self.externs.len() as u32);
+        for s in self.externs.iter() {
+            w.u32(*s);
+        }
+
+        // 引数名は文字列テーブルに含めず、関数ごとに保持する
+        w.u32(self.funcs.len() as u32);
+        for f in self.funcs.iter() {
+            match &f.var {
+                Some(v) => {
+                    w.u32(v.len() as u32);
+                    w.buf.extend_from_slice(v.as_bytes());
+                }
+                None => w.u32(NO_VAR),
+            }
+            w.u32(f.captures.len() as u32);
+            for c in f.captures.iter() {
+                w.u32(*c);
+            }
+            w.u32(f.num_locals);
+            w.u32(f.code.len() as u32);
+            for op in f.code.iter() {
+                w.op(op);
+            }
+        }
+        w.buf
+    }
+
+    /// `.linc`形式のバイト列から読み込む
+    ///
+    /// 命令のオペランドが指す関数や局所変数の妥当性は、実行時にチェックする。
+    /// 局所変数の数は、関数の命令から確保されうる数を超えないかを読み込み時にチェックする
+    pub fn from_bytes(buf: &[u8]) -> Result<Program, String> {
+        let mut r = Reader { buf, pos: 0 };
+        if r.bytes(4).ok() != Some(MAGIC.as_slice()) {
+            return Err("lincファイルでない".to_string());
+        }
+        let version = u16::from_le_bytes(r.bytes(2)?.try_into().unwrap());
+        if version != VERSION {
+            return Err(format!(
+                "バージョン{}のlincファイルには対応していない（対応するバージョンは{}）",
+                version, VERSION
+            ));
+        }
+
+        let string = |r: &mut Reader, len: u32| -> Result<String, String> {
+            String::from_utf8(r.bytes(len as usize)?.to_vec())
+                .map_err(|_| "lincファイルの文字列がUTF-8でない".to_string())
+        };
+
+        let mut strings = Vec::new();
+        for _ in 0..r.u32()? {
+            let len = r.u32()?;
+            strings.push(string(&mut r, len)?);
+        }
+
+        let mut externs = Vec::new();
+        for _ in 0..r.u32()? {
+            externs.push(r.u32()?);
+        }
+
+        let mut funcs = Vec::new();
+        for _ in 0..r.u32()? {
+            let var = match r.u32()? {
+                NO_VAR => None,
+                len => Some(string(&mut r, len)?),
+            };
+            let mut captures = Vec::new();
+            for _ in 0..r.u32()? {
+                captures.push(r.u32()?);
+            }
+            let num_locals = r.u32()?;
+            let mut code = Vec::new();
+            for _ in 0..r.u32()? {
+                code.push(r.op()?);
+            }
+
+            // 局所変数はキャプチャ、引数、STOREごとに1つずつ（0番の関数は外部定義の分も）確保されるので、
+            // それを超える数は不正とし、実行時に巨大な領域を確保しないようにする
+            let stores = code.iter().filter(|op| matches!(op, Op::Store(_))).count();
+            let max_locals = captures.len()
+                + var.is_some() as usize
+                + stores
+                + if funcs.is_empty() { externs.len() } else { 0 };
+            if num_locals as usize > max_locals {
+                return Err(format!(
+                    "lincファイルの関数#{}の局所変数の数{}が多すぎる（最大{}）",
+                    funcs.len(),
+                    num_locals,
+                    max_locals
+                ));
+            }
+            funcs.push(Func {
+                var,
+                captures,
+                num_locals,
+                code,
+            });
+        }
+
+        if r.pos != buf.len() {
+            return Err("lincファイルの末尾に余分なデータがある".to_string());
+        }
+        if funcs.is_empty() {
+            return Err("lincファイルに関数がない".to_string());
+        }
+        Ok(Program {
+            strings,
+            externs,
+            funcs,
+        })
+    }
+}
+
+/// 逆アセンブルした結果を表示
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "; linc バージョン{}", VERSION)?;
+
+        // 文字列テーブルの番号は、文字列に置き換えて表示する
+        let s = |n: &u32| match self.strings.get(*n as usize) {
+            Some(s) => format!("{:?}", s),
+            None => format!("#{}（範囲外）", n),
+        };
+        let externs: Vec<String> = self.externs.iter().map(s).collect();
+        writeln!(f, "; 外部定義: {}", externs.join(", "))?;
+
+        for (i, func) in self.funcs.iter().enumerate() {
+            write!(f, "\nfn#{}", i)?;
+            if let Some(v) = &func.var {
+                write!(f, " {}", v)?;
+            }
+            if !func.captures.is_empty() {
+                let caps: Vec<String> = func.captures.iter().map(|c| c.to_string()).collect();
+                write!(f, " キャプチャ: [{}]", caps.join(", "))?;
+            }
+            writeln!(f, " 局所変数: {}", func.num_locals)?;
+
+            for (pc, op) in func.code.iter().enumerate() {
+                match op {
+                    Op::PushStr(_, n) | Op::RegionNew(n) => {
+                        writeln!(f, "  {:04}  {:<24}; {}", pc, op.to_string(), s(n))?
+                    }
+                    _ => writeln!(f, "  {:04}  {}", pc, op)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,599 @@
+//! ## CEK機械
+//!
+//! 制御（Control）、環境（Environment）、継続（Kontinuation）の3つ組を状態とし、
+//! 状態を1ステップずつ遷移させて式を評価する抽象機械。
+//! 制御は評価中の式か、評価を終えた値のアドレスで、
+//! 継続は部分式の評価を終えた後に行う計算を、穴（□）の空いた式のスタックとして保持する。
+//!
+//! ヒープは評価器と共有し、値の確保と解放も評価器と同じ時点で行うため、
+//! 評価結果とヒープの統計情報は評価器と一致する。
+//! 各状態は[Machine]のDisplayで表示でき、環境の変数が指すヒープ上のセルと、
+//! 直前のステップで確保、消費、解放したセルを含む。
+//!
+//! チャネルとスレッドには対応していない。
+
+use crate::{
+    eval::{self, Addr, Binding, Data, Env, Heap, HeapStats, Value},
+    externs::Externs,
+    parser,
+};
+use std::fmt;
+
+/// 制御
+#[derive(Debug)]
+enum Control<'a> {
+    Expr(&'a parser::Expr), // 評価中の式
+    Value(Addr),            // 評価を終えた値
+}
+
+/// 継続のフレーム。□は評価中の部分式を表す
+#[derive(Debug)]
+enum Kont<'a> {
+    Let(&'a parser::LetExpr, Env),            // let x : T = □; e
+    LetBang(&'a parser::LetBangExpr, Env),    // let !x = □; e
+    If(&'a parser::IfExpr, Env),              // if □ { e1 } else { e2 }
+    Split(&'a parser::SplitExpr, Env),        // split □ as x, y { e }
+    AppFun(&'a parser::AppExpr, Env),         // (□ e)
+    AppArg(Addr),                             // (f □)
+    Proj(parser::Proj),                       // fst □
+    Borrow,                                   // &□
+    PairFst(&'a parser::QValExpr, Env),       // q <□, e>
+    PairSnd(&'a parser::QValExpr, Addr),      // q <v, □>
+    NewRef,                                   // new □
+    SwapCell(&'a parser::SwapExpr, Env),      // swap □ e
+    SwapVal(Addr),                            // swap r □
+    LetRegion(&'a str, usize),                // letregion r { □ }
+    AllocLen(&'a parser::AllocExpr, Env),     // alloc □ e
+    AllocVal(usize),                          // alloc n □
+    GetArray(&'a parser::GetExpr, Env),       // get □ e
+    GetIndex(Addr),                           // get a □
+    SetArray(&'a parser::SetExpr, Env),       // set □ e1 e2
+    SetIndex(&'a parser::SetExpr, Addr, Env), // set a □ e
+    SetVal(Addr, usize),                      // set a i □
+}
+
+impl fmt::Display for Kont<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kont::Let(e, _) => write!(f, "let {} : {} = □; {}", e.var, e.ty, e.expr2),
+            Kont::LetBang(e, _) => write!(f, "let !{} = □; {}", e.var, e.expr2),
+            Kont::If(e, _) => write!(f, "if □ {{ {} }} else {{ {} }}", e.then_expr, e.else_expr),
+            Kont::Split(e, _) => write!(f, "split □ as {}, {} {{ {} }}", e.left, e.right, e.body),
+            Kont::AppFun(e, _) => write!(f, "(□ {})", e.expr2),
+            Kont::AppArg(a) => write!(f, "(#{} □)", a),
+            Kont::Proj(parser::Proj::Fst) => write!(f, "fst □"),
+            Kont::Proj(parser::Proj::Snd) => write!(f, "snd □"),
+            Kont::Borrow => write!(f, "&□"),
+            Kont::PairFst(e, _) => match &e.val {
+                parser::ValExpr::Pair(_, e2) => write!(f, "{} <□, {}>", qual(e), e2),
+                _ => write!(f, "{} <□, □>", qual(e)),
+            },
+            Kont::PairSnd(e, a) => write!(f, "{} <#{}, □>", qual(e), a),
+            Kont::NewRef => write!(f, "new □"),
+            Kont::SwapCell(e, _) => write!(f, "swap □ {}", e.expr),
+            Kont::SwapVal(a) => write!(f, "swap #{} □", a),
+            Kont::LetRegion(r, _) => write!(f, "letregion {} {{ □ }}", r),
+            Kont::AllocLen(e, _) => write!(f, "alloc □ {}", e.expr),
+            Kont::AllocVal(n) => write!(f, "alloc {} □", n),
+            Kont::GetArray(e, _) => write!(f, "get □ {}", e.index),
+            Kont::GetIndex(a) => write!(f, "get #{} □", a),
+            Kont::SetArray(e, _) => write!(f, "set □ {} {}", e.index, e.expr),
+            Kont::SetIndex(e, a, _) => write!(f, "set #{} □ {}", a, e.expr),
+            Kont::SetVal(a, i) => write!(f, "set #{} {} □", a, i),
+        }
+    }
+}
+
+/// 修飾子付き値の修飾子とリージョンの表示
+fn qual(e: &parser::QValExpr) -> String {
+    match &e.region {
+        Some(r) => format!("{}@{}", e.qual, r),
+        None => e.qual.to_string(),
+    }
+}
+
+/// ステップ中にセルを解放した理由
+#[derive(Debug, Clone, Copy)]
+enum Cause<'a> {
+    Consume,         // 分解による消費
+    Extern,          // 外部関数に渡した
+    Free,            // free文
+    Region(&'a str), // リージョンを抜けた
+}
+
+type MResult<T> = Result<T, String>;
+
+/// CEK機械の状態
+pub struct Machine<'a> {
+    externs: &'a Externs,
+    heap: Heap<'a>,
+    control: Control<'a>,
+    env: Env,
+    kont: Vec<Kont<'a>>,            // 継続。後のものほど内側
+    globals: Env,                   // 外部定義を束縛した初期環境。状態の表示では省略する
+    regions: Vec<(&'a str, usize)>, // 評価中のletregion式のリージョン名と、リージョンの番号
+    num_steps: usize,
+    cause: Cause<'a>,
+    events: Vec<String>, // 直前のステップで確保、消費、解放したセル
+}
+
+impl<'a> Machine<'a> {
+    /// 外部定義を束縛した初期環境で、式を評価する初期状態を作成
+    ///
+    /// [Externs::type_env]で作成した型環境で型付けに成功した式を渡すこと
+    pub fn new(expr: &'a parser::Expr, externs: &'a Externs) -> Result<Machine<'a>, String> {
+        let mut heap = Heap::default();
+        let globals = heap.init_env(externs)?;
+        Ok(Machine {
+            externs,
+            heap,
+            control: Control::Expr(expr),
+            env: globals.clone(),
+            kont: Vec::new(),
+            globals,
+            regions: Vec::new(),
+            num_steps: 0,
+            cause: Cause::Consume,
+            events: Vec::new(),
+        })
+    }
+
+    /// 評価を終えたかを判定
+    pub fn is_final(&self) -> bool {
+        matches!(self.control, Control::Value(_)) && self.kont.is_empty()
+    }
+
+    /// 状態を1ステップ遷移させる。評価を終えていた場合はfalseを返す
+    pub fn step(&mut self) -> Result<bool, String> {
+        if self.is_final() {
+            return Ok(false);
+        }
+
+        // 遷移前に生きているセルと、環境の変数や制御の値が指すセルの内容を記録
+        let num_cells = self.heap.num_cells();
+        let live: Vec<Option<parser::Qual>> = (0..num_cells)
+            .map(|a| self.heap.get(a).ok().map(|c| c.qual))
+            .collect();
+        let mut known = Vec::new();
+        for (var, b) in self.locals() {
+            let (Binding::Val(a) | Binding::Bang(a)) = b;
+            known.push((a, format!("{}（{}）", self.cell(a), var)));
+        }
+        if let Control::Value(a) = self.control {
+            known.push((a, self.cell(a)));
+            if let Some(Kont::AppArg(f)) = self.kont.last() {
+                known.push((*f, self.cell(*f)));
+            }
+        }
+
+        self.cause = Cause::Consume;
+        match self.control {
+            Control::Expr(e) => self.eval(e)?,
+            Control::Value(a) => {
+                let k = self.kont.pop().unwrap();
+                self.ret(k, a)?;
+            }
+        }
+        self.num_steps += 1;
+
+        // 遷移中に確保したセルと解放したセルを記録
+        self.events.clear();
+        for a in num_cells..self.heap.num_cells() {
+            let e = format!("確保: {}", self.cell(a));
+            self.events.push(e);
+        }
+        for (a, q) in live.iter().enumerate() {
+            if let (Some(q), Err(_)) = (q, self.heap.get(a)) {
+                let desc = match known.iter().find(|(k, _)| *k == a) {
+                    Some((_, desc)) => desc.clone(),
+                    None => format!("#{} {}型の値", a, q),
+                };
+                let e = match self.cause {
+                    Cause::Consume => format!("消費して解放: {}", desc),
+                    Cause::Extern => format!("外部関数に渡して解放: {}", desc),
+                    Cause::Free => format!("freeで解放: {}", desc),
+                    Cause::Region(r) => format!("リージョン{}を抜けて一括解放: {}", r, desc),
+                };
+                self.events.push(e);
+            }
+        }
+        Ok(true)
+    }
+
+    /// 評価を終えた状態から、評価結果の値と、評価後のヒープの統計情報を返す
+    pub fn result(&self) -> Result<(Value, HeapStats), String> {
+        match self.control {
+            Control::Value(a) if self.kont.is_empty() => {
+                Ok((self.heap.read(a, self.externs)?, self.heap.stats(a)))
+            }
+            _ => Err("評価を終えていない".to_string()),
+        }
+    }
+
+    /// 外部定義を除いた、環境の変数の束縛
+    fn locals(&self) -> impl Iterator<Item = (&String, Binding)> {
+        self.env
+            .iter()
+            .filter(|(var, b)| self.globals.get(*var) != Some(*b))
+            .map(|(var, b)| (var, *b))
+    }
+
+    /// セルのアドレスと内容の表示
+    fn cell(&self, addr: Addr) -> String {
+        match self.heap.read(addr, self.externs) {
+            Ok(v) => format!("#{} = {}", addr, v),
+            Err(_) => format!("#{}（解放済み）", addr),
+        }
+    }
+
+    /// 修飾子付き値を、リージョンを指定した場合はリージョン内に確保して制御とする
+    fn alloc(&mut self, expr: &parser::QValExpr, data: Data<'a>) -> MResult<()> {
+        let a = match &expr.region {
+            Some(r) => match self.regions.iter().rev().find(|(r2, _)| r2 == r) {
+                Some((_, id)) => self.heap.alloc_in(*id, expr.qual, data),
+                None => return Err(format!("リージョン\"{}\"は作成されていない", r)),
+            },
+            None => self.heap.alloc(expr.qual, data),
+        };
+        self.control = Control::Value(a);
+        Ok(())
+    }
+
+    /// 環境をenvとし、式exprを制御とする
+    fn goto(&mut self, expr: &'a parser::Expr, env: Env) {
+        self.control = Control::Expr(expr);
+        self.env = env;
+    }
+
+    /// !型の値の中身を制御とする
+    fn derelict(&mut self, addr: Addr) -> MResult<()> {
+        match &self.heap.get(addr)?.data {
+            Data::Bang(env, e) => {
+                let (env, e) = (env.clone(), *e);
+                self.goto(e, env);
+                Ok(())
+            }
+            _ => Err("!型でない値を取り出そうとした".to_string()),
+        }
+    }
+
+    /// 借用。参照の借用は同じ参照とする
+    fn borrow(&mut self, addr: Addr) -> MResult<()> {
+        let a = match self.heap.get(addr)?.data {
+            Data::Ref(_) => addr,
+            _ => self.heap.alloc(parser::Qual::Un, Data::Ref(addr)),
+        };
+        self.control = Control::Value(a);
+        Ok(())
+    }
+
+    /// 配列の位置か長さを表す整数を消費し、範囲内かをチェックした位置を返す
+    ///
+    /// lenがNoneの場合は配列の長さを表すものとし、負でないかのみをチェック
+    fn index(&mut self, addr: Addr, len: Option<usize>) -> MResult<usize> {
+        let n = match self.heap.get(addr)?.data {
+            Data::Int(n) => n,
+            _ => return Err("配列の位置か長さがintでない".to_string()),
+        };
+        self.heap.consume(addr)?;
+
+        match usize::try_from(n) {
+            Ok(i) if len.is_none_or(|len| i < len) => Ok(i),
+            _ => Err(format!("配列の範囲外の位置{}を指定した", n)),
+        }
+    }
+
+    /// 配列のアドレスから、要素のアドレスを取得
+    fn elems(&mut self, addr: Addr) -> MResult<&mut Vec<Addr>> {
+        match &mut self.heap.get_mut(addr)?.data {
+            Data::Array(elems) => Ok(elems),
+            _ => Err("配列でない値を読み書きした".to_string()),
+        }
+    }
+
+    /// 制御が式の場合の遷移
+    fn eval(&mut self, expr: &'a parser::Expr) -> MResult<()> {
+        let env = self.env.clone();
+        match expr {
+            parser::Expr::Let(e) => {
+                self.kont.push(Kont::Let(e, env));
+                self.control = Control::Expr(&e.expr1);
+            }
+            parser::Expr::LetBang(e) => {
+                self.kont.push(Kont::LetBang(e, env));
+                self.control = Control::Expr(&e.expr1);
+            }
+            parser::Expr::If(e) => {
+                self.kont.push(Kont::If(e, env));
+                self.control = Control::Expr(&e.cond_expr);
+            }
+            parser::Expr::Split(e) => {
+                self.kont.push(Kont::Split(e, env));
+                self.control = Control::Expr(&e.expr);
+            }
+            parser::Expr::Free(e) => {
+                match env.get(&e.var) {
+                    Some(Binding::Val(a)) => {
+                        // ペアの要素やrefの中身も含めて解放
+                        self.cause = Cause::Free;
+                        self.heap.release(*a)?;
+                    }
+                    _ => return Err(format!("変数\"{}\"をfreeできない", e.var)),
+                }
+                self.control = Control::Expr(&e.expr);
+            }
+            parser::Expr::App(e) => {
+                self.kont.push(Kont::AppFun(e, env));
+                self.control = Control::Expr(&e.expr1);
+            }
+            parser::Expr::Proj(e) => {
+                self.kont.push(Kont::Proj(e.proj));
+                self.control = Control::Expr(&e.expr);
+            }
+            parser::Expr::Promote(e) => {
+                let a = self.heap.alloc(parser::Qual::Un, Data::Bang(env, &e.expr));
+                self.control = Control::Value(a);
+            }
+            parser::Expr::Borrow(var) => match env.get(var) {
+                Some(Binding::Val(a)) => self.borrow(*a)?,
+                Some(Binding::Bang(a)) => {
+                    self.kont.push(Kont::Borrow);
+                    self.derelict(*a)?;
+                }
+                None => return Err(format!("\"{}\"という変数は定義されていない", var)),
+            },
+            parser::Expr::NewRef(e) => {
+                self.kont.push(Kont::NewRef);
+                self.control = Control::Expr(&e.expr);
+            }
+            parser::Expr::Swap(e) => {
+                self.kont.push(Kont::SwapCell(e, env));
+                self.control = Control::Expr(&e.cell);
+            }
+            parser::Expr::LetRegion(e) => {
+                let id = self.heap.new_region(&e.region);
+                self.regions.push((&e.region, id));
+                self.kont.push(Kont::LetRegion(&e.region, id));
+                self.control = Control::Expr(&e.expr);
+            }
+            parser::Expr::Alloc(e) => {
+                self.kont.push(Kont::AllocLen(e, env));
+                self.control = Control::Expr(&e.len);
+            }
+            parser::Expr::Get(e) => {
+                self.kont.push(Kont::GetArray(e, env));
+                self.control = Control::Expr(&e.array);
+            }
+            parser::Expr::Set(e) => {
+                self.kont.push(Kont::SetArray(e, env));
+                self.control = Control::Expr(&e.array);
+            }
+            parser::Expr::Var(var) => match env.get(var) {
+                Some(Binding::Val(a)) => self.control = Control::Value(*a),
+                Some(Binding::Bang(a)) => self.derelict(*a)?,
+                None => return Err(format!("\"{}\"という変数は定義されていない", var)),
+            },
+            parser::Expr::QVal(e) => match &e.val {
+                parser::ValExpr::Bool(b) => self.alloc(e, Data::Bool(*b))?,
+                parser::ValExpr::Int(n) => self.alloc(e, Data::Int(*n))?,
+                parser::ValExpr::Str(s) => self.alloc(e, Data::Str(s.clone()))?,
+                parser::ValExpr::Pair(e1, _) => {
+                    self.kont.push(Kont::PairFst(e, env));
+                    self.control = Control::Expr(e1);
+                }
+                parser::ValExpr::With(e1, e2) => self.alloc(e, Data::With(env, e1, e2))?,
+                parser::ValExpr::Fun(f) => self.alloc(e, Data::Fun(env, &f.var, &f.expr))?,
+            },
+            parser::Expr::New(_)
+            | parser::Expr::Send(_)
+            | parser::Expr::Recv(_)
+            | parser::Expr::Close(_)
+            | parser::Expr::Fork(_) => {
+                return Err("チャネルとスレッドはCEK機械では評価できない".to_string())
+            }
+        }
+        Ok(())
+    }
+
+    /// 制御が値の場合の、継続のフレームkへの遷移
+    fn ret(&mut self, k: Kont<'a>, a: Addr) -> MResult<()> {
+        match k {
+            Kont::Let(e, mut env) => {
+                env.insert(e.var.clone(), Binding::Val(a));
+                self.goto(&e.expr2, env);
+            }
+            Kont::LetBang(e, mut env) => {
+                env.insert(e.var.clone(), Binding::Bang(a));
+                self.goto(&e.expr2, env);
+            }
+            Kont::If(e, env) => {
+                let target = self.heap.deref(a)?;
+                let b = match self.heap.get(target)?.data {
+                    Data::Bool(b) => b,
+                    _ => return Err("ifの条件式がboolでない".to_string()),
+                };
+                self.heap.consume(a)?;
+                self.goto(if b { &e.then_expr } else { &e.else_expr }, env);
+            }
+            Kont::Split(e, mut env) => {
+                let target = self.heap.deref(a)?;
+                let (mut a1, mut a2) = match self.heap.get(target)?.data {
+                    Data::Pair(a1, a2) => (a1, a2),
+                    _ => return Err("splitの引数がペアでない".to_string()),
+                };
+                self.heap.consume(a)?;
+
+                // ペアへの参照を分解した場合、各要素への参照を束縛
+                if target != a {
+                    a1 = self.heap.alloc(parser::Qual::Un, Data::Ref(a1));
+                    a2 = self.heap.alloc(parser::Qual::Un, Data::Ref(a2));
+                }
+                env.insert(e.left.clone(), Binding::Val(a1));
+                env.insert(e.right.clone(), Binding::Val(a2));
+                self.goto(&e.body, env);
+            }
+            Kont::AppFun(e, env) => {
+                self.kont.push(Kont::AppArg(a));
+                self.goto(&e.expr2, env);
+            }
+            Kont::AppArg(f) => match &self.heap.get(f)?.data {
+                Data::Fun(fenv, var, body) => {
+                    let (mut env, var, body) = (fenv.clone(), *var, *body);
+                    self.heap.consume(f)?;
+                    env.insert(var.to_string(), Binding::Val(a));
+                    self.goto(body, env);
+                }
+                Data::Extern(id, args) => {
+                    let (id, mut args) = (*id, args.clone());
+                    self.cause = Cause::Extern;
+                    self.heap.consume(f)?;
+                    args.push(a);
+                    let v = self.heap.apply_extern(self.externs, id, args)?;
+                    self.control = Control::Value(v);
+                }
+                _ => return Err("関数でない値を関数適用した".to_string()),
+            },
+            Kont::Proj(p) => {
+                let (env, e) = match &self.heap.get(a)?.data {
+                    Data::With(env, e1, e2) => match p {
+                        parser::Proj::Fst => (env.clone(), *e1),
+                        parser::Proj::Snd => (env.clone(), *e2),
+                    },
+                    _ => return Err("fstかsndの引数が加法的ペアでない".to_string()),
+                };
+                self.heap.consume(a)?;
+                self.goto(e, env);
+            }
+            Kont::Borrow => self.borrow(a)?,
+            Kont::PairFst(e, env) => {
+                if let parser::ValExpr::Pair(_, e2) = &e.val {
+                    self.kont.push(Kont::PairSnd(e, a));
+                    self.goto(e2, env);
+                }
+            }
+            Kont::PairSnd(e, a1) => self.alloc(e, Data::Pair(a1, a))?,
+            Kont::NewRef => {
+                let q = parser::Qual::Lin.join(self.heap.get(a)?.qual);
+                self.control = Control::Value(self.heap.alloc(q, Data::Cell(a)));
+            }
+            Kont::SwapCell(e, env) => {
+                self.kont.push(Kont::SwapVal(a));
+                self.goto(&e.expr, env);
+            }
+            Kont::SwapVal(c) => {
+                let cell = self.heap.get(c)?;
+                let (q, old) = match cell.data {
+                    Data::Cell(old) => (cell.qual, old),
+                    _ => return Err("refでない値をswapした".to_string()),
+                };
+
+                // セルを書き換えて中身を交換し、同じ参照と元の中身のペアを返す
+                self.heap.set(c, Data::Cell(a))?;
+                let qo = self.heap.get(old)?.qual;
+                self.control = Control::Value(self.heap.alloc(q.join(qo), Data::Pair(c, old)));
+            }
+            Kont::LetRegion(r, id) => {
+                // 評価結果はリージョン外の値のため、リージョン内の値を一括して解放
+                self.regions.pop();
+                self.cause = Cause::Region(r);
+                self.heap.free_region(id)?;
+                self.control = Control::Value(a);
+            }
+            Kont::AllocLen(e, env) => {
+                let n = self.index(a, None)?;
+                self.kont.push(Kont::AllocVal(n));
+                self.goto(&e.expr, env);
+            }
+            Kont::AllocVal(n) => {
+                // 要素はun型で解放されないため、全ての要素で同じ値を共有する
+                let elems = eval::array_elems(a, n)?;
+                let arr = self.heap.alloc(parser::Qual::Lin, Data::Array(elems));
+                self.control = Control::Value(arr);
+            }
+            Kont::GetArray(e, env) => {
+                self.elems(a)?;
+                self.kont.push(Kont::GetIndex(a));
+                self.goto(&e.index, env);
+            }
+            Kont::GetIndex(arr) => {
+                // 配列は消費せずに、同じ配列と要素のペアを返す
+                let len = self.elems(arr)?.len();
+                let i = self.index(a, Some(len))?;
+                let v = self.elems(arr)?[i];
+                let q = self.heap.get(arr)?.qual;
+                self.control = Control::Value(self.heap.alloc(q, Data::Pair(arr, v)));
+            }
+            Kont::SetArray(e, env) => {
+                self.elems(a)?;
+                self.kont.push(Kont::SetIndex(e, a, env.clone()));
+                self.goto(&e.index, env);
+            }
+            Kont::SetIndex(e, arr, env) => {
+                let len = self.elems(arr)?.len();
+                let i = self.index(a, Some(len))?;
+                self.kont.push(Kont::SetVal(arr, i));
+                self.goto(&e.expr, env);
+            }
+            Kont::SetVal(arr, i) => {
+                // 配列はlin型で他から参照されないため、コピーせずに書き換える
+                self.elems(arr)?[i] = a;
+                self.control = Control::Value(arr);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 状態の表示
+///
+/// 直前のステップで確保、消費、解放したセルと、制御、環境、継続を表示する
+impl fmt::Display for Machine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "[ステップ{}]", self.num_steps)?;
+        for e in self.events.iter() {
+            writeln!(f, "  {}", e)?;
+        }
+
+        match self.control {
+            Control::Expr(e) => writeln!(f, "  C: {}", e)?,
+            Control::Value(a) => writeln!(f, "  C: 値 {}", self.cell(a))?,
+        }
+
+        let vars: Vec<String> = self
+            .locals()
+            .map(|(var, b)| match b {
+                Binding::Val(a) => format!("{} ↦ {}", var, self.cell(a)),
+                Binding::Bang(a) => format!("!{} ↦ {}", var, self.cell(a)),
+            })
+            .collect();
+        if vars.is_empty() {
+            writeln!(f, "  E: （外部定義のみ）")?;
+        } else {
+            writeln!(f, "  E: {}", vars.join(", "))?;
+        }
+
+        if self.kont.is_empty() {
+            writeln!(f, "  K: （空）")?;
+        }
+        for (i, k) in self.kont.iter().rev().enumerate() {
+            writeln!(f, "  {} {}", if i == 0 { "K:" } else { "  " }, k)?;
+        }
+        Ok(())
+    }
+}
+
+/// 外部定義を束縛した初期環境で式を評価し、評価結果の値と、評価後のヒープの統計情報を返す
+///
+/// 初期状態と、遷移した各状態をtraceに渡す
+pub fn eval(
+    expr: &parser::Expr,
+    externs: &Externs,
+    mut trace: impl FnMut(&Machine),
+) -> Result<(Value, HeapStats), String> {
+    let mut m = Machine::new(expr, externs)?;
+    trace(&m);
+    while m.step()? {
+        trace(&m);
+    }
+    m.result()
+}
@@ -0,0 +1,569 @@
+//! ## C言語へのコード生成
+//!
+//! 型付けに成功した式を、C言語のプログラムに変換する。
+//! 評価器と同様に、修飾子付き値はmallocで確保したセルとなり、
+//! ord、lin、aff型の値は分解した時点とfree文でfree()により解放される。
+//! un型とrel型の値は、プログラムの終了時にまとめて解放される。
+//!
+//! 関数はクロージャ変換により、キャプチャした変数の配列を受け取るC言語の関数と、
+//! 関数と配列を保持する構造体に変換する。
+//! 加法的ペアの要素と!型の中身も、同様に評価を遅延する関数に変換する。
+//!
+//! 生成したプログラムは評価結果とヒープの統計情報を表示し、
+//! 評価結果から到達できないordとlin型の値が残っていた場合は終了コード1で終了する。
+//! リークした値は解放せずに残すため、AddressSanitizerやValgrindでも検出できる。
+//! free文は関数と加法的ペアがキャプチャした値を解放しないが、型付けではun、aff型の値のみを
+//! キャプチャした関数と加法的ペアに限りfreeを許すため、キャプチャしたordとlin型の値がリークすることはない。
+//!
+//! チャネルとスレッドには対応しておらず、外部関数は組み込み関数のみ利用できる。
+
+use crate::{
+    eval::Value,
+    externs::{self, ExternVal, Externs},
+    parser,
+    subst::{free_vars, free_vars_in},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+/// ランタイム
+const RUNTIME: &str = include_str!("codegen_c/runtime.c");
+
+/// C言語の実装を持つ組み込み関数
+const BUILTINS: [&str; 6] = ["not", "concat", "length", "open", "write", "fclose"];
+
+type CResult = Result<String, String>;
+
+/// 変数の束縛
+#[derive(Debug, Clone)]
+struct Var {
+    name: String, // C言語の変数名
+    bang: bool,   // let !式で束縛された!型の値か。利用する度に中身を評価する
+}
+
+/// 変数とリージョンから、C言語の変数名への対応
+#[derive(Debug, Clone, Default)]
+struct Ctx {
+    vars: BTreeMap<String, Var>,
+    regions: BTreeMap<String, String>,
+}
+
+/// 生成中の関数本体
+#[derive(Debug, Default)]
+struct Body {
+    code: String,
+    indent: usize,
+}
+
+impl Body {
+    /// 1行を追加
+    fn line(&mut self, s: &str) {
+        for _ in 0..self.indent {
+            self.code.push_str("    ");
+        }
+        self.code.push_str(s);
+        self.code.push('\n');
+    }
+
+    /// 言語の変数を宣言。un型の変数は利用されないことがあるため、未使用の警告を抑制する
+    fn bind(&mut self, name: &str, value: &str) {
+        self.line(&format!("Cell *{} = {};", name, value));
+        self.line(&format!("(void){};", name));
+    }
+}
+
+/// コード生成器
+struct Codegen<'a> {
+    externs: &'a Externs,
+    funcs: Vec<String>, // クロージャ変換した関数の定義
+    next: usize,        // 一時変数などの番号
+}
+
+impl<'a> Codegen<'a> {
+    /// 新しい番号を返す
+    fn fresh(&mut self) -> usize {
+        self.next += 1;
+        self.next
+    }
+
+    /// 新しい一時変数に式の値を代入し、一時変数名を返す
+    fn temp(&mut self, body: &mut Body, expr: &str) -> String {
+        let t = format!("t{}", self.fresh());
+        body.line(&format!("Cell *{} = {};", t, expr));
+        t
+    }
+
+    /// 言語の変数varに対応する、新しいC言語の変数名を返す
+    fn var_name(&mut self, var: &str) -> String {
+        format!("v{}_{}", self.fresh(), var)
+    }
+
+    /// 式を変換し、評価結果を保持するC言語の変数名を返す
+    fn expr(&mut self, expr: &parser::Expr, ctx: &Ctx, body: &mut Body) -> CResult {
+        match expr {
+            parser::Expr::Let(e) => self.gen_let(e, ctx, body),
+            parser::Expr::LetBang(e) => self.gen_let_bang(e, ctx, body),
+            parser::Expr::If(e) => self.gen_if(e, ctx, body),
+            parser::Expr::Split(e) => self.gen_split(e, ctx, body),
+            parser::Expr::Free(e) => self.gen_free(e, ctx, body),
+            parser::Expr::App(e) => {
+                let f = self.expr(&e.expr1, ctx, body)?;
+                let a = self.expr(&e.expr2, ctx, body)?;
+                Ok(self.temp(body, &format!("rt_apply({}, {})", f, a)))
+            }
+            parser::Expr::Proj(e) => {
+                let a = self.expr(&e.expr, ctx, body)?;
+                let snd = (e.proj == parser::Proj::Snd) as u8;
+                Ok(self.temp(body, &format!("rt_proj({}, {})", a, snd)))
+            }
+            parser::Expr::Promote(e) => {
+                let (code, env, n) = self.thunks(&[&e.expr], ctx, body)?;
+                Ok(self.temp(body, &format!("rt_bang({}, {}, {})", code[0], env, n)))
+            }
+            parser::Expr::Borrow(var) => {
+                let a = self.gen_var(var, ctx, body)?;
+                Ok(self.temp(body, &format!("rt_borrow({})", a)))
+            }
+            parser::Expr::NewRef(e) => {
+                let a = self.expr(&e.expr, ctx, body)?;
+                Ok(self.temp(body, &format!("rt_new_ref({})", a)))
+            }
+            parser::Expr::Swap(e) => {
+                let c = self.expr(&e.cell, ctx, body)?;
+                let v = self.expr(&e.expr, ctx, body)?;
+                Ok(self.temp(body, &format!("rt_swap({}, {})", c, v)))
+            }
+            parser::Expr::LetRegion(e) => self.gen_letregion(e, ctx, body),
+            parser::Expr::Alloc(e) => {
+                let n = self.expr(&e.len, ctx, body)?;
+                let v = self.expr(&e.expr, ctx, body)?;
+                Ok(self.temp(body, &format!("rt_alloc_array({}, {})", n, v)))
+            }
+            parser::Expr::Get(e) => {
+                let a = self.expr(&e.array, ctx, body)?;
+                let i = self.expr(&e.index, ctx, body)?;
+                Ok(self.temp(body, &format!("rt_get({}, {})", a, i)))
+            }
+            parser::Expr::Set(e) => {
+                let a = self.expr(&e.array, ctx, body)?;
+                let i = self.expr(&e.index, ctx, body)?;
+                let v = self.expr(&e.expr, ctx, body)?;
+                Ok(self.temp(body, &format!("rt_set({}, {}, {})", a, i, v)))
+            }
+            parser::Expr::Var(var) => self.gen_var(var, ctx, body),
+            parser::Expr::QVal(e) => self.gen_qval(e, ctx, body),
+            parser::Expr::New(_)
+            | parser::Expr::Send(_)
+            | parser::Expr::Recv(_)
+            | parser::Expr::Close(_)
+            | parser::Expr::Fork(_) => Err("チャネルとスレッドはC言語に変換できない".to_string()),
+        }
+    }
+
+    /// 変数の変換
+    fn gen_var(&mut self, var: &str, ctx: &Ctx, body: &mut Body) -> CResult {
+        match ctx.vars.get(var) {
+            Some(Var { name, bang: false }) => Ok(name.clone()),
+            Some(Var { name, bang: true }) => Ok(self.temp(body, &format!("rt_force({})", name))),
+            None => Err(format!("\"{}\"という変数は定義されていない", var)),
+        }
+    }
+
+    /// 修飾子付き値の変換
+    fn gen_qval(&mut self, expr: &parser::QValExpr, ctx: &Ctx, body: &mut Body) -> CResult {
+        let q = qual(expr.qual);
+        let r = match &expr.region {
+            Some(r) => ctx
+                .regions
+                .get(r)
+                .cloned()
+                .ok_or_else(|| format!("リージョン\"{}\"は作成されていない", r))?,
+            None => "-1".to_string(),
+        };
+
+        let e = match &expr.val {
+            parser::ValExpr::Bool(b) => format!("rt_bool({}, {}, {})", q, r, *b as u8),
+            parser::ValExpr::Int(n) => format!("rt_int({}, {}, INT64_C({}))", q, r, n),
+            parser::ValExpr::Str(s) => {
+                format!("rt_str({}, {}, {}, {})", q, r, c_str(s), s.len())
+            }
+            parser::ValExpr::Pair(e1, e2) => {
+                let a1 = self.expr(e1, ctx, body)?;
+                let a2 = self.expr(e2, ctx, body)?;
+                format!("rt_pair({}, {}, {}, {})", q, r, a1, a2)
+            }
+            parser::ValExpr::With(e1, e2) => {
+                let (code, env, n) = self.thunks(&[e1, e2], ctx, body)?;
+                format!(
+                    "rt_with({}, {}, {}, {}, {}, {})",
+                    q, r, code[0], code[1], env, n
+                )
+            }
+            parser::ValExpr::Fun(e) => {
+                let (code, env, n) = self.closure(e, ctx, body)?;
+                format!(
+                    "rt_fun({}, {}, {}, {}, {}, {})",
+                    q,
+                    r,
+                    code,
+                    c_str(&e.var),
+                    env,
+                    n
+                )
+            }
+        };
+        Ok(self.temp(body, &e))
+    }
+
+    /// exprsの自由変数をキャプチャした変数の配列を作成し、
+    /// 配列の変数名と要素数、関数内でキャプチャした変数を束縛した型環境を返す
+    ///
+    /// 関数内では、外側のリージョンに値を確保できないため、リージョンは引き継がない
+    fn capture(
+        &mut self,
+        exprs: &[&parser::Expr],
+        bound: &[&str],
+        ctx: &Ctx,
+        body: &mut Body,
+    ) -> (String, usize, Vec<(usize, String, Var)>) {
+        let mut fv = BTreeSet::new();
+        for e in exprs {
+            free_vars_in(
+                e,
+                &mut bound.iter().map(|v| v.to_string()).collect(),
+                &mut fv,
+            );
+        }
+
+        // 自由変数のうち、束縛されているものをキャプチャ
+        let caps: Vec<(String, &Var)> = fv
+            .into_iter()
+            .filter_map(|v| ctx.vars.get(&v).map(|b| (v, b)))
+            .collect();
+
+        let env = format!("env{}", self.fresh());
+        body.line(&format!("Cell **{} = rt_env({});", env, caps.len()));
+        let mut inner = Vec::new();
+        for (i, (v, b)) in caps.into_iter().enumerate() {
+            body.line(&format!("{}[{}] = {};", env, i, b.name));
+            let name = self.var_name(&v);
+            inner.push((i, v, Var { name, bang: b.bang }));
+        }
+        let n = inner.len();
+        (env, n, inner)
+    }
+
+    /// 関数本体の先頭で、キャプチャした変数を束縛
+    fn bind_captures(caps: &[(usize, String, Var)], fbody: &mut Body) -> Ctx {
+        let mut ctx = Ctx::default();
+        for (i, v, b) in caps {
+            fbody.bind(&b.name, &format!("env[{}]", i));
+            ctx.vars.insert(v.clone(), b.clone());
+        }
+        ctx
+    }
+
+    /// 関数をクロージャ変換し、C言語の関数名と、キャプチャした変数の配列の変数名と要素数を返す
+    fn closure(
+        &mut self,
+        expr: &parser::FnExpr,
+        ctx: &Ctx,
+        body: &mut Body,
+    ) -> Result<(String, String, usize), String> {
+        let (env, n, caps) = self.capture(&[&expr.expr], &[&expr.var], ctx, body);
+
+        let name = format!("fn{}", self.fresh());
+        let mut fbody = Body {
+            code: String::new(),
+            indent: 1,
+        };
+        let mut inner = Self::bind_captures(&caps, &mut fbody);
+        let arg = self.var_name(&expr.var);
+        fbody.bind(&arg, "arg");
+        inner.vars.insert(
+            expr.var.clone(),
+            Var {
+                name: arg,
+                bang: false,
+            },
+        );
+
+        let r = self.expr(&expr.expr, &inner, &mut fbody)?;
+        fbody.line(&format!("return {};", r));
+        self.funcs.push(format!(
+            "/* fn {} */\nstatic Cell *{}(Cell **env, Cell *arg) {{\n{}}}\n",
+            expr.var, name, fbody.code
+        ));
+
+        Ok((name, env, n))
+    }
+
+    /// 加法的ペアの要素や!型の中身など、評価を遅延する式を変換し、
+    /// 式ごとのC言語の関数名と、キャプチャした変数の配列の変数名と要素数を返す
+    ///
+    /// 全ての式で、キャプチャした変数の配列を共有する
+    fn thunks(
+        &mut self,
+        exprs: &[&parser::Expr],
+        ctx: &Ctx,
+        body: &mut Body,
+    ) -> Result<(Vec<String>, String, usize), String> {
+        let (env, n, caps) = self.capture(exprs, &[], ctx, body);
+
+        let mut names = Vec::new();
+        for e in exprs {
+            let name = format!("thunk{}", self.fresh());
+            let mut fbody = Body {
+                code: String::new(),
+                indent: 1,
+            };
+            let inner = Self::bind_captures(&caps, &mut fbody);
+            let r = self.expr(e, &inner, &mut fbody)?;
+            fbody.line(&format!("return {};", r));
+            self.funcs.push(format!(
+                "static Cell *{}(Cell **env) {{\n{}}}\n",
+                name, fbody.code
+            ));
+            names.push(name);
+        }
+
+        Ok((names, env, n))
+    }
+
+    /// let式の変換
+    fn gen_let(&mut self, expr: &parser::LetExpr, ctx: &Ctx, body: &mut Body) -> CResult {
+        let a = self.expr(&expr.expr1, ctx, body)?;
+        let name = self.var_name(&expr.var);
+        body.bind(&name, &a);
+
+        let mut ctx = ctx.clone();
+        ctx.vars.insert(expr.var.clone(), Var { name, bang: false });
+        self.expr(&expr.expr2, &ctx, body)
+    }
+
+    /// let !式の変換
+    fn gen_let_bang(&mut self, expr: &parser::LetBangExpr, ctx: &Ctx, body: &mut Body) -> CResult {
+        let a = self.expr(&expr.expr1, ctx, body)?;
+        let name = self.var_name(&expr.var);
+        body.bind(&name, &a);
+
+        let mut ctx = ctx.clone();
+        ctx.vars.insert(expr.var.clone(), Var { name, bang: true });
+        self.expr(&expr.expr2, &ctx, body)
+    }
+
+    /// if式の変換
+    fn gen_if(&mut self, expr: &parser::IfExpr, ctx: &Ctx, body: &mut Body) -> CResult {
+        let c = self.expr(&expr.cond_expr, ctx, body)?;
+        let r = format!("t{}", self.fresh());
+        body.line(&format!("Cell *{};", r));
+
+        body.line(&format!("if (rt_cond({})) {{", c));
+        body.indent += 1;
+        let a = self.expr(&expr.then_expr, ctx, body)?;
+        body.line(&format!("{} = {};", r, a));
+        body.indent -= 1;
+        body.line("} else {");
+        body.indent += 1;
+        let a = self.expr(&expr.else_expr, ctx, body)?;
+        body.line(&format!("{} = {};", r, a));
+        body.indent -= 1;
+        body.line("}");
+
+        Ok(r)
+    }
+
+    /// split式の変換
+    fn gen_split(&mut self, expr: &parser::SplitExpr, ctx: &Ctx, body: &mut Body) -> CResult {
+        let a = self.expr(&expr.expr, ctx, body)?;
+        let left = self.var_name(&expr.left);
+        let right = self.var_name(&expr.right);
+        body.line(&format!("Cell *{}, *{};", left, right));
+        body.line(&format!("rt_split({}, &{}, &{});", a, left, right));
+
+        let mut ctx = ctx.clone();
+        ctx.vars.insert(
+            expr.left.clone(),
+            Var {
+                name: left,
+                bang: false,
+            },
+        );
+        ctx.vars.insert(
+            expr.right.clone(),
+            Var {
+                name: right,
+                bang: false,
+            },
+        );
+        self.expr(&expr.body, &ctx, body)
+    }
+
+    /// free文の変換
+    fn gen_free(&mut self, expr: &parser::FreeExpr, ctx: &Ctx, body: &mut Body) -> CResult {
+        match ctx.vars.get(&expr.var) {
+            Some(Var { name, bang: false }) => body.line(&format!("rt_free_var({});", name)),
+            _ => return Err(format!("変数\"{}\"をfreeできない", expr.var)),
+        }
+        self.expr(&expr.expr, ctx, body)
+    }
+
+    /// letregion式の変換
+    fn gen_letregion(
+        &mut self,
+        expr: &parser::LetRegionExpr,
+        ctx: &Ctx,
+        body: &mut Body,
+    ) -> CResult {
+        let r = format!("r{}_{}", self.fresh(), expr.region);
+        body.line(&format!("long {} = rt_region_new();", r));
+
+        let mut ctx = ctx.clone();
+        ctx.regions.insert(expr.region.clone(), r.clone());
+        let a = self.expr(&expr.expr, &ctx, body)?;
+
+        // 評価結果はリージョン外の値のため、リージョン内の値を一括して解放
+        body.line(&format!("rt_region_free({});", r));
+        Ok(a)
+    }
+
+    /// プログラムで利用する外部定義を、main関数の先頭で束縛
+    fn init(&mut self, expr: &parser::Expr, body: &mut Body) -> Result<(Ctx, String), String> {
+        let fv = free_vars(expr);
+
+        let mut ctx = Ctx::default();
+        let mut decls = String::new();
+        for var in fv {
+            let def = self
+                .externs
+                .defs
+                .iter()
+                .rev()
+                .find(|d| d.name == var)
+                .ok_or_else(|| format!("\"{}\"という変数は定義されていない", var))?;
+            let name = self.var_name(&var);
+
+            let e = match &def.val {
+                ExternVal::Value(v) => value(v)?,
+                ExternVal::Fun(_) => {
+                    if !BUILTINS.contains(&var.as_str()) {
+                        return Err(format!("外部関数\"{}\"はC言語の実装を持たない", var));
+                    }
+
+                    // 引数をn個与えた後の修飾子の一覧と、外部関数の定義を出力
+                    let n = externs::arity(&def.ty);
+                    let quals: Vec<&str> = (1..n)
+                        .map(|k| qual(externs::applied(&def.ty, k).qual))
+                        .collect();
+                    writeln!(decls, "#define USE_EXT_{}", var).unwrap();
+                    writeln!(
+                        decls,
+                        "static const enum Qual ext_{}_quals[] = {{{}}};",
+                        var,
+                        if quals.is_empty() {
+                            "UN".to_string()
+                        } else {
+                            quals.join(", ")
+                        }
+                    )
+                    .unwrap();
+                    writeln!(
+                        decls,
+                        "static const Extern ext_{}_def = {{{}, {}, ext_{}_quals, ext_{}}};",
+                        var,
+                        c_str(&var),
+                        n,
+                        var,
+                        var
+                    )
+                    .unwrap();
+                    format!(
+                        "rt_extern({}, &ext_{}_def, NULL, 0)",
+                        qual(def.ty.qual),
+                        var
+                    )
+                }
+            };
+            body.bind(&name, &e);
+            ctx.vars.insert(var, Var { name, bang: false });
+        }
+        Ok((ctx, decls))
+    }
+}
+
+/// 修飾子に対応するランタイムの定数
+fn qual(q: parser::Qual) -> &'static str {
+    match q {
+        parser::Qual::Ord => "ORD",
+        parser::Qual::Lin => "LIN",
+        parser::Qual::Aff => "AFF",
+        parser::Qual::Rel => "REL",
+        parser::Qual::Un => "UN",
+    }
+}
+
+/// 文字列をC言語の文字列リテラルに変換
+///
+/// 印字可能なASCII文字以外は8進数のエスケープとする
+fn c_str(s: &str) -> String {
+    let mut lit = String::from("\"");
+    for b in s.bytes() {
+        match b {
+            b'"' => lit.push_str("\\\""),
+            b'\\' => lit.push_str("\\\\"),
+            b'?' => lit.push_str("\\?"), // トライグラフを避ける
+            0x20..=0x7e => lit.push(b as char),
+            _ => write!(lit, "\\{:03o}", b).unwrap(),
+        }
+    }
+    lit.push('"');
+    lit
+}
+
+/// 外部定義の値を作成するランタイムの呼び出し
+fn value(v: &Value) -> CResult {
+    Ok(match v {
+        Value::Bool(q, b) => format!("rt_bool({}, -1, {})", qual(*q), *b as u8),
+        Value::Unit(q) => format!("rt_unit({}, -1)", qual(*q)),
+        Value::Int(q, n) => format!("rt_int({}, -1, INT64_C({}))", qual(*q), n),
+        Value::Str(q, s) => format!("rt_str({}, -1, {}, {})", qual(*q), c_str(s), s.len()),
+        _ => return Err(format!("外部定義の値{}はC言語に変換できない", v)),
+    })
+}
+
+/// 外部定義を束縛した初期環境で式を評価する、C言語のプログラムを生成
+///
+/// 型付けに成功した式を渡すこと
+pub fn codegen(expr: &parser::Expr, externs: &Externs) -> Result<String, String> {
+    let mut cg = Codegen {
+        externs,
+        funcs: Vec::new(),
+        next: 0,
+    };
+
+    let mut body = Body {
+        code: String::new(),
+        indent: 1,
+    };
+    let (ctx, decls) = cg.init(expr, &mut body)?;
+    let r = cg.expr(expr, &ctx, &mut body)?;
+    body.line(&format!("return rt_finish({});", r));
+
+    let mut out = String::new();
+    writeln!(out, "/* 線形型言語から生成したコード */\n").unwrap();
+    for line in decls.lines().filter(|l| l.starts_with("#define")) {
+        writeln!(out, "{}", line).unwrap();
+    }
+    out.push_str(RUNTIME);
+    out.push('\n');
+    for line in decls.lines().filter(|l| !l.starts_with("#define")) {
+        writeln!(out, "{}", line).unwrap();
+    }
+    for f in cg.funcs.iter() {
+        writeln!(out, "\n{}", f.trim_end()).unwrap();
+    }
+    write!(out, "\nint main(void) {{\n{}}}\n", body.code).unwrap();
+    Ok(out)
+}
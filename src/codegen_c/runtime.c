@@ -0,0 +1,751 @@
+/*
+ * 線形型言語から変換したC言語のコードのランタイム
+ *
+ * 評価器と同様に、修飾子付き値はすべてmallocで確保したセルとなる。
+ * ord、lin、aff型のセルは分解した時点とfree文でfree()により解放し、
+ * 何度でも利用されうるun型とrel型のセルは、プログラムの終了時にまとめて解放する。
+ * 終了時に評価結果から到達できないordとlin型のセルが残っていた場合はリークとして報告する。
+ */
+
+#include <inttypes.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/* ランタイムの関数。利用しない関数があっても警告しない */
+#if defined(__GNUC__)
+#define RT_FN static __attribute__((unused))
+#else
+#define RT_FN static
+#endif
+
+/* 修飾子 */
+enum Qual { ORD, LIN, AFF, REL, UN };
+
+/* セルの種類 */
+enum Tag {
+    T_BOOL,   /* 真偽値 */
+    T_UNIT,   /* ユニット */
+    T_HANDLE, /* ファイルハンドル */
+    T_INT,    /* 整数 */
+    T_STR,    /* 文字列 */
+    T_PAIR,   /* ペア */
+    T_WITH,   /* 加法的ペア。射影するまで評価しない */
+    T_FUN,    /* 関数（クロージャ） */
+    T_BANG,   /* !型の値。取り出すまで評価しない */
+    T_EXTERN, /* 外部関数 */
+    T_REF,    /* 参照 */
+    T_CELL,   /* 可変な参照 */
+    T_ARRAY,  /* 配列 */
+};
+
+typedef struct Cell Cell;
+
+/* クロージャ変換した関数。envはキャプチャした変数の配列 */
+typedef Cell *(*FunCode)(Cell **env, Cell *arg);
+
+/* 加法的ペアの要素と、!型の中身を評価する関数 */
+typedef Cell *(*ThunkCode)(Cell **env);
+
+/* 外部関数の定義 */
+typedef struct Extern {
+    const char *name;
+    size_t arity;
+    const enum Qual *quals; /* n個の引数を与えた後の修飾子（nは1からarity - 1） */
+    Cell *(*code)(Cell **args);
+} Extern;
+
+/* ヒープ上のセル */
+struct Cell {
+    enum Qual qual;
+    enum Tag tag;
+    long region;       /* 確保したリージョン。リージョン外の場合は-1 */
+    Cell *prev, *next; /* 解放されていないセルのリスト */
+    int mark;          /* 評価結果から到達可能か */
+    union {
+        int b;
+        int64_t n;
+        char *s;
+        struct {
+            FILE *fp;
+            long id;
+        } handle;
+        struct {
+            Cell *a1, *a2;
+        } pair;
+        struct {
+            FunCode code;
+            const char *var; /* 引数名 */
+            Cell **env;
+            size_t n;
+        } fun;
+        struct {
+            ThunkCode code1, code2; /* !型の値はcode1のみ利用する */
+            Cell **env;
+            size_t n;
+        } thunk;
+        struct {
+            const Extern *ext;
+            Cell **args; /* 受け取った引数 */
+            size_t n;
+        } ext;
+        Cell *ref; /* 参照先か、可変な参照の中身 */
+        struct {
+            Cell **elems;
+            size_t len;
+        } array;
+    } u;
+};
+
+static Cell *live;       /* 解放されていないセルのリスト */
+static size_t num_alloc; /* 確保したセルの数 */
+static size_t num_free;  /* 解放したセルの数 */
+static long num_regions; /* 作成したリージョンの数 */
+
+#define RT_MAX_ARRAY_LEN ((size_t)1 << 24) /* 配列の長さの上限。評価器と同じ */
+
+/* 評価エラー */
+RT_FN void rt_error(const char *msg) {
+    fprintf(stderr, "評価エラー: %s\n", msg);
+    exit(2);
+}
+
+/* mallocし、失敗した場合は終了 */
+RT_FN void *rt_malloc(size_t size) {
+    void *p = malloc(size == 0 ? 1 : size);
+    if (p == NULL) {
+        rt_error("メモリを確保できない");
+    }
+    return p;
+}
+
+/* 修飾子の束の上限 */
+RT_FN enum Qual rt_join(enum Qual q1, enum Qual q2) {
+    if (q1 == q2 || q2 == UN) {
+        return q1;
+    }
+    if (q1 == UN) {
+        return q2;
+    }
+    if (q1 == ORD || q2 == ORD) {
+        return ORD;
+    }
+    return LIN;
+}
+
+/* セルを確保し、解放されていないセルのリストに追加 */
+RT_FN Cell *rt_alloc(enum Qual q, long region, enum Tag tag) {
+    Cell *c = rt_malloc(sizeof(Cell));
+    memset(c, 0, sizeof(Cell));
+    c->qual = q;
+    c->tag = tag;
+    c->region = region;
+    c->next = live;
+    if (live != NULL) {
+        live->prev = c;
+    }
+    live = c;
+    num_alloc++;
+    return c;
+}
+
+/* セルを解放 */
+RT_FN void rt_free(Cell *c) {
+    if (c->prev != NULL) {
+        c->prev->next = c->next;
+    } else {
+        live = c->next;
+    }
+    if (c->next != NULL) {
+        c->next->prev = c->prev;
+    }
+
+    switch (c->tag) {
+    case T_STR:
+        free(c->u.s);
+        break;
+    case T_WITH:
+    case T_BANG:
+        free(c->u.thunk.env);
+        break;
+    case T_FUN:
+        free(c->u.fun.env);
+        break;
+    case T_EXTERN:
+        free(c->u.ext.args);
+        break;
+    case T_ARRAY:
+        free(c->u.array.elems);
+        break;
+    default:
+        break;
+    }
+    free(c);
+    num_free++;
+}
+
+/* 分解したセルを、修飾子に応じて解放 */
+RT_FN void rt_consume(Cell *c) {
+    if (c->qual == ORD || c->qual == LIN || c->qual == AFF) {
+        rt_free(c);
+    }
+}
+
+/* freeしたセルや外部関数に渡したセルを、ペアの要素やrefの中身も含めて修飾子に応じて解放 */
+RT_FN void rt_release(Cell *c) {
+    if (c->tag == T_PAIR) {
+        rt_release(c->u.pair.a1);
+        rt_release(c->u.pair.a2);
+    } else if (c->tag == T_CELL) {
+        rt_release(c->u.ref);
+    }
+    rt_consume(c);
+}
+
+/* キャプチャした変数の配列を確保 */
+RT_FN Cell **rt_env(size_t n) {
+    return n == 0 ? NULL : rt_malloc(sizeof(Cell *) * n);
+}
+
+RT_FN Cell *rt_bool(enum Qual q, long region, int b) {
+    Cell *c = rt_alloc(q, region, T_BOOL);
+    c->u.b = b;
+    return c;
+}
+
+RT_FN Cell *rt_unit(enum Qual q, long region) {
+    return rt_alloc(q, region, T_UNIT);
+}
+
+RT_FN Cell *rt_int(enum Qual q, long region, int64_t n) {
+    Cell *c = rt_alloc(q, region, T_INT);
+    c->u.n = n;
+    return c;
+}
+
+/* 長さlenの文字列sを複製して確保 */
+RT_FN Cell *rt_str(enum Qual q, long region, const char *s, size_t len) {
+    Cell *c = rt_alloc(q, region, T_STR);
+    c->u.s = rt_malloc(len + 1);
+    memcpy(c->u.s, s, len);
+    c->u.s[len] = '\0';
+    return c;
+}
+
+RT_FN Cell *rt_pair(enum Qual q, long region, Cell *a1, Cell *a2) {
+    Cell *c = rt_alloc(q, region, T_PAIR);
+    c->u.pair.a1 = a1;
+    c->u.pair.a2 = a2;
+    return c;
+}
+
+RT_FN Cell *rt_with(enum Qual q, long region, ThunkCode code1, ThunkCode code2, Cell **env,
+                   size_t n) {
+    Cell *c = rt_alloc(q, region, T_WITH);
+    c->u.thunk.code1 = code1;
+    c->u.thunk.code2 = code2;
+    c->u.thunk.env = env;
+    c->u.thunk.n = n;
+    return c;
+}
+
+RT_FN Cell *rt_fun(enum Qual q, long region, FunCode code, const char *var, Cell **env,
+                   size_t n) {
+    Cell *c = rt_alloc(q, region, T_FUN);
+    c->u.fun.code = code;
+    c->u.fun.var = var;
+    c->u.fun.env = env;
+    c->u.fun.n = n;
+    return c;
+}
+
+RT_FN Cell *rt_bang(ThunkCode code, Cell **env, size_t n) {
+    Cell *c = rt_alloc(UN, -1, T_BANG);
+    c->u.thunk.code1 = code;
+    c->u.thunk.env = env;
+    c->u.thunk.n = n;
+    return c;
+}
+
+/* 受け取った引数argsを保持した外部関数 */
+RT_FN Cell *rt_extern(enum Qual q, const Extern *ext, Cell **args, size_t n) {
+    Cell *c = rt_alloc(q, -1, T_EXTERN);
+    c->u.ext.ext = ext;
+    c->u.ext.args = args;
+    c->u.ext.n = n;
+    return c;
+}
+
+/* 参照をたどり、参照でないセルを返す */
+RT_FN Cell *rt_deref(Cell *c) {
+    while (c->tag == T_REF) {
+        c = c->u.ref;
+    }
+    return c;
+}
+
+/* !型の値の中身を評価 */
+RT_FN Cell *rt_force(Cell *c) {
+    if (c->tag != T_BANG) {
+        rt_error("!型でない値を取り出そうとした");
+    }
+    return c->u.thunk.code1(c->u.thunk.env);
+}
+
+/* 借用。参照の借用は同じ参照とする */
+RT_FN Cell *rt_borrow(Cell *c) {
+    if (c->tag == T_REF) {
+        return c;
+    }
+    Cell *r = rt_alloc(UN, -1, T_REF);
+    r->u.ref = c;
+    return r;
+}
+
+/* ifの条件式の真偽値を取り出し、条件式の値を消費 */
+RT_FN int rt_cond(Cell *c) {
+    Cell *t = rt_deref(c);
+    if (t->tag != T_BOOL) {
+        rt_error("ifの条件式がboolでない");
+    }
+    int b = t->u.b;
+    rt_consume(c);
+    return b;
+}
+
+/* ペアを分解。ペアへの参照を分解した場合は、各要素への参照とする */
+RT_FN void rt_split(Cell *c, Cell **a1, Cell **a2) {
+    Cell *t = rt_deref(c);
+    if (t->tag != T_PAIR) {
+        rt_error("splitの引数がペアでない");
+    }
+    *a1 = t->u.pair.a1;
+    *a2 = t->u.pair.a2;
+    rt_consume(c);
+
+    if (t != c) {
+        *a1 = rt_borrow(*a1);
+        *a2 = rt_borrow(*a2);
+    }
+}
+
+/* free文。ペアの要素やrefの中身も含めて解放 */
+RT_FN void rt_free_var(Cell *c) {
+    rt_release(c);
+}
+
+/* 外部関数に引数argを与える */
+RT_FN Cell *rt_apply_extern(Cell *f, Cell *arg) {
+    const Extern *ext = f->u.ext.ext;
+    size_t n = f->u.ext.n + 1;
+    Cell **args = rt_malloc(sizeof(Cell *) * n);
+    if (f->u.ext.n > 0) {
+        memcpy(args, f->u.ext.args, sizeof(Cell *) * f->u.ext.n);
+    }
+    args[n - 1] = arg;
+    rt_consume(f);
+
+    // 全ての引数がそろっていない場合は、部分適用した外部関数を返す
+    if (n < ext->arity) {
+        return rt_extern(ext->quals[n - 1], ext, args, n);
+    }
+
+    // 外部関数を呼び出し、引数を外部関数に渡したものとして解放
+    Cell *r = ext->code(args);
+    for (size_t i = 0; i < n; i++) {
+        rt_release(args[i]);
+    }
+    free(args);
+    return r;
+}
+
+/* 関数適用 */
+RT_FN Cell *rt_apply(Cell *f, Cell *arg) {
+    if (f->tag == T_EXTERN) {
+        return rt_apply_extern(f, arg);
+    }
+    if (f->tag != T_FUN) {
+        rt_error("関数でない値を関数適用した");
+    }
+
+    // 関数を消費する場合は、キャプチャした変数の配列を関数の評価後に解放
+    FunCode code = f->u.fun.code;
+    Cell **env = f->u.fun.env;
+    int consumed = f->qual == ORD || f->qual == LIN || f->qual == AFF;
+    if (consumed) {
+        f->u.fun.env = NULL;
+        rt_free(f);
+    }
+
+    Cell *r = code(env, arg);
+    if (consumed) {
+        free(env);
+    }
+    return r;
+}
+
+/* fstとsnd。sndの場合はsndを非0とする */
+RT_FN Cell *rt_proj(Cell *c, int snd) {
+    if (c->tag != T_WITH) {
+        rt_error("fstかsndの引数が加法的ペアでない");
+    }
+
+    ThunkCode code = snd ? c->u.thunk.code2 : c->u.thunk.code1;
+    Cell **env = c->u.thunk.env;
+    int consumed = c->qual == ORD || c->qual == LIN || c->qual == AFF;
+    if (consumed) {
+        c->u.thunk.env = NULL;
+        rt_free(c);
+    }
+
+    Cell *r = code(env);
+    if (consumed) {
+        free(env);
+    }
+    return r;
+}
+
+/* new式（可変な参照の作成） */
+RT_FN Cell *rt_new_ref(Cell *v) {
+    Cell *c = rt_alloc(rt_join(LIN, v->qual), -1, T_CELL);
+    c->u.ref = v;
+    return c;
+}
+
+/* swap式。中身を交換し、同じ参照と元の中身のペアを返す */
+RT_FN Cell *rt_swap(Cell *c, Cell *v) {
+    if (c->tag != T_CELL) {
+        rt_error("refでない値をswapした");
+    }
+    Cell *old = c->u.ref;
+    c->u.ref = v;
+    return rt_pair(rt_join(c->qual, old->qual), -1, c, old);
+}
+
+/* 配列の位置か長さを取り出し、消費。lenが負でない場合は範囲内かをチェック */
+RT_FN size_t rt_index(Cell *c, int64_t len) {
+    if (c->tag != T_INT) {
+        rt_error("配列の位置か長さがintでない");
+    }
+    int64_t n = c->u.n;
+    rt_consume(c);
+    if (n < 0 || (len >= 0 && n >= len)) {
+        fprintf(stderr, "評価エラー: 配列の範囲外の位置%" PRId64 "を指定した\n", n);
+        exit(2);
+    }
+    return (size_t)n;
+}
+
+/* alloc式。要素はun型で解放されないため、全ての要素で同じ値を共有する */
+RT_FN Cell *rt_alloc_array(Cell *len, Cell *v) {
+    size_t n = rt_index(len, -1);
+    if (n > RT_MAX_ARRAY_LEN) {
+        rt_error("配列の長さが上限を超えている");
+    }
+    Cell *c = rt_alloc(LIN, -1, T_ARRAY);
+    c->u.array.elems = rt_malloc(sizeof(Cell *) * n);
+    c->u.array.len = n;
+    for (size_t i = 0; i < n; i++) {
+        c->u.array.elems[i] = v;
+    }
+    return c;
+}
+
+/* 配列でない場合は評価エラー */
+RT_FN void rt_check_array(Cell *c) {
+    if (c->tag != T_ARRAY) {
+        rt_error("配列でない値を読み書きした");
+    }
+}
+
+/* get式。配列は消費せずに、同じ配列と要素のペアを返す */
+RT_FN Cell *rt_get(Cell *c, Cell *index) {
+    rt_check_array(c);
+    size_t i = rt_index(index, (int64_t)c->u.array.len);
+    return rt_pair(c->qual, -1, c, c->u.array.elems[i]);
+}
+
+/* set式。配列はlin型で他から参照されないため、コピーせずに書き換える */
+RT_FN Cell *rt_set(Cell *c, Cell *index, Cell *v) {
+    rt_check_array(c);
+    size_t i = rt_index(index, (int64_t)c->u.array.len);
+    c->u.array.elems[i] = v;
+    return c;
+}
+
+/* リージョンを作成し、リージョンの番号を返す */
+RT_FN long rt_region_new(void) {
+    return num_regions++;
+}
+
+/* リージョン内の解放されていないセルを、修飾子に関わらず一括して解放 */
+RT_FN void rt_region_free(long region) {
+    Cell *c = live;
+    while (c != NULL) {
+        Cell *next = c->next;
+        if (c->region == region) {
+            rt_free(c);
+        }
+        c = next;
+    }
+}
+
+#ifdef USE_EXT_not
+/* not : un (lin bool -> lin bool) */
+RT_FN Cell *ext_not(Cell **args) {
+    if (args[0]->tag != T_BOOL) {
+        rt_error("外部関数\"not\"のエラー: notの引数がboolでない");
+    }
+    return rt_bool(LIN, -1, !args[0]->u.b);
+}
+#endif
+
+#ifdef USE_EXT_concat
+/* concat : un (lin str -> lin (lin str -> lin str)) */
+RT_FN Cell *ext_concat(Cell **args) {
+    if (args[0]->tag != T_STR || args[1]->tag != T_STR) {
+        rt_error("外部関数\"concat\"のエラー: concatの引数がstrでない");
+    }
+    size_t n1 = strlen(args[0]->u.s), n2 = strlen(args[1]->u.s);
+    Cell *c = rt_str(LIN, -1, args[0]->u.s, n1);
+    c->u.s = realloc(c->u.s, n1 + n2 + 1);
+    if (c->u.s == NULL) {
+        rt_error("メモリを確保できない");
+    }
+    memcpy(c->u.s + n1, args[1]->u.s, n2 + 1);
+    return c;
+}
+#endif
+
+#ifdef USE_EXT_length
+/* length : un (lin str -> lin (lin str * un int))。長さはUTF-8の文字数 */
+RT_FN Cell *ext_length(Cell **args) {
+    if (args[0]->tag != T_STR) {
+        rt_error("外部関数\"length\"のエラー: lengthの引数がstrでない");
+    }
+    const char *s = args[0]->u.s;
+    int64_t n = 0;
+    for (size_t i = 0; s[i] != '\0'; i++) {
+        if (((unsigned char)s[i] & 0xc0) != 0x80) {
+            n++;
+        }
+    }
+    Cell *str = rt_str(args[0]->qual, -1, s, strlen(s));
+    return rt_pair(LIN, -1, str, rt_int(UN, -1, n));
+}
+#endif
+
+#ifdef USE_EXT_open
+static long num_handles; /* 開いたファイルの数 */
+
+/* open : un (un str -> lin handle) */
+RT_FN Cell *ext_open(Cell **args) {
+    if (args[0]->tag != T_STR) {
+        rt_error("外部関数\"open\"のエラー: openの引数がstrでない");
+    }
+    FILE *fp = fopen(args[0]->u.s, "w");
+    if (fp == NULL) {
+        fprintf(stderr, "評価エラー: 外部関数\"open\"のエラー: %sを開けない\n", args[0]->u.s);
+        exit(2);
+    }
+    Cell *c = rt_alloc(LIN, -1, T_HANDLE);
+    c->u.handle.fp = fp;
+    c->u.handle.id = num_handles++;
+    return c;
+}
+#endif
+
+#ifdef USE_EXT_write
+/* write : un (lin handle -> lin (lin str -> lin handle)) */
+RT_FN Cell *ext_write(Cell **args) {
+    if (args[0]->tag != T_HANDLE || args[1]->tag != T_STR) {
+        rt_error("外部関数\"write\"のエラー: writeの引数がhandleとstrでない");
+    }
+    if (fputs(args[1]->u.s, args[0]->u.handle.fp) == EOF) {
+        rt_error("外部関数\"write\"のエラー: 書き込みに失敗した");
+    }
+    Cell *c = rt_alloc(LIN, -1, T_HANDLE);
+    c->u.handle = args[0]->u.handle;
+    return c;
+}
+#endif
+
+#ifdef USE_EXT_fclose
+/* fclose : un (lin handle -> un unit) */
+RT_FN Cell *ext_fclose(Cell **args) {
+    if (args[0]->tag != T_HANDLE) {
+        rt_error("外部関数\"fclose\"のエラー: fcloseの引数がhandleでない");
+    }
+    fclose(args[0]->u.handle.fp);
+    return rt_unit(UN, -1);
+}
+#endif
+
+static const char *qual_names[] = {"ord", "lin", "aff", "rel", "un"};
+
+/* 文字列を、評価器と同様にエスケープして表示 */
+RT_FN void rt_print_str(const char *s) {
+    putchar('"');
+    for (; *s != '\0'; s++) {
+        unsigned char ch = (unsigned char)*s;
+        switch (ch) {
+        case '\n':
+            fputs("\\n", stdout);
+            break;
+        case '\t':
+            fputs("\\t", stdout);
+            break;
+        case '\r':
+            fputs("\\r", stdout);
+            break;
+        case '"':
+            fputs("\\\"", stdout);
+            break;
+        case '\\':
+            fputs("\\\\", stdout);
+            break;
+        default:
+            if (ch < 0x20 || ch == 0x7f) {
+                printf("\\u{%x}", ch);
+            } else {
+                putchar(ch);
+            }
+        }
+    }
+    putchar('"');
+}
+
+/* 値を、評価器の評価結果と同じ形式で表示 */
+RT_FN void rt_print(Cell *c) {
+    printf("%s ", qual_names[c->qual]);
+    switch (c->tag) {
+    case T_BOOL:
+        fputs(c->u.b ? "true" : "false", stdout);
+        break;
+    case T_UNIT:
+        fputs("unit", stdout);
+        break;
+    case T_HANDLE:
+        printf("handle#%ld", c->u.handle.id);
+        break;
+    case T_INT:
+        printf("%" PRId64, c->u.n);
+        break;
+    case T_STR:
+        rt_print_str(c->u.s);
+        break;
+    case T_PAIR:
+        putchar('<');
+        rt_print(c->u.pair.a1);
+        fputs(", ", stdout);
+        rt_print(c->u.pair.a2);
+        putchar('>');
+        break;
+    case T_WITH:
+        fputs("<| ... |>", stdout);
+        break;
+    case T_FUN:
+        printf("fn %s { ... }", c->u.fun.var);
+        break;
+    case T_BANG:
+        fputs("promote ...", stdout);
+        break;
+    case T_EXTERN:
+        printf("fn %s { ... }", c->u.ext.ext->name);
+        break;
+    case T_REF:
+        putchar('&');
+        rt_print(c->u.ref);
+        break;
+    case T_CELL:
+        fputs("ref ", stdout);
+        rt_print(c->u.ref);
+        break;
+    case T_ARRAY:
+        putchar('[');
+        for (size_t i = 0; i < c->u.array.len; i++) {
+            if (i > 0) {
+                fputs(", ", stdout);
+            }
+            rt_print(c->u.array.elems[i]);
+        }
+        putchar(']');
+        break;
+    }
+}
+
+/* cから到達可能なセルに印を付ける */
+RT_FN void rt_mark(Cell *c) {
+    if (c->mark) {
+        return;
+    }
+    c->mark = 1;
+
+    switch (c->tag) {
+    case T_PAIR:
+        rt_mark(c->u.pair.a1);
+        rt_mark(c->u.pair.a2);
+        break;
+    case T_WITH:
+    case T_BANG:
+        for (size_t i = 0; i < c->u.thunk.n; i++) {
+            rt_mark(c->u.thunk.env[i]);
+        }
+        break;
+    case T_FUN:
+        for (size_t i = 0; i < c->u.fun.n; i++) {
+            rt_mark(c->u.fun.env[i]);
+        }
+        break;
+    case T_EXTERN:
+        for (size_t i = 0; i < c->u.ext.n; i++) {
+            rt_mark(c->u.ext.args[i]);
+        }
+        break;
+    case T_REF:
+    case T_CELL:
+        rt_mark(c->u.ref);
+        break;
+    case T_ARRAY:
+        for (size_t i = 0; i < c->u.array.len; i++) {
+            rt_mark(c->u.array.elems[i]);
+        }
+        break;
+    default:
+        break;
+    }
+}
+
+/*
+ * 評価結果を表示し、リークしたもの以外のセルを解放して終了コードを返す
+ *
+ * 評価結果から到達できないordとlin型のセルが残っていた場合はリークとして報告し、1を返す。
+ * リークしたセルはAddressSanitizerやValgrindでも検出できるよう、解放せずに残す
+ */
+RT_FN int rt_finish(Cell *result) {
+    printf("評価結果:\n");
+    rt_print(result);
+    printf("\n");
+
+    rt_mark(result);
+    size_t leaked = 0;
+    for (Cell *c = live; c != NULL; c = c->next) {
+        if (!c->mark && (c->qual == ORD || c->qual == LIN)) {
+            leaked++;
+        }
+    }
+    printf("\nヒープ:\n確保: %zu, 解放: %zu, リーク: %zu\n", num_alloc, num_free, leaked);
+    fflush(stdout); /* LeakSanitizerがリークを報告して終了しても、表示が失われないようにする */
+
+    Cell *c = live;
+    while (c != NULL) {
+        Cell *next = c->next;
+        if (c->mark || !(c->qual == ORD || c->qual == LIN)) {
+            rt_free(c);
+        }
+        c = next;
+    }
+    live = NULL; /* リークしたセルを、グローバル変数から到達できないようにする */
+    return leaked == 0 ? 0 : 1;
+}
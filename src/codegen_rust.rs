@@ -0,0 +1,1007 @@
+//! ## Rustへのコード生成
+//!
+//! 型付けに成功した式を、安全なRustのプログラムに変換する。
+//! 式の型に従って、ord、lin、aff型の値は所有権を持つRustの値
+//! （`Lin`で包んだ値、`FnOnce`のクロージャ）に、
+//! un、rel型の値は複製できるRustの値（`Un`で包んだ値、`Fn`のクロージャ）に変換する。
+//! free文は`drop`に、可変な参照は`Box`に、配列は`Vec`に、借用はRustの参照に対応する。
+//!
+//! そのため、生成したプログラムをrustcでコンパイルすると、
+//! lin型の値を高々一度しか利用しないことが型検査器とは独立に検査される。
+//! ただし、rustcは少なくとも一度利用することと、ord型の利用順序は検査しない。
+//!
+//! 値は評価器と同じく作成した時点の修飾子を保持し、評価結果の表示に利用する。
+//! そのため、部分型関係によりun型の値をlin型の値として扱っても、unと表示する。
+//! リージョンは、所有権により値ごとに解放されるため無視する。
+//!
+//! チャネルとスレッドには対応しておらず、外部関数は組み込み関数のみ利用できる。
+
+use crate::{
+    eval::Value,
+    externs::{ExternVal, Externs},
+    parser::{self, PrimType, Qual, TypeExpr},
+    subst::{free_vars, free_vars_in},
+    typing,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+/// ランタイム
+const RUNTIME: &str = include_str!("codegen_rust/runtime.rs");
+
+/// Rustの実装を持つ組み込み関数
+const BUILTINS: [&str; 6] = ["not", "concat", "length", "open", "write", "fclose"];
+
+type RResult = Result<String, String>;
+type TResult = Result<(String, TypeExpr), String>;
+
+/// 変数の束縛
+#[derive(Debug, Clone)]
+struct Var {
+    name: String, // Rustの変数名
+    ty: TypeExpr, // 変数の型。let !式で束縛された変数は!型の中身の型
+    bang: bool,   // let !式で束縛された!型の値か。利用する度に中身を評価する
+}
+
+/// 変数から、Rustの変数への対応
+#[derive(Debug, Clone, Default)]
+struct Ctx {
+    vars: BTreeMap<String, Var>,
+}
+
+/// 生成中のブロック
+#[derive(Debug, Default)]
+struct Body {
+    code: String,
+    indent: usize,
+}
+
+impl Body {
+    /// インデントの深さがindentの、空のブロック
+    fn new(indent: usize) -> Self {
+        Body {
+            code: String::new(),
+            indent,
+        }
+    }
+
+    /// 1行を追加
+    fn line(&mut self, s: &str) {
+        for _ in 0..self.indent {
+            self.code.push_str("    ");
+        }
+        self.code.push_str(s);
+        self.code.push('\n');
+    }
+
+    /// 内側のブロックを追加
+    fn append(&mut self, inner: Body) {
+        self.code.push_str(&inner.code);
+    }
+}
+
+/// コード生成器
+struct Codegen<'a> {
+    externs: &'a Externs,
+    next: usize, // 一時変数などの番号
+}
+
+impl<'a> Codegen<'a> {
+    /// 新しい番号を返す
+    fn fresh(&mut self) -> usize {
+        self.next += 1;
+        self.next
+    }
+
+    /// 新しい一時変数に式の値を代入し、一時変数名を返す
+    fn temp(&mut self, body: &mut Body, expr: &str) -> String {
+        let t = format!("t{}", self.fresh());
+        body.line(&format!("let {} = {};", t, expr));
+        t
+    }
+
+    /// 言語の変数varに対応する、新しいRustの変数名を返す
+    fn var_name(&mut self, var: &str) -> String {
+        format!("v{}_{}", self.fresh(), var)
+    }
+
+    /// 式を変換し、評価結果を表すRustの式と、式の型を返す
+    ///
+    /// 副作用のある式は一時変数に代入し、評価順序を保つ
+    fn expr(&mut self, expr: &parser::Expr, ctx: &Ctx, body: &mut Body) -> TResult {
+        match expr {
+            parser::Expr::Let(e) => self.gen_let(e, ctx, body),
+            parser::Expr::LetBang(e) => self.gen_let_bang(e, ctx, body),
+            parser::Expr::If(e) => self.gen_if(e, ctx, body),
+            parser::Expr::Split(e) => self.gen_split(e, ctx, body),
+            parser::Expr::Free(e) => self.gen_free(e, ctx, body),
+            parser::Expr::App(e) => {
+                let (f, tf) = self.receiver(&e.expr1, ctx, body)?;
+                let (a, ta) = self.expr(&e.expr2, ctx, body)?;
+                match tf.prim {
+                    PrimType::Arrow(t_in, t_out) => {
+                        let a = self.coerce(&a, &ta, &t_in)?;
+                        Ok((self.temp(body, &format!("{}.call({})", f, a)), *t_out))
+                    }
+                    _ => Err("関数型でない値を関数適用している".to_string()),
+                }
+            }
+            parser::Expr::Proj(e) => {
+                let (a, t) = self.receiver(&e.expr, ctx, body)?;
+                match (t.prim, e.proj) {
+                    (PrimType::With(t1, _), parser::Proj::Fst) => {
+                        Ok((self.temp(body, &format!("{}.fst()", a)), *t1))
+                    }
+                    (PrimType::With(_, t2), parser::Proj::Snd) => {
+                        Ok((self.temp(body, &format!("{}.snd()", a)), *t2))
+                    }
+                    _ => Err("fstかsndの引数が加法的ペア型でない".to_string()),
+                }
+            }
+            parser::Expr::Promote(e) => {
+                let (code, t) = self.thunk(&e.expr, ctx, body.indent)?;
+                let t = TypeExpr {
+                    qual: Qual::Un,
+                    region: None,
+                    prim: PrimType::Bang(Box::new(t)),
+                };
+                Ok((self.temp(body, &code), t))
+            }
+            parser::Expr::Borrow(var) => self.gen_borrow(var, ctx, body),
+            parser::Expr::NewRef(e) => {
+                let (a, t) = self.expr(&e.expr, ctx, body)?;
+                let t = TypeExpr {
+                    qual: Qual::Lin.join(t.qual),
+                    region: None,
+                    prim: PrimType::Cell(Box::new(t)),
+                };
+                Ok((format!("new_ref({})", a), t))
+            }
+            parser::Expr::Swap(e) => self.gen_swap(e, ctx, body),
+            parser::Expr::LetRegion(e) => self.expr(&e.expr, ctx, body),
+            parser::Expr::Alloc(e) => self.gen_alloc(e, ctx, body),
+            parser::Expr::Get(e) => self.gen_get(e, ctx, body),
+            parser::Expr::Set(e) => self.gen_set(e, ctx, body),
+            parser::Expr::Var(var) => self.gen_var(var, ctx, body),
+            parser::Expr::QVal(e) => self.gen_qval(e, ctx, body),
+            parser::Expr::New(_)
+            | parser::Expr::Send(_)
+            | parser::Expr::Recv(_)
+            | parser::Expr::Close(_)
+            | parser::Expr::Fork(_) => Err("チャネルとスレッドはRustに変換できない".to_string()),
+        }
+    }
+
+    /// 関数適用や射影の対象となる式の変換
+    ///
+    /// un型の変数は`&self`でメソッドを呼び出せるため、複製せずに変数名を返す
+    fn receiver(&mut self, expr: &parser::Expr, ctx: &Ctx, body: &mut Body) -> TResult {
+        if let parser::Expr::Var(var) = expr {
+            if let Some(Var {
+                name,
+                ty,
+                bang: false,
+            }) = ctx.vars.get(var)
+            {
+                if shared(ty) {
+                    return Ok((name.clone(), ty.clone()));
+                }
+            }
+        }
+        self.expr(expr, ctx, body)
+    }
+
+    /// 変数の変換
+    fn gen_var(&mut self, var: &str, ctx: &Ctx, body: &mut Body) -> TResult {
+        match ctx.vars.get(var) {
+            Some(Var {
+                name,
+                ty,
+                bang: true,
+            }) => Ok((self.temp(body, &format!("{}.force()", name)), ty.clone())),
+            Some(Var { name, ty, .. }) if shared(ty) => Ok((clone(name, ty), ty.clone())),
+            Some(Var { name, ty, .. }) => Ok((name.clone(), ty.clone())),
+            None => Err(format!("\"{}\"という変数は定義されていない", var)),
+        }
+    }
+
+    /// 借用の変換
+    fn gen_borrow(&mut self, var: &str, ctx: &Ctx, body: &mut Body) -> TResult {
+        let (name, ty) = match ctx.vars.get(var) {
+            // !型の中身は一時変数に取り出して借用する
+            Some(Var {
+                name,
+                ty,
+                bang: true,
+            }) => (self.temp(body, &format!("{}.force()", name)), ty.clone()),
+            // 参照型の変数の借用は、同じ参照とする
+            Some(Var { name, ty, .. }) if matches!(ty.prim, PrimType::Ref(_)) => {
+                return Ok((name.clone(), ty.clone()))
+            }
+            Some(Var { name, ty, .. }) => (name.clone(), ty.clone()),
+            None => return Err(format!("\"{}\"という変数は定義されていない", var)),
+        };
+        let t = TypeExpr {
+            qual: Qual::Un,
+            region: None,
+            prim: PrimType::Ref(Box::new(ty)),
+        };
+        Ok((format!("&{}", name), t))
+    }
+
+    /// 修飾子付き値の変換
+    fn gen_qval(&mut self, expr: &parser::QValExpr, ctx: &Ctx, body: &mut Body) -> TResult {
+        let q = expr.qual;
+        let (code, prim) = match &expr.val {
+            parser::ValExpr::Bool(b) => (qval(&b.to_string(), q, q), PrimType::Bool),
+            parser::ValExpr::Int(n) => (qval(&format!("{}i64", n), q, q), PrimType::Int),
+            parser::ValExpr::Str(s) => (str_lit(s, q, q), PrimType::Str),
+            parser::ValExpr::Pair(e1, e2) => {
+                let (a1, t1) = self.expr(e1, ctx, body)?;
+                let (a2, t2) = self.expr(e2, ctx, body)?;
+                (
+                    qval(&format!("({}, {})", a1, a2), q, q),
+                    PrimType::Pair(Box::new(t1), Box::new(t2)),
+                )
+            }
+            parser::ValExpr::With(e1, e2) => {
+                let (code, t1, t2) = self.with(e1, e2, q, ctx, body.indent)?;
+                (
+                    self.temp(body, &code),
+                    PrimType::With(Box::new(t1), Box::new(t2)),
+                )
+            }
+            parser::ValExpr::Fun(e) => {
+                let (code, t) = self.closure(e, q, ctx, body.indent)?;
+                (
+                    self.temp(body, &code),
+                    PrimType::Arrow(Box::new(e.ty.clone()), Box::new(t)),
+                )
+            }
+        };
+
+        let t = TypeExpr {
+            qual: expr.qual,
+            region: expr.region.clone(),
+            prim,
+        };
+        Ok((code, t))
+    }
+
+    /// exprsの自由変数のうち、クロージャに移動せず複製を渡すものを複製するブロックを返す
+    ///
+    /// un型の値とlet !式で束縛された値は、クロージャの外側でも利用できるよう複製する
+    fn capture(
+        &mut self,
+        exprs: &[&parser::Expr],
+        bound: &[&str],
+        ctx: &Ctx,
+        indent: usize,
+    ) -> Body {
+        let mut fv = BTreeSet::new();
+        for e in exprs {
+            free_vars_in(
+                e,
+                &mut bound.iter().map(|v| v.to_string()).collect(),
+                &mut fv,
+            );
+        }
+
+        let mut block = Body::new(indent + 1);
+        for v in fv {
+            if let Some(b) = ctx.vars.get(&v) {
+                if b.bang || (shared(&b.ty) && !is_copy(&b.ty)) {
+                    block.line(&format!("let {} = Clone::clone(&{});", b.name, b.name));
+                }
+            }
+        }
+        block
+    }
+
+    /// 複製するブロックblockの有無に応じた、クロージャの先頭行のインデントの深さ
+    fn body_indent(indent: usize, block: &Body) -> usize {
+        if block.code.is_empty() {
+            indent
+        } else {
+            indent + 1
+        }
+    }
+
+    /// headerで始まり、本体がinnerの`move`クロージャを作成する式を返す
+    ///
+    /// 複製した変数がある場合は、複製するブロックblockで囲む
+    fn wrap(indent: usize, block: Body, header: &str, inner: Body) -> String {
+        if block.code.is_empty() {
+            let mut out = Body::new(indent);
+            out.append(inner);
+            out.line("})");
+            return format!("{}\n{}", header, out.code.trim_end());
+        }
+
+        let mut out = block;
+        out.line(header);
+        out.append(inner);
+        out.line("})");
+        out.indent -= 1;
+        out.line("}");
+        format!("{{\n{}", out.code.trim_end())
+    }
+
+    /// 関数を変換し、クロージャを作成するRustの式と、戻り値の型を返す
+    fn closure(&mut self, expr: &parser::FnExpr, qual: Qual, ctx: &Ctx, indent: usize) -> TResult {
+        let block = self.capture(&[&expr.expr], &[&expr.var], ctx, indent);
+        let inner_indent = Self::body_indent(indent, &block);
+
+        let arg = self.var_name(&expr.var);
+        let mut inner = ctx.clone();
+        inner.vars.insert(
+            expr.var.clone(),
+            Var {
+                name: arg.clone(),
+                ty: expr.ty.clone(),
+                bang: false,
+            },
+        );
+
+        let mut fbody = Body::new(inner_indent + 1);
+        let (r, t) = self.expr(&expr.expr, &inner, &mut fbody)?;
+        fbody.line(&r);
+
+        let header = format!(
+            "{}::new({}, {:?}, move |{}: {}| -> {} {{",
+            fn_name(&expr.ty, &t, !shared_qual(qual))?,
+            q_lit(qual),
+            expr.var,
+            arg,
+            rty(&expr.ty)?,
+            rty(&t)?
+        );
+        Ok((Self::wrap(indent, block, &header, fbody), t))
+    }
+
+    /// 加法的ペアを変換し、加法的ペアを作成するRustの式と、各要素の型を返す
+    ///
+    /// 両方の要素は同じキャプチャした値を利用するため、射影する要素を受け取る1つのクロージャとする
+    fn with(
+        &mut self,
+        e1: &parser::Expr,
+        e2: &parser::Expr,
+        qual: Qual,
+        ctx: &Ctx,
+        indent: usize,
+    ) -> Result<(String, TypeExpr, TypeExpr), String> {
+        let block = self.capture(&[e1, e2], &[], ctx, indent);
+        let inner_indent = Self::body_indent(indent, &block);
+
+        let mut b1 = Body::new(inner_indent + 2);
+        let (r1, t1) = self.expr(e1, ctx, &mut b1)?;
+        b1.line(&format!("Either::Fst({})", r1));
+        let mut b2 = Body::new(inner_indent + 2);
+        let (r2, t2) = self.expr(e2, ctx, &mut b2)?;
+        b2.line(&format!("Either::Snd({})", r2));
+
+        let mut fbody = Body::new(inner_indent + 1);
+        fbody.line("if !snd {");
+        fbody.append(b1);
+        fbody.line("} else {");
+        fbody.append(b2);
+        fbody.line("}");
+
+        let header = format!(
+            "{}::new({}, move |snd: bool| -> Either<{}, {}> {{",
+            if shared_qual(qual) {
+                "UnWith"
+            } else {
+                "LinWith"
+            },
+            q_lit(qual),
+            rty(&t1)?,
+            rty(&t2)?
+        );
+        Ok((Self::wrap(indent, block, &header, fbody), t1, t2))
+    }
+
+    /// promote式の中身を変換し、!型の値を作成するRustの式と、中身の型を返す
+    fn thunk(&mut self, expr: &parser::Expr, ctx: &Ctx, indent: usize) -> TResult {
+        let block = self.capture(&[expr], &[], ctx, indent);
+        let inner_indent = Self::body_indent(indent, &block);
+
+        let mut fbody = Body::new(inner_indent + 1);
+        let (r, t) = self.expr(expr, ctx, &mut fbody)?;
+        fbody.line(&r);
+
+        let header = format!("Bang::new(Q::Un, move || -> {} {{", rty(&t)?);
+        Ok((Self::wrap(indent, block, &header, fbody), t))
+    }
+
+    /// let式の変換
+    fn gen_let(&mut self, expr: &parser::LetExpr, ctx: &Ctx, body: &mut Body) -> TResult {
+        let (a, t) = self.expr(&expr.expr1, ctx, body)?;
+        let a = self.coerce(&a, &t, &expr.ty)?;
+        let name = self.var_name(&expr.var);
+        body.line(&format!("let {}: {} = {};", name, rty(&expr.ty)?, a));
+
+        let mut ctx = ctx.clone();
+        ctx.vars.insert(
+            expr.var.clone(),
+            Var {
+                name,
+                ty: expr.ty.clone(),
+                bang: false,
+            },
+        );
+        self.expr(&expr.expr2, &ctx, body)
+    }
+
+    /// let !式の変換
+    fn gen_let_bang(&mut self, expr: &parser::LetBangExpr, ctx: &Ctx, body: &mut Body) -> TResult {
+        let (a, t) = self.expr(&expr.expr1, ctx, body)?;
+        let ty = match t.prim {
+            PrimType::Bang(t) => *t,
+            _ => {
+                return Err(format!(
+                    "let !式で束縛する変数\"{}\"の値が!型でない",
+                    expr.var
+                ))
+            }
+        };
+        let name = self.var_name(&expr.var);
+        body.line(&format!("let {} = {};", name, a));
+
+        let mut ctx = ctx.clone();
+        ctx.vars.insert(
+            expr.var.clone(),
+            Var {
+                name,
+                ty,
+                bang: true,
+            },
+        );
+        self.expr(&expr.expr2, &ctx, body)
+    }
+
+    /// if式の変換
+    fn gen_if(&mut self, expr: &parser::IfExpr, ctx: &Ctx, body: &mut Body) -> TResult {
+        let (c, tc) = self.expr(&expr.cond_expr, ctx, body)?;
+
+        // 条件の式は、boolへの参照であれば参照先を取り出す
+        let mut c = c;
+        let mut tc = &tc;
+        while let PrimType::Ref(t) = &tc.prim {
+            c = format!("*{}", c);
+            tc = t;
+        }
+        c = field(&c, "0");
+
+        let mut b1 = Body::new(body.indent + 1);
+        let (r1, t1) = self.expr(&expr.then_expr, ctx, &mut b1)?;
+        let mut b2 = Body::new(body.indent + 1);
+        let (r2, t2) = self.expr(&expr.else_expr, ctx, &mut b2)?;
+
+        // thenとelse部の型を合流させた型に変換
+        let t = typing::join(&t1, &t2).ok_or("ifのthenとelseの式の型が異なる")?;
+        let r1 = self.coerce(&r1, &t1, &t)?;
+        b1.line(&r1);
+        let r2 = self.coerce(&r2, &t2, &t)?;
+        b2.line(&r2);
+
+        let r = format!("t{}", self.fresh());
+        body.line(&format!("let {}: {} = if {} {{", r, rty(&t)?, c));
+        body.append(b1);
+        body.line("} else {");
+        body.append(b2);
+        body.line("};");
+        Ok((r, t))
+    }
+
+    /// split式の変換
+    fn gen_split(&mut self, expr: &parser::SplitExpr, ctx: &Ctx, body: &mut Body) -> TResult {
+        let (a, t) = self.expr(&expr.expr, ctx, body)?;
+
+        // ペアはLinかUnで包まれているため、中身のタプルを取り出す
+        // ペアへの参照を分解した場合、各要素への参照を束縛
+        let p = deref(&t);
+        let a = match &t.prim {
+            PrimType::Pair(_, _) => field(&a, "0"),
+            _ => {
+                let mut a = a;
+                let mut t = &t;
+                while let PrimType::Ref(inner) = &t.prim {
+                    a = format!("*{}", a);
+                    t = inner;
+                }
+                format!("&({}).0", a)
+            }
+        };
+        let (t1, t2) = match (&t.prim, &p.prim) {
+            (PrimType::Pair(t1, t2), _) => (*t1.clone(), *t2.clone()),
+            (PrimType::Ref(_), PrimType::Pair(t1, t2)) => {
+                let r = |t: &TypeExpr| TypeExpr {
+                    qual: Qual::Un,
+                    region: None,
+                    prim: PrimType::Ref(Box::new(t.clone())),
+                };
+                (r(t1), r(t2))
+            }
+            _ => return Err("splitの引数がペア型でない".to_string()),
+        };
+
+        let left = self.var_name(&expr.left);
+        let right = self.var_name(&expr.right);
+        body.line(&format!("let ({}, {}) = {};", left, right, a));
+
+        let mut ctx = ctx.clone();
+        ctx.vars.insert(
+            expr.left.clone(),
+            Var {
+                name: left,
+                ty: t1,
+                bang: false,
+            },
+        );
+        ctx.vars.insert(
+            expr.right.clone(),
+            Var {
+                name: right,
+                ty: t2,
+                bang: false,
+            },
+        );
+        self.expr(&expr.body, &ctx, body)
+    }
+
+    /// free文の変換
+    fn gen_free(&mut self, expr: &parser::FreeExpr, ctx: &Ctx, body: &mut Body) -> TResult {
+        match ctx.vars.get(&expr.var) {
+            Some(Var {
+                name, bang: false, ..
+            }) => body.line(&format!("drop({});", name)),
+            _ => return Err(format!("変数\"{}\"をfreeできない", expr.var)),
+        }
+        self.expr(&expr.expr, ctx, body)
+    }
+
+    /// swap式の変換
+    fn gen_swap(&mut self, expr: &parser::SwapExpr, ctx: &Ctx, body: &mut Body) -> TResult {
+        let (c, tc) = self.expr(&expr.cell, ctx, body)?;
+        let (v, tv) = self.expr(&expr.expr, ctx, body)?;
+        let t = match &tc.prim {
+            PrimType::Cell(t) => *t.clone(),
+            _ => return Err(format!("ref型でない値{}をswapしている", tc)),
+        };
+        let v = self.coerce(&v, &tv, &t)?;
+
+        // 中身を書き換え、参照と元の中身のペアを返す
+        let r = self.temp(body, &format!("swap({}, {})", c, v));
+        let t = TypeExpr {
+            qual: tc.qual.join(t.qual),
+            region: None,
+            prim: PrimType::Pair(Box::new(tc), Box::new(t)),
+        };
+        Ok((r, t))
+    }
+
+    /// alloc式の変換
+    fn gen_alloc(&mut self, expr: &parser::AllocExpr, ctx: &Ctx, body: &mut Body) -> TResult {
+        let (n, _) = self.expr(&expr.len, ctx, body)?;
+        let n = self.temp(body, &format!("rt_len({})", int(&n)));
+        let (v, t) = self.expr(&expr.expr, ctx, body)?;
+        let t = TypeExpr {
+            qual: Qual::Lin,
+            region: None,
+            prim: PrimType::Array(Box::new(t)),
+        };
+        Ok((
+            self.temp(body, &format!("Lin(vec![{}; {}], Q::Lin)", v, n)),
+            t,
+        ))
+    }
+
+    /// get式の変換
+    fn gen_get(&mut self, expr: &parser::GetExpr, ctx: &Ctx, body: &mut Body) -> TResult {
+        let (a, ta) = self.expr(&expr.array, ctx, body)?;
+        let a = self.temp(body, &a);
+        let (i, _) = self.expr(&expr.index, ctx, body)?;
+        let i = self.temp(body, &format!("rt_index({}, {}.0.len())", int(&i), a));
+
+        // 配列は消費せずに、同じ配列と要素のペアを返す。ペアの修飾子は配列のもの
+        let t = match &ta.prim {
+            PrimType::Array(t) => *t.clone(),
+            _ => return Err(format!("array型でない値{}から読み出している", ta)),
+        };
+        let v = self.temp(body, &clone(&format!("{}.0[{}]", a, i), &t));
+        let q = self.temp(body, &format!("{}.1", a));
+        let t = TypeExpr {
+            qual: ta.qual,
+            region: None,
+            prim: PrimType::Pair(Box::new(ta), Box::new(t)),
+        };
+        Ok((format!("Lin(({}, {}), {})", a, v, q), t))
+    }
+
+    /// set式の変換
+    fn gen_set(&mut self, expr: &parser::SetExpr, ctx: &Ctx, body: &mut Body) -> TResult {
+        let (a, ta) = self.expr(&expr.array, ctx, body)?;
+        let arr = format!("t{}", self.fresh());
+        body.line(&format!("let mut {} = {};", arr, a));
+        let (i, _) = self.expr(&expr.index, ctx, body)?;
+        let i = self.temp(body, &format!("rt_index({}, {}.0.len())", int(&i), arr));
+        let (v, tv) = self.expr(&expr.expr, ctx, body)?;
+
+        // 配列は所有権を持つため、コピーせずに書き換える
+        let t = match &ta.prim {
+            PrimType::Array(t) => t,
+            _ => return Err(format!("array型でない値{}に書き込んでいる", ta)),
+        };
+        let v = self.coerce(&v, &tv, t)?;
+        body.line(&format!("{}.0[{}] = {};", arr, i, v));
+        Ok((arr, ta))
+    }
+
+    /// 型fromの値を表すRustの式codeを、部分型関係に従って型toの値に変換
+    ///
+    /// un型の値をlin型の値として扱う場合など、Rustでの表現が異なる場合のみ変換する。
+    /// 値の修飾子は作成した時点のものを保つ
+    fn coerce(&mut self, code: &str, from: &TypeExpr, to: &TypeExpr) -> RResult {
+        let (r1, r2) = (rty(from)?, rty(to)?);
+        if r1 == r2 {
+            return Ok(code.to_string());
+        }
+
+        let n = self.fresh();
+        match (&from.prim, &to.prim) {
+            (PrimType::Str, PrimType::Str) => Ok(format!("own_str({})", code)),
+            (PrimType::Bool, _) | (PrimType::Int, _) | (PrimType::Unit, _) => {
+                Ok(format!("own({})", code))
+            }
+            (PrimType::Pair(a1, b1), PrimType::Pair(a2, b2)) => {
+                let a = self.coerce(&format!("a{}", n), a1, a2)?;
+                let b = self.coerce(&format!("b{}", n), b1, b2)?;
+                Ok(format!(
+                    "{{ let p{} = {}; let (a{}, b{}) = p{}.0; {}(({}, {}), p{}.1) }}",
+                    n,
+                    code,
+                    n,
+                    n,
+                    n,
+                    if shared(to) { "Un" } else { "Lin" },
+                    a,
+                    b,
+                    n
+                ))
+            }
+            (PrimType::Arrow(a1, b1), PrimType::Arrow(a2, b2)) => {
+                // 引数は反変のため、新しい引数の型から元の引数の型に変換
+                let f = format!("f{}", n);
+                let x = self.coerce(&format!("x{}", n), a2, a1)?;
+                let r = self.coerce(&format!("{}.call({})", f, x), b1, b2)?;
+                Ok(format!(
+                    "{{ let {} = {}; {}::new({}.q, {}.var, move |x{}: {}| -> {} {{ {} }}) }}",
+                    f,
+                    code,
+                    fn_name(a2, b2, !shared(to))?,
+                    f,
+                    f,
+                    n,
+                    rty(a2)?,
+                    rty(b2)?,
+                    r
+                ))
+            }
+            (PrimType::With(a1, b1), PrimType::With(a2, b2)) => {
+                let w = format!("w{}", n);
+                let a = self.coerce(&format!("{}.fst()", w), a1, a2)?;
+                let b = self.coerce(&format!("{}.snd()", w), b1, b2)?;
+                Ok(format!(
+                    "{{ let {} = {}; {}::new({}.1, move |snd: bool| if !snd {{ Either::Fst({}) }} else {{ Either::Snd({}) }}) }}",
+                    w,
+                    code,
+                    if shared(to) { "UnWith" } else { "LinWith" },
+                    w,
+                    a,
+                    b
+                ))
+            }
+            (PrimType::Bang(a1), PrimType::Bang(a2)) => {
+                let b = format!("b{}", n);
+                let a = self.coerce(&format!("{}.force()", b), a1, a2)?;
+                Ok(format!(
+                    "{{ let {} = {}; Bang::new({}.1, move || {}) }}",
+                    b, code, b, a
+                ))
+            }
+            _ => Err(format!(
+                "{}型の値を{}型の値としてRustに変換できない",
+                from, to
+            )),
+        }
+    }
+
+    /// 型tの値への参照を表すRustの式codeから、評価結果を表示する文字列を作成する式を返す
+    ///
+    /// 修飾子は、値が保持する作成した時点のものを表示する
+    fn show(&mut self, code: &str, t: &TypeExpr) -> RResult {
+        let q = field(code, "1");
+        Ok(match &t.prim {
+            PrimType::Bool | PrimType::Int => {
+                format!("format!(\"{{}} {{}}\", {}, {})", q, field(code, "0"))
+            }
+            PrimType::Unit => format!("format!(\"{{}} unit\", {})", q),
+            PrimType::Str => format!("format!(\"{{}} {{:?}}\", {}, {})", q, field(code, "0")),
+            PrimType::Handle => format!(
+                "format!(\"{{}} handle#{{}}\", {}, {}.id)",
+                q,
+                field(code, "0")
+            ),
+            PrimType::Pair(t1, t2) => {
+                let n = self.fresh();
+                let s1 = self.show(&format!("a{}", n), t1)?;
+                let s2 = self.show(&format!("b{}", n), t2)?;
+                format!(
+                    "{{ let (a{}, b{}) = &{}; format!(\"{{}} <{{}}, {{}}>\", {}, {}, {}) }}",
+                    n,
+                    n,
+                    field(code, "0"),
+                    q,
+                    s1,
+                    s2
+                )
+            }
+            PrimType::With(_, _) => format!("format!(\"{{}} <| ... |>\", {})", q),
+            PrimType::Arrow(_, _) => {
+                format!(
+                    "format!(\"{{}} fn {{}} {{{{ ... }}}}\", {}, {})",
+                    field(code, "q"),
+                    field(code, "var")
+                )
+            }
+            PrimType::Bang(_) => format!("format!(\"{{}} promote ...\", {})", q),
+            PrimType::Ref(t) => {
+                // 借用した参照は常にun型
+                let s = self.show(&format!("*{}", code), t)?;
+                format!("format!(\"un &{{}}\", {})", s)
+            }
+            PrimType::Cell(t) => {
+                let s = self.show(&format!("&*{}", field(code, "0")), t)?;
+                format!("format!(\"{{}} ref {{}}\", {}, {})", q, s)
+            }
+            PrimType::Array(t) => {
+                let x = format!("x{}", self.fresh());
+                let s = self.show(&x, t)?;
+                format!(
+                    "format!(\"{{}} [{{}}]\", {}, {}.iter().map(|{}| {}).collect::<Vec<_>>().join(\", \"))",
+                    q,
+                    field(code, "0"),
+                    x,
+                    s
+                )
+            }
+            PrimType::Chan(_) => return Err("チャネルとスレッドはRustに変換できない".to_string()),
+        })
+    }
+
+    /// プログラムで利用する外部定義を、main関数の先頭で束縛
+    fn init(&mut self, expr: &parser::Expr, body: &mut Body) -> Result<Ctx, String> {
+        let fv = free_vars(expr);
+
+        let mut ctx = Ctx::default();
+        for var in fv {
+            let def = self
+                .externs
+                .defs
+                .iter()
+                .rev()
+                .find(|d| d.name == var)
+                .ok_or_else(|| format!("\"{}\"という変数は定義されていない", var))?;
+            let name = self.var_name(&var);
+
+            let e = match &def.val {
+                ExternVal::Value(v) => value(v, def.ty.qual)?,
+                ExternVal::Fun(_) => {
+                    if !BUILTINS.contains(&var.as_str()) {
+                        return Err(format!("外部関数\"{}\"はRustの実装を持たない", var));
+                    }
+                    format!("ext_{}()", var)
+                }
+            };
+            body.line(&format!("let {}: {} = {};", name, rty(&def.ty)?, e));
+            ctx.vars.insert(
+                var,
+                Var {
+                    name,
+                    ty: def.ty.clone(),
+                    bang: false,
+                },
+            );
+        }
+        Ok(ctx)
+    }
+}
+
+/// 修飾子qualの値を、複製できるRustの値で表すかを判定
+fn shared_qual(qual: Qual) -> bool {
+    matches!(qual, Qual::Un | Qual::Rel)
+}
+
+/// 型tの値を、複製できるRustの値で表すかを判定
+fn shared(t: &TypeExpr) -> bool {
+    shared_qual(t.qual)
+}
+
+/// 型tの値を、Copyなrustの値で表すかを判定
+fn is_copy(t: &TypeExpr) -> bool {
+    match &t.prim {
+        PrimType::Ref(_) => true,
+        PrimType::Bool | PrimType::Int | PrimType::Unit => shared(t),
+        PrimType::Pair(t1, t2) => shared(t) && is_copy(t1) && is_copy(t2),
+        _ => false,
+    }
+}
+
+/// 型tの値を表すRustの式codeを、複製する式
+fn clone(code: &str, t: &TypeExpr) -> String {
+    if is_copy(t) {
+        code.to_string()
+    } else {
+        format!("Clone::clone(&{})", code)
+    }
+}
+
+/// Rustの式codeの値のフィールドnameを取り出す式
+fn field(code: &str, name: &str) -> String {
+    if code.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        format!("{}.{}", code, name)
+    } else {
+        format!("({}).{}", code, name)
+    }
+}
+
+/// 参照型の参照先をたどり、参照型でない型を返す
+fn deref(t: &TypeExpr) -> &TypeExpr {
+    match &t.prim {
+        PrimType::Ref(t) => deref(t),
+        _ => t,
+    }
+}
+
+/// 型tの値を表すRustの型
+fn rty(t: &TypeExpr) -> RResult {
+    let owned = !shared(t);
+    Ok(match &t.prim {
+        PrimType::Bool => lin_ty("bool", owned),
+        PrimType::Int => lin_ty("i64", owned),
+        PrimType::Unit => lin_ty("()", owned),
+        PrimType::Str if owned => "Lin<String>".to_string(),
+        PrimType::Str => "Un<Rc<str>>".to_string(),
+        PrimType::Handle if owned => "Lin<Handle>".to_string(),
+        PrimType::Pair(t1, t2) => lin_ty(&format!("({}, {})", rty(t1)?, rty(t2)?), owned),
+        PrimType::With(t1, t2) => format!(
+            "{}<{}, {}>",
+            if owned { "LinWith" } else { "UnWith" },
+            rty(t1)?,
+            rty(t2)?
+        ),
+        PrimType::Arrow(t1, t2) => format!(
+            "{}<{}, {}>",
+            fn_name(t1, t2, owned)?,
+            rty(deref1(t1))?,
+            rty(t2)?
+        ),
+        PrimType::Bang(t) => format!("Bang<{}>", rty(t)?),
+        PrimType::Ref(t) => format!("&{}", rty(t)?),
+        PrimType::Cell(t) => lin_ty(&format!("Box<{}>", rty(t)?), owned),
+        PrimType::Array(t) => lin_ty(&format!("Vec<{}>", rty(t)?), owned),
+        PrimType::Chan(_) => return Err("チャネルとスレッドはRustに変換できない".to_string()),
+        _ => return Err(format!("{}型の値はRustに変換できない", t)),
+    })
+}
+
+/// 引数の型がt1、戻り値の型がt2の関数を表すRustの型の名前
+///
+/// 参照を引数とする関数は、呼び出す度に異なる寿命の参照を受け取れるよう区別する
+fn fn_name(t1: &TypeExpr, t2: &TypeExpr, owned: bool) -> Result<&'static str, String> {
+    if typing::has_ref(t2) {
+        return Err(format!(
+            "参照を含む{}型の値を返す関数はRustに変換できない",
+            t2
+        ));
+    }
+    Ok(match (&t1.prim, owned) {
+        (PrimType::Ref(_), true) => "LinRefFn",
+        (PrimType::Ref(_), false) => "UnRefFn",
+        _ if typing::has_ref(t1) => {
+            return Err(format!(
+                "参照を含む{}型の引数を受け取る関数はRustに変換できない",
+                t1
+            ))
+        }
+        (_, true) => "LinFn",
+        (_, false) => "UnFn",
+    })
+}
+
+/// 参照型であれば参照先の型を返す
+fn deref1(t: &TypeExpr) -> &TypeExpr {
+    match &t.prim {
+        PrimType::Ref(t) => t,
+        _ => t,
+    }
+}
+
+/// 所有権を持つ値の場合はLinで、複製できる値の場合はUnで包んだ型
+fn lin_ty(ty: &str, owned: bool) -> String {
+    if owned {
+        format!("Lin<{}>", ty)
+    } else {
+        format!("Un<{}>", ty)
+    }
+}
+
+/// 修飾子qualを表すRustの式
+fn q_lit(qual: Qual) -> &'static str {
+    match qual {
+        Qual::Ord => "Q::Ord",
+        Qual::Lin => "Q::Lin",
+        Qual::Aff => "Q::Aff",
+        Qual::Rel => "Q::Rel",
+        Qual::Un => "Q::Un",
+    }
+}
+
+/// 修飾子がtyの型の値として、作成した時点の修飾子qualを保持する値を作成する式
+fn qval(code: &str, ty: Qual, qual: Qual) -> String {
+    if shared_qual(ty) {
+        format!("Un({}, {})", code, q_lit(qual))
+    } else {
+        format!("Lin({}, {})", code, q_lit(qual))
+    }
+}
+
+/// int型の値を表すRustの式codeから、i64の値を取り出す式
+fn int(code: &str) -> String {
+    field(code, "0")
+}
+
+/// 文字列リテラル
+///
+/// strのDebugによる表示は、Rustの文字列リテラルとして解釈できる
+fn str_lit(s: &str, ty: Qual, qual: Qual) -> String {
+    if shared_qual(ty) {
+        qval(&format!("Rc::<str>::from({:?})", s), ty, qual)
+    } else {
+        qval(&format!("String::from({:?})", s), ty, qual)
+    }
+}
+
+/// 修飾子がtyの型の外部定義の値を作成するRustの式
+fn value(v: &Value, ty: Qual) -> RResult {
+    Ok(match v {
+        Value::Bool(q, b) => qval(&b.to_string(), ty, *q),
+        Value::Unit(q) => qval("()", ty, *q),
+        Value::Int(q, n) => qval(&format!("{}i64", n), ty, *q),
+        Value::Str(q, s) => str_lit(s, ty, *q),
+        _ => return Err(format!("外部定義の値{}はRustに変換できない", v)),
+    })
+}
+
+/// 外部定義を束縛した初期環境で式を評価する、Rustのプログラムを生成
+///
+/// 型付けに成功した式を渡すこと
+pub fn codegen(expr: &parser::Expr, externs: &Externs) -> Result<String, String> {
+    let mut cg = Codegen { externs, next: 0 };
+
+    let mut body = Body::new(1);
+    let ctx = cg.init(expr, &mut body)?;
+    let (r, t) = cg.expr(expr, &ctx, &mut body)?;
+    body.line(&format!("let result: {} = {};", rty(&t)?, r));
+    let s = cg.show("&result", &t)?;
+    body.line(&format!("println!(\"評価結果:\\n{{}}\", {});", s));
+
+    let mut out = String::new();
+    writeln!(out, "// 線形型言語から生成したコード\n").unwrap();
+    out.push_str(RUNTIME);
+    write!(out, "\nfn main() {{\n{}}}\n", body.code).unwrap();
+    Ok(out)
+}
@@ -0,0 +1,428 @@
+// 線形型言語から変換したRustのコードのランタイム
+//
+// ord、lin、aff型の値は所有権を持つ値（Lin、FnOnceのクロージャ）とし、
+// un、rel型の値は複製できる値（Un、Fnのクロージャ）として利用する度に複製する。
+// そのため、lin型の値を二度利用するなど型付けの規則に従わないプログラムは、
+// rustcの所有権と借用の検査によりコンパイルエラーとなる。
+//
+// 評価器と同じ評価結果を表示するため、値は作成した時点の修飾子（Q）を保持する。
+// 部分型関係により修飾子の異なる型の値として扱っても、表示する修飾子は変わらない。
+
+#![allow(unused, non_snake_case, dropping_copy_types, dropping_references, clippy::all)]
+
+use std::io::Write;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// 評価エラーを表示して終了
+fn error(msg: &str) -> ! {
+    eprintln!("評価エラー: {}", msg);
+    std::process::exit(2)
+}
+
+/// 修飾子
+#[derive(Clone, Copy, PartialEq)]
+enum Q {
+    Ord,
+    Lin,
+    Aff,
+    Rel,
+    Un,
+}
+
+impl Q {
+    /// 修飾子の束の上限
+    fn join(self, other: Q) -> Q {
+        match (self, other) {
+            (Q::Ord, _) | (_, Q::Ord) => Q::Ord,
+            (Q::Un, q) | (q, Q::Un) => q,
+            (q1, q2) if q1 == q2 => q1,
+            _ => Q::Lin,
+        }
+    }
+}
+
+impl std::fmt::Display for Q {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Q::Ord => "ord",
+            Q::Lin => "lin",
+            Q::Aff => "aff",
+            Q::Rel => "rel",
+            Q::Un => "un",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// 作成した時点の修飾子を保持する値
+trait Qualified {
+    fn q(&self) -> Q;
+}
+
+/// ord、lin、aff型の、関数と加法的ペア、!型以外の値。Copyでないため一度しか利用できない
+struct Lin<T>(T, Q);
+
+/// un、rel型の、関数と加法的ペア、!型以外の値。複製して何度でも利用できる
+#[derive(Clone, Copy)]
+struct Un<T>(T, Q);
+
+impl<T> Qualified for Lin<T> {
+    fn q(&self) -> Q {
+        self.1
+    }
+}
+
+impl<T> Qualified for Un<T> {
+    fn q(&self) -> Q {
+        self.1
+    }
+}
+
+/// 借用した参照はun型
+impl<T> Qualified for &T {
+    fn q(&self) -> Q {
+        Q::Un
+    }
+}
+
+/// un、rel型の値を、ord、lin、aff型の値として扱う
+fn own<T>(x: Un<T>) -> Lin<T> {
+    Lin(x.0, x.1)
+}
+
+/// un、rel型の文字列を、ord、lin、aff型の文字列として扱う
+fn own_str(s: Un<Rc<str>>) -> Lin<String> {
+    Lin(String::from(&*s.0), s.1)
+}
+
+/// 可変な参照を作成する。修飾子は中身の修飾子とlinの上限
+fn new_ref<T: Qualified>(x: T) -> Lin<Box<T>> {
+    let q = Q::Lin.join(x.q());
+    Lin(Box::new(x), q)
+}
+
+/// 可変な参照の中身を交換し、参照と元の中身のペアを返す
+fn swap<T: Qualified>(mut cell: Lin<Box<T>>, x: T) -> Lin<(Lin<Box<T>>, T)> {
+    let old = std::mem::replace(&mut *cell.0, x);
+    let q = cell.1.join(old.q());
+    Lin((cell, old), q)
+}
+
+/// ord、lin、aff型の関数。一度だけ呼び出せる
+struct LinFn<A, B> {
+    q: Q,              // 作成した時点の修飾子
+    var: &'static str, // 引数名
+    f: Box<dyn FnOnce(A) -> B>,
+}
+
+impl<A, B> LinFn<A, B> {
+    fn new(q: Q, var: &'static str, f: impl FnOnce(A) -> B + 'static) -> Self {
+        LinFn {
+            q,
+            var,
+            f: Box::new(f),
+        }
+    }
+
+    fn call(self, arg: A) -> B {
+        (self.f)(arg)
+    }
+}
+
+/// un、rel型の関数。複製して何度でも呼び出せる
+struct UnFn<A, B> {
+    q: Q,              // 作成した時点の修飾子
+    var: &'static str, // 引数名
+    f: Rc<dyn Fn(A) -> B>,
+}
+
+impl<A, B> Clone for UnFn<A, B> {
+    fn clone(&self) -> Self {
+        UnFn {
+            q: self.q,
+            var: self.var,
+            f: self.f.clone(),
+        }
+    }
+}
+
+impl<A, B> UnFn<A, B> {
+    fn new(q: Q, var: &'static str, f: impl Fn(A) -> B + 'static) -> Self {
+        UnFn {
+            q,
+            var,
+            f: Rc::new(f),
+        }
+    }
+
+    fn call(&self, arg: A) -> B {
+        (self.f)(arg)
+    }
+}
+
+/// 参照を引数とするord、lin、aff型の関数
+///
+/// 呼び出す度に異なる寿命の参照を受け取れるよう、引数の寿命を高階のものとする
+struct LinRefFn<A, B> {
+    q: Q,              // 作成した時点の修飾子
+    var: &'static str, // 引数名
+    f: Box<dyn for<'r> FnOnce(&'r A) -> B>,
+}
+
+impl<A, B> LinRefFn<A, B> {
+    fn new(q: Q, var: &'static str, f: impl for<'r> FnOnce(&'r A) -> B + 'static) -> Self {
+        LinRefFn {
+            q,
+            var,
+            f: Box::new(f),
+        }
+    }
+
+    fn call(self, arg: &A) -> B {
+        (self.f)(arg)
+    }
+}
+
+/// 参照を引数とするun、rel型の関数
+struct UnRefFn<A, B> {
+    q: Q,              // 作成した時点の修飾子
+    var: &'static str, // 引数名
+    f: Rc<dyn for<'r> Fn(&'r A) -> B>,
+}
+
+impl<A, B> Clone for UnRefFn<A, B> {
+    fn clone(&self) -> Self {
+        UnRefFn {
+            q: self.q,
+            var: self.var,
+            f: self.f.clone(),
+        }
+    }
+}
+
+impl<A, B> UnRefFn<A, B> {
+    fn new(q: Q, var: &'static str, f: impl for<'r> Fn(&'r A) -> B + 'static) -> Self {
+        UnRefFn {
+            q,
+            var,
+            f: Rc::new(f),
+        }
+    }
+
+    fn call(&self, arg: &A) -> B {
+        (self.f)(arg)
+    }
+}
+
+/// 加法的ペアを射影した結果
+enum Either<A, B> {
+    Fst(A),
+    Snd(B),
+}
+
+/// ord、lin、aff型の加法的ペア。一度だけ射影できる
+///
+/// 両方の要素がキャプチャした値を共有するため、射影する要素を受け取る1つのクロージャとする
+struct LinWith<A, B>(Box<dyn FnOnce(bool) -> Either<A, B>>, Q);
+
+impl<A, B> LinWith<A, B> {
+    fn new(q: Q, f: impl FnOnce(bool) -> Either<A, B> + 'static) -> Self {
+        LinWith(Box::new(f), q)
+    }
+
+    fn fst(self) -> A {
+        match (self.0)(false) {
+            Either::Fst(a) => a,
+            Either::Snd(_) => unreachable!(),
+        }
+    }
+
+    fn snd(self) -> B {
+        match (self.0)(true) {
+            Either::Snd(b) => b,
+            Either::Fst(_) => unreachable!(),
+        }
+    }
+}
+
+/// un、rel型の加法的ペア。複製して何度でも射影できる
+struct UnWith<A, B>(Rc<dyn Fn(bool) -> Either<A, B>>, Q);
+
+impl<A, B> Clone for UnWith<A, B> {
+    fn clone(&self) -> Self {
+        UnWith(self.0.clone(), self.1)
+    }
+}
+
+impl<A, B> UnWith<A, B> {
+    fn new(q: Q, f: impl Fn(bool) -> Either<A, B> + 'static) -> Self {
+        UnWith(Rc::new(f), q)
+    }
+
+    fn fst(&self) -> A {
+        match (self.0)(false) {
+            Either::Fst(a) => a,
+            Either::Snd(_) => unreachable!(),
+        }
+    }
+
+    fn snd(&self) -> B {
+        match (self.0)(true) {
+            Either::Snd(b) => b,
+            Either::Fst(_) => unreachable!(),
+        }
+    }
+}
+
+/// !型の値。取り出す度に中身を評価する
+struct Bang<A>(Rc<dyn Fn() -> A>, Q);
+
+impl<A> Clone for Bang<A> {
+    fn clone(&self) -> Self {
+        Bang(self.0.clone(), self.1)
+    }
+}
+
+impl<A> Bang<A> {
+    fn new(q: Q, f: impl Fn() -> A + 'static) -> Self {
+        Bang(Rc::new(f), q)
+    }
+
+    fn force(&self) -> A {
+        (self.0)()
+    }
+}
+
+/// ファイルハンドル。dropで閉じる
+struct Handle {
+    file: std::fs::File,
+    id: usize,
+}
+
+impl<A, B> Qualified for LinFn<A, B> {
+    fn q(&self) -> Q {
+        self.q
+    }
+}
+
+impl<A, B> Qualified for UnFn<A, B> {
+    fn q(&self) -> Q {
+        self.q
+    }
+}
+
+impl<A, B> Qualified for LinRefFn<A, B> {
+    fn q(&self) -> Q {
+        self.q
+    }
+}
+
+impl<A, B> Qualified for UnRefFn<A, B> {
+    fn q(&self) -> Q {
+        self.q
+    }
+}
+
+impl<A, B> Qualified for LinWith<A, B> {
+    fn q(&self) -> Q {
+        self.1
+    }
+}
+
+impl<A, B> Qualified for UnWith<A, B> {
+    fn q(&self) -> Q {
+        self.1
+    }
+}
+
+impl<A> Qualified for Bang<A> {
+    fn q(&self) -> Q {
+        self.1
+    }
+}
+
+/// 開いたファイルの数
+static NUM_HANDLES: AtomicUsize = AtomicUsize::new(0);
+
+/// 配列の長さの上限。評価器と同じ
+const MAX_ARRAY_LEN: usize = 1 << 24;
+
+/// 配列の長さを取り出す
+fn rt_len(n: i64) -> usize {
+    if n < 0 {
+        error(&format!("配列の範囲外の位置{}を指定した", n));
+    }
+    if n as u64 > MAX_ARRAY_LEN as u64 {
+        error(&format!(
+            "配列の長さ{}が上限の{}を超えている",
+            n, MAX_ARRAY_LEN
+        ));
+    }
+    n as usize
+}
+
+/// 配列の位置を取り出し、範囲内かをチェック
+fn rt_index(i: i64, len: usize) -> usize {
+    if i < 0 || i as u64 >= len as u64 {
+        error(&format!("配列の範囲外の位置{}を指定した", i));
+    }
+    i as usize
+}
+
+/// not : un (lin bool -> lin bool)
+fn ext_not() -> UnFn<Lin<bool>, Lin<bool>> {
+    UnFn::new(Q::Un, "not", |b: Lin<bool>| Lin(!b.0, Q::Lin))
+}
+
+/// concat : un (lin str -> lin (lin str -> lin str))
+fn ext_concat() -> UnFn<Lin<String>, LinFn<Lin<String>, Lin<String>>> {
+    UnFn::new(Q::Un, "concat", |s1: Lin<String>| {
+        LinFn::new(Q::Lin, "concat", move |s2: Lin<String>| {
+            Lin(s1.0 + &s2.0, Q::Lin)
+        })
+    })
+}
+
+/// length : un (lin str -> lin (lin str * un int))。長さは文字数
+fn ext_length() -> UnFn<Lin<String>, Lin<(Lin<String>, Un<i64>)>> {
+    UnFn::new(Q::Un, "length", |s: Lin<String>| {
+        let n = s.0.chars().count() as i64;
+        Lin((s, Un(n, Q::Un)), Q::Lin)
+    })
+}
+
+/// open : un (un str -> lin handle)
+fn ext_open() -> UnFn<Un<Rc<str>>, Lin<Handle>> {
+    UnFn::new(Q::Un, "open", |path: Un<Rc<str>>| {
+        let file = std::fs::File::create(&*path.0).unwrap_or_else(|e| {
+            error(&format!(
+                "外部関数\"open\"のエラー: {}を開けない: {}",
+                path.0, e
+            ))
+        });
+        let id = NUM_HANDLES.fetch_add(1, Ordering::SeqCst);
+        Lin(Handle { file, id }, Q::Lin)
+    })
+}
+
+/// write : un (lin handle -> lin (lin str -> lin handle))
+fn ext_write() -> UnFn<Lin<Handle>, LinFn<Lin<String>, Lin<Handle>>> {
+    UnFn::new(Q::Un, "write", |h: Lin<Handle>| {
+        LinFn::new(Q::Lin, "write", move |s: Lin<String>| {
+            let mut h = h;
+            if let Err(e) = h.0.file.write_all(s.0.as_bytes()) {
+                error(&format!("外部関数\"write\"のエラー: 書き込みに失敗した: {}", e));
+            }
+            Lin(h.0, Q::Lin)
+        })
+    })
+}
+
+/// fclose : un (lin handle -> un unit)
+fn ext_fclose() -> UnFn<Lin<Handle>, Un<()>> {
+    UnFn::new(Q::Un, "fclose", |h: Lin<Handle>| {
+        drop(h);
+        Un((), Q::Un)
+    })
+}
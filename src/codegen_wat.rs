@@ -0,0 +1,705 @@
+//! ## WebAssemblyへのコード生成
+//!
+//! 型付けに成功した式を、WebAssemblyのテキスト形式（WAT）のモジュールに変換する。
+//! C言語へのコード生成と同様に、修飾子付き値は線形メモリ上のセルとなり、
+//! ord、lin、aff型の値は分解した時点とfree文で解放される。
+//! メモリはバンプアロケータで確保し、解放したブロックはフリーリストで再利用する。
+//!
+//! 関数はクロージャ変換により、キャプチャした変数の配列を受け取るWebAssemblyの関数に変換し、
+//! funcrefのテーブルに登録してcall_indirectで呼び出す。
+//!
+//! 生成したモジュールはWASIの`fd_write`と`proc_exit`のみをインポートし、
+//! `_start`で評価結果とヒープの統計情報を表示する。
+//! 評価結果から到達できないordとlin型の値が残っていた場合は終了コード1で終了する。
+//!
+//! チャネルとスレッドには対応しておらず、外部関数はnot、concat、lengthのみ利用できる。
+
+use crate::{
+    eval::Value,
+    externs::{self, ExternVal, Externs},
+    parser,
+    subst::{free_vars, free_vars_in},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+/// ランタイム
+///
+/// ランタイム中の`(str "...")`は、データセグメントに配置した文字列の位置とバイト数に置き換える
+const RUNTIME: &str = include_str!("codegen_wat/runtime.wat");
+
+/// WebAssemblyの実装を持つ組み込み関数
+const BUILTINS: [&str; 3] = ["not", "concat", "length"];
+
+/// データセグメントの先頭。それより前はランタイムの作業領域
+const DATA_BASE: usize = 64;
+
+type WResult = Result<String, String>;
+
+/// 変数の束縛
+#[derive(Debug, Clone)]
+struct Var {
+    name: String, // WebAssemblyのローカル変数名
+    bang: bool,   // let !式で束縛された!型の値か。利用する度に中身を評価する
+}
+
+/// 変数とリージョンから、WebAssemblyのローカル変数名への対応
+#[derive(Debug, Clone, Default)]
+struct Ctx {
+    vars: BTreeMap<String, Var>,
+    regions: BTreeMap<String, String>,
+}
+
+/// 生成中の関数本体
+#[derive(Debug, Default)]
+struct Body {
+    code: String,
+    indent: usize,
+    locals: Vec<String>, // ローカル変数名
+}
+
+impl Body {
+    fn new() -> Body {
+        Body {
+            indent: 2,
+            ..Default::default()
+        }
+    }
+
+    /// 1行を追加
+    fn line(&mut self, s: &str) {
+        for _ in 0..self.indent {
+            self.code.push_str("  ");
+        }
+        self.code.push_str(s);
+        self.code.push('\n');
+    }
+
+    /// ローカル変数を宣言し、値を代入
+    fn bind(&mut self, name: &str, value: &str) {
+        self.locals.push(name.to_string());
+        self.line(&format!("(local.set {} {})", name, value));
+    }
+
+    /// 関数の定義。headerは関数名と引数、返り値の型
+    fn func(&self, header: &str) -> String {
+        let mut f = format!("  (func {}\n", header);
+        for l in self.locals.iter() {
+            writeln!(f, "    (local {} i32)", l).unwrap();
+        }
+        f.push_str(self.code.trim_end());
+        f.push(')');
+        f
+    }
+}
+
+/// ローカル変数の値
+fn get(name: &str) -> String {
+    format!("(local.get {})", name)
+}
+
+/// コード生成器
+struct Codegen<'a> {
+    externs: &'a Externs,
+    funcs: Vec<String>, // クロージャ変換した関数の定義
+    table: Vec<String>, // テーブルに登録した関数名
+    data: Vec<String>,  // データセグメントの定義
+    data_len: usize,    // データセグメントの合計のバイト数
+    next: usize,        // ローカル変数などの番号
+}
+
+impl<'a> Codegen<'a> {
+    /// 新しい番号を返す
+    fn fresh(&mut self) -> usize {
+        self.next += 1;
+        self.next
+    }
+
+    /// 新しいローカル変数に式の値を代入し、ローカル変数名を返す
+    fn temp(&mut self, body: &mut Body, expr: &str) -> String {
+        let t = format!("$t{}", self.fresh());
+        body.bind(&t, expr);
+        t
+    }
+
+    /// 言語の変数varに対応する、新しいローカル変数名を返す
+    fn var_name(&mut self, var: &str) -> String {
+        format!("$v{}_{}", self.fresh(), var)
+    }
+
+    /// 関数をテーブルに登録し、テーブルの位置を返す
+    fn register(&mut self, name: &str) -> usize {
+        self.table.push(name.to_string());
+        self.table.len() - 1
+    }
+
+    /// WATの文字列リテラルで、lenバイトのデータセグメントを配置し、位置を返す
+    fn segment(&mut self, lit: String, len: usize) -> usize {
+        let pos = DATA_BASE + self.data_len;
+        self.data
+            .push(format!("(data (i32.const {}) {})", pos, lit));
+        self.data_len += len;
+        pos
+    }
+
+    /// バイト列をデータセグメントに配置し、位置を返す
+    fn data(&mut self, bytes: &[u8]) -> usize {
+        let mut lit = String::from("\"");
+        for b in bytes {
+            write!(lit, "\\{:02x}", b).unwrap();
+        }
+        lit.push('"');
+        self.segment(lit, bytes.len())
+    }
+
+    /// 文字列をデータセグメントに配置し、位置とバイト数の2つの値を返す
+    fn str_const(&mut self, s: &str) -> String {
+        let pos = self.segment(wat_str(s), s.len());
+        format!("(i32.const {}) (i32.const {})", pos, s.len())
+    }
+
+    /// ランタイム中の文字列をデータセグメントに配置
+    fn runtime(&mut self) -> String {
+        let mut out = String::new();
+        let mut rest = RUNTIME;
+        while let Some(i) = rest.find("(str \"") {
+            out.push_str(&rest[..i]);
+            rest = &rest[i + 6..];
+
+            // エスケープは\nと、\"などの1文字のみ
+            let mut s = String::new();
+            let mut chars = rest.char_indices();
+            let mut end = rest.len();
+            while let Some((j, c)) = chars.next() {
+                match c {
+                    '"' => {
+                        end = j;
+                        break;
+                    }
+                    '\\' => match chars.next() {
+                        Some((_, 'n')) => s.push('\n'),
+                        Some((_, c)) => s.push(c),
+                        None => (),
+                    },
+                    _ => s.push(c),
+                }
+            }
+            out.push_str(&self.str_const(&s));
+            rest = &rest[end + 2..];
+        }
+        out.push_str(rest);
+        out
+    }
+
+    /// 式を変換し、評価結果を保持するローカル変数名を返す
+    fn expr(&mut self, expr: &parser::Expr, ctx: &Ctx, body: &mut Body) -> WResult {
+        match expr {
+            parser::Expr::Let(e) => self.gen_let(e, ctx, body),
+            parser::Expr::LetBang(e) => self.gen_let_bang(e, ctx, body),
+            parser::Expr::If(e) => self.gen_if(e, ctx, body),
+            parser::Expr::Split(e) => self.gen_split(e, ctx, body),
+            parser::Expr::Free(e) => self.gen_free(e, ctx, body),
+            parser::Expr::App(e) => {
+                let f = self.expr(&e.expr1, ctx, body)?;
+                let a = self.expr(&e.expr2, ctx, body)?;
+                Ok(self.temp(body, &format!("(call $rt_apply {} {})", get(&f), get(&a))))
+            }
+            parser::Expr::Proj(e) => {
+                let a = self.expr(&e.expr, ctx, body)?;
+                let snd = (e.proj == parser::Proj::Snd) as u8;
+                Ok(self.temp(
+                    body,
+                    &format!("(call $rt_proj {} (i32.const {}))", get(&a), snd),
+                ))
+            }
+            parser::Expr::Promote(e) => {
+                let (code, env, n) = self.thunks(&[&e.expr], ctx, body)?;
+                Ok(self.temp(
+                    body,
+                    &format!(
+                        "(call $rt_bang (i32.const {}) {} (i32.const {}))",
+                        code[0],
+                        get(&env),
+                        n
+                    ),
+                ))
+            }
+            parser::Expr::Borrow(var) => {
+                let a = self.gen_var(var, ctx, body)?;
+                Ok(self.temp(body, &format!("(call $rt_borrow {})", get(&a))))
+            }
+            parser::Expr::NewRef(e) => {
+                let a = self.expr(&e.expr, ctx, body)?;
+                Ok(self.temp(body, &format!("(call $rt_new_ref {})", get(&a))))
+            }
+            parser::Expr::Swap(e) => {
+                let c = self.expr(&e.cell, ctx, body)?;
+                let v = self.expr(&e.expr, ctx, body)?;
+                Ok(self.temp(body, &format!("(call $rt_swap {} {})", get(&c), get(&v))))
+            }
+            parser::Expr::LetRegion(e) => self.gen_letregion(e, ctx, body),
+            parser::Expr::Alloc(e) => {
+                let n = self.expr(&e.len, ctx, body)?;
+                let v = self.expr(&e.expr, ctx, body)?;
+                Ok(self.temp(
+                    body,
+                    &format!("(call $rt_alloc_array {} {})", get(&n), get(&v)),
+                ))
+            }
+            parser::Expr::Get(e) => {
+                let a = self.expr(&e.array, ctx, body)?;
+                let i = self.expr(&e.index, ctx, body)?;
+                Ok(self.temp(body, &format!("(call $rt_get {} {})", get(&a), get(&i))))
+            }
+            parser::Expr::Set(e) => {
+                let a = self.expr(&e.array, ctx, body)?;
+                let i = self.expr(&e.index, ctx, body)?;
+                let v = self.expr(&e.expr, ctx, body)?;
+                Ok(self.temp(
+                    body,
+                    &format!("(call $rt_set {} {} {})", get(&a), get(&i), get(&v)),
+                ))
+            }
+            parser::Expr::Var(var) => self.gen_var(var, ctx, body),
+            parser::Expr::QVal(e) => self.gen_qval(e, ctx, body),
+            parser::Expr::New(_)
+            | parser::Expr::Send(_)
+            | parser::Expr::Recv(_)
+            | parser::Expr::Close(_)
+            | parser::Expr::Fork(_) => {
+                Err("チャネルとスレッドはWebAssemblyに変換できない".to_string())
+            }
+        }
+    }
+
+    /// 変数の変換
+    fn gen_var(&mut self, var: &str, ctx: &Ctx, body: &mut Body) -> WResult {
+        match ctx.vars.get(var) {
+            Some(Var { name, bang: false }) => Ok(name.clone()),
+            Some(Var { name, bang: true }) => {
+                Ok(self.temp(body, &format!("(call $rt_force {})", get(name))))
+            }
+            None => Err(format!("\"{}\"という変数は定義されていない", var)),
+        }
+    }
+
+    /// 修飾子付き値の変換
+    fn gen_qval(&mut self, expr: &parser::QValExpr, ctx: &Ctx, body: &mut Body) -> WResult {
+        let q = format!("(i32.const {})", qual(expr.qual));
+        let r = match &expr.region {
+            Some(r) => get(ctx
+                .regions
+                .get(r)
+                .ok_or_else(|| format!("リージョン\"{}\"は作成されていない", r))?),
+            None => "(i32.const -1)".to_string(),
+        };
+
+        let e = match &expr.val {
+            parser::ValExpr::Bool(b) => {
+                format!("(call $rt_bool {} {} (i32.const {}))", q, r, *b as u8)
+            }
+            parser::ValExpr::Int(n) => format!("(call $rt_int {} {} (i64.const {}))", q, r, n),
+            parser::ValExpr::Str(s) => {
+                format!("(call $rt_str {} {} {})", q, r, self.str_const(s))
+            }
+            parser::ValExpr::Pair(e1, e2) => {
+                let a1 = self.expr(e1, ctx, body)?;
+                let a2 = self.expr(e2, ctx, body)?;
+                format!("(call $rt_pair {} {} {} {})", q, r, get(&a1), get(&a2))
+            }
+            parser::ValExpr::With(e1, e2) => {
+                let (code, env, n) = self.thunks(&[e1, e2], ctx, body)?;
+                format!(
+                    "(call $rt_with {} {} (i32.const {}) (i32.const {}) {} (i32.const {}))",
+                    q,
+                    r,
+                    code[0],
+                    code[1],
+                    get(&env),
+                    n
+                )
+            }
+            parser::ValExpr::Fun(e) => {
+                let (code, env, n) = self.closure(e, ctx, body)?;
+                let var = self.str_const(&e.var);
+                format!(
+                    "(call $rt_fun {} {} (i32.const {}) {} {} (i32.const {}))",
+                    q,
+                    r,
+                    code,
+                    var,
+                    get(&env),
+                    n
+                )
+            }
+        };
+        Ok(self.temp(body, &e))
+    }
+
+    /// exprsの自由変数をキャプチャした変数の配列を作成し、
+    /// 配列のローカル変数名と要素数、関数内でキャプチャした変数を束縛した型環境を返す
+    ///
+    /// 関数内では、外側のリージョンに値を確保できないため、リージョンは引き継がない
+    fn capture(
+        &mut self,
+        exprs: &[&parser::Expr],
+        bound: &[&str],
+        ctx: &Ctx,
+        body: &mut Body,
+    ) -> (String, usize, Vec<(usize, String, Var)>) {
+        let mut fv = BTreeSet::new();
+        for e in exprs {
+            free_vars_in(
+                e,
+                &mut bound.iter().map(|v| v.to_string()).collect(),
+                &mut fv,
+            );
+        }
+
+        // 自由変数のうち、束縛されているものをキャプチャ
+        let caps: Vec<(String, &Var)> = fv
+            .into_iter()
+            .filter_map(|v| ctx.vars.get(&v).map(|b| (v, b)))
+            .collect();
+
+        let env = format!("$env{}", self.fresh());
+        body.bind(&env, &format!("(call $rt_env (i32.const {}))", caps.len()));
+        let mut inner = Vec::new();
+        for (i, (v, b)) in caps.into_iter().enumerate() {
+            body.line(&format!(
+                "(i32.store offset={} {} {})",
+                i * 4,
+                get(&env),
+                get(&b.name)
+            ));
+            let name = self.var_name(&v);
+            inner.push((i, v, Var { name, bang: b.bang }));
+        }
+        let n = inner.len();
+        (env, n, inner)
+    }
+
+    /// 関数本体の先頭で、キャプチャした変数を束縛
+    fn bind_captures(caps: &[(usize, String, Var)], fbody: &mut Body) -> Ctx {
+        let mut ctx = Ctx::default();
+        for (i, v, b) in caps {
+            fbody.bind(
+                &b.name,
+                &format!("(i32.load offset={} (local.get $env))", i * 4),
+            );
+            ctx.vars.insert(v.clone(), b.clone());
+        }
+        ctx
+    }
+
+    /// 関数をクロージャ変換し、テーブルの位置と、キャプチャした変数の配列のローカル変数名と要素数を返す
+    fn closure(
+        &mut self,
+        expr: &parser::FnExpr,
+        ctx: &Ctx,
+        body: &mut Body,
+    ) -> Result<(usize, String, usize), String> {
+        let (env, n, caps) = self.capture(&[&expr.expr], &[&expr.var], ctx, body);
+
+        let name = format!("$fn{}", self.fresh());
+        let mut fbody = Body::new();
+        let mut inner = Self::bind_captures(&caps, &mut fbody);
+        let arg = self.var_name(&expr.var);
+        fbody.bind(&arg, "(local.get $arg)");
+        inner.vars.insert(
+            expr.var.clone(),
+            Var {
+                name: arg,
+                bang: false,
+            },
+        );
+
+        let r = self.expr(&expr.expr, &inner, &mut fbody)?;
+        fbody.line(&get(&r));
+        self.funcs.push(format!(
+            "  ;; fn {}\n{}",
+            expr.var,
+            fbody.func(&format!(
+                "{} (param $env i32) (param $arg i32) (result i32)",
+                name
+            ))
+        ));
+
+        Ok((self.register(&name), env, n))
+    }
+
+    /// 加法的ペアの要素や!型の中身など、評価を遅延する式を変換し、
+    /// 式ごとのテーブルの位置と、キャプチャした変数の配列のローカル変数名と要素数を返す
+    ///
+    /// 全ての式で、キャプチャした変数の配列を共有する
+    fn thunks(
+        &mut self,
+        exprs: &[&parser::Expr],
+        ctx: &Ctx,
+        body: &mut Body,
+    ) -> Result<(Vec<usize>, String, usize), String> {
+        let (env, n, caps) = self.capture(exprs, &[], ctx, body);
+
+        let mut codes = Vec::new();
+        for e in exprs {
+            let name = format!("$thunk{}", self.fresh());
+            let mut fbody = Body::new();
+            let inner = Self::bind_captures(&caps, &mut fbody);
+            let r = self.expr(e, &inner, &mut fbody)?;
+            fbody.line(&get(&r));
+            self.funcs
+                .push(fbody.func(&format!("{} (param $env i32) (result i32)", name)));
+            codes.push(self.register(&name));
+        }
+
+        Ok((codes, env, n))
+    }
+
+    /// let式の変換
+    fn gen_let(&mut self, expr: &parser::LetExpr, ctx: &Ctx, body: &mut Body) -> WResult {
+        let a = self.expr(&expr.expr1, ctx, body)?;
+        let name = self.var_name(&expr.var);
+        body.bind(&name, &get(&a));
+
+        let mut ctx = ctx.clone();
+        ctx.vars.insert(expr.var.clone(), Var { name, bang: false });
+        self.expr(&expr.expr2, &ctx, body)
+    }
+
+    /// let !式の変換
+    fn gen_let_bang(&mut self, expr: &parser::LetBangExpr, ctx: &Ctx, body: &mut Body) -> WResult {
+        let a = self.expr(&expr.expr1, ctx, body)?;
+        let name = self.var_name(&expr.var);
+        body.bind(&name, &get(&a));
+
+        let mut ctx = ctx.clone();
+        ctx.vars.insert(expr.var.clone(), Var { name, bang: true });
+        self.expr(&expr.expr2, &ctx, body)
+    }
+
+    /// if式の変換
+    fn gen_if(&mut self, expr: &parser::IfExpr, ctx: &Ctx, body: &mut Body) -> WResult {
+        let c = self.expr(&expr.cond_expr, ctx, body)?;
+        let r = format!("$t{}", self.fresh());
+        body.locals.push(r.clone());
+
+        body.line(&format!("(if (call $rt_cond {})", get(&c)));
+        body.indent += 1;
+        body.line("(then");
+        body.indent += 1;
+        let a = self.expr(&expr.then_expr, ctx, body)?;
+        body.line(&format!("(local.set {} {}))", r, get(&a)));
+        body.indent -= 1;
+        body.line("(else");
+        body.indent += 1;
+        let a = self.expr(&expr.else_expr, ctx, body)?;
+        body.line(&format!("(local.set {} {})))", r, get(&a)));
+        body.indent -= 2;
+
+        Ok(r)
+    }
+
+    /// split式の変換。$rt_splitは2つの要素を返す
+    fn gen_split(&mut self, expr: &parser::SplitExpr, ctx: &Ctx, body: &mut Body) -> WResult {
+        let a = self.expr(&expr.expr, ctx, body)?;
+        let left = self.var_name(&expr.left);
+        let right = self.var_name(&expr.right);
+        body.locals.push(left.clone());
+        body.locals.push(right.clone());
+        body.line(&format!("(call $rt_split {})", get(&a)));
+        body.line(&format!("(local.set {})", right));
+        body.line(&format!("(local.set {})", left));
+
+        let mut ctx = ctx.clone();
+        ctx.vars.insert(
+            expr.left.clone(),
+            Var {
+                name: left,
+                bang: false,
+            },
+        );
+        ctx.vars.insert(
+            expr.right.clone(),
+            Var {
+                name: right,
+                bang: false,
+            },
+        );
+        self.expr(&expr.body, &ctx, body)
+    }
+
+    /// free文の変換
+    fn gen_free(&mut self, expr: &parser::FreeExpr, ctx: &Ctx, body: &mut Body) -> WResult {
+        match ctx.vars.get(&expr.var) {
+            Some(Var { name, bang: false }) => {
+                body.line(&format!("(call $rt_free_var {})", get(name)))
+            }
+            _ => return Err(format!("変数\"{}\"をfreeできない", expr.var)),
+        }
+        self.expr(&expr.expr, ctx, body)
+    }
+
+    /// letregion式の変換
+    fn gen_letregion(
+        &mut self,
+        expr: &parser::LetRegionExpr,
+        ctx: &Ctx,
+        body: &mut Body,
+    ) -> WResult {
+        let r = format!("$r{}_{}", self.fresh(), expr.region);
+        body.bind(&r, "(call $rt_region_new)");
+
+        let mut ctx = ctx.clone();
+        ctx.regions.insert(expr.region.clone(), r.clone());
+        let a = self.expr(&expr.expr, &ctx, body)?;
+
+        // 評価結果はリージョン外の値のため、リージョン内の値を一括して解放
+        body.line(&format!("(call $rt_region_free {})", get(&r)));
+        Ok(a)
+    }
+
+    /// 外部定義の値を作成するランタイムの呼び出し
+    fn value(&mut self, v: &Value) -> WResult {
+        Ok(match v {
+            Value::Bool(q, b) => format!(
+                "(call $rt_bool (i32.const {}) (i32.const -1) (i32.const {}))",
+                qual(*q),
+                *b as u8
+            ),
+            Value::Unit(q) => format!("(call $rt_unit (i32.const {}) (i32.const -1))", qual(*q)),
+            Value::Int(q, n) => format!(
+                "(call $rt_int (i32.const {}) (i32.const -1) (i64.const {}))",
+                qual(*q),
+                n
+            ),
+            Value::Str(q, s) => format!(
+                "(call $rt_str (i32.const {}) (i32.const -1) {})",
+                qual(*q),
+                self.str_const(s)
+            ),
+            _ => return Err(format!("外部定義の値{}はWebAssemblyに変換できない", v)),
+        })
+    }
+
+    /// プログラムで利用する外部定義を、_startの先頭で束縛
+    fn init(&mut self, expr: &parser::Expr, body: &mut Body) -> Result<Ctx, String> {
+        let fv = free_vars(expr);
+
+        let mut ctx = Ctx::default();
+        for var in fv {
+            let def = self
+                .externs
+                .defs
+                .iter()
+                .rev()
+                .find(|d| d.name == var)
+                .ok_or_else(|| format!("\"{}\"という変数は定義されていない", var))?;
+            let name = self.var_name(&var);
+
+            let e = match &def.val {
+                ExternVal::Value(v) => self.value(v)?,
+                ExternVal::Fun(_) => {
+                    if !BUILTINS.contains(&var.as_str()) {
+                        return Err(format!("外部関数\"{}\"はWebAssemblyの実装を持たない", var));
+                    }
+
+                    // 名前と、引数をn個与えた後の修飾子の一覧と、外部関数の定義を配置
+                    let n = externs::arity(&def.ty);
+                    let quals: Vec<u8> = (1..n)
+                        .map(|k| qual(externs::applied(&def.ty, k).qual))
+                        .collect();
+                    let name_pos = self.segment(wat_str(&var), var.len());
+                    let quals_pos = self.data(&quals);
+                    let code = self.register(&format!("$ext_{}", var));
+                    self.data_len = self.data_len.div_ceil(4) * 4;
+                    let mut def_bytes = Vec::new();
+                    for field in [name_pos, var.len(), n, quals_pos, code] {
+                        def_bytes.extend_from_slice(&(field as u32).to_le_bytes());
+                    }
+                    let def_pos = self.data(&def_bytes);
+
+                    format!(
+                        "(call $rt_extern (i32.const {}) (i32.const {}) (i32.const 0) (i32.const 0))",
+                        qual(def.ty.qual),
+                        def_pos
+                    )
+                }
+            };
+            body.bind(&name, &e);
+            ctx.vars.insert(var, Var { name, bang: false });
+        }
+        Ok(ctx)
+    }
+}
+
+/// 修飾子に対応するランタイムの定数
+fn qual(q: parser::Qual) -> u8 {
+    match q {
+        parser::Qual::Ord => 0,
+        parser::Qual::Lin => 1,
+        parser::Qual::Aff => 2,
+        parser::Qual::Rel => 3,
+        parser::Qual::Un => 4,
+    }
+}
+
+/// 文字列をWATの文字列リテラルに変換
+///
+/// 制御文字と"、\\は16進数のエスケープとする
+fn wat_str(s: &str) -> String {
+    let mut lit = String::from("\"");
+    for c in s.chars() {
+        if c.is_control() || c == '"' || c == '\\' {
+            let mut buf = [0; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                write!(lit, "\\{:02x}", b).unwrap();
+            }
+        } else {
+            lit.push(c);
+        }
+    }
+    lit.push('"');
+    lit
+}
+
+/// 外部定義を束縛した初期環境で式を評価する、WebAssemblyのモジュールを生成
+///
+/// 型付けに成功した式を渡すこと
+pub fn codegen(expr: &parser::Expr, externs: &Externs) -> Result<String, String> {
+    let mut cg = Codegen {
+        externs,
+        funcs: Vec::new(),
+        table: Vec::new(),
+        data: Vec::new(),
+        data_len: 0,
+        next: 0,
+    };
+
+    let runtime = cg.runtime();
+    let mut body = Body::new();
+    let ctx = cg.init(expr, &mut body)?;
+    let r = cg.expr(expr, &ctx, &mut body)?;
+    body.line(&format!("(call $proc_exit (call $rt_finish {}))", get(&r)));
+
+    // ヒープはデータセグメントの後から確保する
+    let heap = (DATA_BASE + cg.data_len).div_ceil(8) * 8;
+
+    let mut out = String::new();
+    writeln!(out, ";; 線形型言語から生成したコード\n(module").unwrap();
+    out.push_str(&runtime);
+    writeln!(out, "\n  (global $bump (mut i32) (i32.const {}))", heap).unwrap();
+    writeln!(out, "  (table {} funcref)", cg.table.len()).unwrap();
+    if !cg.table.is_empty() {
+        writeln!(out, "  (elem (i32.const 0) func {})", cg.table.join(" ")).unwrap();
+    }
+    for d in cg.data.iter() {
+        writeln!(out, "  {}", d).unwrap();
+    }
+    for f in cg.funcs.iter() {
+        writeln!(out, "\n{}", f).unwrap();
+    }
+    writeln!(out, "\n{})", body.func("$start (export \"_start\")")).unwrap();
+    Ok(out)
+}
@@ -0,0 +1,775 @@
+  ;; 線形型言語から変換したWebAssemblyのコードのランタイム
+  ;;
+  ;; C言語のランタイムと同様に、修飾子付き値はすべてヒープに確保したセルとなる。
+  ;; ord、lin、aff型のセルは分解した時点とfree文で解放し、
+  ;; 何度でも利用されうるun型とrel型のセルは解放しない。
+  ;; 終了時に評価結果から到達できないordとlin型のセルが残っていた場合はリークとして報告する。
+  ;;
+  ;; ヒープはバンプアロケータで確保し、解放したブロックは大きさごとのフリーリストで再利用する。
+  ;; ブロックの直前の8バイトにはブロックの大きさを格納する。
+  ;;
+  ;; 修飾子: 0 = ord, 1 = lin, 2 = aff, 3 = rel, 4 = un
+  ;;
+  ;; セルの種類（tag）と、各フィールド（オフセット24以降）の用途
+  ;;   0 = bool   : 24 真偽値
+  ;;   1 = unit
+  ;;   2 = int    : 24 整数（i64）
+  ;;   3 = str    : 24 文字列の先頭, 28 バイト数
+  ;;   4 = ペア   : 24 第1要素, 28 第2要素
+  ;;   5 = 加法的ペア : 24 第1要素を評価する関数, 28 第2要素を評価する関数, 32 env, 36 envの要素数
+  ;;   6 = 関数   : 24 関数, 28 引数名の先頭, 32 env, 36 envの要素数, 40 引数名のバイト数
+  ;;   7 = !型    : 24 中身を評価する関数, 32 env, 36 envの要素数
+  ;;   8 = 外部関数 : 24 外部関数の定義, 32 受け取った引数の配列, 36 引数の数
+  ;;   9 = 参照   : 24 参照先
+  ;;  10 = 可変な参照 : 24 中身
+  ;;  11 = 配列   : 24 要素の配列, 28 長さ
+  ;; 共通のフィールドは 0 修飾子, 4 tag, 8 リージョン（リージョン外は-1）,
+  ;; 12と16 解放されていないセルのリストの前後, 20 評価結果から到達可能か
+  ;;
+  ;; 外部関数の定義は 0 名前の先頭, 4 名前のバイト数, 8 引数の数,
+  ;; 12 n個の引数を与えた後の修飾子の配列（1バイトずつ）, 16 関数
+  ;;
+  ;; 関数は全てテーブルに登録し、call_indirectで呼び出す。
+  ;; envはキャプチャした変数の配列
+  ;;
+  ;; メモリの0から63はランタイムの作業領域
+
+  (import "wasi_snapshot_preview1" "fd_write"
+    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+
+  (memory (export "memory") 1)
+
+  ;; クロージャ変換した関数
+  (type $fun (func (param i32 i32) (result i32)))
+  ;; 加法的ペアの要素と、!型の中身を評価する関数
+  (type $thunk (func (param i32) (result i32)))
+  ;; 外部関数。引数の配列を受け取る
+  (type $ext (func (param i32) (result i32)))
+
+  (global $live (mut i32) (i32.const 0))        ;; 解放されていないセルのリスト
+  (global $free_list (mut i32) (i32.const 0))   ;; 解放したブロックのリスト
+  (global $num_alloc (mut i32) (i32.const 0))   ;; 確保したセルの数
+  (global $num_free (mut i32) (i32.const 0))    ;; 解放したセルの数
+  (global $num_regions (mut i32) (i32.const 0)) ;; 作成したリージョンの数
+
+  ;; ファイルディスクリプタfdに、ptrからlenバイトを書き込む
+  (func $rt_write (param $fd i32) (param $ptr i32) (param $len i32)
+    (i32.store (i32.const 0) (local.get $ptr))
+    (i32.store (i32.const 4) (local.get $len))
+    (drop (call $fd_write (local.get $fd) (i32.const 0) (i32.const 1) (i32.const 8))))
+
+  ;; 標準出力に書き込む
+  (func $rt_puts (param $ptr i32) (param $len i32)
+    (call $rt_write (i32.const 1) (local.get $ptr) (local.get $len)))
+
+  ;; 1バイトを書き込む
+  (func $rt_putc (param $fd i32) (param $ch i32)
+    (i32.store8 (i32.const 16) (local.get $ch))
+    (call $rt_write (local.get $fd) (i32.const 16) (i32.const 1)))
+
+  ;; 整数を10進数で書き込む
+  (func $rt_write_int (param $fd i32) (param $n i64)
+    (local $p i32) (local $m i64)
+    (local.set $p (i32.const 48))
+    ;; 負の場合は絶対値を符号なし整数として扱う
+    (local.set $m (local.get $n))
+    (if (i64.lt_s (local.get $n) (i64.const 0))
+      (then (local.set $m (i64.sub (i64.const 0) (local.get $n)))))
+    (loop $digits
+      (local.set $p (i32.sub (local.get $p) (i32.const 1)))
+      (i32.store8 (local.get $p)
+        (i32.add (i32.const 48) (i32.wrap_i64 (i64.rem_u (local.get $m) (i64.const 10)))))
+      (local.set $m (i64.div_u (local.get $m) (i64.const 10)))
+      (br_if $digits (i64.ne (local.get $m) (i64.const 0))))
+    (if (i64.lt_s (local.get $n) (i64.const 0))
+      (then
+        (local.set $p (i32.sub (local.get $p) (i32.const 1)))
+        (i32.store8 (local.get $p) (i32.const 45))))
+    (call $rt_write (local.get $fd) (local.get $p) (i32.sub (i32.const 48) (local.get $p))))
+
+  ;; 評価エラー
+  (func $rt_error (param $ptr i32) (param $len i32)
+    (call $rt_write (i32.const 2) (str "評価エラー: "))
+    (call $rt_write (i32.const 2) (local.get $ptr) (local.get $len))
+    (call $rt_putc (i32.const 2) (i32.const 10))
+    (call $proc_exit (i32.const 2))
+    (unreachable))
+
+  ;; sizeバイトのブロックを確保
+  (func $rt_malloc (param $size i32) (result i32)
+    (local $p i32) (local $prev i32) (local $pages i32)
+    (local.set $size (i32.and (i32.add (local.get $size) (i32.const 7)) (i32.const -8)))
+    (if (i32.eqz (local.get $size))
+      (then (local.set $size (i32.const 8))))
+
+    ;; 同じ大きさの解放したブロックがあれば再利用
+    (local.set $p (global.get $free_list))
+    (block $done
+      (loop $next
+        (br_if $done (i32.eqz (local.get $p)))
+        (if (i32.eq (i32.load (i32.sub (local.get $p) (i32.const 8))) (local.get $size))
+          (then
+            (if (local.get $prev)
+              (then (i32.store (local.get $prev) (i32.load (local.get $p))))
+              (else (global.set $free_list (i32.load (local.get $p)))))
+            (return (local.get $p))))
+        (local.set $prev (local.get $p))
+        (local.set $p (i32.load (local.get $p)))
+        (br $next)))
+
+    ;; なければ末尾から確保し、足りない場合はメモリを拡張
+    (local.set $p (i32.add (global.get $bump) (i32.const 8)))
+    (global.set $bump (i32.add (local.get $p) (local.get $size)))
+    (if (i32.gt_u (global.get $bump) (i32.shl (memory.size) (i32.const 16)))
+      (then
+        (local.set $pages
+          (i32.shr_u
+            (i32.add
+              (i32.sub (global.get $bump) (i32.shl (memory.size) (i32.const 16)))
+              (i32.const 65535))
+            (i32.const 16)))
+        (if (i32.eq (memory.grow (local.get $pages)) (i32.const -1))
+          (then (call $rt_error (str "メモリを確保できない"))))))
+    (i32.store (i32.sub (local.get $p) (i32.const 8)) (local.get $size))
+    (local.get $p))
+
+  ;; ブロックをフリーリストに戻す。0の場合は何もしない
+  (func $rt_mfree (param $p i32)
+    (if (i32.eqz (local.get $p))
+      (then (return)))
+    (i32.store (local.get $p) (global.get $free_list))
+    (global.set $free_list (local.get $p)))
+
+  ;; 修飾子の束の上限
+  (func $rt_join (param $q1 i32) (param $q2 i32) (result i32)
+    (if (i32.or (i32.eq (local.get $q1) (local.get $q2)) (i32.eq (local.get $q2) (i32.const 4)))
+      (then (return (local.get $q1))))
+    (if (i32.eq (local.get $q1) (i32.const 4))
+      (then (return (local.get $q2))))
+    (if (i32.or (i32.eqz (local.get $q1)) (i32.eqz (local.get $q2)))
+      (then (return (i32.const 0))))
+    (i32.const 1))
+
+  ;; 分解した時点で解放する修飾子か
+  (func $rt_consumed (param $q i32) (result i32)
+    (i32.lt_u (local.get $q) (i32.const 3)))
+
+  ;; セルを確保し、解放されていないセルのリストに追加
+  (func $rt_alloc (param $q i32) (param $region i32) (param $tag i32) (result i32)
+    (local $c i32)
+    (local.set $c (call $rt_malloc (i32.const 44)))
+    (memory.fill (local.get $c) (i32.const 0) (i32.const 44))
+    (i32.store offset=0 (local.get $c) (local.get $q))
+    (i32.store offset=4 (local.get $c) (local.get $tag))
+    (i32.store offset=8 (local.get $c) (local.get $region))
+    (i32.store offset=16 (local.get $c) (global.get $live))
+    (if (global.get $live)
+      (then (i32.store offset=12 (global.get $live) (local.get $c))))
+    (global.set $live (local.get $c))
+    (global.set $num_alloc (i32.add (global.get $num_alloc) (i32.const 1)))
+    (local.get $c))
+
+  ;; セルを解放
+  (func $rt_free (param $c i32)
+    (local $prev i32) (local $next i32) (local $tag i32)
+    (local.set $prev (i32.load offset=12 (local.get $c)))
+    (local.set $next (i32.load offset=16 (local.get $c)))
+    (if (local.get $prev)
+      (then (i32.store offset=16 (local.get $prev) (local.get $next)))
+      (else (global.set $live (local.get $next))))
+    (if (local.get $next)
+      (then (i32.store offset=12 (local.get $next) (local.get $prev))))
+
+    ;; 文字列、env、引数と要素の配列
+    (local.set $tag (i32.load offset=4 (local.get $c)))
+    (if (i32.or (i32.eq (local.get $tag) (i32.const 3)) (i32.eq (local.get $tag) (i32.const 11)))
+      (then (call $rt_mfree (i32.load offset=24 (local.get $c)))))
+    (if (i32.and (i32.ge_u (local.get $tag) (i32.const 5)) (i32.le_u (local.get $tag) (i32.const 8)))
+      (then (call $rt_mfree (i32.load offset=32 (local.get $c)))))
+
+    (call $rt_mfree (local.get $c))
+    (global.set $num_free (i32.add (global.get $num_free) (i32.const 1))))
+
+  ;; 分解したセルを、修飾子に応じて解放
+  (func $rt_consume (param $c i32)
+    (if (call $rt_consumed (i32.load (local.get $c)))
+      (then (call $rt_free (local.get $c)))))
+
+  ;; freeしたセルや外部関数に渡したセルを、ペアの要素やrefの中身も含めて修飾子に応じて解放
+  (func $rt_release (param $c i32)
+    (if (i32.eq (i32.load offset=4 (local.get $c)) (i32.const 4))
+      (then
+        (call $rt_release (i32.load offset=24 (local.get $c)))
+        (call $rt_release (i32.load offset=28 (local.get $c)))))
+    (if (i32.eq (i32.load offset=4 (local.get $c)) (i32.const 10))
+      (then (call $rt_release (i32.load offset=24 (local.get $c)))))
+    (call $rt_consume (local.get $c)))
+
+  ;; キャプチャした変数の配列を確保
+  (func $rt_env (param $n i32) (result i32)
+    (if (result i32) (i32.eqz (local.get $n))
+      (then (i32.const 0))
+      (else (call $rt_malloc (i32.shl (local.get $n) (i32.const 2))))))
+
+  (func $rt_bool (param $q i32) (param $region i32) (param $b i32) (result i32)
+    (local $c i32)
+    (local.set $c (call $rt_alloc (local.get $q) (local.get $region) (i32.const 0)))
+    (i32.store offset=24 (local.get $c) (local.get $b))
+    (local.get $c))
+
+  (func $rt_unit (param $q i32) (param $region i32) (result i32)
+    (call $rt_alloc (local.get $q) (local.get $region) (i32.const 1)))
+
+  (func $rt_int (param $q i32) (param $region i32) (param $n i64) (result i32)
+    (local $c i32)
+    (local.set $c (call $rt_alloc (local.get $q) (local.get $region) (i32.const 2)))
+    (i64.store offset=24 (local.get $c) (local.get $n))
+    (local.get $c))
+
+  ;; ptrからlenバイトの文字列を複製して確保
+  (func $rt_str (param $q i32) (param $region i32) (param $ptr i32) (param $len i32) (result i32)
+    (local $c i32) (local $s i32)
+    (local.set $s (call $rt_malloc (local.get $len)))
+    (memory.copy (local.get $s) (local.get $ptr) (local.get $len))
+    (local.set $c (call $rt_alloc (local.get $q) (local.get $region) (i32.const 3)))
+    (i32.store offset=24 (local.get $c) (local.get $s))
+    (i32.store offset=28 (local.get $c) (local.get $len))
+    (local.get $c))
+
+  (func $rt_pair (param $q i32) (param $region i32) (param $a1 i32) (param $a2 i32) (result i32)
+    (local $c i32)
+    (local.set $c (call $rt_alloc (local.get $q) (local.get $region) (i32.const 4)))
+    (i32.store offset=24 (local.get $c) (local.get $a1))
+    (i32.store offset=28 (local.get $c) (local.get $a2))
+    (local.get $c))
+
+  (func $rt_with (param $q i32) (param $region i32) (param $code1 i32) (param $code2 i32)
+    (param $env i32) (param $n i32) (result i32)
+    (local $c i32)
+    (local.set $c (call $rt_alloc (local.get $q) (local.get $region) (i32.const 5)))
+    (i32.store offset=24 (local.get $c) (local.get $code1))
+    (i32.store offset=28 (local.get $c) (local.get $code2))
+    (i32.store offset=32 (local.get $c) (local.get $env))
+    (i32.store offset=36 (local.get $c) (local.get $n))
+    (local.get $c))
+
+  (func $rt_fun (param $q i32) (param $region i32) (param $code i32) (param $var i32)
+    (param $var_len i32) (param $env i32) (param $n i32) (result i32)
+    (local $c i32)
+    (local.set $c (call $rt_alloc (local.get $q) (local.get $region) (i32.const 6)))
+    (i32.store offset=24 (local.get $c) (local.get $code))
+    (i32.store offset=28 (local.get $c) (local.get $var))
+    (i32.store offset=32 (local.get $c) (local.get $env))
+    (i32.store offset=36 (local.get $c) (local.get $n))
+    (i32.store offset=40 (local.get $c) (local.get $var_len))
+    (local.get $c))
+
+  (func $rt_bang (param $code i32) (param $env i32) (param $n i32) (result i32)
+    (local $c i32)
+    (local.set $c (call $rt_alloc (i32.const 4) (i32.const -1) (i32.const 7)))
+    (i32.store offset=24 (local.get $c) (local.get $code))
+    (i32.store offset=32 (local.get $c) (local.get $env))
+    (i32.store offset=36 (local.get $c) (local.get $n))
+    (local.get $c))
+
+  ;; 受け取った引数argsを保持した外部関数
+  (func $rt_extern (param $q i32) (param $ext i32) (param $args i32) (param $n i32) (result i32)
+    (local $c i32)
+    (local.set $c (call $rt_alloc (local.get $q) (i32.const -1) (i32.const 8)))
+    (i32.store offset=24 (local.get $c) (local.get $ext))
+    (i32.store offset=32 (local.get $c) (local.get $args))
+    (i32.store offset=36 (local.get $c) (local.get $n))
+    (local.get $c))
+
+  ;; 参照をたどり、参照でないセルを返す
+  (func $rt_deref (param $c i32) (result i32)
+    (block $done
+      (loop $next
+        (br_if $done (i32.ne (i32.load offset=4 (local.get $c)) (i32.const 9)))
+        (local.set $c (i32.load offset=24 (local.get $c)))
+        (br $next)))
+    (local.get $c))
+
+  ;; !型の値の中身を評価
+  (func $rt_force (param $c i32) (result i32)
+    (if (i32.ne (i32.load offset=4 (local.get $c)) (i32.const 7))
+      (then (call $rt_error (str "!型でない値を取り出そうとした"))))
+    (call_indirect (type $thunk)
+      (i32.load offset=32 (local.get $c))
+      (i32.load offset=24 (local.get $c))))
+
+  ;; 借用。参照の借用は同じ参照とする
+  (func $rt_borrow (param $c i32) (result i32)
+    (local $r i32)
+    (if (i32.eq (i32.load offset=4 (local.get $c)) (i32.const 9))
+      (then (return (local.get $c))))
+    (local.set $r (call $rt_alloc (i32.const 4) (i32.const -1) (i32.const 9)))
+    (i32.store offset=24 (local.get $r) (local.get $c))
+    (local.get $r))
+
+  ;; ifの条件式の真偽値を取り出し、条件式の値を消費
+  (func $rt_cond (param $c i32) (result i32)
+    (local $t i32) (local $b i32)
+    (local.set $t (call $rt_deref (local.get $c)))
+    (if (i32.ne (i32.load offset=4 (local.get $t)) (i32.const 0))
+      (then (call $rt_error (str "ifの条件式がboolでない"))))
+    (local.set $b (i32.load offset=24 (local.get $t)))
+    (call $rt_consume (local.get $c))
+    (local.get $b))
+
+  ;; ペアを分解し、2つの要素を返す。ペアへの参照を分解した場合は、各要素への参照とする
+  (func $rt_split (param $c i32) (result i32 i32)
+    (local $t i32) (local $a1 i32) (local $a2 i32)
+    (local.set $t (call $rt_deref (local.get $c)))
+    (if (i32.ne (i32.load offset=4 (local.get $t)) (i32.const 4))
+      (then (call $rt_error (str "splitの引数がペアでない"))))
+    (local.set $a1 (i32.load offset=24 (local.get $t)))
+    (local.set $a2 (i32.load offset=28 (local.get $t)))
+    (call $rt_consume (local.get $c))
+
+    (if (i32.ne (local.get $t) (local.get $c))
+      (then
+        (local.set $a1 (call $rt_borrow (local.get $a1)))
+        (local.set $a2 (call $rt_borrow (local.get $a2)))))
+    (local.get $a1)
+    (local.get $a2))
+
+  ;; free文。ペアの要素やrefの中身も含めて解放
+  (func $rt_free_var (param $c i32)
+    (call $rt_release (local.get $c)))
+
+  ;; 外部関数に引数argを与える
+  (func $rt_apply_extern (param $f i32) (param $arg i32) (result i32)
+    (local $ext i32) (local $n i32) (local $args i32) (local $r i32) (local $i i32)
+    (local.set $ext (i32.load offset=24 (local.get $f)))
+    (local.set $n (i32.add (i32.load offset=36 (local.get $f)) (i32.const 1)))
+    (local.set $args (call $rt_malloc (i32.shl (local.get $n) (i32.const 2))))
+    (memory.copy
+      (local.get $args)
+      (i32.load offset=32 (local.get $f))
+      (i32.shl (i32.sub (local.get $n) (i32.const 1)) (i32.const 2)))
+    (i32.store
+      (i32.add (local.get $args) (i32.shl (i32.sub (local.get $n) (i32.const 1)) (i32.const 2)))
+      (local.get $arg))
+    (call $rt_consume (local.get $f))
+
+    ;; 全ての引数がそろっていない場合は、部分適用した外部関数を返す
+    (if (i32.lt_u (local.get $n) (i32.load offset=8 (local.get $ext)))
+      (then
+        (return
+          (call $rt_extern
+            (i32.load8_u
+              (i32.add (i32.load offset=12 (local.get $ext)) (i32.sub (local.get $n) (i32.const 1))))
+            (local.get $ext)
+            (local.get $args)
+            (local.get $n)))))
+
+    ;; 外部関数を呼び出し、引数を外部関数に渡したものとして解放
+    (local.set $r
+      (call_indirect (type $ext) (local.get $args) (i32.load offset=16 (local.get $ext))))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $n)))
+        (call $rt_release
+          (i32.load (i32.add (local.get $args) (i32.shl (local.get $i) (i32.const 2)))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (call $rt_mfree (local.get $args))
+    (local.get $r))
+
+  ;; 関数適用
+  (func $rt_apply (param $f i32) (param $arg i32) (result i32)
+    (local $code i32) (local $env i32) (local $consumed i32) (local $r i32)
+    (if (i32.eq (i32.load offset=4 (local.get $f)) (i32.const 8))
+      (then (return (call $rt_apply_extern (local.get $f) (local.get $arg)))))
+    (if (i32.ne (i32.load offset=4 (local.get $f)) (i32.const 6))
+      (then (call $rt_error (str "関数でない値を関数適用した"))))
+
+    ;; 関数を消費する場合は、キャプチャした変数の配列を関数の評価後に解放
+    (local.set $code (i32.load offset=24 (local.get $f)))
+    (local.set $env (i32.load offset=32 (local.get $f)))
+    (local.set $consumed (call $rt_consumed (i32.load (local.get $f))))
+    (if (local.get $consumed)
+      (then
+        (i32.store offset=32 (local.get $f) (i32.const 0))
+        (call $rt_free (local.get $f))))
+
+    (local.set $r
+      (call_indirect (type $fun) (local.get $env) (local.get $arg) (local.get $code)))
+    (if (local.get $consumed)
+      (then (call $rt_mfree (local.get $env))))
+    (local.get $r))
+
+  ;; fstとsnd。sndの場合はsndを非0とする
+  (func $rt_proj (param $c i32) (param $snd i32) (result i32)
+    (local $code i32) (local $env i32) (local $consumed i32) (local $r i32)
+    (if (i32.ne (i32.load offset=4 (local.get $c)) (i32.const 5))
+      (then (call $rt_error (str "fstかsndの引数が加法的ペアでない"))))
+
+    (local.set $code
+      (if (result i32) (local.get $snd)
+        (then (i32.load offset=28 (local.get $c)))
+        (else (i32.load offset=24 (local.get $c)))))
+    (local.set $env (i32.load offset=32 (local.get $c)))
+    (local.set $consumed (call $rt_consumed (i32.load (local.get $c))))
+    (if (local.get $consumed)
+      (then
+        (i32.store offset=32 (local.get $c) (i32.const 0))
+        (call $rt_free (local.get $c))))
+
+    (local.set $r (call_indirect (type $thunk) (local.get $env) (local.get $code)))
+    (if (local.get $consumed)
+      (then (call $rt_mfree (local.get $env))))
+    (local.get $r))
+
+  ;; new式（可変な参照の作成）
+  (func $rt_new_ref (param $v i32) (result i32)
+    (local $c i32)
+    (local.set $c
+      (call $rt_alloc
+        (call $rt_join (i32.const 1) (i32.load (local.get $v)))
+        (i32.const -1)
+        (i32.const 10)))
+    (i32.store offset=24 (local.get $c) (local.get $v))
+    (local.get $c))
+
+  ;; swap式。中身を交換し、同じ参照と元の中身のペアを返す
+  (func $rt_swap (param $c i32) (param $v i32) (result i32)
+    (local $old i32)
+    (if (i32.ne (i32.load offset=4 (local.get $c)) (i32.const 10))
+      (then (call $rt_error (str "refでない値をswapした"))))
+    (local.set $old (i32.load offset=24 (local.get $c)))
+    (i32.store offset=24 (local.get $c) (local.get $v))
+    (call $rt_pair
+      (call $rt_join (i32.load (local.get $c)) (i32.load (local.get $old)))
+      (i32.const -1)
+      (local.get $c)
+      (local.get $old)))
+
+  ;; 配列の位置か長さを取り出し、消費。lenが負でない場合は範囲内かをチェック
+  (func $rt_index (param $c i32) (param $len i64) (result i32)
+    (local $n i64)
+    (if (i32.ne (i32.load offset=4 (local.get $c)) (i32.const 2))
+      (then (call $rt_error (str "配列の位置か長さがintでない"))))
+    (local.set $n (i64.load offset=24 (local.get $c)))
+    (call $rt_consume (local.get $c))
+    (if (i32.or
+          (i64.lt_s (local.get $n) (i64.const 0))
+          (i32.and
+            (i64.ge_s (local.get $len) (i64.const 0))
+            (i64.ge_s (local.get $n) (local.get $len))))
+      (then
+        (call $rt_write (i32.const 2) (str "評価エラー: 配列の範囲外の位置"))
+        (call $rt_write_int (i32.const 2) (local.get $n))
+        (call $rt_write (i32.const 2) (str "を指定した\n"))
+        (call $proc_exit (i32.const 2))))
+    ;; 要素の配列を確保できない長さ
+    (if (i64.gt_s (local.get $n) (i64.const 0x10000000))
+      (then (call $rt_error (str "メモリを確保できない"))))
+    (i32.wrap_i64 (local.get $n)))
+
+  ;; alloc式。要素はun型で解放されないため、全ての要素で同じ値を共有する
+  (func $rt_alloc_array (param $len i32) (param $v i32) (result i32)
+    (local $n i32) (local $c i32) (local $elems i32) (local $i i32)
+    (local.set $n (call $rt_index (local.get $len) (i64.const -1)))
+    (local.set $elems (call $rt_malloc (i32.shl (local.get $n) (i32.const 2))))
+    (local.set $c (call $rt_alloc (i32.const 1) (i32.const -1) (i32.const 11)))
+    (i32.store offset=24 (local.get $c) (local.get $elems))
+    (i32.store offset=28 (local.get $c) (local.get $n))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $n)))
+        (i32.store
+          (i32.add (local.get $elems) (i32.shl (local.get $i) (i32.const 2)))
+          (local.get $v))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (local.get $c))
+
+  ;; 配列の要素のアドレス。配列でない場合は評価エラー
+  (func $rt_elem (param $c i32) (param $index i32) (result i32)
+    (if (i32.ne (i32.load offset=4 (local.get $c)) (i32.const 11))
+      (then (call $rt_error (str "配列でない値を読み書きした"))))
+    (i32.add
+      (i32.load offset=24 (local.get $c))
+      (i32.shl
+        (call $rt_index (local.get $index) (i64.extend_i32_u (i32.load offset=28 (local.get $c))))
+        (i32.const 2))))
+
+  ;; get式。配列は消費せずに、同じ配列と要素のペアを返す
+  (func $rt_get (param $c i32) (param $index i32) (result i32)
+    (call $rt_pair
+      (i32.load (local.get $c))
+      (i32.const -1)
+      (local.get $c)
+      (i32.load (call $rt_elem (local.get $c) (local.get $index)))))
+
+  ;; set式。配列はlin型で他から参照されないため、コピーせずに書き換える
+  (func $rt_set (param $c i32) (param $index i32) (param $v i32) (result i32)
+    (i32.store (call $rt_elem (local.get $c) (local.get $index)) (local.get $v))
+    (local.get $c))
+
+  ;; リージョンを作成し、リージョンの番号を返す
+  (func $rt_region_new (result i32)
+    (global.set $num_regions (i32.add (global.get $num_regions) (i32.const 1)))
+    (i32.sub (global.get $num_regions) (i32.const 1)))
+
+  ;; リージョン内の解放されていないセルを、修飾子に関わらず一括して解放
+  (func $rt_region_free (param $region i32)
+    (local $c i32) (local $next i32)
+    (local.set $c (global.get $live))
+    (block $done
+      (loop $loop
+        (br_if $done (i32.eqz (local.get $c)))
+        (local.set $next (i32.load offset=16 (local.get $c)))
+        (if (i32.eq (i32.load offset=8 (local.get $c)) (local.get $region))
+          (then (call $rt_free (local.get $c))))
+        (local.set $c (local.get $next))
+        (br $loop))))
+
+  ;; not : un (lin bool -> lin bool)
+  (func $ext_not (param $args i32) (result i32)
+    (local $a i32)
+    (local.set $a (i32.load (local.get $args)))
+    (if (i32.ne (i32.load offset=4 (local.get $a)) (i32.const 0))
+      (then (call $rt_error (str "外部関数\"not\"のエラー: notの引数がboolでない"))))
+    (call $rt_bool (i32.const 1) (i32.const -1) (i32.eqz (i32.load offset=24 (local.get $a)))))
+
+  ;; concat : un (lin str -> lin (lin str -> lin str))
+  (func $ext_concat (param $args i32) (result i32)
+    (local $a1 i32) (local $a2 i32) (local $n1 i32) (local $n2 i32) (local $c i32)
+    (local.set $a1 (i32.load (local.get $args)))
+    (local.set $a2 (i32.load offset=4 (local.get $args)))
+    (if (i32.or
+          (i32.ne (i32.load offset=4 (local.get $a1)) (i32.const 3))
+          (i32.ne (i32.load offset=4 (local.get $a2)) (i32.const 3)))
+      (then (call $rt_error (str "外部関数\"concat\"のエラー: concatの引数がstrでない"))))
+    (local.set $n1 (i32.load offset=28 (local.get $a1)))
+    (local.set $n2 (i32.load offset=28 (local.get $a2)))
+    (local.set $c
+      (call $rt_str
+        (i32.const 1)
+        (i32.const -1)
+        (i32.load offset=24 (local.get $a1))
+        (i32.add (local.get $n1) (local.get $n2))))
+    (memory.copy
+      (i32.add (i32.load offset=24 (local.get $c)) (local.get $n1))
+      (i32.load offset=24 (local.get $a2))
+      (local.get $n2))
+    (local.get $c))
+
+  ;; length : un (lin str -> lin (lin str * un int))。長さはUTF-8の文字数
+  (func $ext_length (param $args i32) (result i32)
+    (local $a i32) (local $s i32) (local $len i32) (local $i i32) (local $n i64)
+    (local.set $a (i32.load (local.get $args)))
+    (if (i32.ne (i32.load offset=4 (local.get $a)) (i32.const 3))
+      (then (call $rt_error (str "外部関数\"length\"のエラー: lengthの引数がstrでない"))))
+    (local.set $s (i32.load offset=24 (local.get $a)))
+    (local.set $len (i32.load offset=28 (local.get $a)))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+        (if (i32.ne
+              (i32.and (i32.load8_u (i32.add (local.get $s) (local.get $i))) (i32.const 0xc0))
+              (i32.const 0x80))
+          (then (local.set $n (i64.add (local.get $n) (i64.const 1)))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (call $rt_pair
+      (i32.const 1)
+      (i32.const -1)
+      (call $rt_str (i32.load (local.get $a)) (i32.const -1) (local.get $s) (local.get $len))
+      (call $rt_int (i32.const 4) (i32.const -1) (local.get $n))))
+
+  ;; 16進数の1桁を表示
+  (func $rt_put_hex (param $d i32)
+    (call $rt_putc (i32.const 1)
+      (i32.add
+        (local.get $d)
+        (if (result i32) (i32.lt_u (local.get $d) (i32.const 10))
+          (then (i32.const 48))
+          (else (i32.const 87))))))
+
+  ;; 文字列を、評価器と同様にエスケープして表示
+  (func $rt_print_str (param $s i32) (param $len i32)
+    (local $i i32) (local $ch i32)
+    (call $rt_putc (i32.const 1) (i32.const 34))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+        (local.set $ch (i32.load8_u (i32.add (local.get $s) (local.get $i))))
+        (block $escaped
+          (if (i32.eq (local.get $ch) (i32.const 10))
+            (then (call $rt_puts (str "\\n")) (br $escaped)))
+          (if (i32.eq (local.get $ch) (i32.const 9))
+            (then (call $rt_puts (str "\\t")) (br $escaped)))
+          (if (i32.eq (local.get $ch) (i32.const 13))
+            (then (call $rt_puts (str "\\r")) (br $escaped)))
+          (if (i32.eq (local.get $ch) (i32.const 34))
+            (then (call $rt_puts (str "\\\"")) (br $escaped)))
+          (if (i32.eq (local.get $ch) (i32.const 92))
+            (then (call $rt_puts (str "\\\\")) (br $escaped)))
+          (if (i32.or (i32.lt_u (local.get $ch) (i32.const 0x20)) (i32.eq (local.get $ch) (i32.const 0x7f)))
+            (then
+              (call $rt_puts (str "\\u{"))
+              (if (i32.ge_u (local.get $ch) (i32.const 16))
+                (then (call $rt_put_hex (i32.shr_u (local.get $ch) (i32.const 4)))))
+              (call $rt_put_hex (i32.and (local.get $ch) (i32.const 15)))
+              (call $rt_putc (i32.const 1) (i32.const 125))
+              (br $escaped)))
+          (call $rt_putc (i32.const 1) (local.get $ch)))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (call $rt_putc (i32.const 1) (i32.const 34)))
+
+  ;; 修飾子を表示
+  (func $rt_print_qual (param $q i32)
+    (block $un
+      (block $rel
+        (block $aff
+          (block $lin
+            (block $ord
+              (br_table $ord $lin $aff $rel $un (local.get $q)))
+            (call $rt_puts (str "ord"))
+            (return))
+          (call $rt_puts (str "lin"))
+          (return))
+        (call $rt_puts (str "aff"))
+        (return))
+      (call $rt_puts (str "rel"))
+      (return))
+    (call $rt_puts (str "un")))
+
+  ;; 値を、評価器の評価結果と同じ形式で表示
+  (func $rt_print (param $c i32)
+    (local $i i32) (local $ext i32)
+    (call $rt_print_qual (i32.load (local.get $c)))
+    (call $rt_putc (i32.const 1) (i32.const 32))
+    (block $array
+      (block $cell
+        (block $ref
+          (block $extern
+            (block $bang
+              (block $fun
+                (block $with
+                  (block $pair
+                    (block $str
+                      (block $int
+                        (block $unit
+                          (block $bool
+                            (br_table $bool $unit $int $str $pair $with $fun $bang $extern $ref $cell $array
+                              (i32.load offset=4 (local.get $c))))
+                          (if (i32.load offset=24 (local.get $c))
+                            (then (call $rt_puts (str "true")))
+                            (else (call $rt_puts (str "false"))))
+                          (return))
+                        (call $rt_puts (str "unit"))
+                        (return))
+                      (call $rt_write_int (i32.const 1) (i64.load offset=24 (local.get $c)))
+                      (return))
+                    (call $rt_print_str
+                      (i32.load offset=24 (local.get $c))
+                      (i32.load offset=28 (local.get $c)))
+                    (return))
+                  (call $rt_putc (i32.const 1) (i32.const 60))
+                  (call $rt_print (i32.load offset=24 (local.get $c)))
+                  (call $rt_puts (str ", "))
+                  (call $rt_print (i32.load offset=28 (local.get $c)))
+                  (call $rt_putc (i32.const 1) (i32.const 62))
+                  (return))
+                (call $rt_puts (str "<| ... |>"))
+                (return))
+              (call $rt_puts (str "fn "))
+              (call $rt_puts (i32.load offset=28 (local.get $c)) (i32.load offset=40 (local.get $c)))
+              (call $rt_puts (str " { ... }"))
+              (return))
+            (call $rt_puts (str "promote ..."))
+            (return))
+          (local.set $ext (i32.load offset=24 (local.get $c)))
+          (call $rt_puts (str "fn "))
+          (call $rt_puts (i32.load (local.get $ext)) (i32.load offset=4 (local.get $ext)))
+          (call $rt_puts (str " { ... }"))
+          (return))
+        (call $rt_putc (i32.const 1) (i32.const 38))
+        (call $rt_print (i32.load offset=24 (local.get $c)))
+        (return))
+      (call $rt_puts (str "ref "))
+      (call $rt_print (i32.load offset=24 (local.get $c)))
+      (return))
+    (call $rt_putc (i32.const 1) (i32.const 91))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (i32.load offset=28 (local.get $c))))
+        (if (local.get $i)
+          (then (call $rt_puts (str ", "))))
+        (call $rt_print
+          (i32.load (i32.add (i32.load offset=24 (local.get $c)) (i32.shl (local.get $i) (i32.const 2)))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (call $rt_putc (i32.const 1) (i32.const 93)))
+
+  ;; 配列ptrのn個のセルに印を付ける
+  (func $rt_mark_all (param $ptr i32) (param $n i32)
+    (local $i i32)
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $n)))
+        (call $rt_mark (i32.load (i32.add (local.get $ptr) (i32.shl (local.get $i) (i32.const 2)))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next))))
+
+  ;; cから到達可能なセルに印を付ける
+  (func $rt_mark (param $c i32)
+    (local $tag i32)
+    (if (i32.load offset=20 (local.get $c))
+      (then (return)))
+    (i32.store offset=20 (local.get $c) (i32.const 1))
+
+    (local.set $tag (i32.load offset=4 (local.get $c)))
+    ;; ペア
+    (if (i32.eq (local.get $tag) (i32.const 4))
+      (then
+        (call $rt_mark (i32.load offset=24 (local.get $c)))
+        (call $rt_mark (i32.load offset=28 (local.get $c)))))
+    ;; 加法的ペア、関数、!型の値、外部関数
+    (if (i32.and (i32.ge_u (local.get $tag) (i32.const 5)) (i32.le_u (local.get $tag) (i32.const 8)))
+      (then
+        (call $rt_mark_all (i32.load offset=32 (local.get $c)) (i32.load offset=36 (local.get $c)))))
+    ;; 参照、可変な参照
+    (if (i32.or (i32.eq (local.get $tag) (i32.const 9)) (i32.eq (local.get $tag) (i32.const 10)))
+      (then (call $rt_mark (i32.load offset=24 (local.get $c)))))
+    ;; 配列
+    (if (i32.eq (local.get $tag) (i32.const 11))
+      (then
+        (call $rt_mark_all (i32.load offset=24 (local.get $c)) (i32.load offset=28 (local.get $c))))))
+
+  ;; 評価結果を表示し、終了コードを返す
+  ;;
+  ;; 評価結果から到達できないordとlin型のセルが残っていた場合はリークとして報告し、1を返す
+  (func $rt_finish (param $result i32) (result i32)
+    (local $c i32) (local $leaked i32)
+    (call $rt_puts (str "評価結果:\n"))
+    (call $rt_print (local.get $result))
+    (call $rt_putc (i32.const 1) (i32.const 10))
+
+    (call $rt_mark (local.get $result))
+    (local.set $c (global.get $live))
+    (block $done
+      (loop $next
+        (br_if $done (i32.eqz (local.get $c)))
+        (if (i32.and
+              (i32.eqz (i32.load offset=20 (local.get $c)))
+              (i32.lt_u (i32.load (local.get $c)) (i32.const 2)))
+          (then (local.set $leaked (i32.add (local.get $leaked) (i32.const 1)))))
+        (local.set $c (i32.load offset=16 (local.get $c)))
+        (br $next)))
+
+    (call $rt_puts (str "\nヒープ:\n確保: "))
+    (call $rt_write_int (i32.const 1) (i64.extend_i32_u (global.get $num_alloc)))
+    (call $rt_puts (str ", 解放: "))
+    (call $rt_write_int (i32.const 1) (i64.extend_i32_u (global.get $num_free)))
+    (call $rt_puts (str ", リーク: "))
+    (call $rt_write_int (i32.const 1) (i64.extend_i32_u (local.get $leaked)))
+    (call $rt_putc (i32.const 1) (i32.const 10))
+    (i32.ne (local.get $leaked) (i32.const 0)))
@@ -0,0 +1,1130 @@
+//! ## 線形型言語の評価器
+//!
+//! 型付けに成功した式を評価する。
+//! 修飾子付き値はすべてヒープ上に確保され、変数はヒープ上のアドレスを指す。
+//!
+//! ord、lin、aff型の値は高々一度しか利用されないため、
+//! 分解（ifの条件、split、関数適用、射影）した時点で解放される。
+//! また、free文によっても解放される。
+//! rel型とun型の値は何度でも利用されうるため解放しない。
+//! 借用した値への参照はun型の値で、参照を通じて分解しても参照先は解放しない。
+//! ref型の値はヒープ上のセルを書き換えて中身を交換し、freeした時点で中身も解放される。
+//! array型の値もlin型のため、setはヒープ上の配列をコピーせずに直接書き換える。
+//! リージョンを指定した値はリージョン内に確保され、letregion式の評価を終えた時点で、
+//! 解放されていないものが修飾子に関わらず一括して解放される。
+//!
+//! 外部定義の値は初期環境に束縛され、外部関数は全ての引数がそろった時点で呼び出される。
+//! 外部関数に渡した値は、外部関数が消費したものとして解放される。
+//!
+//! forkしたスレッドはOSのスレッドで評価するが、同時に実行するのは常に1つのスレッドのみで、
+//! 実行中のスレッドが受信待ちか終了した時点で、次に実行できるスレッドへ順番に実行権を渡す。
+//! そのため、スレッドの実行順は決定的となる。
+//! 実行できるスレッドがなくなった場合はデッドロックとして、
+//! チャネルの端点のセッション型に従わない通信はプロトコル違反として、評価エラーとなる。
+
+use crate::{
+    externs::{self, ExternVal, Externs},
+    parser, typing,
+};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    sync::{Condvar, Mutex, MutexGuard},
+    thread,
+};
+
+/// ヒープ上のアドレス
+pub type Addr = usize;
+
+/// 配列の長さの上限
+pub(crate) const MAX_ARRAY_LEN: usize = 1 << 24;
+
+/// 変数からヒープ上のアドレスへの対応
+pub(crate) type Env = BTreeMap<String, Binding>;
+
+/// 変数の束縛
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum Binding {
+    Val(Addr),  // 値
+    Bang(Addr), // let !式で束縛された!型の値。利用する度に中身を評価する
+}
+
+/// ヒープ上のデータ
+#[derive(Debug)]
+pub(crate) enum Data<'a> {
+    Bool(bool),                                    // 真偽値
+    Unit,                                          // ユニット
+    Handle(u64),                                   // ファイルハンドル
+    Int(i64),                                      // 整数
+    Str(String),                                   // 文字列
+    Pair(Addr, Addr),                              // ペア
+    With(Env, &'a parser::Expr, &'a parser::Expr), // 加法的ペア。射影するまで評価しない
+    Fun(Env, &'a str, &'a parser::Expr),           // 関数（クロージャ）
+    Bang(Env, &'a parser::Expr),                   // !型の値。取り出すまで評価しない
+    Extern(usize, Vec<Addr>),                      // 外部関数。外部定義の番号と、受け取った引数
+    Chan(usize),                                   // チャネルの端点。端点の番号を保持する
+    Ref(Addr),                                     // 参照。参照先のアドレスを保持する
+    Cell(Addr),                                    // 可変な参照。中身のアドレスを保持する
+    Array(Vec<Addr>),                              // 配列。要素のアドレスを保持する
+}
+
+/// ヒープ上のセル
+#[derive(Debug)]
+pub(crate) struct Cell<'a> {
+    pub(crate) qual: parser::Qual,
+    pub(crate) data: Data<'a>,
+}
+
+/// 修飾子qの値を、分解した時点で解放するかを判定
+fn consumes(q: parser::Qual) -> bool {
+    matches!(q, parser::Qual::Ord | parser::Qual::Lin | parser::Qual::Aff)
+}
+
+/// リージョン
+#[derive(Debug)]
+struct Region {
+    name: String,
+    cells: Vec<Addr>,     // リージョン内に確保したセルのアドレス
+    num_bulk_free: usize, // リージョンを抜けた時点で一括して解放したセルの数
+}
+
+/// ヒープ
+#[derive(Debug, Default)]
+pub(crate) struct Heap<'a> {
+    cells: Vec<Option<Cell<'a>>>,
+    num_free: usize,
+    regions: Vec<Region>, // 作成したリージョン。作成順に番号を振る
+}
+
+impl<'a> Heap<'a> {
+    /// セルを確保し、アドレスを返す
+    pub(crate) fn alloc(&mut self, qual: parser::Qual, data: Data<'a>) -> Addr {
+        self.cells.push(Some(Cell { qual, data }));
+        self.cells.len() - 1
+    }
+
+    /// 確保したセルの数。解放したセルも含む
+    pub(crate) fn num_cells(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// リージョンを作成し、リージョンの番号を返す
+    pub(crate) fn new_region(&mut self, name: &str) -> usize {
+        self.regions.push(Region {
+            name: name.to_string(),
+            cells: Vec::new(),
+            num_bulk_free: 0,
+        });
+        self.regions.len() - 1
+    }
+
+    /// リージョン内にセルを確保し、アドレスを返す
+    pub(crate) fn alloc_in(&mut self, region: usize, qual: parser::Qual, data: Data<'a>) -> Addr {
+        let addr = self.alloc(qual, data);
+        self.regions[region].cells.push(addr);
+        addr
+    }
+
+    /// リージョン内の解放されていないセルを、修飾子に関わらず一括して解放
+    pub(crate) fn free_region(&mut self, region: usize) -> Result<(), String> {
+        let cells = self.regions[region].cells.clone();
+        for addr in cells {
+            if self.cells[addr].is_some() {
+                self.free(addr)?;
+                self.regions[region].num_bulk_free += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// セルを取得
+    pub(crate) fn get(&self, addr: Addr) -> Result<&Cell<'a>, String> {
+        match self.cells.get(addr) {
+            Some(Some(c)) => Ok(c),
+            _ => Err(format!("解放済みのアドレス{}を参照した", addr)),
+        }
+    }
+
+    /// セルの内容を書き換える
+    pub(crate) fn set(&mut self, addr: Addr, data: Data<'a>) -> Result<(), String> {
+        match self.cells.get_mut(addr) {
+            Some(Some(c)) => {
+                c.data = data;
+                Ok(())
+            }
+            _ => Err(format!("解放済みのアドレス{}を書き換えた", addr)),
+        }
+    }
+
+    /// セルの内容を、書き換えられるよう取得
+    pub(crate) fn get_mut(&mut self, addr: Addr) -> Result<&mut Cell<'a>, String> {
+        match self.cells.get_mut(addr) {
+            Some(Some(c)) => Ok(c),
+            _ => Err(format!("解放済みのアドレス{}を書き換えた", addr)),
+        }
+    }
+
+    /// セルを解放
+    pub(crate) fn free(&mut self, addr: Addr) -> Result<(), String> {
+        match self.cells.get_mut(addr) {
+            Some(c @ Some(_)) => {
+                *c = None;
+                self.num_free += 1;
+                Ok(())
+            }
+            _ => Err(format!("解放済みのアドレス{}を解放した", addr)),
+        }
+    }
+
+    /// 分解したセルを、修飾子に応じて解放
+    pub(crate) fn consume(&mut self, addr: Addr) -> Result<(), String> {
+        if consumes(self.get(addr)?.qual) {
+            self.free(addr)?;
+        }
+        Ok(())
+    }
+
+    /// freeしたセルや外部関数に渡したセルを、ペアの要素やrefの中身も含めて修飾子に応じて解放
+    pub(crate) fn release(&mut self, addr: Addr) -> Result<(), String> {
+        match self.get(addr)?.data {
+            Data::Pair(a1, a2) => {
+                self.release(a1)?;
+                self.release(a2)?;
+            }
+            Data::Cell(a) => self.release(a)?,
+            _ => (),
+        }
+        self.consume(addr)
+    }
+
+    /// 参照をたどり、参照でない値のアドレスを返す
+    pub(crate) fn deref(&self, addr: Addr) -> Result<Addr, String> {
+        match self.get(addr)?.data {
+            Data::Ref(a) => self.deref(a),
+            _ => Ok(addr),
+        }
+    }
+
+    /// 外部関数に引数argsを与える
+    ///
+    /// 全ての引数がそろった場合は外部関数を呼び出し、そうでない場合は部分適用した外部関数を返す
+    pub(crate) fn apply_extern(
+        &mut self,
+        externs: &Externs,
+        id: usize,
+        args: Vec<Addr>,
+    ) -> Result<Addr, String> {
+        let def = &externs.defs[id];
+        if args.len() < externs::arity(&def.ty) {
+            let qual = externs::applied(&def.ty, args.len()).qual;
+            return Ok(self.alloc(qual, Data::Extern(id, args)));
+        }
+
+        let f = match &def.val {
+            ExternVal::Fun(f) => f.clone(),
+            ExternVal::Value(_) => return Err(format!("\"{}\"は関数でない", def.name)),
+        };
+
+        // 引数を読み出し、外部関数に渡したものとして解放
+        let mut vals = Vec::new();
+        for a in args {
+            vals.push(self.read(a, externs)?);
+            self.release(a)?;
+        }
+
+        let v = f(vals).map_err(|msg| format!("外部関数\"{}\"のエラー: {}", def.name, msg))?;
+        self.alloc_value(&v)
+    }
+
+    /// 値をヒープ上に確保
+    pub(crate) fn alloc_value(&mut self, v: &Value) -> Result<Addr, String> {
+        let (qual, data) = match v {
+            Value::Bool(q, b) => (*q, Data::Bool(*b)),
+            Value::Unit(q) => (*q, Data::Unit),
+            Value::Handle(q, n) => (*q, Data::Handle(*n)),
+            Value::Int(q, n) => (*q, Data::Int(*n)),
+            Value::Str(q, s) => (*q, Data::Str(s.clone())),
+            Value::Pair(q, v1, v2) => {
+                let a1 = self.alloc_value(v1)?;
+                let a2 = self.alloc_value(v2)?;
+                (*q, Data::Pair(a1, a2))
+            }
+            _ => return Err(format!("{}はヒープ上に確保できない", v)),
+        };
+        Ok(self.alloc(qual, data))
+    }
+
+    /// 外部定義を束縛した初期環境を作成
+    pub(crate) fn init_env(&mut self, externs: &Externs) -> Result<Env, String> {
+        let mut env = Env::new();
+        for (id, def) in externs.defs.iter().enumerate() {
+            let a = match &def.val {
+                ExternVal::Value(v) => self.alloc_value(v)?,
+                ExternVal::Fun(_) => self.alloc(def.ty.qual, Data::Extern(id, vec![])),
+            };
+            env.insert(def.name.clone(), Binding::Val(a));
+        }
+        Ok(env)
+    }
+
+    /// ヒープ上の値を読み出す
+    pub(crate) fn read(&self, addr: Addr, externs: &Externs) -> Result<Value, String> {
+        let c = self.get(addr)?;
+        Ok(match &c.data {
+            Data::Bool(b) => Value::Bool(c.qual, *b),
+            Data::Unit => Value::Unit(c.qual),
+            Data::Handle(n) => Value::Handle(c.qual, *n),
+            Data::Int(n) => Value::Int(c.qual, *n),
+            Data::Str(s) => Value::Str(c.qual, s.clone()),
+            Data::Pair(a1, a2) => Value::Pair(
+                c.qual,
+                Box::new(self.read(*a1, externs)?),
+                Box::new(self.read(*a2, externs)?),
+            ),
+            Data::With(..) => Value::With(c.qual),
+            Data::Fun(_, var, _) => Value::Fun(c.qual, var.to_string()),
+            Data::Bang(..) => Value::Bang(c.qual),
+            Data::Extern(id, _) => Value::Fun(c.qual, externs.defs[*id].name.clone()),
+            Data::Chan(e) => Value::Chan(c.qual, *e),
+            Data::Ref(a) => Value::Ref(c.qual, Box::new(self.read(*a, externs)?)),
+            Data::Cell(a) => Value::Cell(c.qual, Box::new(self.read(*a, externs)?)),
+            Data::Array(elems) => Value::Array(
+                c.qual,
+                elems
+                    .iter()
+                    .map(|a| self.read(*a, externs))
+                    .collect::<Result<_, _>>()?,
+            ),
+        })
+    }
+
+    /// addrから到達可能なセルのアドレスをreachableに追加
+    fn mark(&self, addr: Addr, reachable: &mut Vec<bool>) {
+        if reachable[addr] {
+            return;
+        }
+        reachable[addr] = true;
+
+        let env = match self.cells[addr].as_ref().map(|c| &c.data) {
+            Some(Data::Pair(a1, a2)) => {
+                self.mark(*a1, reachable);
+                self.mark(*a2, reachable);
+                return;
+            }
+            Some(Data::Ref(a)) | Some(Data::Cell(a)) => {
+                self.mark(*a, reachable);
+                return;
+            }
+            Some(Data::Extern(_, args)) | Some(Data::Array(args)) => {
+                for a in args {
+                    self.mark(*a, reachable);
+                }
+                return;
+            }
+            Some(Data::With(env, _, _)) | Some(Data::Fun(env, _, _)) | Some(Data::Bang(env, _)) => {
+                env
+            }
+            _ => return,
+        };
+
+        for b in env.values() {
+            match b {
+                Binding::Val(a) | Binding::Bang(a) => self.mark(*a, reachable),
+            }
+        }
+    }
+
+    /// ヒープの統計情報を計算
+    ///
+    /// rootは評価結果のアドレスで、rootから到達できないord型とlin型のセルはリークとなる
+    pub(crate) fn stats(&self, root: Addr) -> HeapStats {
+        let mut reachable = vec![false; self.cells.len()];
+        self.mark(root, &mut reachable);
+
+        let mut leaked = Vec::new();
+        for (addr, c) in self.cells.iter().enumerate() {
+            if let Some(c) = c {
+                if !reachable[addr] && matches!(c.qual, parser::Qual::Ord | parser::Qual::Lin) {
+                    leaked.push((addr, c.qual));
+                }
+            }
+        }
+
+        let regions = self
+            .regions
+            .iter()
+            .map(|r| {
+                let num_live = r.cells.iter().filter(|a| self.cells[**a].is_some()).count();
+                RegionStats {
+                    name: r.name.clone(),
+                    num_alloc: r.cells.len(),
+                    num_free: r.cells.len() - num_live - r.num_bulk_free,
+                    num_bulk_free: r.num_bulk_free,
+                }
+            })
+            .collect();
+
+        HeapStats {
+            num_alloc: self.cells.len(),
+            num_free: self.num_free,
+            leaked,
+            regions,
+        }
+    }
+}
+
+/// 評価後のヒープの統計情報
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HeapStats {
+    pub num_alloc: usize,                  // 確保したセルの数
+    pub num_free: usize,                   // 解放したセルの数
+    pub leaked: Vec<(Addr, parser::Qual)>, // 評価結果から到達できない、未解放のordとlin型のセル
+    pub regions: Vec<RegionStats>,         // 評価中に作成したリージョンごとの統計情報
+}
+
+/// リージョンごとの統計情報
+///
+/// 一括して解放したセルも、ヒープ全体の解放したセルの数に含まれる
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RegionStats {
+    pub name: String,         // リージョン名
+    pub num_alloc: usize,     // リージョン内に確保したセルの数
+    pub num_free: usize,      // freeや分解により、個別に解放したセルの数
+    pub num_bulk_free: usize, // リージョンを抜けた時点で一括して解放したセルの数
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "確保: {}, 解放: {}, リーク: {}",
+            self.num_alloc,
+            self.num_free,
+            self.leaked.len()
+        )?;
+        for r in self.regions.iter() {
+            write!(
+                f,
+                "\n  リージョン{}: 確保: {}, 個別に解放: {}, 一括解放: {}",
+                r.name, r.num_alloc, r.num_free, r.num_bulk_free
+            )?;
+        }
+        for (addr, q) in self.leaked.iter() {
+            write!(f, "\n  アドレス{}の{}型の値", addr, q)?;
+        }
+        Ok(())
+    }
+}
+
+/// 評価結果の値
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Value {
+    Bool(parser::Qual, bool),                   // 真偽値
+    Unit(parser::Qual),                         // ユニット
+    Handle(parser::Qual, u64),                  // ファイルハンドル。ハンドルの番号を保持する
+    Int(parser::Qual, i64),                     // 整数
+    Str(parser::Qual, String),                  // 文字列
+    Pair(parser::Qual, Box<Value>, Box<Value>), // ペア
+    With(parser::Qual),                         // 加法的ペア
+    Fun(parser::Qual, String),                  // 関数。引数名か外部関数名のみを保持する
+    Bang(parser::Qual),                         // !型の値
+    Chan(parser::Qual, usize),                  // チャネルの端点。端点の番号を保持する
+    Ref(parser::Qual, Box<Value>),              // 参照。参照先の値を保持する
+    Cell(parser::Qual, Box<Value>),             // 可変な参照。中身の値を保持する
+    Array(parser::Qual, Vec<Value>),            // 配列
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(q, b) => write!(f, "{} {}", q, b),
+            Value::Unit(q) => write!(f, "{} unit", q),
+            Value::Handle(q, n) => write!(f, "{} handle#{}", q, n),
+            Value::Int(q, n) => write!(f, "{} {}", q, n),
+            Value::Str(q, s) => write!(f, "{} {:?}", q, s),
+            Value::Pair(q, v1, v2) => write!(f, "{} <{}, {}>", q, v1, v2),
+            Value::With(q) => write!(f, "{} <| ... |>", q),
+            Value::Fun(q, var) => write!(f, "{} fn {} {{ ... }}", q, var),
+            Value::Bang(q) => write!(f, "{} promote ...", q),
+            Value::Chan(q, e) => write!(f, "{} chan#{}", q, e),
+            Value::Ref(q, v) => write!(f, "{} &{}", q, v),
+            Value::Cell(q, v) => write!(f, "{} ref {}", q, v),
+            Value::Array(q, vs) => {
+                write!(f, "{} [", q)?;
+                for (i, v) in vs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+/// チャネルの端点
+///
+/// 端点の番号をeとすると、相手の端点の番号はe ^ 1となる
+#[derive(Debug)]
+struct Endpoint {
+    session: Option<parser::Session>, // 残りのセッション型。閉じた場合はNone
+    queue: VecDeque<Addr>,            // 相手の端点から送信され、受信されていない値
+}
+
+/// スレッドの状態
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum ThreadState {
+    Ready,       // 実行できる
+    Recv(usize), // 端点からの受信待ち
+    Join,        // 他の全てのスレッドの終了待ち
+    Done,        // 終了した
+}
+
+/// 全てのスレッドで共有する状態
+#[derive(Debug, Default)]
+struct World<'a> {
+    heap: Heap<'a>,
+    endpoints: Vec<Endpoint>,
+    threads: Vec<ThreadState>, // スレッドの状態。スレッド0はメインスレッド
+    turn: usize,               // 実行権を持つスレッド
+    error: Option<String>,     // いずれかのスレッドで発生した評価エラー
+}
+
+impl<'a> World<'a> {
+    /// スレッドidを実行できるかを判定
+    fn runnable(&self, id: usize) -> bool {
+        match self.threads[id] {
+            ThreadState::Ready => true,
+            ThreadState::Recv(e) => !self.endpoints[e].queue.is_empty(),
+            ThreadState::Join => self
+                .threads
+                .iter()
+                .enumerate()
+                .all(|(i, s)| i == id || *s == ThreadState::Done),
+            ThreadState::Done => false,
+        }
+    }
+
+    /// 実行権を持つスレッドの次から順に、実行できるスレッドを探して実行権を渡す
+    ///
+    /// 実行できるスレッドがない場合はデッドロックとしてエラーを記録する
+    fn schedule(&mut self) {
+        let n = self.threads.len();
+        for k in 1..=n {
+            let id = (self.turn + k) % n;
+            if self.runnable(id) {
+                self.turn = id;
+                return;
+            }
+        }
+
+        let mut msg = "デッドロックした".to_string();
+        for (id, s) in self.threads.iter().enumerate() {
+            match s {
+                ThreadState::Recv(e) => msg.push_str(&format!(
+                    "\n  スレッド{}が端点#{}からの受信を待っている",
+                    id, e
+                )),
+                ThreadState::Join => msg.push_str(&format!(
+                    "\n  スレッド{}が他のスレッドの終了を待っている",
+                    id
+                )),
+                _ => (),
+            }
+        }
+        self.fail(msg);
+    }
+
+    /// 評価エラーを記録。既にエラーが記録されている場合は、先に発生したものを優先する
+    fn fail(&mut self, msg: String) {
+        if self.error.is_none() {
+            self.error = Some(msg);
+        }
+    }
+}
+
+/// スレッド間で共有する状態と、実行権の受け渡しを通知する条件変数
+#[derive(Debug, Default)]
+struct Shared<'a> {
+    world: Mutex<World<'a>>,
+    cv: Condvar,
+}
+
+type EResult = Result<Addr, String>;
+
+/// 評価器。スレッドごとに作成する
+struct Eval<'a, 's, 'e> {
+    shared: &'s Shared<'a>,
+    world: Option<MutexGuard<'s, World<'a>>>, // 実行中はロックを保持する
+    scope: &'s thread::Scope<'s, 'e>,
+    externs: &'a Externs,
+    id: usize,                      // スレッドの番号
+    regions: Vec<(&'a str, usize)>, // 評価中のletregion式のリージョン名と、リージョンの番号
+}
+
+impl<'a: 's, 's, 'e> Eval<'a, 's, 'e> {
+    /// 共有する状態を取得
+    fn world(&self) -> &World<'a> {
+        self.world.as_ref().unwrap()
+    }
+
+    /// 共有する状態を取得
+    fn world_mut(&mut self) -> &mut World<'a> {
+        self.world.as_mut().unwrap()
+    }
+
+    /// ヒープを取得
+    fn heap(&mut self) -> &mut Heap<'a> {
+        &mut self.world_mut().heap
+    }
+
+    /// 実行権を手放し、再び実行権を得るまで待つ
+    ///
+    /// 待っている間に評価エラーが記録された場合は、そのエラーを返す
+    fn wait_turn(&mut self) -> Result<(), String> {
+        self.shared.cv.notify_all();
+        let id = self.id;
+        let world = self.world.take().unwrap();
+        let world = self
+            .shared
+            .cv
+            .wait_while(world, |w| w.turn != id && w.error.is_none())
+            .unwrap();
+        self.world = Some(world);
+
+        match &self.world().error {
+            Some(msg) => Err(msg.clone()),
+            None => Ok(()),
+        }
+    }
+
+    /// スレッドの状態をstateとし、実行できるようになるまで他のスレッドを実行する
+    fn block(&mut self, state: ThreadState) -> Result<(), String> {
+        let id = self.id;
+        let w = self.world_mut();
+        w.threads[id] = state;
+        if !w.runnable(id) {
+            w.schedule();
+            self.wait_turn()?;
+        }
+        self.world_mut().threads[id] = ThreadState::Ready;
+        Ok(())
+    }
+
+    /// 式を評価し、評価結果のアドレスを返す
+    fn eval(&mut self, expr: &'a parser::Expr, env: &Env) -> EResult {
+        match expr {
+            parser::Expr::Let(e) => self.eval_let(e, env),
+            parser::Expr::LetBang(e) => self.eval_let_bang(e, env),
+            parser::Expr::If(e) => self.eval_if(e, env),
+            parser::Expr::Split(e) => self.eval_split(e, env),
+            parser::Expr::Free(e) => self.eval_free(e, env),
+            parser::Expr::App(e) => self.eval_app(e, env),
+            parser::Expr::Proj(e) => self.eval_proj(e, env),
+            parser::Expr::Promote(e) => self.eval_promote(e, env),
+            parser::Expr::New(e) => self.eval_new(e),
+            parser::Expr::Send(e) => self.eval_send(e, env),
+            parser::Expr::Recv(e) => self.eval_recv(e, env),
+            parser::Expr::Close(e) => self.eval_close(e, env),
+            parser::Expr::Fork(e) => self.eval_fork(e, env),
+            parser::Expr::Borrow(e) => self.eval_borrow(e, env),
+            parser::Expr::NewRef(e) => self.eval_new_ref(e, env),
+            parser::Expr::Swap(e) => self.eval_swap(e, env),
+            parser::Expr::LetRegion(e) => self.eval_letregion(e, env),
+            parser::Expr::Alloc(e) => self.eval_alloc(e, env),
+            parser::Expr::Get(e) => self.eval_get(e, env),
+            parser::Expr::Set(e) => self.eval_set(e, env),
+            parser::Expr::Var(e) => self.eval_var(e, env),
+            parser::Expr::QVal(e) => self.eval_qval(e, env),
+        }
+    }
+
+    /// 変数の評価
+    fn eval_var(&mut self, var: &str, env: &Env) -> EResult {
+        match env.get(var) {
+            Some(Binding::Val(addr)) => Ok(*addr),
+            Some(Binding::Bang(addr)) => self.derelict(*addr),
+            None => Err(format!("\"{}\"という変数は定義されていない", var)),
+        }
+    }
+
+    /// 借用の評価
+    fn eval_borrow(&mut self, var: &str, env: &Env) -> EResult {
+        let a = self.eval_var(var, env)?;
+
+        // 参照の借用は同じ参照とする
+        if let Data::Ref(_) = self.heap().get(a)?.data {
+            return Ok(a);
+        }
+        Ok(self.heap().alloc(parser::Qual::Un, Data::Ref(a)))
+    }
+
+    /// !型の値の中身を評価
+    fn derelict(&mut self, addr: Addr) -> EResult {
+        match &self.heap().get(addr)?.data {
+            Data::Bang(env, e) => {
+                let (env, e) = (env.clone(), *e);
+                self.eval(e, &env)
+            }
+            _ => Err("!型でない値を取り出そうとした".to_string()),
+        }
+    }
+
+    /// 修飾子付き値の評価
+    fn eval_qval(&mut self, expr: &'a parser::QValExpr, env: &Env) -> EResult {
+        let data = match &expr.val {
+            parser::ValExpr::Bool(b) => Data::Bool(*b),
+            parser::ValExpr::Int(n) => Data::Int(*n),
+            parser::ValExpr::Str(s) => Data::Str(s.clone()),
+            parser::ValExpr::Pair(e1, e2) => {
+                let a1 = self.eval(e1, env)?;
+                let a2 = self.eval(e2, env)?;
+                Data::Pair(a1, a2)
+            }
+            parser::ValExpr::With(e1, e2) => Data::With(env.clone(), e1, e2),
+            parser::ValExpr::Fun(e) => Data::Fun(env.clone(), &e.var, &e.expr),
+        };
+
+        // リージョンを指定した場合は、同じ名前のリージョンのうち最も内側のものに確保
+        match &expr.region {
+            Some(r) => match self.regions.iter().rev().find(|(r2, _)| r2 == r) {
+                Some((_, id)) => {
+                    let id = *id;
+                    Ok(self.heap().alloc_in(id, expr.qual, data))
+                }
+                None => Err(format!("リージョン\"{}\"は作成されていない", r)),
+            },
+            None => Ok(self.heap().alloc(expr.qual, data)),
+        }
+    }
+
+    /// letregion式の評価
+    fn eval_letregion(&mut self, expr: &'a parser::LetRegionExpr, env: &Env) -> EResult {
+        let id = self.heap().new_region(&expr.region);
+        self.regions.push((&expr.region, id));
+        let a = self.eval(&expr.expr, env);
+        self.regions.pop();
+
+        // 評価結果はリージョン外の値のため、リージョン内の値を一括して解放
+        let a = a?;
+        self.heap().free_region(id)?;
+        Ok(a)
+    }
+
+    /// let式の評価
+    fn eval_let(&mut self, expr: &'a parser::LetExpr, env: &Env) -> EResult {
+        let a = self.eval(&expr.expr1, env)?;
+        let mut env = env.clone();
+        env.insert(expr.var.clone(), Binding::Val(a));
+        self.eval(&expr.expr2, &env)
+    }
+
+    /// let !式の評価
+    fn eval_let_bang(&mut self, expr: &'a parser::LetBangExpr, env: &Env) -> EResult {
+        let a = self.eval(&expr.expr1, env)?;
+        let mut env = env.clone();
+        env.insert(expr.var.clone(), Binding::Bang(a));
+        self.eval(&expr.expr2, &env)
+    }
+
+    /// if式の評価
+    fn eval_if(&mut self, expr: &'a parser::IfExpr, env: &Env) -> EResult {
+        let a = self.eval(&expr.cond_expr, env)?;
+        let target = self.heap().deref(a)?;
+        let b = match self.heap().get(target)?.data {
+            Data::Bool(b) => b,
+            _ => return Err("ifの条件式がboolでない".to_string()),
+        };
+        self.heap().consume(a)?;
+
+        if b {
+            self.eval(&expr.then_expr, env)
+        } else {
+            self.eval(&expr.else_expr, env)
+        }
+    }
+
+    /// split式の評価
+    fn eval_split(&mut self, expr: &'a parser::SplitExpr, env: &Env) -> EResult {
+        let a = self.eval(&expr.expr, env)?;
+        let target = self.heap().deref(a)?;
+        let (mut a1, mut a2) = match self.heap().get(target)?.data {
+            Data::Pair(a1, a2) => (a1, a2),
+            _ => return Err("splitの引数がペアでない".to_string()),
+        };
+        self.heap().consume(a)?;
+
+        // ペアへの参照を分解した場合、各要素への参照を束縛
+        if target != a {
+            a1 = self.heap().alloc(parser::Qual::Un, Data::Ref(a1));
+            a2 = self.heap().alloc(parser::Qual::Un, Data::Ref(a2));
+        }
+
+        let mut env = env.clone();
+        env.insert(expr.left.clone(), Binding::Val(a1));
+        env.insert(expr.right.clone(), Binding::Val(a2));
+        self.eval(&expr.body, &env)
+    }
+
+    /// free文の評価
+    fn eval_free(&mut self, expr: &'a parser::FreeExpr, env: &Env) -> EResult {
+        match env.get(&expr.var) {
+            // ペアの要素やrefの中身も含めて解放
+            Some(Binding::Val(a)) => self.heap().release(*a)?,
+            _ => return Err(format!("変数\"{}\"をfreeできない", expr.var)),
+        }
+        self.eval(&expr.expr, env)
+    }
+
+    /// new式（可変な参照の作成）の評価
+    fn eval_new_ref(&mut self, expr: &'a parser::NewRefExpr, env: &Env) -> EResult {
+        let a = self.eval(&expr.expr, env)?;
+        let q = parser::Qual::Lin.join(self.heap().get(a)?.qual);
+        Ok(self.heap().alloc(q, Data::Cell(a)))
+    }
+
+    /// swap式の評価
+    fn eval_swap(&mut self, expr: &'a parser::SwapExpr, env: &Env) -> EResult {
+        let c = self.eval(&expr.cell, env)?;
+        let v = self.eval(&expr.expr, env)?;
+        let (q, old) = match self.heap().get(c)? {
+            Cell {
+                qual,
+                data: Data::Cell(old),
+            } => (*qual, *old),
+            _ => return Err("refでない値をswapした".to_string()),
+        };
+
+        // セルを書き換えて中身を交換し、同じ参照と元の中身のペアを返す
+        self.heap().set(c, Data::Cell(v))?;
+        let qo = self.heap().get(old)?.qual;
+        Ok(self.heap().alloc(q.join(qo), Data::Pair(c, old)))
+    }
+
+    /// 配列の要素の位置を表す式を評価し、範囲内かをチェックした位置を返す
+    ///
+    /// lenがNoneの場合は配列の長さを表すものとし、負でないかのみをチェック
+    fn eval_index(
+        &mut self,
+        expr: &'a parser::Expr,
+        env: &Env,
+        len: Option<usize>,
+    ) -> Result<usize, String> {
+        let a = self.eval(expr, env)?;
+        let n = match self.heap().get(a)?.data {
+            Data::Int(n) => n,
+            _ => return Err("配列の位置か長さがintでない".to_string()),
+        };
+        self.heap().consume(a)?;
+
+        match usize::try_from(n) {
+            Ok(i) if len.is_none_or(|len| i < len) => Ok(i),
+            _ => Err(format!("配列の範囲外の位置{}を指定した", n)),
+        }
+    }
+
+    /// 配列のアドレスから、要素のアドレスを取得
+    fn elems(&mut self, addr: Addr) -> Result<&mut Vec<Addr>, String> {
+        match &mut self.heap().get_mut(addr)?.data {
+            Data::Array(elems) => Ok(elems),
+            _ => Err("配列でない値を読み書きした".to_string()),
+        }
+    }
+
+    /// alloc式の評価
+    fn eval_alloc(&mut self, expr: &'a parser::AllocExpr, env: &Env) -> EResult {
+        let n = self.eval_index(&expr.len, env, None)?;
+
+        // 要素はun型で解放されないため、全ての要素で同じ値を共有する
+        let v = self.eval(&expr.expr, env)?;
+        let elems = array_elems(v, n)?;
+        Ok(self.heap().alloc(parser::Qual::Lin, Data::Array(elems)))
+    }
+
+    /// get式の評価
+    fn eval_get(&mut self, expr: &'a parser::GetExpr, env: &Env) -> EResult {
+        let a = self.eval(&expr.array, env)?;
+        let len = self.elems(a)?.len();
+        let i = self.eval_index(&expr.index, env, Some(len))?;
+
+        // 配列は消費せずに、同じ配列と要素のペアを返す
+        let v = self.elems(a)?[i];
+        let q = self.heap().get(a)?.qual;
+        Ok(self.heap().alloc(q, Data::Pair(a, v)))
+    }
+
+    /// set式の評価
+    fn eval_set(&mut self, expr: &'a parser::SetExpr, env: &Env) -> EResult {
+        let a = self.eval(&expr.array, env)?;
+        let len = self.elems(a)?.len();
+        let i = self.eval_index(&expr.index, env, Some(len))?;
+        let v = self.eval(&expr.expr, env)?;
+
+        // 配列はlin型で他から参照されないため、コピーせずに書き換える
+        self.elems(a)?[i] = v;
+        Ok(a)
+    }
+
+    /// 関数適用の評価
+    fn eval_app(&mut self, expr: &'a parser::AppExpr, env: &Env) -> EResult {
+        let f = self.eval(&expr.expr1, env)?;
+        let arg = self.eval(&expr.expr2, env)?;
+
+        let (mut fenv, var, body) = match &self.heap().get(f)?.data {
+            Data::Fun(fenv, var, body) => (fenv.clone(), *var, *body),
+            Data::Extern(id, args) => {
+                let (id, mut args) = (*id, args.clone());
+                self.heap().consume(f)?;
+                args.push(arg);
+                let externs = self.externs;
+                return self.heap().apply_extern(externs, id, args);
+            }
+            _ => return Err("関数でない値を関数適用した".to_string()),
+        };
+        self.heap().consume(f)?;
+
+        fenv.insert(var.to_string(), Binding::Val(arg));
+        self.eval(body, &fenv)
+    }
+
+    /// fstとsnd式の評価
+    fn eval_proj(&mut self, expr: &'a parser::ProjExpr, env: &Env) -> EResult {
+        let a = self.eval(&expr.expr, env)?;
+        let (wenv, e) = match &self.heap().get(a)?.data {
+            Data::With(wenv, e1, e2) => match expr.proj {
+                parser::Proj::Fst => (wenv.clone(), *e1),
+                parser::Proj::Snd => (wenv.clone(), *e2),
+            },
+            _ => return Err("fstかsndの引数が加法的ペアでない".to_string()),
+        };
+        self.heap().consume(a)?;
+
+        self.eval(e, &wenv)
+    }
+
+    /// promote式の評価
+    fn eval_promote(&mut self, expr: &'a parser::PromoteExpr, env: &Env) -> EResult {
+        Ok(self
+            .heap()
+            .alloc(parser::Qual::Un, Data::Bang(env.clone(), &expr.expr)))
+    }
+
+    /// new式の評価
+    fn eval_new(&mut self, expr: &'a parser::NewExpr) -> EResult {
+        let w = self.world_mut();
+        let e = w.endpoints.len();
+        for s in [expr.session.clone(), typing::dual(&expr.session)] {
+            w.endpoints.push(Endpoint {
+                session: Some(s),
+                queue: VecDeque::new(),
+            });
+        }
+
+        let a1 = w.heap.alloc(parser::Qual::Lin, Data::Chan(e));
+        let a2 = w.heap.alloc(parser::Qual::Lin, Data::Chan(e ^ 1));
+        Ok(w.heap.alloc(parser::Qual::Lin, Data::Pair(a1, a2)))
+    }
+
+    /// チャネルの端点のアドレスから、端点の番号と修飾子を取得
+    fn endpoint(&mut self, addr: Addr, op: &str) -> Result<(usize, parser::Qual), String> {
+        let c = self.heap().get(addr)?;
+        match c.data {
+            Data::Chan(e) => Ok((e, c.qual)),
+            _ => Err(format!("チャネルでない値に{}した", op)),
+        }
+    }
+
+    /// 端点eの残りのセッション型を取得
+    fn session(&self, e: usize) -> Result<&parser::Session, String> {
+        match &self.world().endpoints[e].session {
+            Some(s) => Ok(s),
+            None => Err(format!("プロトコル違反: 閉じた端点#{}を利用した", e)),
+        }
+    }
+
+    /// send式の評価
+    fn eval_send(&mut self, expr: &'a parser::SendExpr, env: &Env) -> EResult {
+        let a = self.eval(&expr.chan, env)?;
+        let v = self.eval(&expr.expr, env)?;
+        let (e, q) = self.endpoint(a, "送信")?;
+
+        // セッション型を進め、相手の端点に値を送る
+        let s = match self.session(e)? {
+            parser::Session::Send(_, s) => (**s).clone(),
+            s => {
+                return Err(format!(
+                    "プロトコル違反: 端点#{}のセッション型{}では送信できない",
+                    e, s
+                ))
+            }
+        };
+        if self.world().endpoints[e ^ 1].session.is_none() {
+            return Err(format!(
+                "プロトコル違反: 端点#{}の相手の端点は閉じている",
+                e
+            ));
+        }
+        let w = self.world_mut();
+        w.endpoints[e].session = Some(s);
+        w.endpoints[e ^ 1].queue.push_back(v);
+
+        // 送信前の端点を消費し、残りのセッションの端点を返す
+        self.heap().consume(a)?;
+        Ok(self.heap().alloc(q, Data::Chan(e)))
+    }
+
+    /// recv式の評価
+    fn eval_recv(&mut self, expr: &'a parser::RecvExpr, env: &Env) -> EResult {
+        let a = self.eval(&expr.chan, env)?;
+        let (e, q) = self.endpoint(a, "受信")?;
+
+        let s = match self.session(e)? {
+            parser::Session::Recv(_, s) => (**s).clone(),
+            s => {
+                return Err(format!(
+                    "プロトコル違反: 端点#{}のセッション型{}では受信できない",
+                    e, s
+                ))
+            }
+        };
+
+        // 値が届くまで他のスレッドを実行
+        self.block(ThreadState::Recv(e))?;
+        let w = self.world_mut();
+        w.endpoints[e].session = Some(s);
+        let v = w.endpoints[e].queue.pop_front().unwrap();
+
+        // 受信前の端点を消費し、受信した値と残りのセッションの端点のペアを返す
+        self.heap().consume(a)?;
+        let c = self.heap().alloc(q, Data::Chan(e));
+        let qv = self.heap().get(v)?.qual;
+        Ok(self.heap().alloc(qv.join(q), Data::Pair(v, c)))
+    }
+
+    /// close式の評価
+    fn eval_close(&mut self, expr: &'a parser::CloseExpr, env: &Env) -> EResult {
+        let a = self.eval(&expr.chan, env)?;
+        let (e, _) = self.endpoint(a, "close")?;
+
+        match self.session(e)? {
+            parser::Session::End => (),
+            s => {
+                return Err(format!(
+                    "プロトコル違反: 端点#{}のセッション型{}では閉じられない",
+                    e, s
+                ))
+            }
+        }
+        let w = self.world_mut();
+        if !w.endpoints[e].queue.is_empty() {
+            return Err(format!(
+                "プロトコル違反: 端点#{}に受信していない値が残っている",
+                e
+            ));
+        }
+        w.endpoints[e].session = None;
+
+        self.heap().consume(a)?;
+        Ok(self.heap().alloc(parser::Qual::Un, Data::Unit))
+    }
+
+    /// fork式の評価
+    fn eval_fork(&mut self, expr: &'a parser::ForkExpr, env: &Env) -> EResult {
+        // スレッドを登録し、実行権を得るまで待つOSのスレッドを作成
+        let w = self.world_mut();
+        let id = w.threads.len();
+        w.threads.push(ThreadState::Ready);
+
+        let (shared, scope, externs) = (self.shared, self.scope, self.externs);
+        let (e, fenv) = (&*expr.expr1, env.clone());
+        self.scope
+            .spawn(move || Eval::run_thread(shared, scope, externs, id, e, fenv));
+
+        self.eval(&expr.expr2, env)
+    }
+
+    /// forkしたスレッドで式を評価
+    ///
+    /// 評価結果はun unit型のため捨てる。評価エラーはメインスレッドに伝える
+    fn run_thread(
+        shared: &'s Shared<'a>,
+        scope: &'s thread::Scope<'s, 'e>,
+        externs: &'a Externs,
+        id: usize,
+        expr: &'a parser::Expr,
+        env: Env,
+    ) {
+        let mut ev = Eval {
+            shared,
+            world: Some(shared.world.lock().unwrap()),
+            scope,
+            externs,
+            id,
+            regions: Vec::new(),
+        };
+
+        let res = ev.wait_turn().and_then(|_| ev.eval(expr, &env));
+        let w = ev.world_mut();
+        w.threads[id] = ThreadState::Done;
+        match res {
+            Ok(_) => w.schedule(),
+            Err(msg) => w.fail(format!("スレッド{}: {}", id, msg)),
+        }
+        shared.cv.notify_all();
+    }
+
+    /// メインスレッドで式を評価し、他の全てのスレッドの終了を待つ
+    fn run_main(&mut self, expr: &'a parser::Expr) -> Result<(Value, HeapStats), String> {
+        let externs = self.externs;
+        let env = self.heap().init_env(externs)?;
+        let addr = self.eval(expr, &env)?;
+        self.block(ThreadState::Join)?;
+        let heap = &self.world().heap;
+        Ok((heap.read(addr, self.externs)?, heap.stats(addr)))
+    }
+}
+
+/// 全ての要素がvである、長さnの配列の要素を確保
+///
+/// 長さが[MAX_ARRAY_LEN]を超えるか、メモリを確保できない場合は評価エラー
+pub(crate) fn array_elems(v: Addr, n: usize) -> Result<Vec<Addr>, String> {
+    if n > MAX_ARRAY_LEN {
+        return Err(format!(
+            "配列の長さ{}が上限の{}を超えている",
+            n, MAX_ARRAY_LEN
+        ));
+    }
+    let mut elems = Vec::new();
+    elems
+        .try_reserve_exact(n)
+        .map_err(|_| format!("長さ{}の配列を確保できない", n))?;
+    elems.resize(n, v);
+    Ok(elems)
+}
+
+/// 外部定義を束縛した初期環境で式を評価し、評価結果の値と、評価後のヒープの統計情報を返す
+pub fn eval(expr: &parser::Expr, externs: &Externs) -> Result<(Value, HeapStats), String> {
+    let shared = Shared::default();
+    thread::scope(|scope| {
+        let mut ev = Eval {
+            shared: &shared,
+            world: Some(shared.world.lock().unwrap()),
+            scope,
+            externs,
+            id: 0,
+            regions: Vec::new(),
+        };
+        ev.world_mut().threads.push(ThreadState::Ready);
+
+        let res = ev.run_main(expr);
+
+        // 評価エラーの場合は、実行権を待っている他のスレッドを終了させる
+        if let Err(msg) = &res {
+            ev.world_mut().fail(msg.clone());
+            shared.cv.notify_all();
+        }
+        res
+    })
+}
@@ -0,0 +1,134 @@
+//! ## 外部定義
+//!
+//! 埋め込み先のアプリケーションが、線形型言語のプログラムへ変数を提供するための定義。
+//! 変数の型と、値またはRustのクロージャを登録すると、
+//! 型付けでは事前に型を与えた型環境として、評価では初期環境として利用される。
+//!
+//! 例えば、`not : un (lin bool -> lin bool)`は以下のように登録する。
+//!
+//! ```text
+//! let externs = Externs::new().func("not", ty, |args| match &args[0] {
+//!     Value::Bool(_, b) => Ok(Value::Bool(Qual::Lin, !b)),
+//!     _ => Err("notの引数がboolでない".to_string()),
+//! });
+//! ```
+
+use crate::{eval::Value, parser, typing::TypeEnv};
+use std::{fmt, sync::Arc};
+
+/// 外部関数。全ての引数の値を受け取り、戻り値を返す
+pub type ExternFn = Arc<dyn Fn(Vec<Value>) -> Result<Value, String> + Send + Sync>;
+
+/// 外部定義の値
+#[derive(Clone)]
+pub(crate) enum ExternVal {
+    Value(Value),  // 値
+    Fun(ExternFn), // 外部関数
+}
+
+/// 外部定義
+#[derive(Clone)]
+pub(crate) struct ExternDef {
+    pub(crate) name: String,
+    pub(crate) ty: parser::TypeExpr,
+    pub(crate) val: ExternVal,
+}
+
+/// 外部定義の一覧
+#[derive(Clone, Default)]
+pub struct Externs {
+    pub(crate) defs: Vec<ExternDef>,
+}
+
+impl fmt::Debug for Externs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.defs.iter().map(|d| (&d.name, d.ty.to_string())))
+            .finish()
+    }
+}
+
+impl Externs {
+    pub fn new() -> Externs {
+        Externs::default()
+    }
+
+    /// 外部定義を追加。同じ名前の外部定義が既にある場合は置き換える
+    fn push(&mut self, def: ExternDef) {
+        self.defs.retain(|d| d.name != def.name);
+        self.defs.push(def);
+    }
+
+    /// 値を登録
+    ///
+    /// 同じ名前の外部定義が既にある場合は置き換える
+    pub fn value(mut self, name: impl Into<String>, ty: parser::TypeExpr, val: Value) -> Externs {
+        self.push(ExternDef {
+            name: name.into(),
+            ty,
+            val: ExternVal::Value(val),
+        });
+        self
+    }
+
+    /// 外部関数を登録
+    ///
+    /// tyは関数型でなければならない。
+    /// 関数型の戻り値が関数型である限り引数を1つずつ受け取り、
+    /// 全ての引数がそろった時点でfを呼び出す。
+    /// 例えば`un (lin bool -> lin (lin bool -> lin bool))`の場合、fは2つの引数を受け取る。
+    /// 同じ名前の外部定義が既にある場合は置き換える。
+    ///
+    /// # Panics
+    ///
+    /// tyが関数型でない場合panicする
+    pub fn func<F>(mut self, name: impl Into<String>, ty: parser::TypeExpr, f: F) -> Externs
+    where
+        F: Fn(Vec<Value>) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        let name = name.into();
+        assert!(arity(&ty) > 0, "外部関数\"{}\"の型が関数型でない", name);
+        self.push(ExternDef {
+            name,
+            ty,
+            val: ExternVal::Fun(Arc::new(f)),
+        });
+        self
+    }
+
+    /// 別の外部定義の一覧を追加
+    ///
+    /// 同じ名前の外部定義がある場合は、otherのもので置き換える
+    pub fn extend(mut self, other: Externs) -> Externs {
+        for def in other.defs {
+            self.push(def);
+        }
+        self
+    }
+
+    /// 外部定義の型を事前に与えた型環境を作成
+    pub fn type_env(&self) -> TypeEnv {
+        self.defs
+            .iter()
+            .fold(TypeEnv::builder(), |b, d| {
+                b.bind(d.name.clone(), d.ty.clone())
+            })
+            .build()
+    }
+}
+
+/// 型tyの外部関数が受け取る引数の数
+pub(crate) fn arity(ty: &parser::TypeExpr) -> usize {
+    match &ty.prim {
+        parser::PrimType::Arrow(_, t) => 1 + arity(t),
+        _ => 0,
+    }
+}
+
+/// 型tyの外部関数にn個の引数を与えた後の型
+pub(crate) fn applied(ty: &parser::TypeExpr, n: usize) -> &parser::TypeExpr {
+    match (&ty.prim, n) {
+        (parser::PrimType::Arrow(_, t), n) if n > 0 => applied(t, n - 1),
+        _ => ty,
+    }
+}
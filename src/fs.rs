@@ -0,0 +1,98 @@
+//! ## ファイルハンドル
+//!
+//! 実際のファイルシステムを操作する外部関数。
+//! ファイルハンドルはlin型の値として扱われるため、
+//! 閉じ忘れや、閉じた後のハンドルの利用は型付けエラーとなる。
+//!
+//! ```text
+//! open   : un (un str -> lin handle)
+//! write  : un (lin handle -> lin (lin str -> lin handle))
+//! fclose : un (lin handle -> un unit)
+//! ```
+//!
+//! writeは部分適用した時点でハンドルをキャプチャするため、部分適用した関数もlin型となる。
+//! closeはチャネルを閉じるキーワードのため、ファイルを閉じる関数はfcloseとする。
+
+use crate::{eval::Value, externs::Externs, parser, parser::Qual};
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+/// 開いているファイルの一覧
+#[derive(Debug, Default)]
+struct Files {
+    next: u64,
+    files: BTreeMap<u64, File>,
+}
+
+/// 型をパース
+fn ty(src: &str) -> parser::TypeExpr {
+    parser::parse_type(src).unwrap().1
+}
+
+/// ファイルハンドルを操作する外部関数を登録した外部定義を返す
+///
+/// openはファイルを書き込み用に開き、既存のファイルの内容は切り詰める。
+/// writeは文字列を書き込み、lin型の文字列の場合は消費する。
+pub fn externs() -> Externs {
+    let files = Arc::new(Mutex::new(Files::default()));
+
+    let f = files.clone();
+    let open = move |args: Vec<Value>| match &args[0] {
+        Value::Str(_, path) => {
+            let file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)
+                .map_err(|e| format!("{}を開けない: {}", path, e))?;
+
+            let mut f = f.lock().unwrap();
+            let n = f.next;
+            f.next += 1;
+            f.files.insert(n, file);
+            Ok(Value::Handle(Qual::Lin, n))
+        }
+        _ => Err("openの引数がstrでない".to_string()),
+    };
+
+    let f = files.clone();
+    let write = move |args: Vec<Value>| match (&args[0], &args[1]) {
+        (Value::Handle(_, n), Value::Str(_, s)) => {
+            let mut f = f.lock().unwrap();
+            let file = f
+                .files
+                .get_mut(n)
+                .ok_or_else(|| format!("handle#{}は閉じられている", n))?;
+            write!(file, "{}", s).map_err(|e| format!("書き込みに失敗した: {}", e))?;
+            Ok(Value::Handle(Qual::Lin, *n))
+        }
+        _ => Err("writeの引数がhandleとstrでない".to_string()),
+    };
+
+    let f = files;
+    let fclose = move |args: Vec<Value>| match &args[0] {
+        Value::Handle(_, n) => {
+            // ファイルを閉じる
+            f.lock()
+                .unwrap()
+                .files
+                .remove(n)
+                .ok_or_else(|| format!("handle#{}は既に閉じられている", n))?;
+            Ok(Value::Unit(Qual::Un))
+        }
+        _ => Err("fcloseの引数がhandleでない".to_string()),
+    };
+
+    Externs::new()
+        .func("open", ty("un (un str -> lin handle)"), open)
+        .func(
+            "write",
+            ty("un (lin handle -> lin (lin str -> lin handle))"),
+            write,
+        )
+        .func("fclose", ty("un (lin handle -> un unit)"), fclose)
+}
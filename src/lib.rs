@@ -0,0 +1,242 @@
+//! ## 線形型言語
+//!
+//! λ計算に線形型システムを適用した独自の線形型言語の、
+//! パーサ、型検査器、評価器をまとめたライブラリ。
+//!
+//! 構文はparserモジュールのドキュメントを参照（`cargo doc --document-private-items`で表示できる）。
+//! [parse]で構文木を作成し、[check]で型付けを行い、[eval]で評価する。
+//! 型付けの前には定義されていない変数をまとめて報告し、[resolve()]は束縛変数をde Bruijnインデックスで表した式を返す。
+//! [elaborate]は型付けで計算した型を全ての式に付けた、型付き構文木を返す。
+//! [optimize()]は型付き構文木を、型を保存したまま最適化する。
+//! 埋め込み先のアプリケーションが変数を事前に与える場合は、
+//! [TypeEnv::builder]で作成した型環境を[check_with]に渡す。
+//! 変数の値も与える場合は[Externs]に型と値（またはRustのクロージャ）を登録し、
+//! [Externs::type_env]で作成した型環境を[check_with]に、[Externs]を[eval_with]に渡す。
+//! [codegen_c()]、[codegen_wat()]、[codegen_rust()]は、評価する代わりに
+//! C言語、WebAssembly、Rustのプログラムを生成する。
+//! [compile]はスタックマシンのバイトコードにコンパイルし、[run]で実行する。
+//! [eval_cek]はCEK機械により1ステップずつ評価し、各状態を表示できる。
+//! [reduce()]は式を書き換えて代入により簡約し、簡約列を表示できる。
+//! 束縛変数の名前を考慮して式を扱うには、[free_vars]、[subst()]、[alpha_eq]を用いる。
+
+mod bytecode;
+mod cek;
+mod codegen_c;
+mod codegen_rust;
+mod codegen_wat;
+mod eval;
+mod externs;
+mod fs;
+mod helper;
+mod optimize;
+mod parser;
+mod reduce;
+mod resolve;
+mod string;
+mod subst;
+mod typed;
+mod typing;
+mod vm;
+
+use nom::error::convert_error;
+use std::fmt;
+
+pub use bytecode::{Func, Op, Program};
+pub use cek::Machine;
+pub use eval::{HeapStats, RegionStats, Value};
+pub use externs::{ExternFn, Externs};
+pub use fs::externs as fs_externs;
+pub use parser::{
+    AllocExpr, AppExpr, CloseExpr, Expr, FnExpr, ForkExpr, FreeExpr, GetExpr, IfExpr, LetBangExpr,
+    LetExpr, LetRegionExpr, NewExpr, NewRefExpr, PrimType, Proj, ProjExpr, PromoteExpr, QValExpr,
+    Qual, RecvExpr, SendExpr, Session, SetExpr, SplitExpr, SwapExpr, TypeExpr, ValExpr,
+};
+pub use resolve::{Term, Val, Var};
+pub use string::externs as string_externs;
+pub use subst::{alpha_eq, free_vars, subst};
+pub use typed::{Node, TypedExpr, TypedVal, Use};
+pub use typing::{TypeEnv, TypeEnvBuilder};
+
+/// エラー
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    Parse(String),   // パースエラー
+    Resolve(String), // 名前解決エラー
+    Typing(String),  // 型付けエラー
+    Eval(String),    // 評価エラー
+    Codegen(String), // コード生成エラー
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse(msg) => write!(f, "パースエラー:\n{}", msg),
+            Error::Resolve(msg) => write!(f, "名前解決エラー: {}", msg),
+            Error::Typing(msg) => write!(f, "型付けエラー: {}", msg),
+            Error::Eval(msg) => write!(f, "評価エラー: {}", msg),
+            Error::Codegen(msg) => write!(f, "コード生成エラー: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// ソースコードをパースし、構文木を返す
+pub fn parse(src: &str) -> Result<Expr, Error> {
+    match parser::parse_expr(src) {
+        Ok((rest, expr)) => {
+            if rest.trim().is_empty() {
+                Ok(expr)
+            } else {
+                Err(Error::Parse(format!(
+                    "式の後に余分な入力がある:\n{}",
+                    rest.trim()
+                )))
+            }
+        }
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+            Err(Error::Parse(convert_error(src, e)))
+        }
+        Err(nom::Err::Incomplete(_)) => Err(Error::Parse("入力が途中で終わっている".to_string())),
+    }
+}
+
+/// 型をパースする
+///
+/// [Externs]に登録する型や、[TypeEnv::builder]で与える型の作成に利用する
+pub fn parse_type(src: &str) -> Result<TypeExpr, Error> {
+    match parser::parse_type(src.trim()) {
+        Ok(("", ty)) => Ok(ty),
+        Ok((rest, _)) => Err(Error::Parse(format!("型の後に余分な入力がある:\n{}", rest))),
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+            Err(Error::Parse(convert_error(src.trim(), e)))
+        }
+        Err(nom::Err::Incomplete(_)) => Err(Error::Parse("入力が途中で終わっている".to_string())),
+    }
+}
+
+/// 空の型環境で式を型付けし、型を返す
+pub fn check(expr: &Expr) -> Result<TypeExpr, Error> {
+    check_with(expr, &TypeEnv::new())
+}
+
+/// 与えられた型環境で式を型付けし、型を返す
+///
+/// 型付けの前に変数を検査し、定義されていない変数があれば名前解決エラーとなる。
+/// 型環境に事前に与えたlin型の変数なども、消費されていなければ型付けエラーとなる。
+/// 型付けは型環境の複製に対して行い、与えた型環境は書き換えないため、
+/// 同じ型環境で何度でも型付けできる
+pub fn check_with(expr: &Expr, env: &TypeEnv) -> Result<TypeExpr, Error> {
+    elaborate(expr, env).map(|t| t.ty)
+}
+
+/// 与えられた型環境で式を型付けし、全ての式に型を付けた型付き構文木を返す
+///
+/// 変数の参照には、値を移動するか複製するかが付く。
+/// 名前解決と型付けのエラーと、型環境を書き換えないことは[check_with]と同じ
+pub fn elaborate(expr: &Expr, env: &TypeEnv) -> Result<TypedExpr, Error> {
+    resolve::check(expr, &env.names()).map_err(Error::Resolve)?;
+    typing::typing_program(expr, &mut env.clone()).map_err(Error::Typing)
+}
+
+/// 与えられた型環境で式を型付けし、型付き構文木を最適化する
+///
+/// 関数の即時適用の展開、lin型の関数の展開、条件がリテラルのif式の畳み込み、
+/// 参照されないun型の変数の除去を行う。
+/// 式を書き換えたパスごとに、パスの名前と書き換えた後の式をtraceに渡す。
+/// 各パスの後に型付けをやり直して型付き構文木を作り直し、型付けに失敗するか型を保存していなければ、
+/// そのパスによる書き換えを取り消す。式の型付けに失敗した場合は[elaborate]と同じエラーとなる
+pub fn optimize(
+    expr: &Expr,
+    env: &TypeEnv,
+    trace: impl FnMut(&str, &TypedExpr),
+) -> Result<TypedExpr, Error> {
+    let typed = elaborate(expr, env)?;
+    Ok(optimize::optimize(&typed, env, trace))
+}
+
+/// 型環境に与えた変数を自由変数として式の変数を解決し、束縛変数をde Bruijnインデックスで表した式を返す
+///
+/// 定義されていない変数があった場合、それらを全て含めた名前解決エラーを返す。
+/// [check_with]と[elaborate]はこの式を作成せず、定義されていない変数のみを検査する
+pub fn resolve(expr: &Expr, env: &TypeEnv) -> Result<Term, Error> {
+    resolve::resolve(expr, &env.names()).map_err(Error::Resolve)
+}
+
+/// 式を評価し、評価結果の値と、評価後のヒープの統計情報を返す
+///
+/// 型付けに成功した式を渡すこと
+pub fn eval(expr: &Expr) -> Result<(Value, HeapStats), Error> {
+    eval_with(expr, &Externs::new())
+}
+
+/// 外部定義を束縛した初期環境で式を評価し、評価結果の値と、評価後のヒープの統計情報を返す
+///
+/// [Externs::type_env]で作成した型環境で型付けに成功した式を渡すこと
+pub fn eval_with(expr: &Expr, externs: &Externs) -> Result<(Value, HeapStats), Error> {
+    eval::eval(expr, externs).map_err(Error::Eval)
+}
+
+/// 外部定義を束縛した初期環境で、CEK機械により式を評価する
+///
+/// 初期状態と遷移した各状態をtraceに渡し、評価結果の値と、評価後のヒープの統計情報を返す。
+/// チャネルとスレッドには対応していない
+pub fn eval_cek(
+    expr: &Expr,
+    externs: &Externs,
+    trace: impl FnMut(&cek::Machine),
+) -> Result<(Value, HeapStats), Error> {
+    cek::eval(expr, externs, trace).map_err(Error::Eval)
+}
+
+/// 外部定義の値と関数を用いて、代入により式を値になるまで簡約する
+///
+/// 簡約する度に、適用した規則の名前と簡約後の式をtraceに渡し、値になった式を返す。
+/// 借用、可変な参照、リージョンと配列、チャネルとスレッドには対応していない
+pub fn reduce(
+    expr: &Expr,
+    externs: &Externs,
+    trace: impl FnMut(&str, &Expr),
+) -> Result<Expr, Error> {
+    reduce::reduce(expr, externs, trace).map_err(Error::Eval)
+}
+
+/// 外部定義を束縛した初期環境で式を評価する、C言語のプログラムを生成する
+///
+/// [Externs::type_env]で作成した型環境で型付けに成功した式を渡すこと。
+/// 外部関数は、C言語の実装を持つ組み込み関数のみ利用できる
+pub fn codegen_c(expr: &Expr, externs: &Externs) -> Result<String, Error> {
+    codegen_c::codegen(expr, externs).map_err(Error::Codegen)
+}
+
+/// 外部定義を束縛した初期環境で式を評価する、WebAssemblyのテキスト形式のモジュールを生成する
+///
+/// [Externs::type_env]で作成した型環境で型付けに成功した式を渡すこと。
+/// 外部関数は、WebAssemblyの実装を持つ組み込み関数のみ利用できる
+pub fn codegen_wat(expr: &Expr, externs: &Externs) -> Result<String, Error> {
+    codegen_wat::codegen(expr, externs).map_err(Error::Codegen)
+}
+
+/// 外部定義を束縛した初期環境で式を評価する、Rustのプログラムを生成する
+///
+/// [Externs::type_env]で作成した型環境で型付けに成功した式を渡すこと。
+/// 外部関数は、Rustの実装を持つ組み込み関数のみ利用できる
+pub fn codegen_rust(expr: &Expr, externs: &Externs) -> Result<String, Error> {
+    codegen_rust::codegen(expr, externs).map_err(Error::Codegen)
+}
+
+/// 外部定義を束縛した初期環境で式を評価する、スタックマシンのバイトコードにコンパイルする
+///
+/// [Externs::type_env]で作成した型環境で型付けに成功した式を渡すこと。
+/// チャネルとスレッドには対応していない
+pub fn compile(expr: &Expr, externs: &Externs) -> Result<Program, Error> {
+    bytecode::compile(expr, externs).map_err(Error::Codegen)
+}
+
+/// 外部定義を束縛した初期環境でバイトコードを実行し、実行結果の値と、実行後のヒープの統計情報を返す
+///
+/// プログラムが利用する外部定義は、名前により[Externs]から探す
+pub fn run(program: &Program, externs: &Externs) -> Result<(Value, HeapStats), Error> {
+    vm::run(program, externs).map_err(Error::Eval)
+}
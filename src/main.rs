@@ -1,28 +1,115 @@
-mod helper;
-mod parser;
-mod typing;
-
-use nom::error::convert_error;
-use std::{env, fs};
+use lineartype::{Externs, HeapStats, Program, Qual, Value};
+use std::{env, fs, io};
 
 #[derive(Debug)]
 enum LinError {
     Arguments,
     File,
+    Resolve,
     Typing,
     Parse,
+    Eval,
+    Codegen,
+}
+
+/// 組み込み関数を登録した外部定義を作成
+fn builtins() -> Externs {
+    // not : un (lin bool -> lin bool)
+    let ty = lineartype::parse_type("un (lin bool -> lin bool)").unwrap();
+    Externs::new()
+        .func("not", ty, |args| match &args[0] {
+            Value::Bool(_, b) => Ok(Value::Bool(Qual::Lin, !b)),
+            _ => Err("notの引数がboolでない".to_string()),
+        })
+        .extend(lineartype::fs_externs())
+        .extend(lineartype::string_externs())
+}
+
+/// 評価結果とヒープの統計情報を表示
+fn print_result(res: Result<(Value, HeapStats), lineartype::Error>) -> Result<(), LinError> {
+    match res {
+        Ok((v, stats)) => {
+            println!("\n評価結果:\n{}\n\nヒープ:\n{}", v, stats);
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            Err(LinError::Eval)
+        }
+    }
+}
+
+/// .lincファイルのバイトコードを実行するか、--disasmが指定された場合は逆アセンブルする
+fn run_linc(path: &str, disasm: bool) -> Result<(), LinError> {
+    let program = match fs::read(path) {
+        Ok(buf) => match Program::from_bytes(&buf) {
+            Ok(program) => program,
+            Err(e) => {
+                eprintln!("エラー: {}", e);
+                return Err(LinError::File);
+            }
+        },
+        Err(e) => {
+            eprintln!("エラー: {}", e);
+            return Err(LinError::File);
+        }
+    };
+
+    if disasm {
+        print!("{}", program);
+        return Ok(());
+    }
+    print_result(lineartype::run(&program, &builtins()))
 }
 
 fn main() -> Result<(), LinError> {
     // コマンドライン引数の検査
+    // --c <FILE>、--wat <FILE>か--rust <FILE>が指定された場合は、評価せずにC言語、
+    // WebAssemblyのテキスト形式かRustのコードをFILEに出力する
+    // --linc <FILE>が指定された場合はバイトコードをFILEに出力し、
+    // --vmが指定された場合はバイトコードにコンパイルしてスタックマシンで実行し、
+    // --disasmが指定された場合はバイトコードを逆アセンブルして表示する
+    // --traceが指定された場合はCEK機械で評価して各状態を表示し、
+    // --stepが指定された場合は状態を表示する度にEnterキーの入力を待つ
+    // --reduceが指定された場合は代入により簡約し、簡約列を表示する
+    // --typedが指定された場合は評価せずに型付き構文木を表示する
+    // -Oが指定された場合は型付き構文木を最適化し、最適化した式を評価する
     let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        eprintln!("以下のようにファイル名を指定して実行してください\ncargo run codes/ex1.lin");
-        return Err(LinError::Arguments);
+    let (path, target) = match &args[1..] {
+        [path] => (path, None),
+        [path, opt]
+            if [
+                "--vm", "--disasm", "--trace", "--step", "--reduce", "--typed", "-O",
+            ]
+            .contains(&opt.as_str()) =>
+        {
+            (path, Some((opt.as_str(), None)))
+        }
+        [path, opt, out] if ["--c", "--wat", "--rust", "--linc"].contains(&opt.as_str()) => {
+            (path, Some((opt.as_str(), Some(out))))
+        }
+        _ => {
+            eprintln!(
+                "以下のようにファイル名を指定して実行してください\ncargo run codes/ex1.lin\ncargo run codes/ex1.lin --c out.c\ncargo run codes/ex1.lin --wat out.wat\ncargo run codes/ex1.lin --rust out.rs\ncargo run codes/ex1.lin --vm\ncargo run codes/ex1.lin --linc out.linc\ncargo run codes/ex1.lin --disasm\ncargo run codes/ex1.lin --trace\ncargo run codes/ex1.lin --step\ncargo run codes/ex1.lin --reduce\ncargo run codes/ex1.lin --typed\ncargo run codes/ex1.lin -O\ncargo run out.linc"
+            );
+            return Err(LinError::Arguments);
+        }
+    };
+
+    // .lincファイルはパースと型付けを行わずに実行する
+    if path.ends_with(".linc") {
+        return match target {
+            None => run_linc(path, false),
+            Some(("--disasm", _)) => run_linc(path, true),
+            Some(_) => {
+                eprintln!(".lincファイルには--disasmのみ指定できます");
+                Err(LinError::Arguments)
+            }
+        };
     }
 
     // ファイル読み込み
-    let content = match fs::read_to_string(&args[1]) {
+    let content = match fs::read_to_string(path) {
         Ok(s) => s,
         Err(e) => {
             eprintln!("エラー: {}", e);
@@ -30,31 +117,136 @@ fn main() -> Result<(), LinError> {
         }
     };
 
-    let ast = parser::parse_expr(&content); // パース
-    println!("AST:\n{:#?}\n", ast);
-    match ast {
-        Ok((_, expr)) => {
-            let mut ctx = typing::TypeEnv::new();
-            println!("式:\n{}", content);
-
-            // 型付け
-            match typing::typing(&expr, &mut ctx, 0) {
-                Ok(a) => {
-                    println!("の型は\n{}\nです。", a);
-                }
-                Err(e) => {
-                    eprintln!("型付けエラー: {}", e);
-                    return Err(LinError::Typing);
+    // パース
+    let expr = match lineartype::parse(&content) {
+        Ok(expr) => expr,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Err(LinError::Parse);
+        }
+    };
+    println!("AST:\n{:#?}\n", expr);
+    println!("式:\n{}", content);
+
+    // 型付け
+    let externs = builtins();
+    match lineartype::check_with(&expr, &externs.type_env()) {
+        Ok(a) => {
+            println!("の型は\n{}\nです。", a);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            return Err(match e {
+                lineartype::Error::Resolve(_) => LinError::Resolve,
+                _ => LinError::Typing,
+            });
+        }
+    }
+
+    // 型付き構文木を表示
+    if let Some(("--typed", _)) = target {
+        match lineartype::elaborate(&expr, &externs.type_env()) {
+            Ok(typed) => print!("\n型付き構文木:\n{}", typed),
+            Err(e) => {
+                eprintln!("{}", e);
+                return Err(LinError::Typing);
+            }
+        }
+        return Ok(());
+    }
+
+    // バイトコードにコンパイルし、スタックマシンで実行、逆アセンブルするか.lincファイルに出力
+    if let Some((opt @ ("--vm" | "--disasm" | "--linc"), out)) = target {
+        let program = match lineartype::compile(&expr, &externs) {
+            Ok(program) => program,
+            Err(e) => {
+                eprintln!("{}", e);
+                return Err(LinError::Codegen);
+            }
+        };
+        match (opt, out) {
+            ("--vm", _) => return print_result(lineartype::run(&program, &externs)),
+            ("--disasm", _) => print!("\n{}", program),
+            (_, Some(out)) => {
+                if let Err(e) = fs::write(out, program.to_bytes()) {
+                    eprintln!("エラー: {}", e);
+                    return Err(LinError::File);
                 }
+                println!("\nバイトコードを{}に出力しました", out);
             }
+            _ => unreachable!(),
         }
-        Err(nom::Err::Error(e)) => {
-            let msg = convert_error(content.as_str(), e);
-            eprintln!("パースエラー:\n{}", msg);
-            return Err(LinError::Parse);
+        return Ok(());
+    }
+
+    // C言語、WebAssemblyかRustのコードを出力
+    if let Some((opt, Some(out))) = target {
+        let (lang, code) = match opt {
+            "--c" => ("C言語", lineartype::codegen_c(&expr, &externs)),
+            "--wat" => ("WebAssembly", lineartype::codegen_wat(&expr, &externs)),
+            _ => ("Rust", lineartype::codegen_rust(&expr, &externs)),
+        };
+        let code = match code {
+            Ok(code) => code,
+            Err(e) => {
+                eprintln!("{}", e);
+                return Err(LinError::Codegen);
+            }
+        };
+        if let Err(e) = fs::write(out, code) {
+            eprintln!("エラー: {}", e);
+            return Err(LinError::File);
         }
-        _ => (),
+        println!("\n{}のコードを{}に出力しました", lang, out);
+        return Ok(());
+    }
+
+    // CEK機械で評価し、各状態を表示
+    if let Some((opt @ ("--trace" | "--step"), _)) = target {
+        println!();
+        let res = lineartype::eval_cek(&expr, &externs, |m| {
+            println!("{}", m);
+            if opt == "--step" {
+                let mut line = String::new();
+                let _ = io::stdin().read_line(&mut line);
+            }
+        });
+        return print_result(res);
+    }
+
+    // 代入により簡約し、簡約列を表示
+    if let Some(("--reduce", _)) = target {
+        println!("\n簡約:\n  {}", expr);
+        let res = lineartype::reduce(&expr, &externs, |rule, e| {
+            println!("→ ({}) {}", rule, e);
+        });
+        return match res {
+            Ok(v) => {
+                println!("\n簡約結果:\n{}", v);
+                Ok(())
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                Err(LinError::Eval)
+            }
+        };
+    }
+
+    // 型付き構文木を最適化して評価
+    if let Some(("-O", _)) = target {
+        println!("\n最適化:\n  {}", expr);
+        let res = lineartype::optimize(&expr, &externs.type_env(), |pass, e| {
+            println!("→ ({}) {}", pass, e.to_expr());
+        });
+        return match res {
+            Ok(typed) => print_result(lineartype::eval_with(&typed.to_expr(), &externs)),
+            Err(e) => {
+                eprintln!("{}", e);
+                Err(LinError::Typing)
+            }
+        };
     }
 
-    Ok(())
+    // 評価
+    print_result(lineartype::eval_with(&expr, &externs))
 }
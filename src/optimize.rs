@@ -0,0 +1,338 @@
+//! ## 最適化
+//!
+//! 型付き構文木を書き換えて最適化する。以下のパスを順に、書き換えがなくなるまで繰り返す。
+//!
+//! ```text
+//! beta    : (q fn x : T { e } e2)              → let x : T = e2; e
+//! inline  : let x : lin T = v; e               → e[x := v]（vはリージョン外の関数か真偽値、整数、文字列）
+//! if-fold : if q true { e1 } else { e2 }       → e1（falseの場合はe2）
+//! dead-let: let x : un T = v; e                → e（xをeで参照せず、vを捨てても何も起きない場合）
+//! ```
+//!
+//! lin型の変数はちょうど一度利用されるため、関数を展開しても評価される回数は変わらない。
+//! ifのthenとelseの両方で利用される場合は、それぞれに展開する。
+//! 展開先で束縛変数が値の自由変数を捕獲する場合と、変数をfreeか借用している場合は展開しない。
+//!
+//! 各パスは式の型を保存する（型が部分型になることはある）。
+//! 書き換えた式の型と変数の参照の移動と複製は元の式から写したものであるため、
+//! 各パスの後に型付けをやり直して型付き構文木を作り直し、型が元の型の部分型であることを確認する。
+//! 関数の展開はキャプチャする時点を変えるため、ord型の変数を消費する順序が変わることがある。
+//! 型付けをやり直した結果が型付けエラーか元の型の部分型でない場合は、そのパスによる書き換えを取り消す。
+
+use crate::{
+    parser::Qual,
+    subst::free_vars,
+    typed::{Node, TypedExpr, TypedVal, Use},
+    typing::{self, TypeEnv},
+};
+use std::collections::BTreeSet;
+
+/// 最適化を繰り返す回数の上限
+const MAX_ROUNDS: usize = 16;
+
+/// 最適化パス。式の根で書き換えた場合に書き換えた式を返す
+type Rule = fn(&TypedExpr) -> Option<TypedExpr>;
+
+/// パスの名前と書き換え規則
+const PASSES: [(&str, Rule); 4] = [
+    ("beta", beta),
+    ("inline", inline),
+    ("if-fold", fold_if),
+    ("dead-let", dead_let),
+];
+
+/// 部分式をfで変換した式を返す
+///
+/// fには部分式と、部分式のスコープで新たに束縛される変数を渡す。
+/// fがNoneを返した場合はNoneを返す
+fn map(
+    e: &TypedExpr,
+    f: &mut impl FnMut(&TypedExpr, &[&String]) -> Option<TypedExpr>,
+) -> Option<TypedExpr> {
+    let mut g = |c: &TypedExpr, vars: &[&String]| f(c, vars).map(Box::new);
+    let node = match &e.node {
+        Node::Let {
+            var,
+            ty,
+            expr1,
+            expr2,
+        } => Node::Let {
+            var: var.clone(),
+            ty: ty.clone(),
+            expr1: g(expr1, &[])?,
+            expr2: g(expr2, &[var])?,
+        },
+        Node::LetBang { var, expr1, expr2 } => Node::LetBang {
+            var: var.clone(),
+            expr1: g(expr1, &[])?,
+            expr2: g(expr2, &[var])?,
+        },
+        Node::If {
+            cond_expr,
+            then_expr,
+            else_expr,
+        } => Node::If {
+            cond_expr: g(cond_expr, &[])?,
+            then_expr: g(then_expr, &[])?,
+            else_expr: g(else_expr, &[])?,
+        },
+        Node::Split {
+            expr,
+            left,
+            right,
+            body,
+        } => Node::Split {
+            expr: g(expr, &[])?,
+            left: left.clone(),
+            right: right.clone(),
+            body: g(body, &[left, right])?,
+        },
+        Node::Free { var, expr } => Node::Free {
+            var: var.clone(),
+            expr: g(expr, &[])?,
+        },
+        Node::App { expr1, expr2 } => Node::App {
+            expr1: g(expr1, &[])?,
+            expr2: g(expr2, &[])?,
+        },
+        Node::Proj { proj, expr } => Node::Proj {
+            proj: *proj,
+            expr: g(expr, &[])?,
+        },
+        Node::Promote { expr } => Node::Promote {
+            expr: g(expr, &[])?,
+        },
+        Node::Send { chan, expr } => Node::Send {
+            chan: g(chan, &[])?,
+            expr: g(expr, &[])?,
+        },
+        Node::Recv { chan } => Node::Recv {
+            chan: g(chan, &[])?,
+        },
+        Node::Close { chan } => Node::Close {
+            chan: g(chan, &[])?,
+        },
+        Node::Fork { expr1, expr2 } => Node::Fork {
+            expr1: g(expr1, &[])?,
+            expr2: g(expr2, &[])?,
+        },
+        Node::NewRef { expr } => Node::NewRef {
+            expr: g(expr, &[])?,
+        },
+        Node::Swap { cell, expr } => Node::Swap {
+            cell: g(cell, &[])?,
+            expr: g(expr, &[])?,
+        },
+        Node::LetRegion { region, expr } => Node::LetRegion {
+            region: region.clone(),
+            expr: g(expr, &[])?,
+        },
+        Node::Alloc { len, expr } => Node::Alloc {
+            len: g(len, &[])?,
+            expr: g(expr, &[])?,
+        },
+        Node::Get { array, index } => Node::Get {
+            array: g(array, &[])?,
+            index: g(index, &[])?,
+        },
+        Node::Set { array, index, expr } => Node::Set {
+            array: g(array, &[])?,
+            index: g(index, &[])?,
+            expr: g(expr, &[])?,
+        },
+        Node::QVal { qual, region, val } => Node::QVal {
+            qual: *qual,
+            region: region.clone(),
+            val: match val {
+                TypedVal::Pair(e1, e2) => TypedVal::Pair(g(e1, &[])?, g(e2, &[])?),
+                TypedVal::With(e1, e2) => TypedVal::With(g(e1, &[])?, g(e2, &[])?),
+                TypedVal::Fun { var, ty, expr } => TypedVal::Fun {
+                    var: var.clone(),
+                    ty: ty.clone(),
+                    expr: g(expr, &[var])?,
+                },
+                v => v.clone(),
+            },
+        },
+        node => node.clone(),
+    };
+    Some(TypedExpr {
+        node,
+        ty: e.ty.clone(),
+    })
+}
+
+/// 変数xが式eの自由変数か
+fn occurs(e: &TypedExpr, x: &str) -> bool {
+    free_vars(&e.to_expr()).contains(x)
+}
+
+/// 式eの変数xに値vを代入する
+///
+/// fvはvの自由変数。束縛変数がfvを捕獲する場合と、xをfreeか借用している場合はNoneを返す
+fn subst(e: &TypedExpr, x: &str, v: &TypedExpr, fv: &BTreeSet<String>) -> Option<TypedExpr> {
+    match &e.node {
+        Node::Var(y, _) if y == x => Some(v.clone()),
+        Node::Free { var, .. } if var == x => None,
+        Node::Borrow(y) if y == x => None,
+        _ => map(e, &mut |c, vars| {
+            if vars.iter().any(|b| *b == x) {
+                Some(c.clone()) // xは隠蔽されている
+            } else if vars.iter().any(|b| fv.contains(*b)) && occurs(c, x) {
+                None // vの自由変数を捕獲する
+            } else {
+                subst(c, x, v, fv)
+            }
+        }),
+    }
+}
+
+/// 関数の即時適用を、引数を束縛するlet式に書き換える
+fn beta(e: &TypedExpr) -> Option<TypedExpr> {
+    let Node::App { expr1, expr2 } = &e.node else {
+        return None;
+    };
+    let Node::QVal {
+        val: TypedVal::Fun { var, ty, expr },
+        ..
+    } = &expr1.node
+    else {
+        return None;
+    };
+    Some(TypedExpr {
+        node: Node::Let {
+            var: var.clone(),
+            ty: ty.clone(),
+            expr1: expr2.clone(),
+            expr2: expr.clone(),
+        },
+        ty: e.ty.clone(),
+    })
+}
+
+/// lin型の変数に束縛した関数とリテラルを、変数を利用する箇所に展開する
+fn inline(e: &TypedExpr) -> Option<TypedExpr> {
+    let Node::Let {
+        var,
+        ty,
+        expr1,
+        expr2,
+    } = &e.node
+    else {
+        return None;
+    };
+    if ty.qual != Qual::Lin {
+        return None;
+    }
+    match &expr1.node {
+        Node::QVal {
+            region: None,
+            val: TypedVal::Fun { .. } | TypedVal::Bool(_) | TypedVal::Int(_) | TypedVal::Str(_),
+            ..
+        } => {
+            let fv = free_vars(&expr1.to_expr());
+            subst(expr2, var, expr1, &fv)
+        }
+        _ => None,
+    }
+}
+
+/// 条件がリテラルのif式を、thenかelseの式に書き換える
+fn fold_if(e: &TypedExpr) -> Option<TypedExpr> {
+    let Node::If {
+        cond_expr,
+        then_expr,
+        else_expr,
+    } = &e.node
+    else {
+        return None;
+    };
+    match &cond_expr.node {
+        Node::QVal {
+            val: TypedVal::Bool(b),
+            ..
+        } => Some(if *b { then_expr } else { else_expr }.as_ref().clone()),
+        _ => None,
+    }
+}
+
+/// 評価しても変数を消費せず、外部関数も呼び出さないため、捨てても何も起きない式か
+///
+/// un型の関数、加法的ペアと!型の値は、作成時に中身を評価せず、un型の変数のみをキャプチャする
+fn discardable(e: &TypedExpr) -> bool {
+    match &e.node {
+        Node::Var(_, u) => *u == Use::Copy,
+        Node::Promote { .. } => true,
+        Node::QVal { qual, val, .. } => match val {
+            TypedVal::Bool(_) | TypedVal::Int(_) | TypedVal::Str(_) => true,
+            TypedVal::Pair(e1, e2) => discardable(e1) && discardable(e2),
+            TypedVal::With(..) | TypedVal::Fun { .. } => *qual == Qual::Un,
+        },
+        _ => false,
+    }
+}
+
+/// 参照されないun型の変数を束縛するlet式を取り除く
+fn dead_let(e: &TypedExpr) -> Option<TypedExpr> {
+    let Node::Let {
+        var,
+        ty,
+        expr1,
+        expr2,
+    } = &e.node
+    else {
+        return None;
+    };
+    if ty.qual == Qual::Un && discardable(expr1) && !occurs(expr2, var) {
+        Some(expr2.as_ref().clone())
+    } else {
+        None
+    }
+}
+
+/// 部分式から順にruleを適用し、書き換えた回数をcountに加える
+fn bottom_up(e: &TypedExpr, rule: Rule, count: &mut usize) -> TypedExpr {
+    let e = map(e, &mut |c, _| Some(bottom_up(c, rule, count))).unwrap_or_else(|| e.clone());
+    match rule(&e) {
+        Some(e) => {
+            *count += 1;
+            e
+        }
+        None => e,
+    }
+}
+
+/// 型付き構文木を最適化する
+///
+/// envは型付けに用いた型環境で、各パスの後に型付けをやり直す際に用いる。
+/// 式を書き換えたパスごとに、パスの名前と書き換えた後の式をtraceに渡す
+pub fn optimize(
+    typed: &TypedExpr,
+    env: &TypeEnv,
+    mut trace: impl FnMut(&str, &TypedExpr),
+) -> TypedExpr {
+    let mut e = typed.clone();
+    for _ in 0..MAX_ROUNDS {
+        let mut changed = false;
+        for (name, rule) in PASSES {
+            let mut count = 0;
+            let rewritten = bottom_up(&e, rule, &mut count);
+            if count == 0 {
+                continue;
+            }
+
+            // 型付き構文木を作り直し、型を保存しているかを確認
+            // 型付けに失敗するか型を保存しない場合は、パスによる書き換えを取り消す
+            match typing::typing_program(&rewritten.to_expr(), &mut env.clone()) {
+                Ok(t) if typing::subtype(&t.ty, &typed.ty) => e = t,
+                _ => continue,
+            }
+            changed = true;
+            trace(name, &e);
+        }
+        if !changed {
+            break;
+        }
+    }
+    e
+}
@@ -6,38 +6,73 @@
 //! ## 構文
 //!
 //! ```text
-//! <VAR>   := 1文字以上のアルファベットから成り立つ変数
+//! <VAR>   := 1文字以上のアルファベットから成り立つ変数。let、sendなど式の先頭のキーワードを除く
 //!
-//! <E>     := <LET> | <IF> | <SPLIT> | <FREE> | <APP> | <VAR> | <QVAL>
+//! <E>     := <LET> | <LETBANG> | <IF> | <SPLIT> | <FREE> | <APP> | <PROJ> | <PROMOTE> |
+//!            <NEW> | <SEND> | <RECV> | <CLOSE> | <FORK> | <BORROW> | <NEWREF> | <SWAP> |
+//!            <LETREGION> | <ALLOC> | <GET> | <SET> | <VAR> | <QVAL>
 //!
-//! <LET>   := let <VAR> : <T> = <E>; <E>
+//! <LET>     := let <VAR> : <T> = <E>; <E>
+//! <LETBANG> := let ! <VAR> = <E>; <E>
 //! <IF>    := if <E> { <E> } else { <E> }
 //! <SPLIT> := split <E> as <VAR>, <VAR> { <E> }
 //! <FREE>  := free <E>; <E>
 //! <APP>   := ( <E> <E> )
+//! <PROJ>  := fst <E> | snd <E>
+//! <PROMOTE> := promote <E>
+//! <BORROW>  := & <VAR>
+//! <LETREGION> := letregion <VAR> { <E> }
 //!
-//! <Q>     := lin | un
+//! 可変な参照
+//! <NEWREF> := new <E>
+//! <SWAP>   := swap <E> <E>
+//!
+//! 配列
+//! <ALLOC> := alloc <E> <E>
+//! <GET>   := get <E> <E>
+//! <SET>   := set <E> <E> <E>
+//!
+//! チャネル
+//! <NEW>   := new <S>
+//! <SEND>  := send <E> <E>
+//! <RECV>  := recv <E>
+//! <CLOSE> := close <E>
+//! <FORK>  := fork <E>; <E>
+//!
+//! <Q>     := ord | lin | aff | rel | un
+//! <R>     := @ <VAR>
 //!
 //! 値
-//! <QVAL>  := <Q> <VAL>
-//! <VAL>   := <B> | <PAIR> | <FN>
+//! <QVAL>  := <Q> <VAL> | <Q> <R> <VAL>
+//! <VAL>   := <B> | <N> | <STR> | <PAIR> | <WITH> | <FN>
 //! <B>     := true | false
+//! <N>     := 1文字以上の数字から成り立つ整数
+//! <STR>   := " 文字列 "  （\n、\t、\"、\\ のエスケープが可能）
 //! <PAIR>  := < <E> , <E> >
+//! <WITH>  := <| <E> , <E> |>
 //! <FN>    := fn <VAR> : <T> { <E> }
 //!
 //! 型
-//! <T>     := <Q> <P>
-//! <P>     := bool |
-//!            ( <T> * <T> )
-//!            ( <T> -> <T> )
+//! <T>     := <Q> <P> | <Q> <R> <P>
+//! <P>     := bool | int | str | unit | handle |
+//!            ( <T> * <T> ) |
+//!            ( <T> & <T> ) |
+//!            ( <T> -> <T> ) |
+//!            ! <T> |
+//!            & <T> |
+//!            ref <T> |
+//!            array <T> |
+//!            <S>
+//! <S>     := ! <T> . <S> | ? <T> . <S> | end
 //! ```
 
 use nom::{
     branch::alt,
-    bytes::complete::tag,
-    character::complete::{alpha1, char, multispace0, multispace1},
-    error::VerboseError,
-    sequence::delimited,
+    bytes::complete::{escaped_transform, is_not, tag},
+    character::complete::{alpha1, char, digit1, multispace0, multispace1},
+    combinator::{map_res, opt, value, verify},
+    error::{context, VerboseError},
+    sequence::{delimited, preceded},
     IResult,
 };
 use std::fmt;
@@ -45,17 +80,35 @@ use std::fmt;
 /// 抽象構文木
 ///
 /// ```text
-/// <E> := <LET> | <IF> | <SPLIT> | <FREE> | <APP> | <VAR> | <QVAL>
+/// <E> := <LET> | <LETBANG> | <IF> | <SPLIT> | <FREE> | <APP> | <PROJ> | <PROMOTE> |
+///        <NEW> | <SEND> | <RECV> | <CLOSE> | <FORK> | <BORROW> | <NEWREF> | <SWAP> |
+///        <LETREGION> | <ALLOC> | <GET> | <SET> | <VAR> | <QVAL>
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Eq, PartialEq, Clone)]
+#[non_exhaustive]
 pub enum Expr {
-    Let(LetExpr),     // let式
-    If(IfExpr),       // if式
-    Split(SplitExpr), // split式
-    Free(FreeExpr),   // free文
-    App(AppExpr),     // 関数適用
-    Var(String),      // 変数
-    QVal(QValExpr),   // 値
+    Let(LetExpr),             // let式
+    LetBang(LetBangExpr),     // let !式
+    If(IfExpr),               // if式
+    Split(SplitExpr),         // split式
+    Free(FreeExpr),           // free文
+    App(AppExpr),             // 関数適用
+    Proj(ProjExpr),           // 射影
+    Promote(PromoteExpr),     // promote式
+    New(NewExpr),             // チャネルの作成
+    Send(SendExpr),           // 送信
+    Recv(RecvExpr),           // 受信
+    Close(CloseExpr),         // チャネルを閉じる
+    Fork(ForkExpr),           // スレッドの作成
+    Borrow(String),           // 変数の借用
+    NewRef(NewRefExpr),       // 可変な参照の作成
+    Swap(SwapExpr),           // 可変な参照の中身の交換
+    LetRegion(LetRegionExpr), // リージョンの作成
+    Alloc(AllocExpr),         // 配列の作成
+    Get(GetExpr),             // 配列の要素の読み出し
+    Set(SetExpr),             // 配列の要素の書き換え
+    Var(String),              // 変数
+    QVal(QValExpr),           // 値
 }
 
 /// 関数適用
@@ -65,7 +118,7 @@ pub enum Expr {
 ///
 /// (expr1 expr2)
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct AppExpr {
     pub expr1: Box<Expr>,
     pub expr2: Box<Expr>,
@@ -82,7 +135,7 @@ pub struct AppExpr {
 ///     else_expr
 /// }
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct IfExpr {
     pub cond_expr: Box<Expr>,
     pub then_expr: Box<Expr>,
@@ -98,7 +151,7 @@ pub struct IfExpr {
 ///     body
 /// }
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct SplitExpr {
     pub expr: Box<Expr>,
     pub left: String,
@@ -106,6 +159,26 @@ pub struct SplitExpr {
     pub body: Box<Expr>,
 }
 
+/// 射影の種類
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Proj {
+    Fst, // 1つめの要素
+    Snd, // 2つめの要素
+}
+
+/// 射影式。<| e1, e2 |>のどちらか一方を取り出す
+///
+/// ```text
+/// <PROJ> := fst <E> | snd <E>
+///
+/// fst expr
+/// ```
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ProjExpr {
+    pub proj: Proj,
+    pub expr: Box<Expr>,
+}
+
 /// let式
 ///
 /// ```text
@@ -113,7 +186,7 @@ pub struct SplitExpr {
 ///
 /// let var : ty = expr1 { expr2 }
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct LetExpr {
     pub var: String,
     pub ty: TypeExpr,
@@ -121,40 +194,282 @@ pub struct LetExpr {
     pub expr2: Box<Expr>,
 }
 
+/// let !式。!型の値を取り出し、制約のない変数として束縛する
+///
+/// ```text
+/// <LETBANG> := let ! <VAR> = <E>; <E>
+///
+/// let !var = expr1; expr2
+/// ```
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct LetBangExpr {
+    pub var: String,
+    pub expr1: Box<Expr>,
+    pub expr2: Box<Expr>,
+}
+
+/// promote式。lin型の自由変数を利用しない式を、!型の値に昇格する
+///
+/// ```text
+/// <PROMOTE> := promote <E>
+///
+/// promote expr
+/// ```
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct PromoteExpr {
+    pub expr: Box<Expr>,
+}
+
+/// new式。セッション型Sのチャネルを作成し、Sとその双対の型を持つ両端点のペアを返す
+///
+/// ```text
+/// <NEW> := new <S>
+///
+/// new session
+/// ```
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct NewExpr {
+    pub session: Session,
+}
+
+/// new式。値exprを格納した、可変な参照を作成する
+///
+/// ```text
+/// <NEWREF> := new <E>
+///
+/// new expr
+/// ```
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct NewRefExpr {
+    pub expr: Box<Expr>,
+}
+
+/// swap式。可変な参照cellの中身を値exprと交換し、参照と元の中身のペアを返す
+///
+/// ```text
+/// <SWAP> := swap <E> <E>
+///
+/// swap cell expr
+/// ```
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct SwapExpr {
+    pub cell: Box<Expr>,
+    pub expr: Box<Expr>,
+}
+
+/// letregion式。リージョンregionを作成してexprを評価し、評価後にリージョン内の値を一括して解放する
+///
+/// ```text
+/// <LETREGION> := letregion <VAR> { <E> }
+///
+/// letregion region { expr }
+/// ```
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct LetRegionExpr {
+    pub region: String,
+    pub expr: Box<Expr>,
+}
+
+/// alloc式。長さlenで、全ての要素を値exprとした配列を作成する
+///
+/// ```text
+/// <ALLOC> := alloc <E> <E>
+///
+/// alloc len expr
+/// ```
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct AllocExpr {
+    pub len: Box<Expr>,
+    pub expr: Box<Expr>,
+}
+
+/// get式。配列arrayのindex番目の要素を読み出し、配列と要素のペアを返す
+///
+/// ```text
+/// <GET> := get <E> <E>
+///
+/// get array index
+/// ```
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct GetExpr {
+    pub array: Box<Expr>,
+    pub index: Box<Expr>,
+}
+
+/// set式。配列arrayのindex番目の要素を値exprに書き換え、配列を返す
+///
+/// ```text
+/// <SET> := set <E> <E> <E>
+///
+/// set array index expr
+/// ```
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct SetExpr {
+    pub array: Box<Expr>,
+    pub index: Box<Expr>,
+    pub expr: Box<Expr>,
+}
+
+/// send式。チャネルchanに値exprを送信し、残りのセッションのチャネルを返す
+///
+/// ```text
+/// <SEND> := send <E> <E>
+///
+/// send chan expr
+/// ```
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct SendExpr {
+    pub chan: Box<Expr>,
+    pub expr: Box<Expr>,
+}
+
+/// recv式。チャネルから値を受信し、受信した値と残りのセッションのチャネルのペアを返す
+///
+/// ```text
+/// <RECV> := recv <E>
+///
+/// recv chan
+/// ```
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct RecvExpr {
+    pub chan: Box<Expr>,
+}
+
+/// close式。セッションを終えたチャネルを閉じる
+///
+/// ```text
+/// <CLOSE> := close <E>
+///
+/// close chan
+/// ```
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct CloseExpr {
+    pub chan: Box<Expr>,
+}
+
+/// fork式。expr1を新たなスレッドで評価し、expr2の評価を続ける
+///
+/// ```text
+/// <FORK> := fork <E>; <E>
+///
+/// fork expr1; expr2
+/// ```
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ForkExpr {
+    pub expr1: Box<Expr>,
+    pub expr2: Box<Expr>,
+}
+
 /// 値。真偽値、関数、ペア値などになる
 ///
 /// ```text
-/// <VAL>  := <B> | <PAIR> | <FN>
+/// <VAL>  := <B> | <N> | <STR> | <PAIR> | <WITH> | <FN>
 /// <B>    := true | false
+/// <N>    := 1文字以上の数字から成り立つ整数
+/// <STR>  := " 文字列 "
 /// <PAIR> := < <E> , <E> >
+/// <WITH> := <| <E> , <E> |>
 /// <FN>   := fn <VAR> : <T> { <E> }
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Eq, PartialEq, Clone)]
+#[non_exhaustive]
 pub enum ValExpr {
     Bool(bool),                 // 真偽値リテラル
+    Int(i64),                   // 整数リテラル
+    Str(String),                // 文字列リテラル
     Pair(Box<Expr>, Box<Expr>), // ペア
+    With(Box<Expr>, Box<Expr>), // 加法的ペア（どちらか一方のみ取り出せる）
     Fun(FnExpr),                // 関数（λ抽象）
 }
 
 /// 修飾子
 ///
 /// ```text
-/// <Q> := lin | un
+/// <Q> := ord | lin | aff | rel | un
+/// ```
+///
+/// 修飾子は以下の束を成し、上にあるほど制約が強い。
+///
+/// ```text
+///     ord
+///      |
+///     lin
+///    /   \
+///  aff   rel
+///    \   /
+///     un
 /// ```
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
+#[non_exhaustive]
 pub enum Qual {
-    Lin, // 線形型
+    Ord, // 順序型。束縛とは逆順にちょうど一度利用する
+    Lin, // 線形型。ちょうど一度利用する
+    Aff, // アフィン型。高々一度利用する
+    Rel, // 関連型。少なくとも一度利用する
     Un,  // 制約のない一般的な型
 }
 
+impl Qual {
+    /// 束の順序でselfがother以下か（selfの制約がother以下か）を判定
+    ///
+    /// 修飾子qのペアや関数は、修飾子がq以下の値のみを含められる
+    pub fn leq(self, other: Qual) -> bool {
+        matches!(
+            (self, other),
+            (Qual::Un, _)
+                | (_, Qual::Ord)
+                | (Qual::Lin | Qual::Aff | Qual::Rel, Qual::Lin)
+                | (Qual::Aff, Qual::Aff)
+                | (Qual::Rel, Qual::Rel)
+        )
+    }
+
+    /// 束の上限（selfとotherの両方以上で、最も制約の弱い修飾子）
+    pub fn join(self, other: Qual) -> Qual {
+        if self.leq(other) {
+            other
+        } else if other.leq(self) {
+            self
+        } else {
+            Qual::Lin // affとrelの上限
+        }
+    }
+
+    /// 束の下限（selfとotherの両方以下で、最も制約の強い修飾子）
+    pub fn meet(self, other: Qual) -> Qual {
+        if self.leq(other) {
+            self
+        } else if other.leq(self) {
+            other
+        } else {
+            Qual::Un // affとrelの下限
+        }
+    }
+}
+
+impl fmt::Display for Qual {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Qual::Ord => write!(f, "ord"),
+            Qual::Lin => write!(f, "lin"),
+            Qual::Aff => write!(f, "aff"),
+            Qual::Rel => write!(f, "rel"),
+            Qual::Un => write!(f, "un"),
+        }
+    }
+}
+
 /// 修飾子付き値
 ///
+/// ```text
+/// <QV> := <Q> <VAL> | <Q> <R> <VAL>
 /// ```
-/// <QV> := <Q> <VAL>
-/// ```
-#[derive(Debug)]
+///
+/// リージョンを指定した場合、値はリージョン内に確保される
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct QValExpr {
     pub qual: Qual,
+    pub region: Option<String>,
     pub val: ValExpr,
 }
 
@@ -165,7 +480,7 @@ pub struct QValExpr {
 ///
 /// fn var : ty { expr }
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct FnExpr {
     pub var: String,
     pub ty: TypeExpr,
@@ -179,7 +494,7 @@ pub struct FnExpr {
 ///
 /// free var; expr
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct FreeExpr {
     pub var: String,
     pub expr: Box<Expr>,
@@ -188,20 +503,22 @@ pub struct FreeExpr {
 /// 修飾子付き型
 ///
 /// ```text
-/// <QV> := <Q> <VAL>
+/// <T> := <Q> <P> | <Q> <R> <P>
 /// ```
+///
+/// regionがSomeの場合、リージョン内に確保された値の型となる
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct TypeExpr {
     pub qual: Qual,
+    pub region: Option<String>,
     pub prim: PrimType,
 }
 
 impl fmt::Display for TypeExpr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if self.qual == Qual::Lin {
-            write!(f, "lin {}", self.prim)
-        } else {
-            write!(f, "un {}", self.prim)
+        match &self.region {
+            Some(r) => write!(f, "{}@{} {}", self.qual, r, self.prim),
+            None => write!(f, "{} {}", self.qual, self.prim),
         }
     }
 }
@@ -209,39 +526,195 @@ impl fmt::Display for TypeExpr {
 /// プリミティブ型
 ///
 /// ```text
-/// <P> := bool |
-///        ( <T> * <T> )
-///        ( <T> -> <T> )
+/// <P> := bool | int | str | unit | handle |
+///        ( <T> * <T> ) |
+///        ( <T> & <T> ) |
+///        ( <T> -> <T> ) |
+///        ! <T> |
+///        & <T> |
+///        ref <T> |
+///        array <T> |
+///        <S>
 /// ```
+///
+/// unit、handle型の値はリテラルを持たず、外部関数などから与えられる。
+/// lin型の文字列は解放か消費しなければならない文字列バッファを、
+/// un型の文字列は変更できない文字列リテラルを表す。
+/// 参照型の値は、借用した変数を消費せずに参照するためのun型の値となる。
+/// ref型の値は中身を書き換えられる可変な参照で、lin型でなければならない。
+/// array型の値は要素を書き換えられる配列で、lin型でなければならず、要素はun型に限る
 #[derive(Debug, Eq, PartialEq, Clone)]
+#[non_exhaustive]
 pub enum PrimType {
     Bool,                                // 真偽値型
+    Int,                                 // 整数型
+    Str,                                 // 文字列型
+    Unit,                                // ユニット型
+    Handle,                              // ファイルハンドル型
     Pair(Box<TypeExpr>, Box<TypeExpr>),  // ペア型
+    With(Box<TypeExpr>, Box<TypeExpr>),  // 加法的ペア型
     Arrow(Box<TypeExpr>, Box<TypeExpr>), // 関数型
+    Bang(Box<TypeExpr>),                 // !型（何度でも取り出せる値）
+    Chan(Session),                       // チャネルの端点の型
+    Ref(Box<TypeExpr>),                  // 参照型（借用した値）
+    Cell(Box<TypeExpr>),                 // ref型（可変な参照）
+    Array(Box<TypeExpr>),                // array型（配列）
 }
 
 impl fmt::Display for PrimType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             PrimType::Bool => write!(f, "bool"),
+            PrimType::Int => write!(f, "int"),
+            PrimType::Str => write!(f, "str"),
+            PrimType::Unit => write!(f, "unit"),
+            PrimType::Handle => write!(f, "handle"),
             PrimType::Pair(t1, t2) => write!(f, "({} * {})", t1, t2),
+            PrimType::With(t1, t2) => write!(f, "({} & {})", t1, t2),
             PrimType::Arrow(t1, t2) => write!(f, "({} -> {})", t1, t2),
+            PrimType::Bang(t) => write!(f, "!{}", t),
+            PrimType::Chan(s) => write!(f, "{}", s),
+            PrimType::Ref(t) => write!(f, "&{}", t),
+            PrimType::Cell(t) => write!(f, "ref {}", t),
+            PrimType::Array(t) => write!(f, "array {}", t),
+        }
+    }
+}
+
+/// セッション型。チャネルの端点で行う通信の手順を表す
+///
+/// ```text
+/// <S> := ! <T> . <S> | ? <T> . <S> | end
+/// ```
+///
+/// !T.Sは型Tの値を送信した後にSとなり、?T.Sは型Tの値を受信した後にSとなる。
+/// endは通信を終え、閉じるだけのチャネルを表す
+#[derive(Debug, Eq, PartialEq, Clone)]
+#[non_exhaustive]
+pub enum Session {
+    Send(Box<TypeExpr>, Box<Session>), // 送信
+    Recv(Box<TypeExpr>, Box<Session>), // 受信
+    End,                               // 終了
+}
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Session::Send(t, s) => write!(f, "!{}.{}", t, s),
+            Session::Recv(t, s) => write!(f, "?{}.{}", t, s),
+            Session::End => write!(f, "end"),
+        }
+    }
+}
+
+/// 式をソースコードとして1行で表示する。表示した文字列は再びパースできる
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Let(e) => write!(f, "let {} : {} = {}; {}", e.var, e.ty, e.expr1, e.expr2),
+            Expr::LetBang(e) => write!(f, "let !{} = {}; {}", e.var, e.expr1, e.expr2),
+            Expr::If(e) => write!(
+                f,
+                "if {} {{ {} }} else {{ {} }}",
+                e.cond_expr, e.then_expr, e.else_expr
+            ),
+            Expr::Split(e) => write!(
+                f,
+                "split {} as {}, {} {{ {} }}",
+                e.expr, e.left, e.right, e.body
+            ),
+            Expr::Free(e) => write!(f, "free {}; {}", e.var, e.expr),
+            Expr::App(e) => write!(f, "({} {})", e.expr1, e.expr2),
+            Expr::Proj(e) => match e.proj {
+                Proj::Fst => write!(f, "fst {}", e.expr),
+                Proj::Snd => write!(f, "snd {}", e.expr),
+            },
+            Expr::Promote(e) => write!(f, "promote {}", e.expr),
+            Expr::New(e) => write!(f, "new {}", e.session),
+            Expr::Send(e) => write!(f, "send {} {}", e.chan, e.expr),
+            Expr::Recv(e) => write!(f, "recv {}", e.chan),
+            Expr::Close(e) => write!(f, "close {}", e.chan),
+            Expr::Fork(e) => write!(f, "fork {}; {}", e.expr1, e.expr2),
+            Expr::Borrow(var) => write!(f, "&{}", var),
+            Expr::NewRef(e) => write!(f, "new {}", e.expr),
+            Expr::Swap(e) => write!(f, "swap {} {}", e.cell, e.expr),
+            Expr::LetRegion(e) => write!(f, "letregion {} {{ {} }}", e.region, e.expr),
+            Expr::Alloc(e) => write!(f, "alloc {} {}", e.len, e.expr),
+            Expr::Get(e) => write!(f, "get {} {}", e.array, e.index),
+            Expr::Set(e) => write!(f, "set {} {} {}", e.array, e.index, e.expr),
+            Expr::Var(var) => write!(f, "{}", var),
+            Expr::QVal(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl fmt::Display for QValExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.region {
+            Some(r) => write!(f, "{}@{} {}", self.qual, r, self.val),
+            None => write!(f, "{} {}", self.qual, self.val),
+        }
+    }
+}
+
+impl fmt::Display for ValExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValExpr::Bool(b) => write!(f, "{}", b),
+            ValExpr::Int(n) => write!(f, "{}", n),
+            ValExpr::Str(s) => {
+                // パーサが受け付けるエスケープのみを用いる
+                write!(f, "\"")?;
+                for c in s.chars() {
+                    match c {
+                        '\n' => write!(f, "\\n")?,
+                        '\t' => write!(f, "\\t")?,
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        _ => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            ValExpr::Pair(e1, e2) => write!(f, "<{}, {}>", e1, e2),
+            ValExpr::With(e1, e2) => write!(f, "<|{}, {}|>", e1, e2),
+            ValExpr::Fun(e) => write!(f, "fn {} : {} {{ {} }}", e.var, e.ty, e.expr),
         }
     }
 }
 
 pub fn parse_expr(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
     let (i, _) = multispace0(i)?;
-    let (i, val) = alt((alpha1, tag("(")))(i)?;
+    let (i, val) = alt((alpha1, tag("("), tag("&")))(i)?;
 
     match val {
         "let" => parse_let(i),
         "if" => parse_if(i),
         "split" => parse_split(i),
         "free" => parse_free(i),
+        "fst" => parse_proj(Proj::Fst, i),
+        "snd" => parse_proj(Proj::Snd, i),
+        "promote" => parse_promote(i),
+        "new" => parse_new(i),
+        "send" => parse_send(i),
+        "recv" => parse_recv(i),
+        "close" => parse_close(i),
+        "fork" => parse_fork(i),
+        "swap" => parse_swap(i),
+        "letregion" => parse_letregion(i),
+        "alloc" => parse_alloc(i),
+        "get" => parse_get(i),
+        "set" => parse_set(i),
+        "ord" => parse_qval(Qual::Ord, i),
         "lin" => parse_qval(Qual::Lin, i),
+        "aff" => parse_qval(Qual::Aff, i),
+        "rel" => parse_qval(Qual::Rel, i),
         "un" => parse_qval(Qual::Un, i),
         "(" => parse_app(i),
+        "&" => {
+            let (i, var) = parse_var(i)?; // 借用する変数
+            Ok((i, Expr::Borrow(var)))
+        }
         _ => Ok((i, Expr::Var(val.to_string()))),
     }
 }
@@ -284,6 +757,190 @@ fn parse_free(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
     ))
 }
 
+/// fstとsnd式をパース。
+fn parse_proj(p: Proj, i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+    let (i, _) = multispace1(i)?;
+    let (i, e) = parse_expr(i)?; // 射影する値
+
+    Ok((
+        i,
+        Expr::Proj(ProjExpr {
+            proj: p,
+            expr: Box::new(e),
+        }),
+    ))
+}
+
+/// promote式をパース。
+fn parse_promote(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+    let (i, _) = multispace1(i)?;
+    let (i, e) = parse_expr(i)?; // 昇格する式
+
+    Ok((i, Expr::Promote(PromoteExpr { expr: Box::new(e) })))
+}
+
+/// new式をパース。セッション型が続く場合はチャネル、そうでない場合は可変な参照を作成する
+fn parse_new(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+    let (i, _) = multispace1(i)?;
+    if let Ok((i, s)) = parse_session(i) {
+        return Ok((i, Expr::New(NewExpr { session: s }))); // チャネルのセッション型
+    }
+
+    let (i, e) = parse_expr(i)?; // 参照に格納する値
+    Ok((i, Expr::NewRef(NewRefExpr { expr: Box::new(e) })))
+}
+
+/// swap式をパース。
+fn parse_swap(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+    let (i, _) = multispace1(i)?;
+    let (i, e1) = parse_expr(i)?; // 可変な参照
+
+    let (i, _) = multispace1(i)?;
+
+    let (i, e2) = parse_expr(i)?; // 新たに格納する値
+
+    Ok((
+        i,
+        Expr::Swap(SwapExpr {
+            cell: Box::new(e1),
+            expr: Box::new(e2),
+        }),
+    ))
+}
+
+/// letregion式をパース。
+fn parse_letregion(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+    let (i, _) = multispace1(i)?;
+    let (i, region) = parse_var(i)?; // リージョン名
+    let (i, _) = multispace0(i)?;
+
+    // { <E> }というように、波括弧で囲まれた式をパース
+    let (i, e) = delimited(
+        char('{'),
+        delimited(multispace0, parse_expr, multispace0),
+        char('}'),
+    )(i)?;
+
+    Ok((
+        i,
+        Expr::LetRegion(LetRegionExpr {
+            region,
+            expr: Box::new(e),
+        }),
+    ))
+}
+
+/// alloc式をパース。
+fn parse_alloc(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+    let (i, _) = multispace1(i)?;
+    let (i, e1) = parse_expr(i)?; // 配列の長さ
+
+    let (i, _) = multispace1(i)?;
+
+    let (i, e2) = parse_expr(i)?; // 要素の初期値
+
+    Ok((
+        i,
+        Expr::Alloc(AllocExpr {
+            len: Box::new(e1),
+            expr: Box::new(e2),
+        }),
+    ))
+}
+
+/// get式をパース。
+fn parse_get(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+    let (i, _) = multispace1(i)?;
+    let (i, e1) = parse_expr(i)?; // 配列
+
+    let (i, _) = multispace1(i)?;
+
+    let (i, e2) = parse_expr(i)?; // 読み出す要素の位置
+
+    Ok((
+        i,
+        Expr::Get(GetExpr {
+            array: Box::new(e1),
+            index: Box::new(e2),
+        }),
+    ))
+}
+
+/// set式をパース。
+fn parse_set(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+    let (i, _) = multispace1(i)?;
+    let (i, e1) = parse_expr(i)?; // 配列
+
+    let (i, _) = multispace1(i)?;
+
+    let (i, e2) = parse_expr(i)?; // 書き換える要素の位置
+
+    let (i, _) = multispace1(i)?;
+
+    let (i, e3) = parse_expr(i)?; // 新たな要素
+
+    Ok((
+        i,
+        Expr::Set(SetExpr {
+            array: Box::new(e1),
+            index: Box::new(e2),
+            expr: Box::new(e3),
+        }),
+    ))
+}
+
+/// send式をパース。
+fn parse_send(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+    let (i, _) = multispace1(i)?;
+    let (i, e1) = parse_expr(i)?; // 送信するチャネル
+
+    let (i, _) = multispace1(i)?;
+
+    let (i, e2) = parse_expr(i)?; // 送信する値
+
+    Ok((
+        i,
+        Expr::Send(SendExpr {
+            chan: Box::new(e1),
+            expr: Box::new(e2),
+        }),
+    ))
+}
+
+/// recv式をパース。
+fn parse_recv(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+    let (i, _) = multispace1(i)?;
+    let (i, e) = parse_expr(i)?; // 受信するチャネル
+
+    Ok((i, Expr::Recv(RecvExpr { chan: Box::new(e) })))
+}
+
+/// close式をパース。
+fn parse_close(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+    let (i, _) = multispace1(i)?;
+    let (i, e) = parse_expr(i)?; // 閉じるチャネル
+
+    Ok((i, Expr::Close(CloseExpr { chan: Box::new(e) })))
+}
+
+/// fork式をパース。
+fn parse_fork(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+    let (i, _) = multispace1(i)?;
+    let (i, e1) = parse_expr(i)?; // 新たなスレッドで評価する式
+    let (i, _) = multispace0(i)?;
+
+    let (i, _) = char(';')(i)?;
+    let (i, e2) = parse_expr(i)?; // 続けて実行する式
+
+    Ok((
+        i,
+        Expr::Fork(ForkExpr {
+            expr1: Box::new(e1),
+            expr2: Box::new(e2),
+        }),
+    ))
+}
+
 /// split式をパース。
 fn parse_split(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
     let (i, _) = multispace1(i)?;
@@ -358,6 +1015,11 @@ fn parse_if(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
 fn parse_let(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
     let (i, _) = multispace1(i)?;
 
+    // let !式
+    if let Ok((i, _)) = char::<&str, VerboseError<&str>>('!')(i) {
+        return parse_let_bang(i);
+    }
+
     let (i, var) = parse_var(i)?; // 束縛する変数
 
     let (i, _) = multispace0(i)?;
@@ -387,6 +1049,32 @@ fn parse_let(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
     ))
 }
 
+/// let !式をパース。
+fn parse_let_bang(i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+    let (i, _) = multispace0(i)?;
+
+    let (i, var) = parse_var(i)?; // 束縛する変数
+
+    let (i, _) = multispace0(i)?;
+    let (i, _) = char('=')(i)?;
+    let (i, _) = multispace0(i)?;
+
+    let (i, e1) = parse_expr(i)?; // !型の値
+    let (i, _) = multispace0(i)?;
+
+    let (i, _) = char(';')(i)?;
+    let (i, e2) = parse_expr(i)?; // 実行する式
+
+    Ok((
+        i,
+        Expr::LetBang(LetBangExpr {
+            var,
+            expr1: Box::new(e1),
+            expr2: Box::new(e2),
+        }),
+    ))
+}
+
 /// ペアをパース。
 fn parse_pair(i: &str) -> IResult<&str, ValExpr, VerboseError<&str>> {
     let (i, _) = multispace0(i)?;
@@ -405,13 +1093,33 @@ fn parse_pair(i: &str) -> IResult<&str, ValExpr, VerboseError<&str>> {
     Ok((i, ValExpr::Pair(Box::new(v1), Box::new(v2))))
 }
 
-/// linとun修飾子をパース。
+/// 加法的ペアをパース。
+fn parse_with(i: &str) -> IResult<&str, ValExpr, VerboseError<&str>> {
+    let (i, _) = multispace0(i)?;
+
+    let (i, v1) = parse_expr(i)?; // 1つめの値
+
+    let (i, _) = multispace0(i)?;
+    let (i, _) = char(',')(i)?;
+    let (i, _) = multispace0(i)?;
+
+    let (i, v2) = parse_expr(i)?; // 2つめの値
+
+    let (i, _) = multispace0(i)?;
+    let (i, _) = tag("|>")(i)?; // 閉じ括弧
+
+    Ok((i, ValExpr::With(Box::new(v1), Box::new(v2))))
+}
+
+/// ord、lin、aff、rel、un修飾子をパース。
 fn parse_qual(i: &str) -> IResult<&str, Qual, VerboseError<&str>> {
-    let (i, val) = alt((tag("lin"), tag("un")))(i)?;
-    if val == "lin" {
-        Ok((i, Qual::Lin))
-    } else {
-        Ok((i, Qual::Un))
+    let (i, val) = alt((tag("ord"), tag("lin"), tag("aff"), tag("rel"), tag("un")))(i)?;
+    match val {
+        "ord" => Ok((i, Qual::Ord)),
+        "lin" => Ok((i, Qual::Lin)),
+        "aff" => Ok((i, Qual::Aff)),
+        "rel" => Ok((i, Qual::Rel)),
+        _ => Ok((i, Qual::Un)),
     }
 }
 
@@ -444,44 +1152,197 @@ fn parse_fn(i: &str) -> IResult<&str, ValExpr, VerboseError<&str>> {
     ))
 }
 
-/// 真偽値、関数、ペアの値をパース。
+/// 文字列リテラルをパース。開始の"はパース済み。
+fn parse_str(i: &str) -> IResult<&str, ValExpr, VerboseError<&str>> {
+    // エスケープを変換しながら、"か\\が現れるまでの文字列をパース
+    let (i, s) = opt(escaped_transform(
+        is_not("\\\""),
+        '\\',
+        alt((
+            value("\n", char('n')),
+            value("\t", char('t')),
+            value("\"", char('"')),
+            value("\\", char('\\')),
+        )),
+    ))(i)?;
+    let (i, _) = char('"')(i)?; // 閉じ括弧
+
+    Ok((i, ValExpr::Str(s.unwrap_or_default())))
+}
+
+/// 真偽値、整数、文字列、関数、ペアの値をパース。
 fn parse_val(i: &str) -> IResult<&str, ValExpr, VerboseError<&str>> {
-    let (i, val) = alt((tag("fn"), tag("true"), tag("false"), tag("<")))(i)?;
+    // 整数
+    if let Ok((i, n)) = map_res(digit1::<&str, VerboseError<&str>>, str::parse)(i) {
+        return Ok((i, ValExpr::Int(n)));
+    }
+
+    let (i, val) = alt((
+        tag("fn"),
+        tag("true"),
+        tag("false"),
+        tag("<|"),
+        tag("<"),
+        tag("\""),
+    ))(i)?;
     match val {
         "fn" => parse_fn(i),
         "true" => Ok((i, ValExpr::Bool(true))),
         "false" => Ok((i, ValExpr::Bool(false))),
+        "<|" => parse_with(i),
         "<" => parse_pair(i),
+        "\"" => parse_str(i),
         _ => unreachable!(),
     }
 }
 
 /// 修飾子付き値をパース。
 fn parse_qval(q: Qual, i: &str) -> IResult<&str, Expr, VerboseError<&str>> {
+    let (i, r) = parse_region(i)?; // 値を確保するリージョン
     let (i, _) = multispace1(i)?;
     let (i, v) = parse_val(i)?;
 
-    Ok((i, Expr::QVal(QValExpr { qual: q, val: v })))
+    Ok((
+        i,
+        Expr::QVal(QValExpr {
+            qual: q,
+            region: r,
+            val: v,
+        }),
+    ))
 }
 
-/// 変数をパース。変数は1文字以上のアルファベットから成り立つ。
+/// 修飾子の直後の、@から始まるリージョン名をパース。
+fn parse_region(i: &str) -> IResult<&str, Option<String>, VerboseError<&str>> {
+    opt(preceded(char('@'), parse_var))(i)
+}
+
+/// 変数名として利用できないキーワード
+pub(crate) const KEYWORDS: [&str; 27] = [
+    "let",
+    "fn",
+    "true",
+    "false",
+    "if",
+    "else",
+    "split",
+    "as",
+    "free",
+    "fst",
+    "snd",
+    "promote",
+    "new",
+    "send",
+    "recv",
+    "close",
+    "fork",
+    "swap",
+    "letregion",
+    "alloc",
+    "get",
+    "set",
+    "ord",
+    "lin",
+    "aff",
+    "rel",
+    "un",
+];
+
+/// 変数をパース。変数は1文字以上のアルファベットから成り立ち、キーワードは変数にできない。
 fn parse_var(i: &str) -> IResult<&str, String, VerboseError<&str>> {
-    let (i, v) = alpha1(i)?;
+    let (i, v) = context(
+        "キーワードでない変数",
+        verify(alpha1, |v: &str| !KEYWORDS.contains(&v)),
+    )(i)?;
     Ok((i, v.to_string()))
 }
 
-/// 真偽値、関数、ペア型をパース。
-fn parse_type(i: &str) -> IResult<&str, TypeExpr, VerboseError<&str>> {
+/// セッション型をパース。
+fn parse_session(i: &str) -> IResult<&str, Session, VerboseError<&str>> {
+    let (i, val) = alt((tag("!"), tag("?"), tag("end")))(i)?;
+    if val == "end" {
+        return Ok((i, Session::End));
+    }
+
+    let (i, _) = multispace0(i)?;
+    let (i, t) = parse_type(i)?; // 送受信する値の型
+    let (i, _) = multispace0(i)?;
+    let (i, _) = char('.')(i)?;
+    let (i, _) = multispace0(i)?;
+    let (i, s) = parse_session(i)?; // 残りのセッション型
+
+    match val {
+        "!" => Ok((i, Session::Send(Box::new(t), Box::new(s)))),
+        _ => Ok((i, Session::Recv(Box::new(t), Box::new(s)))),
+    }
+}
+
+/// 真偽値、整数、文字列、ユニット、ファイルハンドル、関数、ペア、加法的ペア、!型、
+/// 参照型、ref型、array型、セッション型をパース。
+pub fn parse_type(i: &str) -> IResult<&str, TypeExpr, VerboseError<&str>> {
     let (i, q) = parse_qual(i)?; // 修飾子
+    let (i, r) = parse_region(i)?; // リージョン
     let (i, _) = multispace1(i)?;
-    let (i, val) = alt((tag("bool"), tag("(")))(i)?;
-    if val == "bool" {
-        // bool型
+
+    // セッション型。!T.Sは、!Tの後に.が続くかで!型と区別する
+    if let Ok((i, s)) = parse_session(i) {
+        return Ok((
+            i,
+            TypeExpr {
+                qual: q,
+                region: r,
+                prim: PrimType::Chan(s),
+            },
+        ));
+    }
+
+    let (i, val) = alt((
+        tag("bool"),
+        tag("int"),
+        tag("str"),
+        tag("unit"),
+        tag("handle"),
+        tag("("),
+        tag("!"),
+        tag("&"),
+        tag("ref"),
+        tag("array"),
+    ))(i)?;
+
+    // 要素を持たない型
+    let base = match val {
+        "bool" => Some(PrimType::Bool),
+        "int" => Some(PrimType::Int),
+        "str" => Some(PrimType::Str),
+        "unit" => Some(PrimType::Unit),
+        "handle" => Some(PrimType::Handle),
+        _ => None,
+    };
+
+    if let Some(prim) = base {
         Ok((
             i,
             TypeExpr {
                 qual: q,
-                prim: PrimType::Bool,
+                region: r,
+                prim,
+            },
+        ))
+    } else if val == "!" || val == "&" || val == "ref" || val == "array" {
+        // !型、参照型、ref型、array型
+        let (i, _) = multispace0(i)?;
+        let (i, t) = parse_type(i)?;
+        Ok((
+            i,
+            TypeExpr {
+                qual: q,
+                region: r,
+                prim: match val {
+                    "!" => PrimType::Bang(Box::new(t)),
+                    "&" => PrimType::Ref(Box::new(t)),
+                    "ref" => PrimType::Cell(Box::new(t)),
+                    _ => PrimType::Array(Box::new(t)),
+                },
             },
         ))
     } else {
@@ -490,9 +1351,9 @@ fn parse_type(i: &str) -> IResult<&str, TypeExpr, VerboseError<&str>> {
         let (i, t1) = parse_type(i)?; // 1つめの型
         let (i, _) = multispace0(i)?;
 
-        // ->か*をパース
-        // ->の場合は関数型で、場合はペア型
-        let (i, op) = alt((tag("*"), tag("->")))(i)?;
+        // ->か*か&をパース
+        // ->の場合は関数型で、*の場合はペア型、&の場合は加法的ペア型
+        let (i, op) = alt((tag("*"), tag("&"), tag("->")))(i)?;
 
         let (i, _) = multispace0(i)?;
         let (i, t2) = parse_type(i)?; // 2つめの型
@@ -504,10 +1365,11 @@ fn parse_type(i: &str) -> IResult<&str, TypeExpr, VerboseError<&str>> {
             i,
             TypeExpr {
                 qual: q,
-                prim: if op == "*" {
-                    PrimType::Pair(Box::new(t1), Box::new(t2))
-                } else {
-                    PrimType::Arrow(Box::new(t1), Box::new(t2))
+                region: r,
+                prim: match op {
+                    "*" => PrimType::Pair(Box::new(t1), Box::new(t2)),
+                    "&" => PrimType::With(Box::new(t1), Box::new(t2)),
+                    _ => PrimType::Arrow(Box::new(t1), Box::new(t2)),
                 },
             },
         ))
@@ -0,0 +1,335 @@
+//! ## 代入による簡約
+//!
+//! ヒープと環境を用いずに、式を書き換えて1ステップずつ簡約する。
+//! 値呼びで左から右へ評価し、以下の規則を適用する（vは値）。
+//!
+//! ```text
+//! let       : let x : T = v; e                 → e[x := v]
+//! let-bang  : let !x = promote e1; e2          → e2[x := e1]
+//! beta      : (q fn x : T { e } v)             → e[x := v]
+//! delta     : (f v1 ... vn)                    → fの戻り値（fはn引数の外部関数）
+//! extern    : x                                → xの値（xは外部定義の値）
+//! if-true   : if q true { e1 } else { e2 }     → e1
+//! if-false  : if q false { e1 } else { e2 }    → e2
+//! split-pair: split q <v1, v2> as x, y { e }   → e[x := v1][y := v2]
+//! fst, snd  : fst q <|e1, e2|>、snd q <|e1, e2|> → e1、e2
+//! free-value: free x; e                        → e
+//! ```
+//!
+//! free文は変数しか取らないため、変数xに値を代入してもfree文の変数はそのまま残る。
+//! free-valueは、値が代入されて束縛されなくなった変数のfree文を取り除く。
+//!
+//! 借用、可変な参照、リージョンと配列、チャネルとスレッドには対応していない。
+
+use crate::{
+    eval::Value,
+    externs::{self, ExternVal, Externs},
+    parser::*,
+    subst::{free_vars, fresh, subst},
+};
+
+/// 1ステップの簡約の結果。適用した規則の名前と簡約後の式
+type Step = Option<(&'static str, Expr)>;
+
+struct Reducer<'a> {
+    externs: &'a Externs,
+}
+
+impl Reducer<'_> {
+    /// 外部関数varの定義の番号
+    fn extern_fun(&self, var: &str) -> Option<usize> {
+        let id = self.externs.defs.iter().rposition(|d| d.name == var)?;
+        match self.externs.defs[id].val {
+            ExternVal::Fun(_) => Some(id),
+            ExternVal::Value(_) => None,
+        }
+    }
+
+    /// 外部関数を部分適用した式から、外部関数の定義の番号と引数を取り出す
+    fn spine<'e>(&self, expr: &'e Expr) -> Option<(usize, Vec<&'e Expr>)> {
+        match expr {
+            Expr::Var(var) => Some((self.extern_fun(var)?, vec![])),
+            Expr::App(e) => {
+                let (id, mut args) = self.spine(&e.expr1)?;
+                if args.len() >= externs::arity(&self.externs.defs[id].ty) {
+                    return None;
+                }
+                args.push(&e.expr2);
+                Some((id, args))
+            }
+            _ => None,
+        }
+    }
+
+    /// 式が値かを判定
+    fn is_value(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::QVal(e) => match &e.val {
+                ValExpr::Pair(e1, e2) => self.is_value(e1) && self.is_value(e2),
+                _ => true,
+            },
+            Expr::Promote(_) => true,
+            Expr::Var(_) | Expr::App(_) => match self.spine(expr) {
+                Some((id, args)) => {
+                    args.len() < externs::arity(&self.externs.defs[id].ty)
+                        && args.iter().all(|a| self.is_value(a))
+                }
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// 部分式exprを1ステップ簡約し、簡約後の部分式をfで元の式に戻す
+    fn sub(&self, expr: &Expr, f: impl FnOnce(Expr) -> Expr) -> Result<Step, String> {
+        Ok(self.step(expr)?.map(|(rule, e)| (rule, f(e))))
+    }
+
+    /// 1ステップ簡約する。値の場合はNoneを返す
+    fn step(&self, expr: &Expr) -> Result<Step, String> {
+        if self.is_value(expr) {
+            return Ok(None);
+        }
+
+        match expr {
+            Expr::Let(e) => {
+                if let Some(r) = self.sub(&e.expr1, |e1| {
+                    Expr::Let(LetExpr {
+                        expr1: Box::new(e1),
+                        ..e.clone()
+                    })
+                })? {
+                    return Ok(Some(r));
+                }
+                Ok(Some(("let", subst(&e.expr2, &e.var, &e.expr1))))
+            }
+            Expr::LetBang(e) => {
+                if let Some(r) = self.sub(&e.expr1, |e1| {
+                    Expr::LetBang(LetBangExpr {
+                        expr1: Box::new(e1),
+                        ..e.clone()
+                    })
+                })? {
+                    return Ok(Some(r));
+                }
+                match e.expr1.as_ref() {
+                    Expr::Promote(p) => Ok(Some(("let-bang", subst(&e.expr2, &e.var, &p.expr)))),
+                    _ => Err("let !式の値が!型でない".to_string()),
+                }
+            }
+            Expr::If(e) => {
+                if let Some(r) = self.sub(&e.cond_expr, |c| {
+                    Expr::If(IfExpr {
+                        cond_expr: Box::new(c),
+                        ..e.clone()
+                    })
+                })? {
+                    return Ok(Some(r));
+                }
+                match e.cond_expr.as_ref() {
+                    Expr::QVal(QValExpr {
+                        val: ValExpr::Bool(true),
+                        ..
+                    }) => Ok(Some(("if-true", e.then_expr.as_ref().clone()))),
+                    Expr::QVal(QValExpr {
+                        val: ValExpr::Bool(false),
+                        ..
+                    }) => Ok(Some(("if-false", e.else_expr.as_ref().clone()))),
+                    _ => Err("ifの条件がboolでない".to_string()),
+                }
+            }
+            Expr::Split(e) => {
+                if let Some(r) = self.sub(&e.expr, |v| {
+                    Expr::Split(SplitExpr {
+                        expr: Box::new(v),
+                        ..e.clone()
+                    })
+                })? {
+                    return Ok(Some(r));
+                }
+                match e.expr.as_ref() {
+                    Expr::QVal(QValExpr {
+                        val: ValExpr::Pair(v1, v2),
+                        ..
+                    }) => {
+                        // v1がrightを自由変数に持つ場合は、rightを付け替えてから順に代入する
+                        let (right, body) = if free_vars(v1).contains(&e.right) {
+                            let mut avoid = free_vars(&e.body);
+                            avoid.extend(free_vars(v1));
+                            avoid.extend(free_vars(v2));
+                            avoid.insert(e.left.clone());
+                            let right = fresh(&e.right, &avoid);
+                            let body = subst(&e.body, &e.right, &Expr::Var(right.clone()));
+                            (right, body)
+                        } else {
+                            (e.right.clone(), e.body.as_ref().clone())
+                        };
+                        let body = subst(&body, &e.left, v1);
+                        Ok(Some(("split-pair", subst(&body, &right, v2))))
+                    }
+                    _ => Err("splitの対象がペアでない".to_string()),
+                }
+            }
+            Expr::Free(e) => Ok(Some(("free-value", e.expr.as_ref().clone()))),
+            Expr::App(e) => {
+                if let Some(r) = self.sub(&e.expr1, |e1| {
+                    Expr::App(AppExpr {
+                        expr1: Box::new(e1),
+                        expr2: e.expr2.clone(),
+                    })
+                })? {
+                    return Ok(Some(r));
+                }
+                if let Some(r) = self.sub(&e.expr2, |e2| {
+                    Expr::App(AppExpr {
+                        expr1: e.expr1.clone(),
+                        expr2: Box::new(e2),
+                    })
+                })? {
+                    return Ok(Some(r));
+                }
+                if let Expr::QVal(QValExpr {
+                    val: ValExpr::Fun(f),
+                    ..
+                }) = e.expr1.as_ref()
+                {
+                    return Ok(Some(("beta", subst(&f.expr, &f.var, &e.expr2))));
+                }
+                match self.spine(expr) {
+                    Some((id, args)) => Ok(Some(("delta", self.apply_extern(id, &args)?))),
+                    None => Err("関数でない値を適用している".to_string()),
+                }
+            }
+            Expr::Proj(e) => {
+                if let Some(r) = self.sub(&e.expr, |v| {
+                    Expr::Proj(ProjExpr {
+                        proj: e.proj,
+                        expr: Box::new(v),
+                    })
+                })? {
+                    return Ok(Some(r));
+                }
+                match (e.proj, e.expr.as_ref()) {
+                    (
+                        Proj::Fst,
+                        Expr::QVal(QValExpr {
+                            val: ValExpr::With(e1, _),
+                            ..
+                        }),
+                    ) => Ok(Some(("fst", e1.as_ref().clone()))),
+                    (
+                        Proj::Snd,
+                        Expr::QVal(QValExpr {
+                            val: ValExpr::With(_, e2),
+                            ..
+                        }),
+                    ) => Ok(Some(("snd", e2.as_ref().clone()))),
+                    _ => Err("射影の対象が加法的ペアでない".to_string()),
+                }
+            }
+            Expr::QVal(e) => match &e.val {
+                ValExpr::Pair(e1, e2) => {
+                    let pair = |e1, e2| {
+                        Expr::QVal(QValExpr {
+                            qual: e.qual,
+                            region: e.region.clone(),
+                            val: ValExpr::Pair(e1, e2),
+                        })
+                    };
+                    if let Some(r) = self.sub(e1, |v1| pair(Box::new(v1), e2.clone()))? {
+                        return Ok(Some(r));
+                    }
+                    self.sub(e2, |v2| pair(e1.clone(), Box::new(v2)))
+                }
+                _ => Ok(None),
+            },
+            Expr::Var(var) => match self.externs.defs.iter().rev().find(|d| &d.name == var) {
+                Some(def) => match &def.val {
+                    ExternVal::Value(v) => Ok(Some(("extern", from_value(v)?))),
+                    ExternVal::Fun(_) => Ok(None),
+                },
+                None => Err(format!("\"{}\"という変数は束縛されていない", var)),
+            },
+            Expr::Promote(_) => Ok(None),
+            Expr::Borrow(_) => Err("借用は代入による簡約に対応していない".to_string()),
+            Expr::NewRef(_) | Expr::Swap(_) => {
+                Err("可変な参照は代入による簡約に対応していない".to_string())
+            }
+            Expr::LetRegion(_) | Expr::Alloc(_) | Expr::Get(_) | Expr::Set(_) => {
+                Err("リージョンと配列は代入による簡約に対応していない".to_string())
+            }
+            Expr::New(_) | Expr::Send(_) | Expr::Recv(_) | Expr::Close(_) | Expr::Fork(_) => {
+                Err("チャネルとスレッドは代入による簡約に対応していない".to_string())
+            }
+        }
+    }
+
+    /// 全ての引数がそろった外部関数を呼び出し、戻り値を式にする
+    fn apply_extern(&self, id: usize, args: &[&Expr]) -> Result<Expr, String> {
+        let def = &self.externs.defs[id];
+        let f = match &def.val {
+            ExternVal::Fun(f) => f.clone(),
+            ExternVal::Value(_) => return Err(format!("\"{}\"は関数でない", def.name)),
+        };
+        let vals = args
+            .iter()
+            .map(|a| to_value(a))
+            .collect::<Result<Vec<_>, _>>()?;
+        let v = f(vals).map_err(|msg| format!("外部関数\"{}\"のエラー: {}", def.name, msg))?;
+        from_value(&v)
+    }
+}
+
+/// 値の式を外部関数に渡す値に変換
+fn to_value(expr: &Expr) -> Result<Value, String> {
+    match expr {
+        Expr::QVal(e) => match &e.val {
+            ValExpr::Bool(b) => Ok(Value::Bool(e.qual, *b)),
+            ValExpr::Int(n) => Ok(Value::Int(e.qual, *n)),
+            ValExpr::Str(s) => Ok(Value::Str(e.qual, s.clone())),
+            ValExpr::Pair(e1, e2) => Ok(Value::Pair(
+                e.qual,
+                Box::new(to_value(e1)?),
+                Box::new(to_value(e2)?),
+            )),
+            _ => Err(format!("{}は外部関数に渡せない", expr)),
+        },
+        _ => Err(format!("{}は外部関数に渡せない", expr)),
+    }
+}
+
+/// 外部定義の値を式に変換
+fn from_value(v: &Value) -> Result<Expr, String> {
+    let (qual, val) = match v {
+        Value::Bool(q, b) => (*q, ValExpr::Bool(*b)),
+        Value::Int(q, n) => (*q, ValExpr::Int(*n)),
+        Value::Str(q, s) => (*q, ValExpr::Str(s.clone())),
+        Value::Pair(q, v1, v2) => (
+            *q,
+            ValExpr::Pair(Box::new(from_value(v1)?), Box::new(from_value(v2)?)),
+        ),
+        _ => return Err(format!("{}は式として表せない", v)),
+    };
+    Ok(Expr::QVal(QValExpr {
+        qual,
+        region: None,
+        val,
+    }))
+}
+
+/// 値になるまで簡約し、値を返す
+///
+/// 簡約する度に、適用した規則の名前と簡約後の式をtraceに渡す
+pub fn reduce(
+    expr: &Expr,
+    externs: &Externs,
+    mut trace: impl FnMut(&str, &Expr),
+) -> Result<Expr, String> {
+    let reducer = Reducer { externs };
+    let mut expr = expr.clone();
+    while let Some((rule, e)) = reducer.step(&expr)? {
+        trace(rule, &e);
+        expr = e;
+    }
+    Ok(expr)
+}
@@ -0,0 +1,361 @@
+//! ## 名前解決
+//!
+//! 構文木の全ての変数を解決し、束縛変数をde Bruijnインデックスで表した式（[Term]）を返す。
+//! 型付けの前には、式を作成せずに自由変数のみを求める[check]で、定義されていない変数をまとめて報告する。
+//! 束縛変数は、参照する位置から数えて何番目に内側の束縛かを表すインデックスとなり、
+//! 外部定義などの自由変数は名前のまま残る。
+//! split式はleft、rightの順に束縛するため、本体ではrightが0番、leftが1番となる。
+//!
+//! 型付けと評価は、この式ではなく構文木に対して行う。
+//! 束縛子の変数名は、表示のための名前として保持する。
+
+use crate::{
+    parser::{Expr, Proj, Qual, Session, TypeExpr, ValExpr},
+    subst::free_vars,
+};
+use std::{collections::BTreeSet, fmt};
+
+/// 変数の参照
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Var {
+    Bound(usize), // 束縛変数。de Bruijnインデックス
+    Free(String), // 自由変数。外部定義などの名前
+}
+
+impl fmt::Display for Var {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Var::Bound(i) => write!(f, "#{}", i),
+            Var::Free(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// 名前解決した式
+///
+/// 構文木の[Expr]と同じ構造を持ち、変数を[Var]で参照する。
+/// 束縛子のvar、left、rightは表示のための名前で、参照には用いない
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Term {
+    Let {
+        var: String,
+        ty: TypeExpr,
+        expr1: Box<Term>,
+        expr2: Box<Term>,
+    },
+    LetBang {
+        var: String,
+        expr1: Box<Term>,
+        expr2: Box<Term>,
+    },
+    If {
+        cond_expr: Box<Term>,
+        then_expr: Box<Term>,
+        else_expr: Box<Term>,
+    },
+    Split {
+        expr: Box<Term>,
+        left: String,
+        right: String,
+        body: Box<Term>,
+    },
+    Free {
+        var: Var,
+        expr: Box<Term>,
+    },
+    App {
+        expr1: Box<Term>,
+        expr2: Box<Term>,
+    },
+    Proj {
+        proj: Proj,
+        expr: Box<Term>,
+    },
+    Promote {
+        expr: Box<Term>,
+    },
+    New {
+        session: Session,
+    },
+    Send {
+        chan: Box<Term>,
+        expr: Box<Term>,
+    },
+    Recv {
+        chan: Box<Term>,
+    },
+    Close {
+        chan: Box<Term>,
+    },
+    Fork {
+        expr1: Box<Term>,
+        expr2: Box<Term>,
+    },
+    Borrow(Var),
+    NewRef {
+        expr: Box<Term>,
+    },
+    Swap {
+        cell: Box<Term>,
+        expr: Box<Term>,
+    },
+    LetRegion {
+        region: String,
+        expr: Box<Term>,
+    },
+    Alloc {
+        len: Box<Term>,
+        expr: Box<Term>,
+    },
+    Get {
+        array: Box<Term>,
+        index: Box<Term>,
+    },
+    Set {
+        array: Box<Term>,
+        index: Box<Term>,
+        expr: Box<Term>,
+    },
+    Var(Var),
+    QVal {
+        qual: Qual,
+        region: Option<String>,
+        val: Val,
+    },
+}
+
+/// 名前解決した値
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Val {
+    Bool(bool),
+    Int(i64),
+    Str(String),
+    Pair(Box<Term>, Box<Term>),
+    With(Box<Term>, Box<Term>),
+    Fun {
+        var: String,
+        ty: TypeExpr,
+        expr: Box<Term>,
+    },
+}
+
+/// 式を1行で表示する。束縛変数は#とインデックスで表示する
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Term::Let {
+                var,
+                ty,
+                expr1,
+                expr2,
+            } => write!(f, "let {} : {} = {}; {}", var, ty, expr1, expr2),
+            Term::LetBang { var, expr1, expr2 } => write!(f, "let !{} = {}; {}", var, expr1, expr2),
+            Term::If {
+                cond_expr,
+                then_expr,
+                else_expr,
+            } => write!(
+                f,
+                "if {} {{ {} }} else {{ {} }}",
+                cond_expr, then_expr, else_expr
+            ),
+            Term::Split {
+                expr,
+                left,
+                right,
+                body,
+            } => write!(f, "split {} as {}, {} {{ {} }}", expr, left, right, body),
+            Term::Free { var, expr } => write!(f, "free {}; {}", var, expr),
+            Term::App { expr1, expr2 } => write!(f, "({} {})", expr1, expr2),
+            Term::Proj { proj, expr } => match proj {
+                Proj::Fst => write!(f, "fst {}", expr),
+                Proj::Snd => write!(f, "snd {}", expr),
+            },
+            Term::Promote { expr } => write!(f, "promote {}", expr),
+            Term::New { session } => write!(f, "new {}", session),
+            Term::Send { chan, expr } => write!(f, "send {} {}", chan, expr),
+            Term::Recv { chan } => write!(f, "recv {}", chan),
+            Term::Close { chan } => write!(f, "close {}", chan),
+            Term::Fork { expr1, expr2 } => write!(f, "fork {}; {}", expr1, expr2),
+            Term::Borrow(var) => write!(f, "&{}", var),
+            Term::NewRef { expr } => write!(f, "new {}", expr),
+            Term::Swap { cell, expr } => write!(f, "swap {} {}", cell, expr),
+            Term::LetRegion { region, expr } => write!(f, "letregion {} {{ {} }}", region, expr),
+            Term::Alloc { len, expr } => write!(f, "alloc {} {}", len, expr),
+            Term::Get { array, index } => write!(f, "get {} {}", array, index),
+            Term::Set { array, index, expr } => write!(f, "set {} {} {}", array, index, expr),
+            Term::Var(var) => write!(f, "{}", var),
+            Term::QVal { qual, region, val } => match region {
+                Some(r) => write!(f, "{}@{} {}", qual, r, val),
+                None => write!(f, "{} {}", qual, val),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Val {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Val::Bool(b) => write!(f, "{}", b),
+            Val::Int(n) => write!(f, "{}", n),
+            Val::Str(s) => write!(f, "{}", ValExpr::Str(s.clone())),
+            Val::Pair(e1, e2) => write!(f, "<{}, {}>", e1, e2),
+            Val::With(e1, e2) => write!(f, "<|{}, {}|>", e1, e2),
+            Val::Fun { var, ty, expr } => write!(f, "fn {} : {} {{ {} }}", var, ty, expr),
+        }
+    }
+}
+
+/// 式exprに定義されていない変数がないかを検査する
+///
+/// globalsは自由変数として参照できる変数名で、外部定義や型環境に事前に与えた変数となる。
+/// 束縛されておらずglobalsにも含まれない変数があった場合、それらを名前順に全て含めたエラーを返す
+pub fn check(expr: &Expr, globals: &BTreeSet<String>) -> Result<(), String> {
+    let names: Vec<String> = free_vars(expr)
+        .difference(globals)
+        .map(|v| format!("\"{}\"", v))
+        .collect();
+    if names.is_empty() {
+        return Ok(());
+    }
+    Err(format!("{}という変数は定義されていない", names.join("、")))
+}
+
+/// 式exprの変数を解決し、名前解決した式を返す
+///
+/// 定義されていない変数のエラーは[check]と同じ
+pub fn resolve(expr: &Expr, globals: &BTreeSet<String>) -> Result<Term, String> {
+    check(expr, globals)?;
+    let mut r = Resolver { scope: Vec::new() };
+    Ok(r.expr(expr))
+}
+
+struct Resolver {
+    scope: Vec<String>, // 束縛変数。末尾ほど内側の束縛
+}
+
+impl Resolver {
+    /// 変数名を解決
+    fn var(&self, name: &str) -> Var {
+        match self.scope.iter().rposition(|v| v == name) {
+            Some(i) => Var::Bound(self.scope.len() - 1 - i),
+            None => Var::Free(name.to_string()),
+        }
+    }
+
+    /// 変数varsを束縛して式exprを解決
+    fn under(&mut self, vars: &[&String], expr: &Expr) -> Box<Term> {
+        let n = self.scope.len();
+        self.scope.extend(vars.iter().map(|v| v.to_string()));
+        let term = self.boxed(expr);
+        self.scope.truncate(n);
+        term
+    }
+
+    fn boxed(&mut self, expr: &Expr) -> Box<Term> {
+        Box::new(self.expr(expr))
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Term {
+        match expr {
+            Expr::Let(e) => Term::Let {
+                var: e.var.clone(),
+                ty: e.ty.clone(),
+                expr1: self.boxed(&e.expr1),
+                expr2: self.under(&[&e.var], &e.expr2),
+            },
+            Expr::LetBang(e) => Term::LetBang {
+                var: e.var.clone(),
+                expr1: self.boxed(&e.expr1),
+                expr2: self.under(&[&e.var], &e.expr2),
+            },
+            Expr::If(e) => Term::If {
+                cond_expr: self.boxed(&e.cond_expr),
+                then_expr: self.boxed(&e.then_expr),
+                else_expr: self.boxed(&e.else_expr),
+            },
+            Expr::Split(e) => Term::Split {
+                expr: self.boxed(&e.expr),
+                left: e.left.clone(),
+                right: e.right.clone(),
+                body: self.under(&[&e.left, &e.right], &e.body),
+            },
+            Expr::Free(e) => Term::Free {
+                var: self.var(&e.var),
+                expr: self.boxed(&e.expr),
+            },
+            Expr::App(e) => Term::App {
+                expr1: self.boxed(&e.expr1),
+                expr2: self.boxed(&e.expr2),
+            },
+            Expr::Proj(e) => Term::Proj {
+                proj: e.proj,
+                expr: self.boxed(&e.expr),
+            },
+            Expr::Promote(e) => Term::Promote {
+                expr: self.boxed(&e.expr),
+            },
+            Expr::New(e) => Term::New {
+                session: e.session.clone(),
+            },
+            Expr::Send(e) => Term::Send {
+                chan: self.boxed(&e.chan),
+                expr: self.boxed(&e.expr),
+            },
+            Expr::Recv(e) => Term::Recv {
+                chan: self.boxed(&e.chan),
+            },
+            Expr::Close(e) => Term::Close {
+                chan: self.boxed(&e.chan),
+            },
+            Expr::Fork(e) => Term::Fork {
+                expr1: self.boxed(&e.expr1),
+                expr2: self.boxed(&e.expr2),
+            },
+            Expr::Borrow(var) => Term::Borrow(self.var(var)),
+            Expr::NewRef(e) => Term::NewRef {
+                expr: self.boxed(&e.expr),
+            },
+            Expr::Swap(e) => Term::Swap {
+                cell: self.boxed(&e.cell),
+                expr: self.boxed(&e.expr),
+            },
+            Expr::LetRegion(e) => Term::LetRegion {
+                region: e.region.clone(),
+                expr: self.boxed(&e.expr),
+            },
+            Expr::Alloc(e) => Term::Alloc {
+                len: self.boxed(&e.len),
+                expr: self.boxed(&e.expr),
+            },
+            Expr::Get(e) => Term::Get {
+                array: self.boxed(&e.array),
+                index: self.boxed(&e.index),
+            },
+            Expr::Set(e) => Term::Set {
+                array: self.boxed(&e.array),
+                index: self.boxed(&e.index),
+                expr: self.boxed(&e.expr),
+            },
+            Expr::Var(var) => Term::Var(self.var(var)),
+            Expr::QVal(e) => Term::QVal {
+                qual: e.qual,
+                region: e.region.clone(),
+                val: match &e.val {
+                    ValExpr::Bool(b) => Val::Bool(*b),
+                    ValExpr::Int(n) => Val::Int(*n),
+                    ValExpr::Str(s) => Val::Str(s.clone()),
+                    ValExpr::Pair(e1, e2) => Val::Pair(self.boxed(e1), self.boxed(e2)),
+                    ValExpr::With(e1, e2) => Val::With(self.boxed(e1), self.boxed(e2)),
+                    ValExpr::Fun(f) => Val::Fun {
+                        var: f.var.clone(),
+                        ty: f.ty.clone(),
+                        expr: self.under(&[&f.var], &f.expr),
+                    },
+                },
+            },
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! ## 文字列
+//!
+//! 文字列を操作する外部関数。
+//! lin型の文字列は、解放か消費しなければならない文字列バッファを表す。
+//! un型の文字列は変更できない文字列リテラルを表し、部分型によりlin型の文字列として渡せる。
+//!
+//! ```text
+//! concat : un (lin str -> lin (lin str -> lin str))
+//! length : un (lin str -> lin (lin str * un int))
+//! ```
+//!
+//! concatは2つの文字列を消費し、連結した新しい文字列バッファを返す。
+//! lengthは文字列を消費せずに長さを調べられるよう、文字列と長さ（文字数）のペアを返す。
+
+use crate::{eval::Value, externs::Externs, parser, parser::Qual};
+
+/// 型をパース
+fn ty(src: &str) -> parser::TypeExpr {
+    parser::parse_type(src).unwrap().1
+}
+
+/// 文字列を操作する外部関数を登録した外部定義を返す
+pub fn externs() -> Externs {
+    let concat = |args: Vec<Value>| match (&args[0], &args[1]) {
+        (Value::Str(_, s1), Value::Str(_, s2)) => {
+            Ok(Value::Str(Qual::Lin, format!("{}{}", s1, s2)))
+        }
+        _ => Err("concatの引数がstrでない".to_string()),
+    };
+
+    let length = |args: Vec<Value>| match &args[0] {
+        Value::Str(q, s) => Ok(Value::Pair(
+            Qual::Lin,
+            Box::new(Value::Str(*q, s.clone())),
+            Box::new(Value::Int(Qual::Un, s.chars().count() as i64)),
+        )),
+        _ => Err("lengthの引数がstrでない".to_string()),
+    };
+
+    Externs::new()
+        .func(
+            "concat",
+            ty("un (lin str -> lin (lin str -> lin str))"),
+            concat,
+        )
+        .func(
+            "length",
+            ty("un (lin str -> lin (lin str * un int))"),
+            length,
+        )
+}
@@ -0,0 +1,605 @@
+//! ## 代入とα同値
+//!
+//! 束縛変数の名前を考慮して式を扱うための関数。
+//! [free_vars]は式の自由変数を、[subst]は式の自由変数に式を代入した式を返し、
+//! [alpha_eq]は2つの式が束縛変数の名前の違いを除いて等しいかを判定する。
+//! 代入では、束縛変数が代入する式の自由変数を捕獲する場合は、束縛変数を新しい名前に付け替える。
+//! letregion式のリージョン名も束縛変数として扱い、代入する式のリージョン名を捕獲する場合は同様に付け替える。
+
+use crate::parser::*;
+use std::collections::BTreeSet;
+
+/// 式exprの自由変数の集合
+///
+/// 借用とfree文の変数も自由変数に含める
+pub fn free_vars(expr: &Expr) -> BTreeSet<String> {
+    let mut fv = BTreeSet::new();
+    free_vars_in(expr, &mut vec![], &mut fv);
+    fv
+}
+
+/// 式exprの自由変数のうち、boundに含まれないものをfvに追加
+pub(crate) fn free_vars_in(expr: &Expr, bound: &mut Vec<String>, fv: &mut BTreeSet<String>) {
+    // 変数varを束縛して式eの自由変数を探す
+    fn under(vars: &[&str], e: &Expr, bound: &mut Vec<String>, fv: &mut BTreeSet<String>) {
+        let n = bound.len();
+        bound.extend(vars.iter().map(|v| v.to_string()));
+        free_vars_in(e, bound, fv);
+        bound.truncate(n);
+    }
+
+    match expr {
+        Expr::Var(v) | Expr::Borrow(v) => {
+            if !bound.contains(v) {
+                fv.insert(v.clone());
+            }
+        }
+        Expr::Free(e) => {
+            if !bound.contains(&e.var) {
+                fv.insert(e.var.clone());
+            }
+            free_vars_in(&e.expr, bound, fv);
+        }
+        Expr::Let(e) => {
+            free_vars_in(&e.expr1, bound, fv);
+            under(&[e.var.as_str()], &e.expr2, bound, fv);
+        }
+        Expr::LetBang(e) => {
+            free_vars_in(&e.expr1, bound, fv);
+            under(&[e.var.as_str()], &e.expr2, bound, fv);
+        }
+        Expr::Split(e) => {
+            free_vars_in(&e.expr, bound, fv);
+            under(&[e.left.as_str(), e.right.as_str()], &e.body, bound, fv);
+        }
+        Expr::If(e) => {
+            for e in [&e.cond_expr, &e.then_expr, &e.else_expr] {
+                free_vars_in(e, bound, fv);
+            }
+        }
+        Expr::App(e) => {
+            free_vars_in(&e.expr1, bound, fv);
+            free_vars_in(&e.expr2, bound, fv);
+        }
+        Expr::Proj(e) => free_vars_in(&e.expr, bound, fv),
+        Expr::Promote(e) => free_vars_in(&e.expr, bound, fv),
+        Expr::New(_) => (),
+        Expr::Send(e) => {
+            free_vars_in(&e.chan, bound, fv);
+            free_vars_in(&e.expr, bound, fv);
+        }
+        Expr::Recv(e) => free_vars_in(&e.chan, bound, fv),
+        Expr::Close(e) => free_vars_in(&e.chan, bound, fv),
+        Expr::Fork(e) => {
+            free_vars_in(&e.expr1, bound, fv);
+            free_vars_in(&e.expr2, bound, fv);
+        }
+        Expr::NewRef(e) => free_vars_in(&e.expr, bound, fv),
+        Expr::Swap(e) => {
+            free_vars_in(&e.cell, bound, fv);
+            free_vars_in(&e.expr, bound, fv);
+        }
+        Expr::LetRegion(e) => free_vars_in(&e.expr, bound, fv),
+        Expr::Alloc(e) => {
+            free_vars_in(&e.len, bound, fv);
+            free_vars_in(&e.expr, bound, fv);
+        }
+        Expr::Get(e) => {
+            free_vars_in(&e.array, bound, fv);
+            free_vars_in(&e.index, bound, fv);
+        }
+        Expr::Set(e) => {
+            for e in [&e.array, &e.index, &e.expr] {
+                free_vars_in(e, bound, fv);
+            }
+        }
+        Expr::QVal(e) => match &e.val {
+            ValExpr::Pair(e1, e2) | ValExpr::With(e1, e2) => {
+                free_vars_in(e1, bound, fv);
+                free_vars_in(e2, bound, fv);
+            }
+            ValExpr::Fun(f) => under(&[f.var.as_str()], &f.expr, bound, fv),
+            _ => (),
+        },
+    }
+}
+
+/// 式exprに現れるリージョン名に、letregion式で束縛されているかとともにfを適用する
+///
+/// 値と型に付いたリージョン名と、letregion式の束縛するリージョン名（束縛されているものとする）が対象
+fn visit_regions(expr: &mut Expr, bound: &mut Vec<String>, f: &mut impl FnMut(&mut String, bool)) {
+    fn ty(t: &mut TypeExpr, bound: &[String], f: &mut impl FnMut(&mut String, bool)) {
+        if let Some(r) = &mut t.region {
+            let b = bound.contains(r);
+            f(r, b);
+        }
+        match &mut t.prim {
+            PrimType::Pair(t1, t2) | PrimType::With(t1, t2) | PrimType::Arrow(t1, t2) => {
+                ty(t1, bound, f);
+                ty(t2, bound, f);
+            }
+            PrimType::Bang(t) | PrimType::Ref(t) | PrimType::Cell(t) | PrimType::Array(t) => {
+                ty(t, bound, f)
+            }
+            PrimType::Chan(s) => session(s, bound, f),
+            _ => (),
+        }
+    }
+
+    fn session(s: &mut Session, bound: &[String], f: &mut impl FnMut(&mut String, bool)) {
+        if let Session::Send(t, s) | Session::Recv(t, s) = s {
+            ty(t, bound, f);
+            session(s, bound, f);
+        }
+    }
+
+    match expr {
+        Expr::Var(_) | Expr::Borrow(_) => (),
+        Expr::New(e) => session(&mut e.session, bound, f),
+        Expr::Free(e) => visit_regions(&mut e.expr, bound, f),
+        Expr::Let(e) => {
+            ty(&mut e.ty, bound, f);
+            visit_regions(&mut e.expr1, bound, f);
+            visit_regions(&mut e.expr2, bound, f);
+        }
+        Expr::LetBang(e) => {
+            visit_regions(&mut e.expr1, bound, f);
+            visit_regions(&mut e.expr2, bound, f);
+        }
+        Expr::Split(e) => {
+            visit_regions(&mut e.expr, bound, f);
+            visit_regions(&mut e.body, bound, f);
+        }
+        Expr::If(e) => {
+            for e in [&mut e.cond_expr, &mut e.then_expr, &mut e.else_expr] {
+                visit_regions(e, bound, f);
+            }
+        }
+        Expr::App(e) => {
+            visit_regions(&mut e.expr1, bound, f);
+            visit_regions(&mut e.expr2, bound, f);
+        }
+        Expr::Proj(e) => visit_regions(&mut e.expr, bound, f),
+        Expr::Promote(e) => visit_regions(&mut e.expr, bound, f),
+        Expr::Send(e) => {
+            visit_regions(&mut e.chan, bound, f);
+            visit_regions(&mut e.expr, bound, f);
+        }
+        Expr::Recv(e) => visit_regions(&mut e.chan, bound, f),
+        Expr::Close(e) => visit_regions(&mut e.chan, bound, f),
+        Expr::Fork(e) => {
+            visit_regions(&mut e.expr1, bound, f);
+            visit_regions(&mut e.expr2, bound, f);
+        }
+        Expr::NewRef(e) => visit_regions(&mut e.expr, bound, f),
+        Expr::Swap(e) => {
+            visit_regions(&mut e.cell, bound, f);
+            visit_regions(&mut e.expr, bound, f);
+        }
+        Expr::LetRegion(e) => {
+            f(&mut e.region, true);
+            bound.push(e.region.clone());
+            visit_regions(&mut e.expr, bound, f);
+            bound.pop();
+        }
+        Expr::Alloc(e) => {
+            visit_regions(&mut e.len, bound, f);
+            visit_regions(&mut e.expr, bound, f);
+        }
+        Expr::Get(e) => {
+            visit_regions(&mut e.array, bound, f);
+            visit_regions(&mut e.index, bound, f);
+        }
+        Expr::Set(e) => {
+            for e in [&mut e.array, &mut e.index, &mut e.expr] {
+                visit_regions(e, bound, f);
+            }
+        }
+        Expr::QVal(e) => {
+            if let Some(r) = &mut e.region {
+                let b = bound.contains(r);
+                f(r, b);
+            }
+            match &mut e.val {
+                ValExpr::Pair(e1, e2) | ValExpr::With(e1, e2) => {
+                    visit_regions(e1, bound, f);
+                    visit_regions(e2, bound, f);
+                }
+                ValExpr::Fun(fun) => {
+                    ty(&mut fun.ty, bound, f);
+                    visit_regions(&mut fun.expr, bound, f);
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+/// 式exprの、letregion式で束縛されていないリージョン名の集合
+fn free_regions(expr: &Expr) -> BTreeSet<String> {
+    let mut fr = BTreeSet::new();
+    visit_regions(&mut expr.clone(), &mut vec![], &mut |r, b| {
+        if !b {
+            fr.insert(r.clone());
+        }
+    });
+    fr
+}
+
+/// avoidに含まれず、キーワードでもない、baseを元にした新しい変数名
+///
+/// 変数名は英字のみから成るため、baseの後ろにa, b, ..., z, aa, ab, ...を付け加える
+pub(crate) fn fresh(base: &str, avoid: &BTreeSet<String>) -> String {
+    let mut n = 0usize;
+    loop {
+        let mut suffix = Vec::new();
+        let mut m = n;
+        loop {
+            suffix.push(b'a' + (m % 26) as u8);
+            if m < 26 {
+                break;
+            }
+            m = m / 26 - 1;
+        }
+        suffix.reverse();
+        let name = format!("{}{}", base, String::from_utf8(suffix).unwrap());
+        if !avoid.contains(&name) && !KEYWORDS.contains(&name.as_str()) {
+            return name;
+        }
+        n += 1;
+    }
+}
+
+/// 式exprの自由変数varに式valを代入した式を返す
+///
+/// valの自由変数を捕獲する束縛変数は、英字を付け加えた新しい名前に付け替える。
+/// letregion式のリージョン名がvalの束縛されていないリージョン名を捕獲する場合も、同様に付け替える。
+/// 借用とfree文の変数は式を書けないため、valが変数の場合のみ付け替え、それ以外の場合はそのまま残す
+pub fn subst(expr: &Expr, var: &str, val: &Expr) -> Expr {
+    let val_fv = free_vars(val);
+    let val_fr = free_regions(val);
+    Subst {
+        var,
+        val,
+        val_fv: &val_fv,
+        val_fr: &val_fr,
+    }
+    .expr(expr)
+}
+
+struct Subst<'a> {
+    var: &'a str,
+    val: &'a Expr,
+    val_fv: &'a BTreeSet<String>, // valの自由変数
+    val_fr: &'a BTreeSet<String>, // valの束縛されていないリージョン名
+}
+
+impl Subst<'_> {
+    /// 変数名を付け替える場合の新しい変数名
+    fn rename(&self, name: &str) -> Option<String> {
+        match self.val {
+            Expr::Var(v) if name == self.var => Some(v.clone()),
+            _ => None,
+        }
+    }
+
+    /// 束縛変数varsのスコープにある式bodyへ代入し、付け替えた束縛変数と式を返す
+    fn under(&self, vars: &[&String], body: &Expr) -> (Vec<String>, Expr) {
+        let mut vars: Vec<String> = vars.iter().map(|v| v.to_string()).collect();
+        if vars.iter().any(|v| v == self.var) {
+            return (vars, body.clone()); // varは隠蔽されている
+        }
+
+        let body_fv = free_vars(body);
+        if !body_fv.contains(self.var) {
+            return (vars, body.clone()); // 代入する箇所がない
+        }
+
+        // valの自由変数を捕獲する束縛変数を付け替える
+        let mut body = body.clone();
+        for i in 0..vars.len() {
+            if self.val_fv.contains(&vars[i]) {
+                let mut avoid = body_fv.clone();
+                avoid.extend(self.val_fv.iter().cloned());
+                avoid.extend(vars.iter().cloned());
+                avoid.insert(self.var.to_string());
+                let name = fresh(&vars[i], &avoid);
+                body = subst(&body, &vars[i], &Expr::Var(name.clone()));
+                vars[i] = name;
+            }
+        }
+        (vars, self.expr(&body))
+    }
+
+    fn boxed(&self, expr: &Expr) -> Box<Expr> {
+        Box::new(self.expr(expr))
+    }
+
+    fn expr(&self, expr: &Expr) -> Expr {
+        match expr {
+            Expr::Var(v) if v == self.var => self.val.clone(),
+            Expr::Var(_) | Expr::New(_) => expr.clone(),
+            Expr::Borrow(v) => Expr::Borrow(self.rename(v).unwrap_or_else(|| v.clone())),
+            Expr::Free(e) => Expr::Free(FreeExpr {
+                var: self.rename(&e.var).unwrap_or_else(|| e.var.clone()),
+                expr: self.boxed(&e.expr),
+            }),
+            Expr::Let(e) => {
+                let (vars, expr2) = self.under(&[&e.var], &e.expr2);
+                Expr::Let(LetExpr {
+                    var: vars[0].clone(),
+                    ty: e.ty.clone(),
+                    expr1: self.boxed(&e.expr1),
+                    expr2: Box::new(expr2),
+                })
+            }
+            Expr::LetBang(e) => {
+                let (vars, expr2) = self.under(&[&e.var], &e.expr2);
+                Expr::LetBang(LetBangExpr {
+                    var: vars[0].clone(),
+                    expr1: self.boxed(&e.expr1),
+                    expr2: Box::new(expr2),
+                })
+            }
+            Expr::Split(e) => {
+                let (vars, body) = self.under(&[&e.left, &e.right], &e.body);
+                Expr::Split(SplitExpr {
+                    expr: self.boxed(&e.expr),
+                    left: vars[0].clone(),
+                    right: vars[1].clone(),
+                    body: Box::new(body),
+                })
+            }
+            Expr::If(e) => Expr::If(IfExpr {
+                cond_expr: self.boxed(&e.cond_expr),
+                then_expr: self.boxed(&e.then_expr),
+                else_expr: self.boxed(&e.else_expr),
+            }),
+            Expr::App(e) => Expr::App(AppExpr {
+                expr1: self.boxed(&e.expr1),
+                expr2: self.boxed(&e.expr2),
+            }),
+            Expr::Proj(e) => Expr::Proj(ProjExpr {
+                proj: e.proj,
+                expr: self.boxed(&e.expr),
+            }),
+            Expr::Promote(e) => Expr::Promote(PromoteExpr {
+                expr: self.boxed(&e.expr),
+            }),
+            Expr::Send(e) => Expr::Send(SendExpr {
+                chan: self.boxed(&e.chan),
+                expr: self.boxed(&e.expr),
+            }),
+            Expr::Recv(e) => Expr::Recv(RecvExpr {
+                chan: self.boxed(&e.chan),
+            }),
+            Expr::Close(e) => Expr::Close(CloseExpr {
+                chan: self.boxed(&e.chan),
+            }),
+            Expr::Fork(e) => Expr::Fork(ForkExpr {
+                expr1: self.boxed(&e.expr1),
+                expr2: self.boxed(&e.expr2),
+            }),
+            Expr::NewRef(e) => Expr::NewRef(NewRefExpr {
+                expr: self.boxed(&e.expr),
+            }),
+            Expr::Swap(e) => Expr::Swap(SwapExpr {
+                cell: self.boxed(&e.cell),
+                expr: self.boxed(&e.expr),
+            }),
+            Expr::LetRegion(e) => {
+                let mut region = e.region.clone();
+                let mut body = (*e.expr).clone();
+
+                // valの束縛されていないリージョン名を捕獲する場合、リージョン名を付け替える
+                // 付け替えた名前は、本体に現れる全てのリージョン名と異なるものとする
+                if self.val_fr.contains(&region) && free_vars(&body).contains(self.var) {
+                    let mut avoid = self.val_fr.clone();
+                    visit_regions(&mut body.clone(), &mut vec![], &mut |r, _| {
+                        avoid.insert(r.clone());
+                    });
+                    let name = fresh(&region, &avoid);
+                    visit_regions(&mut body, &mut vec![], &mut |r, b| {
+                        if !b && *r == region {
+                            *r = name.clone();
+                        }
+                    });
+                    region = name;
+                }
+
+                Expr::LetRegion(LetRegionExpr {
+                    region,
+                    expr: self.boxed(&body),
+                })
+            }
+            Expr::Alloc(e) => Expr::Alloc(AllocExpr {
+                len: self.boxed(&e.len),
+                expr: self.boxed(&e.expr),
+            }),
+            Expr::Get(e) => Expr::Get(GetExpr {
+                array: self.boxed(&e.array),
+                index: self.boxed(&e.index),
+            }),
+            Expr::Set(e) => Expr::Set(SetExpr {
+                array: self.boxed(&e.array),
+                index: self.boxed(&e.index),
+                expr: self.boxed(&e.expr),
+            }),
+            Expr::QVal(e) => Expr::QVal(QValExpr {
+                qual: e.qual,
+                region: e.region.clone(),
+                val: match &e.val {
+                    ValExpr::Pair(e1, e2) => ValExpr::Pair(self.boxed(e1), self.boxed(e2)),
+                    ValExpr::With(e1, e2) => ValExpr::With(self.boxed(e1), self.boxed(e2)),
+                    ValExpr::Fun(f) => {
+                        let (vars, body) = self.under(&[&f.var], &f.expr);
+                        ValExpr::Fun(FnExpr {
+                            var: vars[0].clone(),
+                            ty: f.ty.clone(),
+                            expr: Box::new(body),
+                        })
+                    }
+                    v => v.clone(),
+                },
+            }),
+        }
+    }
+}
+
+/// 2つの式が、束縛変数の名前の違いを除いて等しいかを判定する（α同値）
+///
+/// letregion式のリージョン名も束縛変数として扱い、値と型のリージョン名はそれに従って比較する
+pub fn alpha_eq(e1: &Expr, e2: &Expr) -> bool {
+    AlphaEq::default().expr(e1, e2)
+}
+
+/// 束縛変数がscopeに同じ深さで束縛されているか、どちらも自由変数で同じ名前かを判定
+fn same(scope: &[(String, String)], x: &str, y: &str) -> bool {
+    let i = scope.iter().rposition(|(a, _)| a == x);
+    let j = scope.iter().rposition(|(_, b)| b == y);
+    match (i, j) {
+        (None, None) => x == y,
+        _ => i == j,
+    }
+}
+
+#[derive(Default)]
+struct AlphaEq {
+    vars: Vec<(String, String)>,    // 左右の式で対応する束縛変数
+    regions: Vec<(String, String)>, // 左右の式で対応するリージョン名
+}
+
+impl AlphaEq {
+    /// 束縛変数varsのスコープにある式e1とe2を比較
+    fn under(&mut self, vars: &[(&String, &String)], e1: &Expr, e2: &Expr) -> bool {
+        let n = self.vars.len();
+        self.vars
+            .extend(vars.iter().map(|(x, y)| (x.to_string(), y.to_string())));
+        let eq = self.expr(e1, e2);
+        self.vars.truncate(n);
+        eq
+    }
+
+    fn region(&self, r1: &Option<String>, r2: &Option<String>) -> bool {
+        match (r1, r2) {
+            (Some(r1), Some(r2)) => same(&self.regions, r1, r2),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+
+    fn ty(&self, t1: &TypeExpr, t2: &TypeExpr) -> bool {
+        t1.qual == t2.qual
+            && self.region(&t1.region, &t2.region)
+            && match (&t1.prim, &t2.prim) {
+                (PrimType::Pair(a1, b1), PrimType::Pair(a2, b2))
+                | (PrimType::With(a1, b1), PrimType::With(a2, b2))
+                | (PrimType::Arrow(a1, b1), PrimType::Arrow(a2, b2)) => {
+                    self.ty(a1, a2) && self.ty(b1, b2)
+                }
+                (PrimType::Bang(t1), PrimType::Bang(t2))
+                | (PrimType::Ref(t1), PrimType::Ref(t2))
+                | (PrimType::Cell(t1), PrimType::Cell(t2))
+                | (PrimType::Array(t1), PrimType::Array(t2)) => self.ty(t1, t2),
+                (PrimType::Chan(s1), PrimType::Chan(s2)) => self.session(s1, s2),
+                (p1, p2) => p1 == p2,
+            }
+    }
+
+    fn session(&self, s1: &Session, s2: &Session) -> bool {
+        match (s1, s2) {
+            (Session::Send(t1, s1), Session::Send(t2, s2))
+            | (Session::Recv(t1, s1), Session::Recv(t2, s2)) => {
+                self.ty(t1, t2) && self.session(s1, s2)
+            }
+            (Session::End, Session::End) => true,
+            _ => false,
+        }
+    }
+
+    fn expr(&mut self, e1: &Expr, e2: &Expr) -> bool {
+        match (e1, e2) {
+            (Expr::Var(x), Expr::Var(y)) | (Expr::Borrow(x), Expr::Borrow(y)) => {
+                same(&self.vars, x, y)
+            }
+            (Expr::Free(a), Expr::Free(b)) => {
+                same(&self.vars, &a.var, &b.var) && self.expr(&a.expr, &b.expr)
+            }
+            (Expr::Let(a), Expr::Let(b)) => {
+                self.ty(&a.ty, &b.ty)
+                    && self.expr(&a.expr1, &b.expr1)
+                    && self.under(&[(&a.var, &b.var)], &a.expr2, &b.expr2)
+            }
+            (Expr::LetBang(a), Expr::LetBang(b)) => {
+                self.expr(&a.expr1, &b.expr1) && self.under(&[(&a.var, &b.var)], &a.expr2, &b.expr2)
+            }
+            (Expr::Split(a), Expr::Split(b)) => {
+                self.expr(&a.expr, &b.expr)
+                    && self.under(
+                        &[(&a.left, &b.left), (&a.right, &b.right)],
+                        &a.body,
+                        &b.body,
+                    )
+            }
+            (Expr::If(a), Expr::If(b)) => {
+                self.expr(&a.cond_expr, &b.cond_expr)
+                    && self.expr(&a.then_expr, &b.then_expr)
+                    && self.expr(&a.else_expr, &b.else_expr)
+            }
+            (Expr::App(a), Expr::App(b)) => {
+                self.expr(&a.expr1, &b.expr1) && self.expr(&a.expr2, &b.expr2)
+            }
+            (Expr::Proj(a), Expr::Proj(b)) => a.proj == b.proj && self.expr(&a.expr, &b.expr),
+            (Expr::Promote(a), Expr::Promote(b)) => self.expr(&a.expr, &b.expr),
+            (Expr::New(a), Expr::New(b)) => self.session(&a.session, &b.session),
+            (Expr::Send(a), Expr::Send(b)) => {
+                self.expr(&a.chan, &b.chan) && self.expr(&a.expr, &b.expr)
+            }
+            (Expr::Recv(a), Expr::Recv(b)) => self.expr(&a.chan, &b.chan),
+            (Expr::Close(a), Expr::Close(b)) => self.expr(&a.chan, &b.chan),
+            (Expr::Fork(a), Expr::Fork(b)) => {
+                self.expr(&a.expr1, &b.expr1) && self.expr(&a.expr2, &b.expr2)
+            }
+            (Expr::NewRef(a), Expr::NewRef(b)) => self.expr(&a.expr, &b.expr),
+            (Expr::Swap(a), Expr::Swap(b)) => {
+                self.expr(&a.cell, &b.cell) && self.expr(&a.expr, &b.expr)
+            }
+            (Expr::LetRegion(a), Expr::LetRegion(b)) => {
+                self.regions.push((a.region.clone(), b.region.clone()));
+                let eq = self.expr(&a.expr, &b.expr);
+                self.regions.pop();
+                eq
+            }
+            (Expr::Alloc(a), Expr::Alloc(b)) => {
+                self.expr(&a.len, &b.len) && self.expr(&a.expr, &b.expr)
+            }
+            (Expr::Get(a), Expr::Get(b)) => {
+                self.expr(&a.array, &b.array) && self.expr(&a.index, &b.index)
+            }
+            (Expr::Set(a), Expr::Set(b)) => {
+                self.expr(&a.array, &b.array)
+                    && self.expr(&a.index, &b.index)
+                    && self.expr(&a.expr, &b.expr)
+            }
+            (Expr::QVal(a), Expr::QVal(b)) => {
+                a.qual == b.qual
+                    && self.region(&a.region, &b.region)
+                    && match (&a.val, &b.val) {
+                        (ValExpr::Bool(x), ValExpr::Bool(y)) => x == y,
+                        (ValExpr::Int(x), ValExpr::Int(y)) => x == y,
+                        (ValExpr::Str(x), ValExpr::Str(y)) => x == y,
+                        (ValExpr::Pair(a1, b1), ValExpr::Pair(a2, b2))
+                        | (ValExpr::With(a1, b1), ValExpr::With(a2, b2)) => {
+                            self.expr(a1, a2) && self.expr(b1, b2)
+                        }
+                        (ValExpr::Fun(f1), ValExpr::Fun(f2)) => {
+                            self.ty(&f1.ty, &f2.ty)
+                                && self.under(&[(&f1.var, &f2.var)], &f1.expr, &f2.expr)
+                        }
+                        _ => false,
+                    }
+            }
+            _ => false,
+        }
+    }
+}
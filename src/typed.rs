@@ -0,0 +1,355 @@
+//! ## 型付き構文木
+//!
+//! 型付けで計算した型を、全ての式に付けた構文木。
+//! 変数の参照には、値を移動するか（ord、lin、aff型の変数）、
+//! 複製するか（rel、un型の変数と、let !式で束縛された変数）を付ける。
+//! free文は常に変数を消費し、借用は変数を消費しない。
+//!
+//! 型付け関数が、各式の型付けと同時に作成する。
+
+use crate::parser::{self, Expr, FnExpr, Proj, QValExpr, Qual, Session, TypeExpr, ValExpr};
+use std::fmt;
+
+/// 変数の利用の種類
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Use {
+    Move, // 移動。変数を消費する
+    Copy, // 複製。変数は消費されない
+}
+
+impl fmt::Display for Use {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Use::Move => write!(f, "move"),
+            Use::Copy => write!(f, "copy"),
+        }
+    }
+}
+
+/// 型付きの式
+#[derive(Debug, Clone)]
+pub struct TypedExpr {
+    pub node: Node,
+    pub ty: TypeExpr, // 式の型
+}
+
+/// 型付きの式の種類
+///
+/// 構文木の[Expr]と同じ構造を持ち、部分式は[TypedExpr]となる
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Node {
+    Let {
+        var: String,
+        ty: TypeExpr,
+        expr1: Box<TypedExpr>,
+        expr2: Box<TypedExpr>,
+    },
+    LetBang {
+        var: String,
+        expr1: Box<TypedExpr>,
+        expr2: Box<TypedExpr>,
+    },
+    If {
+        cond_expr: Box<TypedExpr>,
+        then_expr: Box<TypedExpr>,
+        else_expr: Box<TypedExpr>,
+    },
+    Split {
+        expr: Box<TypedExpr>,
+        left: String,
+        right: String,
+        body: Box<TypedExpr>,
+    },
+    Free {
+        var: String,
+        expr: Box<TypedExpr>,
+    },
+    App {
+        expr1: Box<TypedExpr>,
+        expr2: Box<TypedExpr>,
+    },
+    Proj {
+        proj: Proj,
+        expr: Box<TypedExpr>,
+    },
+    Promote {
+        expr: Box<TypedExpr>,
+    },
+    New {
+        session: Session,
+    },
+    Send {
+        chan: Box<TypedExpr>,
+        expr: Box<TypedExpr>,
+    },
+    Recv {
+        chan: Box<TypedExpr>,
+    },
+    Close {
+        chan: Box<TypedExpr>,
+    },
+    Fork {
+        expr1: Box<TypedExpr>,
+        expr2: Box<TypedExpr>,
+    },
+    Borrow(String),
+    NewRef {
+        expr: Box<TypedExpr>,
+    },
+    Swap {
+        cell: Box<TypedExpr>,
+        expr: Box<TypedExpr>,
+    },
+    LetRegion {
+        region: String,
+        expr: Box<TypedExpr>,
+    },
+    Alloc {
+        len: Box<TypedExpr>,
+        expr: Box<TypedExpr>,
+    },
+    Get {
+        array: Box<TypedExpr>,
+        index: Box<TypedExpr>,
+    },
+    Set {
+        array: Box<TypedExpr>,
+        index: Box<TypedExpr>,
+        expr: Box<TypedExpr>,
+    },
+    Var(String, Use),
+    QVal {
+        qual: Qual,
+        region: Option<String>,
+        val: TypedVal,
+    },
+}
+
+/// 型付きの値
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum TypedVal {
+    Bool(bool),
+    Int(i64),
+    Str(String),
+    Pair(Box<TypedExpr>, Box<TypedExpr>),
+    With(Box<TypedExpr>, Box<TypedExpr>),
+    Fun {
+        var: String,
+        ty: TypeExpr,
+        expr: Box<TypedExpr>,
+    },
+}
+
+impl TypedExpr {
+    /// 型を取り除き、構文木に戻す
+    pub fn to_expr(&self) -> Expr {
+        let b = |e: &TypedExpr| Box::new(e.to_expr());
+        match &self.node {
+            Node::Let {
+                var,
+                ty,
+                expr1,
+                expr2,
+            } => Expr::Let(parser::LetExpr {
+                var: var.clone(),
+                ty: ty.clone(),
+                expr1: b(expr1),
+                expr2: b(expr2),
+            }),
+            Node::LetBang { var, expr1, expr2 } => Expr::LetBang(parser::LetBangExpr {
+                var: var.clone(),
+                expr1: b(expr1),
+                expr2: b(expr2),
+            }),
+            Node::If {
+                cond_expr,
+                then_expr,
+                else_expr,
+            } => Expr::If(parser::IfExpr {
+                cond_expr: b(cond_expr),
+                then_expr: b(then_expr),
+                else_expr: b(else_expr),
+            }),
+            Node::Split {
+                expr,
+                left,
+                right,
+                body,
+            } => Expr::Split(parser::SplitExpr {
+                expr: b(expr),
+                left: left.clone(),
+                right: right.clone(),
+                body: b(body),
+            }),
+            Node::Free { var, expr } => Expr::Free(parser::FreeExpr {
+                var: var.clone(),
+                expr: b(expr),
+            }),
+            Node::App { expr1, expr2 } => Expr::App(parser::AppExpr {
+                expr1: b(expr1),
+                expr2: b(expr2),
+            }),
+            Node::Proj { proj, expr } => Expr::Proj(parser::ProjExpr {
+                proj: *proj,
+                expr: b(expr),
+            }),
+            Node::Promote { expr } => Expr::Promote(parser::PromoteExpr { expr: b(expr) }),
+            Node::New { session } => Expr::New(parser::NewExpr {
+                session: session.clone(),
+            }),
+            Node::Send { chan, expr } => Expr::Send(parser::SendExpr {
+                chan: b(chan),
+                expr: b(expr),
+            }),
+            Node::Recv { chan } => Expr::Recv(parser::RecvExpr { chan: b(chan) }),
+            Node::Close { chan } => Expr::Close(parser::CloseExpr { chan: b(chan) }),
+            Node::Fork { expr1, expr2 } => Expr::Fork(parser::ForkExpr {
+                expr1: b(expr1),
+                expr2: b(expr2),
+            }),
+            Node::Borrow(var) => Expr::Borrow(var.clone()),
+            Node::NewRef { expr } => Expr::NewRef(parser::NewRefExpr { expr: b(expr) }),
+            Node::Swap { cell, expr } => Expr::Swap(parser::SwapExpr {
+                cell: b(cell),
+                expr: b(expr),
+            }),
+            Node::LetRegion { region, expr } => Expr::LetRegion(parser::LetRegionExpr {
+                region: region.clone(),
+                expr: b(expr),
+            }),
+            Node::Alloc { len, expr } => Expr::Alloc(parser::AllocExpr {
+                len: b(len),
+                expr: b(expr),
+            }),
+            Node::Get { array, index } => Expr::Get(parser::GetExpr {
+                array: b(array),
+                index: b(index),
+            }),
+            Node::Set { array, index, expr } => Expr::Set(parser::SetExpr {
+                array: b(array),
+                index: b(index),
+                expr: b(expr),
+            }),
+            Node::Var(var, _) => Expr::Var(var.clone()),
+            Node::QVal { qual, region, val } => Expr::QVal(QValExpr {
+                qual: *qual,
+                region: region.clone(),
+                val: match val {
+                    TypedVal::Bool(v) => ValExpr::Bool(*v),
+                    TypedVal::Int(n) => ValExpr::Int(*n),
+                    TypedVal::Str(s) => ValExpr::Str(s.clone()),
+                    TypedVal::Pair(e1, e2) => ValExpr::Pair(b(e1), b(e2)),
+                    TypedVal::With(e1, e2) => ValExpr::With(b(e1), b(e2)),
+                    TypedVal::Fun { var, ty, expr } => ValExpr::Fun(FnExpr {
+                        var: var.clone(),
+                        ty: ty.clone(),
+                        expr: b(expr),
+                    }),
+                },
+            }),
+        }
+    }
+
+    /// 部分式の一覧
+    pub fn children(&self) -> Vec<&TypedExpr> {
+        match &self.node {
+            Node::Let { expr1, expr2, .. }
+            | Node::LetBang { expr1, expr2, .. }
+            | Node::App { expr1, expr2 }
+            | Node::Fork { expr1, expr2 } => vec![expr1, expr2],
+            Node::If {
+                cond_expr,
+                then_expr,
+                else_expr,
+            } => vec![cond_expr, then_expr, else_expr],
+            Node::Split { expr, body, .. } => vec![expr, body],
+            Node::Free { expr, .. }
+            | Node::Proj { expr, .. }
+            | Node::Promote { expr }
+            | Node::NewRef { expr }
+            | Node::LetRegion { expr, .. } => vec![expr],
+            Node::Send { chan, expr } => vec![chan, expr],
+            Node::Recv { chan } | Node::Close { chan } => vec![chan],
+            Node::Swap { cell, expr } => vec![cell, expr],
+            Node::Alloc { len, expr } => vec![len, expr],
+            Node::Get { array, index } => vec![array, index],
+            Node::Set { array, index, expr } => vec![array, index, expr],
+            Node::QVal { val, .. } => match val {
+                TypedVal::Pair(e1, e2) | TypedVal::With(e1, e2) => vec![e1, e2],
+                TypedVal::Fun { expr, .. } => vec![expr],
+                _ => vec![],
+            },
+            Node::New { .. } | Node::Borrow(_) | Node::Var(..) => vec![],
+        }
+    }
+
+    /// 式の種類と、束縛する変数などを表す見出し
+    fn head(&self) -> String {
+        match &self.node {
+            Node::Let { var, ty, .. } => format!("let {} : {}", var, ty),
+            Node::LetBang { var, .. } => format!("let !{}", var),
+            Node::If { .. } => "if".to_string(),
+            Node::Split { left, right, .. } => format!("split as {}, {}", left, right),
+            Node::Free { var, .. } => format!("free {}", var),
+            Node::App { .. } => "app".to_string(),
+            Node::Proj {
+                proj: Proj::Fst, ..
+            } => "fst".to_string(),
+            Node::Proj {
+                proj: Proj::Snd, ..
+            } => "snd".to_string(),
+            Node::Promote { .. } => "promote".to_string(),
+            Node::New { session } => format!("new {}", session),
+            Node::Send { .. } => "send".to_string(),
+            Node::Recv { .. } => "recv".to_string(),
+            Node::Close { .. } => "close".to_string(),
+            Node::Fork { .. } => "fork".to_string(),
+            Node::Borrow(var) => format!("&{}", var),
+            Node::NewRef { .. } => "new".to_string(),
+            Node::Swap { .. } => "swap".to_string(),
+            Node::LetRegion { region, .. } => format!("letregion {}", region),
+            Node::Alloc { .. } => "alloc".to_string(),
+            Node::Get { .. } => "get".to_string(),
+            Node::Set { .. } => "set".to_string(),
+            Node::Var(var, u) => format!("{} ({})", var, u),
+            Node::QVal { qual, region, val } => {
+                let q = match region {
+                    Some(r) => format!("{}@{}", qual, r),
+                    None => qual.to_string(),
+                };
+                match val {
+                    TypedVal::Bool(b) => format!("{} {}", q, b),
+                    TypedVal::Int(n) => format!("{} {}", q, n),
+                    TypedVal::Str(s) => format!("{} {}", q, ValExpr::Str(s.clone())),
+                    TypedVal::Pair(..) => format!("{} <_, _>", q),
+                    TypedVal::With(..) => format!("{} <|_, _|>", q),
+                    TypedVal::Fun { var, ty, .. } => format!("{} fn {} : {}", q, var, ty),
+                }
+            }
+        }
+    }
+
+    fn fmt_indent(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
+        writeln!(
+            f,
+            "{:indent$}{} : {}",
+            "",
+            self.head(),
+            self.ty,
+            indent = indent
+        )?;
+        for e in self.children() {
+            e.fmt_indent(f, indent + 2)?;
+        }
+        Ok(())
+    }
+}
+
+/// 1行に1つの式を、部分式を字下げして「見出し : 型」の形式で表示する
+impl fmt::Display for TypedExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_indent(f, 0)
+    }
+}
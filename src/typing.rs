@@ -1,58 +1,381 @@
-use crate::{helper::safe_add, parser};
-use std::{cmp::Ordering, collections::BTreeMap, mem};
+use crate::{
+    helper::safe_add,
+    parser,
+    subst::free_vars,
+    typed::{Node, TypedExpr, TypedVal, Use},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    mem,
+};
 
 type VarToType = BTreeMap<String, Option<parser::TypeExpr>>;
 
+/// ord以外の修飾子の一覧。ord用の型環境は束縛順を保持するため別に扱う
+const QUALS: [parser::Qual; 4] = [
+    parser::Qual::Lin,
+    parser::Qual::Aff,
+    parser::Qual::Rel,
+    parser::Qual::Un,
+];
+
 /// 型環境
+///
+/// 修飾子ごとに変数の利用規則が異なるため、修飾子ごとにスタックを持つ
+/// - ord: 利用すると消費され、束縛とは逆順（LIFO）に消費しなければならない
+/// - lin: 利用すると消費され、スコープの終わりまでに消費されていなければならない
+/// - aff: 利用すると消費されるが、消費せずにスコープを抜けてもよい
+/// - rel: 何度でも利用できるが、スコープの終わりまでに一度は利用されていなければならない
+/// - un: 制約なし
+///
+/// また、借用されている変数を凍結し、借用が終わるまで消費できないようにする。
+/// letregion式で作成したリージョンと、freeで解放できると分かった関数や加法的ペアの変数は、
+/// 変数と同様にdepthとともに保持する
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct TypeEnv {
-    env_lin: TypeEnvStack, // lin用
-    env_un: TypeEnvStack,  // un用
+    env_ord: OrdEnvStack,            // ord用
+    env_lin: TypeEnvStack,           // lin用
+    env_aff: TypeEnvStack,           // aff用
+    env_rel: TypeEnvStack,           // rel用
+    env_un: TypeEnvStack,            // un用
+    frozen: Vec<(String, usize)>,    // 借用により凍結された変数と、変数を束縛したdepth
+    floor: usize,                    // 借用できる変数と、利用できる参照型の変数のdepthの下限
+    regions: Vec<(String, usize)>,   // スコープ内のリージョンと、リージョンを作成したdepth
+    droppable: Vec<(String, usize)>, // 捨てられる値のみをキャプチャした関数か加法的ペアの変数と、変数を束縛したdepth
+}
+
+impl Default for TypeEnv {
+    fn default() -> Self {
+        TypeEnv::new()
+    }
 }
 
 impl TypeEnv {
     pub fn new() -> TypeEnv {
         TypeEnv {
+            env_ord: OrdEnvStack::new(),
             env_lin: TypeEnvStack::new(),
+            env_aff: TypeEnvStack::new(),
+            env_rel: TypeEnvStack::new(),
             env_un: TypeEnvStack::new(),
+            frozen: Vec::new(),
+            floor: 0,
+            regions: Vec::new(),
+            droppable: Vec::new(),
+        }
+    }
+
+    /// 変数の型を事前に与えた型環境を作成するビルダを返す
+    pub fn builder() -> TypeEnvBuilder {
+        TypeEnvBuilder::default()
+    }
+
+    /// 型環境に束縛されている変数名の一覧
+    pub(crate) fn names(&self) -> BTreeSet<String> {
+        let ord = self.env_ord.vars.values().flatten().map(|(k, _)| k);
+        let others = QUALS
+            .iter()
+            .flat_map(|q| self.stack(*q).vars.values().flat_map(|e| e.keys()));
+        ord.chain(others).cloned().collect()
+    }
+
+    /// ord以外の修飾子に対応するスタックを取得
+    fn stack(&self, q: parser::Qual) -> &TypeEnvStack {
+        match q {
+            parser::Qual::Ord => unreachable!(),
+            parser::Qual::Lin => &self.env_lin,
+            parser::Qual::Aff => &self.env_aff,
+            parser::Qual::Rel => &self.env_rel,
+            parser::Qual::Un => &self.env_un,
+        }
+    }
+
+    /// ord以外の修飾子に対応するスタックを取得
+    fn stack_mut(&mut self, q: parser::Qual) -> &mut TypeEnvStack {
+        match q {
+            parser::Qual::Ord => unreachable!(),
+            parser::Qual::Lin => &mut self.env_lin,
+            parser::Qual::Aff => &mut self.env_aff,
+            parser::Qual::Rel => &mut self.env_rel,
+            parser::Qual::Un => &mut self.env_un,
         }
     }
 
     /// 型環境をpush
     fn push(&mut self, depth: usize) {
-        self.env_lin.push(depth);
-        self.env_un.push(depth);
+        self.env_ord.push(depth);
+        for q in QUALS {
+            self.stack_mut(q).push(depth);
+        }
     }
 
-    /// 型環境をpop
-    fn pop(&mut self, depth: usize) -> (Option<VarToType>, Option<VarToType>) {
-        let t1 = self.env_lin.pop(depth);
-        let t2 = self.env_un.pop(depth);
-        (t1, t2)
+    /// 型環境をpopし、popした型環境の中に消費されていないordかlin型の変数か、
+    /// 一度も利用されていないrel型の変数が含まれていた場合、型付けエラー
+    /// 凍結されたままの変数が含まれていた場合は、参照がスコープの外に出るため型付けエラー
+    ///
+    /// placeはエラーメッセージに含める式の場所
+    fn pop(&mut self, depth: usize, place: &str) -> Result<(), String> {
+        if let Some((k, _)) = self.frozen.iter().find(|(_, d)| *d == depth) {
+            return Err(format!(
+                "{}で借用した変数\"{}\"の参照が、変数のスコープの外に出ている",
+                place, k
+            ));
+        }
+
+        self.droppable.retain(|(_, d)| *d != depth);
+
+        let eord = self.env_ord.pop(depth);
+        let elin = self.env_lin.pop(depth);
+        self.env_aff.pop(depth);
+        let erel = self.env_rel.pop(depth);
+        self.env_un.pop(depth);
+
+        for (k, v) in eord.iter().flatten() {
+            if v.is_some() {
+                return Err(format!("{}でord型の変数\"{}\"を消費していない", place, k));
+            }
+        }
+
+        // スコープ内のリージョンに確保したlin型の値は、リージョンを抜けた時点で一括して解放される
+        for (k, v) in elin.iter().flatten() {
+            if v.as_ref()
+                .is_some_and(|t| !freed_by_region(t, &self.regions))
+            {
+                return Err(format!("{}でlin型の変数\"{}\"を消費していない", place, k));
+            }
+        }
+
+        // rel型の変数は、利用されるとun用の型環境に移動するため、
+        // rel用の型環境に残っている変数は一度も利用されていない
+        if let Some(k) = erel.iter().flat_map(|e| e.keys()).next() {
+            return Err(format!(
+                "{}でrel型の変数\"{}\"を一度も利用していない",
+                place, k
+            ));
+        }
+
+        Ok(())
     }
 
     /// 型環境へ変数と型をpush
     fn insert(&mut self, key: String, value: parser::TypeExpr) {
-        if value.qual == parser::Qual::Lin {
-            self.env_lin.insert(key, value);
+        if value.qual == parser::Qual::Ord {
+            self.env_ord.insert(key, value);
         } else {
-            self.env_un.insert(key, value);
+            self.stack_mut(value.qual).insert(key, value);
         }
     }
 
-    /// linとunの型環境からget_mutし、depthが大きい方を返す
-    fn get_mut(&mut self, key: &str) -> Option<&mut Option<parser::TypeExpr>> {
-        match (self.env_lin.get_mut(key), self.env_un.get_mut(key)) {
-            (Some((d1, t1)), Some((d2, t2))) => match d1.cmp(&d2) {
-                Ordering::Less => Some(t2),
-                Ordering::Greater => Some(t1),
-                Ordering::Equal => panic!("invalid type environment"),
-            },
-            (Some((_, t1)), None) => Some(t1),
-            (None, Some((_, t2))) => Some(t2),
-            _ => None,
+    /// 型の修飾子に関わらず、un用の型環境へ変数と型をpush
+    fn insert_un(&mut self, key: String, value: parser::TypeExpr) {
+        self.env_un.insert(key, value);
+    }
+
+    /// 全ての修飾子の型環境から変数を探し、depthが最も大きいもののdepthと、
+    /// 変数が含まれていた型環境の修飾子を返す
+    fn find(&self, key: &str) -> Option<(usize, parser::Qual)> {
+        let mut found: Option<(usize, parser::Qual)> = None;
+        for q in [parser::Qual::Ord].into_iter().chain(QUALS) {
+            let d = if q == parser::Qual::Ord {
+                self.env_ord.get(key).map(|(d, _)| d)
+            } else {
+                self.stack(q).get(key).map(|(d, _)| d)
+            };
+
+            if let Some(d) = d {
+                match found {
+                    Some((d2, _)) if d2 > d => (),
+                    Some((d2, _)) if d2 == d => panic!("invalid type environment"),
+                    _ => found = Some((d, q)),
+                }
+            }
+        }
+        found
+    }
+
+    /// 全ての修飾子の型環境から変数を探し、depthが最も大きいものを返す
+    /// 変数が含まれていた型環境の修飾子を併せて返す
+    fn get_mut(&mut self, key: &str) -> Option<(parser::Qual, &mut Option<parser::TypeExpr>)> {
+        let (_, q) = self.find(key)?;
+        let (_, t) = if q == parser::Qual::Ord {
+            self.env_ord.get_mut(key)?
+        } else {
+            self.stack_mut(q).get_mut(key)?
+        };
+        Some((q, t))
+    }
+
+    /// ord型の変数を消費できるかをチェック
+    ///
+    /// ord型の変数は束縛とは逆順に消費しなければならないため、
+    /// 消費されていないord型の変数のうち、最後に束縛されたもの以外は消費できない
+    fn check_ord(&self, key: &str) -> Result<(), String> {
+        match self.env_ord.last() {
+            Some(last) if last != key => Err(format!(
+                "ord型の変数\"{}\"を、\"{}\"より先に消費しなければならない",
+                last, key
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// 変数が借用により凍結されているかを判定
+    fn is_frozen(&self, key: &str) -> bool {
+        match self.find(key) {
+            Some((d, _)) => self.frozen.iter().any(|(k, d2)| k == key && *d2 == d),
+            None => false,
+        }
+    }
+
+    /// 変数を探し、束縛したdepthと、消費されていなければ型を返す
+    fn lookup(&self, key: &str) -> Option<(usize, Option<&parser::TypeExpr>)> {
+        let (d, q) = self.find(key)?;
+        let (_, t) = if q == parser::Qual::Ord {
+            self.env_ord.get(key)?
+        } else {
+            self.stack(q).get(key)?
+        };
+        Some((d, t.as_ref()))
+    }
+
+    /// 変数の値を、freeで利用せずに解放できるかを判定
+    ///
+    /// 関数と加法的ペアは型からはキャプチャした値が分からないため、
+    /// let式で束縛した際に、捨てられる値のみをキャプチャしていると分かったものも解放できる。
+    /// 消費済みの変数は、freeや利用の際に型付けエラーとなるため解放できるとみなす
+    fn is_droppable(&self, key: &str) -> bool {
+        match self.lookup(key) {
+            Some((d, Some(t))) => {
+                droppable(t) || self.droppable.iter().any(|(k, d2)| k == key && *d2 == d)
+            }
+            _ => true,
+        }
+    }
+
+    /// 関数か加法的ペアがキャプチャした変数の値を、関数か加法的ペアとともに捨てられるかを判定
+    ///
+    /// freeはキャプチャした値を解放しないため、解放せずに残しても良いun、aff型の値のみ捨てられる
+    fn is_discardable(&self, key: &str) -> bool {
+        match self.lookup(key) {
+            Some((_, Some(t))) => {
+                matches!(t.qual, parser::Qual::Un | parser::Qual::Aff) && droppable(t)
+            }
+            _ => true,
+        }
+    }
+
+    /// 借用できる変数と、利用できる参照型の変数のdepthの下限をfloorとし、以前の下限を返す
+    ///
+    /// 関数や!型の値などは作成した後に評価されるため、その時点では借用が終わっているかもしれない。
+    /// そのため、これらの内側では外側の変数を借用できず、外側の参照型の変数も利用できない
+    fn set_floor(&mut self, floor: usize) -> usize {
+        mem::replace(&mut self.floor, floor)
+    }
+
+    /// 型に現れるリージョンが、全てスコープ内にあるかをチェック
+    fn check_regions(&self, t: &parser::TypeExpr) -> Result<(), String> {
+        match find_region(t, &|r| !self.regions.iter().any(|(r2, _)| r2 == r)) {
+            Some(r) => Err(format!("リージョン\"{}\"はスコープ内にない", r)),
+            None => Ok(()),
+        }
+    }
+
+    /// rel型の変数を利用済みとし、同じdepthのun用の型環境に移動
+    fn mark_used(&mut self, key: &str) {
+        if let Some((depth, Some(t))) = self.env_rel.remove(key) {
+            self.env_un.insert_at(depth, key.to_string(), t);
         }
     }
+
+    /// 修飾子qの値がキャプチャできない変数の型環境を取り除き、取り除いた型環境を返す
+    ///
+    /// 修飾子qの値は、修飾子がq以下の変数のみキャプチャできる。
+    /// 例えば、un型の関数はun型の変数のみを、aff型の関数はaffとun型の変数のみをキャプチャできる
+    fn take_uncapturable(&mut self, q: parser::Qual) -> TypeEnv {
+        let mut taken = TypeEnv::new();
+        if q != parser::Qual::Ord {
+            mem::swap(&mut self.env_ord, &mut taken.env_ord);
+        }
+        for q2 in QUALS {
+            if !q2.leq(q) {
+                mem::swap(self.stack_mut(q2), taken.stack_mut(q2));
+            }
+        }
+        taken
+    }
+
+    /// take_uncapturableで取り除いた型環境を復元
+    fn restore(&mut self, q: parser::Qual, mut taken: TypeEnv) {
+        if q != parser::Qual::Ord {
+            mem::swap(&mut self.env_ord, &mut taken.env_ord);
+        }
+        for q2 in QUALS {
+            if !q2.leq(q) {
+                mem::swap(self.stack_mut(q2), taken.stack_mut(q2));
+            }
+        }
+    }
+
+    /// aff型の変数は消費しなくてもよいため、
+    /// 一方の型環境で消費されたaff型の変数を、もう一方の型環境でも消費済みとする
+    ///
+    /// ifのthenとelseなど、2つの型環境を合流させる前に呼び出す
+    fn join_aff(&mut self, other: &mut TypeEnv) {
+        for (depth, vars) in self.env_aff.vars.iter_mut() {
+            if let Some(other_vars) = other.env_aff.vars.get_mut(depth) {
+                for (k, v) in vars.iter_mut() {
+                    if let Some(v2) = other_vars.get_mut(k) {
+                        if v.is_none() || v2.is_none() {
+                            *v = None;
+                            *v2 = None;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// ifのthenとelseなど、2つの型環境を合流させる前に呼び出し、
+    /// 一方の型環境で凍結された変数を、もう一方の型環境でも凍結する
+    fn join_frozen(&mut self, other: &mut TypeEnv) {
+        for f in other.frozen.iter() {
+            if !self.frozen.contains(f) {
+                self.frozen.push(f.clone());
+            }
+        }
+        other.frozen = self.frozen.clone();
+    }
+}
+
+/// 型環境のビルダ
+///
+/// 埋め込み先のアプリケーションが提供する変数の型を、depth 0の型環境に事前に与える。
+/// 事前に与えたlin型などの変数も、型付け中の変数と同様に利用規則に従う必要がある。
+#[derive(Debug, Clone, Default)]
+pub struct TypeEnvBuilder {
+    vars: Vec<(String, parser::TypeExpr)>,
+}
+
+impl TypeEnvBuilder {
+    /// 変数と型を追加
+    ///
+    /// 同じ名前の変数が既にある場合は、型を置き換える
+    pub fn bind(mut self, var: impl Into<String>, ty: parser::TypeExpr) -> TypeEnvBuilder {
+        let var = var.into();
+        self.vars.retain(|(v, _)| *v != var);
+        self.vars.push((var, ty));
+        self
+    }
+
+    /// 型環境を作成
+    pub fn build(self) -> TypeEnv {
+        let mut env = TypeEnv::new();
+        env.push(0);
+        for (var, ty) in self.vars {
+            env.insert(var, ty);
+        }
+        env
+    }
 }
 
 /// 型環境のスタック
@@ -85,6 +408,23 @@ impl TypeEnvStack {
         }
     }
 
+    // 指定したdepthの型環境に変数名と型を追加
+    fn insert_at(&mut self, depth: usize, key: String, value: parser::TypeExpr) {
+        if let Some(vars) = self.vars.get_mut(&depth) {
+            vars.insert(key, Some(value));
+        }
+    }
+
+    // スタックを上からたどっていき、はじめに見つかる変数の型を取得
+    fn get(&self, key: &str) -> Option<(usize, &Option<parser::TypeExpr>)> {
+        for (depth, elm) in self.vars.iter().rev() {
+            if let Some(e) = elm.get(key) {
+                return Some((*depth, e));
+            }
+        }
+        None
+    }
+
     // スタックを上からたどっていき、はじめに見つかる変数の型を取得
     fn get_mut(&mut self, key: &str) -> Option<(usize, &mut Option<parser::TypeExpr>)> {
         for (depth, elm) in self.vars.iter_mut().rev() {
@@ -94,62 +434,595 @@ impl TypeEnvStack {
         }
         None
     }
+
+    // スタックを上からたどっていき、はじめに見つかる変数を削除
+    fn remove(&mut self, key: &str) -> Option<(usize, Option<parser::TypeExpr>)> {
+        for (depth, elm) in self.vars.iter_mut().rev() {
+            if let Some(e) = elm.remove(key) {
+                return Some((*depth, e));
+            }
+        }
+        None
+    }
+}
+
+/// ord用の型環境のスタック
+///
+/// 同じdepthに束縛された変数の間でも束縛順を保持する
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+struct OrdEnvStack {
+    vars: BTreeMap<usize, Vec<(String, Option<parser::TypeExpr>)>>,
+}
+
+impl OrdEnvStack {
+    fn new() -> OrdEnvStack {
+        OrdEnvStack {
+            vars: BTreeMap::new(),
+        }
+    }
+
+    // 型環境をpush
+    fn push(&mut self, depth: usize) {
+        self.vars.insert(depth, Vec::new());
+    }
+
+    // 型環境をpop
+    fn pop(&mut self, depth: usize) -> Option<Vec<(String, Option<parser::TypeExpr>)>> {
+        self.vars.remove(&depth)
+    }
+
+    // スタックの最も上にある型環境の末尾に変数名と型を追加
+    fn insert(&mut self, key: String, value: parser::TypeExpr) {
+        if let Some(last) = self.vars.iter_mut().next_back() {
+            last.1.push((key, Some(value)));
+        }
+    }
+
+    // 後に束縛されたものからたどっていき、はじめに見つかる変数の型を取得
+    fn get(&self, key: &str) -> Option<(usize, &Option<parser::TypeExpr>)> {
+        for (depth, elm) in self.vars.iter().rev() {
+            if let Some((_, t)) = elm.iter().rev().find(|(k, _)| k == key) {
+                return Some((*depth, t));
+            }
+        }
+        None
+    }
+
+    // 後に束縛されたものからたどっていき、はじめに見つかる変数の型を取得
+    fn get_mut(&mut self, key: &str) -> Option<(usize, &mut Option<parser::TypeExpr>)> {
+        for (depth, elm) in self.vars.iter_mut().rev() {
+            if let Some((_, t)) = elm.iter_mut().rev().find(|(k, _)| k == key) {
+                return Some((*depth, t));
+            }
+        }
+        None
+    }
+
+    // 消費されていない変数のうち、最後に束縛されたものの変数名を取得
+    fn last(&self) -> Option<&str> {
+        self.vars
+            .values()
+            .rev()
+            .flat_map(|elm| elm.iter().rev())
+            .find(|(_, t)| t.is_some())
+            .map(|(k, _)| k.as_str())
+    }
+}
+
+type TResult = Result<TypedExpr, String>;
+
+/// 型注釈が妥当な型かをチェック
+///
+/// 型付けで値に与えられる型と同様に、修飾子qのペア型と加法的ペア型は修飾子がq以下の型のみを要素に持てる。
+/// 例えばun (lin bool * lin bool)は、un型のペアにlin型の値を含めているため妥当ではない。
+/// !型の中身も、同様に妥当な型でなければならない。
+/// 関数型の修飾子はキャプチャする変数を制限するが、キャプチャする変数の型は関数型に現れないため、
+/// 関数型の引数と戻り値の修飾子には制約を設けない。
+///
+/// 妥当でない場合、型の中の位置を示すパスを含めたエラーを返す。
+/// パスは、全体を$として、ペア型と加法的ペア型の要素を.0と.1、
+/// 関数型の引数と戻り値を.argと.ret、!型の中身を.!、参照型の参照先を.&、ref型の中身を.ref、
+/// array型の要素を.arrayで表す。
+///
+/// ref型は中身を書き換えられるため、複製できないよう修飾子はlinかordに限る。
+/// array型も同様に修飾子はlinかordに限り、allocで要素を複製するため要素の修飾子はunに限る。
+/// チャネルは相手の端点と通信を終えるまで手放せないため、チャネル型の修飾子はlinかordに限る。
+/// また、他のスレッドに参照を渡すと借用が終わった後も利用されうるため、参照を含む値は送受信できない。
+/// セッション型のn番目の通信で送受信する値の型は、パス.nで表す。
+pub fn check_wf(t: &parser::TypeExpr) -> Result<(), String> {
+    check_wf_path(t, &mut "$".to_string())
+}
+
+fn check_wf_path(t: &parser::TypeExpr, path: &mut String) -> Result<(), String> {
+    let len = path.len();
+    let children: Vec<(&str, &parser::TypeExpr)> = match &t.prim {
+        parser::PrimType::Bool
+        | parser::PrimType::Unit
+        | parser::PrimType::Handle
+        | parser::PrimType::Int
+        | parser::PrimType::Str => vec![],
+        parser::PrimType::Pair(t1, t2) | parser::PrimType::With(t1, t2) => {
+            // ペア型と加法的ペア型の要素の修飾子をチェック
+            for (p, c) in [(".0", t1), (".1", t2)] {
+                if !c.qual.leq(t.qual) {
+                    return Err(format!(
+                        "{}{}の{}型の値を、{}型のペア内に含められない",
+                        path, p, c.qual, t.qual
+                    ));
+                }
+            }
+            vec![(".0", t1), (".1", t2)]
+        }
+        parser::PrimType::Arrow(t1, t2) => vec![(".arg", t1), (".ret", t2)],
+        parser::PrimType::Bang(t) => vec![(".!", t)],
+        parser::PrimType::Ref(t) => vec![(".&", t)],
+        parser::PrimType::Cell(c) => {
+            if !parser::Qual::Lin.leq(t.qual) {
+                return Err(format!(
+                    "{}のref型の修飾子が{}。linかordでなければならない",
+                    path, t.qual
+                ));
+            }
+            if !c.qual.leq(t.qual) {
+                return Err(format!(
+                    "{}.refの{}型の値を、{}型のref内に含められない",
+                    path, c.qual, t.qual
+                ));
+            }
+            vec![(".ref", c)]
+        }
+        parser::PrimType::Array(e) => {
+            if !parser::Qual::Lin.leq(t.qual) {
+                return Err(format!(
+                    "{}のarray型の修飾子が{}。linかordでなければならない",
+                    path, t.qual
+                ));
+            }
+            if e.qual != parser::Qual::Un {
+                return Err(format!(
+                    "{}.arrayの{}型の値を、配列の要素にできない。unでなければならない",
+                    path, e.qual
+                ));
+            }
+            vec![(".array", e)]
+        }
+        parser::PrimType::Chan(s) => {
+            if !parser::Qual::Lin.leq(t.qual) {
+                return Err(format!(
+                    "{}のチャネル型の修飾子が{}。linかordでなければならない",
+                    path, t.qual
+                ));
+            }
+
+            // 送受信する値の型をチェック
+            let mut s = s;
+            let mut n = 0;
+            while let parser::Session::Send(m, next) | parser::Session::Recv(m, next) = s {
+                path.push_str(&format!(".{}", n));
+                if has_ref(m) {
+                    return Err(format!("{}の参照を含む値は送受信できない", path));
+                }
+                check_wf_path(m, path)?;
+                path.truncate(len);
+                s = next;
+                n += 1;
+            }
+            vec![]
+        }
+    };
+
+    for (p, c) in children {
+        path.push_str(p);
+        check_wf_path(c, path)?;
+        path.truncate(len);
+    }
+
+    Ok(())
+}
+
+/// 参照を保持する値の型かを判定
+///
+/// 関数や!型の値は参照をキャプチャできず、チャネルは参照を送受信できないため、
+/// 参照型と、それを含むペア型、ref型、array型のみが参照を保持する
+pub(crate) fn has_ref(t: &parser::TypeExpr) -> bool {
+    match &t.prim {
+        parser::PrimType::Ref(_) => true,
+        parser::PrimType::Pair(t1, t2) => has_ref(t1) || has_ref(t2),
+        parser::PrimType::Cell(t) | parser::PrimType::Array(t) => has_ref(t),
+        _ => false,
+    }
+}
+
+/// 型の中に現れるリージョンのうち、predを満たす最初のものを返す
+///
+/// 関数型の引数と戻り値や、セッション型で送受信する値の型も含めて探す
+fn find_region<'t>(t: &'t parser::TypeExpr, pred: &impl Fn(&str) -> bool) -> Option<&'t str> {
+    if let Some(r) = t.region.as_deref().filter(|r| pred(r)) {
+        return Some(r);
+    }
+
+    match &t.prim {
+        parser::PrimType::Pair(t1, t2)
+        | parser::PrimType::With(t1, t2)
+        | parser::PrimType::Arrow(t1, t2) => {
+            find_region(t1, pred).or_else(|| find_region(t2, pred))
+        }
+        parser::PrimType::Bang(t)
+        | parser::PrimType::Ref(t)
+        | parser::PrimType::Cell(t)
+        | parser::PrimType::Array(t) => find_region(t, pred),
+        parser::PrimType::Chan(s) => {
+            let mut s = s;
+            while let parser::Session::Send(m, next) | parser::Session::Recv(m, next) = s {
+                if let Some(r) = find_region(m, pred) {
+                    return Some(r);
+                }
+                s = next;
+            }
+            None
+        }
+        _ => None,
+    }
+}
+
+/// 参照型の参照先をたどり、参照型でない型を返す
+fn deref(t: &parser::TypeExpr) -> &parser::TypeExpr {
+    match &t.prim {
+        parser::PrimType::Ref(t) => deref(t),
+        _ => t,
+    }
+}
+
+/// セッション型の双対を計算
+///
+/// チャネルの一方の端点で送信する箇所は、もう一方の端点では受信となる
+pub fn dual(s: &parser::Session) -> parser::Session {
+    match s {
+        parser::Session::Send(t, s) => parser::Session::Recv(t.clone(), Box::new(dual(s))),
+        parser::Session::Recv(t, s) => parser::Session::Send(t.clone(), Box::new(dual(s))),
+        parser::Session::End => parser::Session::End,
+    }
+}
+
+/// セッション型の部分型関係s1 <: s2を判定
+///
+/// 送信する値の型は反変、受信する値の型は共変となる
+fn subsession(s1: &parser::Session, s2: &parser::Session) -> bool {
+    match (s1, s2) {
+        (parser::Session::Send(t1, s1), parser::Session::Send(t2, s2)) => {
+            subtype(t2, t1) && subsession(s1, s2)
+        }
+        (parser::Session::Recv(t1, s1), parser::Session::Recv(t2, s2)) => {
+            subtype(t1, t2) && subsession(s1, s2)
+        }
+        (parser::Session::End, parser::Session::End) => true,
+        _ => false,
+    }
+}
+
+/// 要素を持たない型かを判定
+fn is_base(p: &parser::PrimType) -> bool {
+    matches!(
+        p,
+        parser::PrimType::Bool
+            | parser::PrimType::Unit
+            | parser::PrimType::Handle
+            | parser::PrimType::Int
+            | parser::PrimType::Str
+    )
+}
+
+/// 部分型関係t1 <: t2を判定
+///
+/// t1の値をt2の値として扱ってもよい場合に真となる。
+/// 修飾子は束の順序で弱める方向のみ許し（例えばun boolはlin boolの部分型）、
+/// 関数型の引数は反変、関数型の戻り値、ペア型、加法的ペア型、!型の中身、参照先は共変となる。
+/// ref型の中身とarray型の要素は読み書きできるため不変となる。
+/// チャネル型はセッション型の部分型関係に従う。
+/// リージョン外の値はリージョン内の値として扱えるが、その逆や、異なるリージョン間では扱えない
+pub fn subtype(t1: &parser::TypeExpr, t2: &parser::TypeExpr) -> bool {
+    if !t1.qual.leq(t2.qual) || (t1.region.is_some() && t1.region != t2.region) {
+        return false;
+    }
+
+    match (&t1.prim, &t2.prim) {
+        (p1, p2) if is_base(p1) => p1 == p2,
+        (parser::PrimType::Pair(a1, b1), parser::PrimType::Pair(a2, b2))
+        | (parser::PrimType::With(a1, b1), parser::PrimType::With(a2, b2)) => {
+            subtype(a1, a2) && subtype(b1, b2)
+        }
+        (parser::PrimType::Arrow(a1, b1), parser::PrimType::Arrow(a2, b2)) => {
+            subtype(a2, a1) && subtype(b1, b2)
+        }
+        (parser::PrimType::Bang(a1), parser::PrimType::Bang(a2))
+        | (parser::PrimType::Ref(a1), parser::PrimType::Ref(a2)) => subtype(a1, a2),
+        (parser::PrimType::Cell(a1), parser::PrimType::Cell(a2))
+        | (parser::PrimType::Array(a1), parser::PrimType::Array(a2)) => {
+            subtype(a1, a2) && subtype(a2, a1)
+        }
+        (parser::PrimType::Chan(s1), parser::PrimType::Chan(s2)) => subsession(s1, s2),
+        _ => false,
+    }
+}
+
+/// t1とt2の両方を部分型とする、最小の型を計算
+///
+/// ifのthenとelseのように、2つの式の型を合流させる箇所で利用する
+pub(crate) fn join(t1: &parser::TypeExpr, t2: &parser::TypeExpr) -> Option<parser::TypeExpr> {
+    let prim = match (&t1.prim, &t2.prim) {
+        (p1, p2) if is_base(p1) && p1 == p2 => p1.clone(),
+        (parser::PrimType::Pair(a1, b1), parser::PrimType::Pair(a2, b2)) => {
+            parser::PrimType::Pair(Box::new(join(a1, a2)?), Box::new(join(b1, b2)?))
+        }
+        (parser::PrimType::With(a1, b1), parser::PrimType::With(a2, b2)) => {
+            parser::PrimType::With(Box::new(join(a1, a2)?), Box::new(join(b1, b2)?))
+        }
+        (parser::PrimType::Arrow(a1, b1), parser::PrimType::Arrow(a2, b2)) => {
+            parser::PrimType::Arrow(Box::new(meet(a1, a2)?), Box::new(join(b1, b2)?))
+        }
+        (parser::PrimType::Bang(a1), parser::PrimType::Bang(a2)) => {
+            parser::PrimType::Bang(Box::new(join(a1, a2)?))
+        }
+        (parser::PrimType::Ref(a1), parser::PrimType::Ref(a2)) => {
+            parser::PrimType::Ref(Box::new(join(a1, a2)?))
+        }
+        (parser::PrimType::Cell(a1), parser::PrimType::Cell(a2)) if a1 == a2 => {
+            parser::PrimType::Cell(a1.clone())
+        }
+        (parser::PrimType::Array(a1), parser::PrimType::Array(a2)) if a1 == a2 => {
+            parser::PrimType::Array(a1.clone())
+        }
+        (parser::PrimType::Chan(s1), parser::PrimType::Chan(s2)) if s1 == s2 => {
+            parser::PrimType::Chan(s1.clone())
+        }
+        _ => return None,
+    };
+
+    // 一方のみがリージョン内の値の場合、リージョン内の値とする
+    let region = match (&t1.region, &t2.region) {
+        (r1, r2) if r1 == r2 => r1.clone(),
+        (r, None) | (None, r) => r.clone(),
+        _ => return None,
+    };
+
+    Some(parser::TypeExpr {
+        qual: t1.qual.join(t2.qual),
+        region,
+        prim,
+    })
 }
 
-type TResult = Result<parser::TypeExpr, String>;
+/// t1とt2の両方の部分型となる、最大の型を計算
+fn meet(t1: &parser::TypeExpr, t2: &parser::TypeExpr) -> Option<parser::TypeExpr> {
+    let prim = match (&t1.prim, &t2.prim) {
+        (p1, p2) if is_base(p1) && p1 == p2 => p1.clone(),
+        (parser::PrimType::Pair(a1, b1), parser::PrimType::Pair(a2, b2)) => {
+            parser::PrimType::Pair(Box::new(meet(a1, a2)?), Box::new(meet(b1, b2)?))
+        }
+        (parser::PrimType::With(a1, b1), parser::PrimType::With(a2, b2)) => {
+            parser::PrimType::With(Box::new(meet(a1, a2)?), Box::new(meet(b1, b2)?))
+        }
+        (parser::PrimType::Arrow(a1, b1), parser::PrimType::Arrow(a2, b2)) => {
+            parser::PrimType::Arrow(Box::new(join(a1, a2)?), Box::new(meet(b1, b2)?))
+        }
+        (parser::PrimType::Bang(a1), parser::PrimType::Bang(a2)) => {
+            parser::PrimType::Bang(Box::new(meet(a1, a2)?))
+        }
+        (parser::PrimType::Ref(a1), parser::PrimType::Ref(a2)) => {
+            parser::PrimType::Ref(Box::new(meet(a1, a2)?))
+        }
+        (parser::PrimType::Cell(a1), parser::PrimType::Cell(a2)) if a1 == a2 => {
+            parser::PrimType::Cell(a1.clone())
+        }
+        (parser::PrimType::Array(a1), parser::PrimType::Array(a2)) if a1 == a2 => {
+            parser::PrimType::Array(a1.clone())
+        }
+        (parser::PrimType::Chan(s1), parser::PrimType::Chan(s2)) if s1 == s2 => {
+            parser::PrimType::Chan(s1.clone())
+        }
+        _ => return None,
+    };
+
+    // 異なるリージョンの値の両方の部分型となるのは、リージョン外の値のみ
+    let region = if t1.region == t2.region {
+        t1.region.clone()
+    } else {
+        None
+    };
+
+    Some(parser::TypeExpr {
+        qual: t1.qual.meet(t2.qual),
+        region,
+        prim,
+    })
+}
 
 /// 型付け関数
-/// 式を受け取り、型を返す
+/// 式を受け取り、全ての式に型を付けた型付き構文木を返す
+///
+/// 式の型が参照を保持しない場合、式の中で行った借用は式の評価とともに終わるため、
+/// 式の中で凍結した変数の凍結を解除する
 pub fn typing(expr: &parser::Expr, env: &mut TypeEnv, depth: usize) -> TResult {
+    let n = env.frozen.len();
+    let t = typing_expr(expr, env, depth)?;
+    if !has_ref(&t.ty) {
+        env.frozen.truncate(n);
+    }
+    Ok(t)
+}
+
+fn typing_expr(expr: &parser::Expr, env: &mut TypeEnv, depth: usize) -> TResult {
     match expr {
         parser::Expr::App(e) => typing_app(e, env, depth),
         parser::Expr::QVal(e) => typing_qval(e, env, depth),
         parser::Expr::Free(e) => typing_free(e, env, depth),
         parser::Expr::If(e) => typing_if(e, env, depth),
         parser::Expr::Split(e) => typing_split(e, env, depth),
-        parser::Expr::Var(e) => typing_var(e, env),
+        parser::Expr::Proj(e) => typing_proj(e, env, depth),
+        parser::Expr::Var(e) => {
+            let (ty, u) = typing_var(e, env)?;
+            Ok(TypedExpr {
+                node: Node::Var(e.clone(), u),
+                ty,
+            })
+        }
         parser::Expr::Let(e) => typing_let(e, env, depth),
+        parser::Expr::LetBang(e) => typing_let_bang(e, env, depth),
+        parser::Expr::Promote(e) => typing_promote(e, env, depth),
+        parser::Expr::New(e) => Ok(TypedExpr {
+            ty: typing_new(e, env)?,
+            node: Node::New {
+                session: e.session.clone(),
+            },
+        }),
+        parser::Expr::Send(e) => typing_send(e, env, depth),
+        parser::Expr::Recv(e) => typing_recv(e, env, depth),
+        parser::Expr::Close(e) => typing_close(e, env, depth),
+        parser::Expr::Fork(e) => typing_fork(e, env, depth),
+        parser::Expr::Borrow(e) => Ok(TypedExpr {
+            ty: typing_borrow(e, env)?,
+            node: Node::Borrow(e.clone()),
+        }),
+        parser::Expr::NewRef(e) => typing_new_ref(e, env, depth),
+        parser::Expr::Swap(e) => typing_swap(e, env, depth),
+        parser::Expr::LetRegion(e) => typing_letregion(e, env, depth),
+        parser::Expr::Alloc(e) => typing_alloc(e, env, depth),
+        parser::Expr::Get(e) => typing_get(e, env, depth),
+        parser::Expr::Set(e) => typing_set(e, env, depth),
     }
 }
 
+/// プログラム全体の型付け関数
+///
+/// 式をdepth 0で型付けした後、型環境に事前に与えた変数のうち、
+/// 消費されていないlin型の変数などがあれば型付けエラーとする
+pub fn typing_program(expr: &parser::Expr, env: &mut TypeEnv) -> TResult {
+    let t = typing(expr, env, 0)?;
+    env.pop(0, "プログラム全体")?;
+    Ok(t)
+}
+
 /// 関数適用の型付け
 fn typing_app(expr: &parser::AppExpr, env: &mut TypeEnv, depth: usize) -> TResult {
-    // TODO: ここを実装せよ
-    // todo!()は削除すること
-    todo!()
+    // 関数と引数の型を計算
+    let t1 = typing(&expr.expr1, env, depth)?;
+    let t2 = typing(&expr.expr2, env, depth)?;
+
+    // 実際の引数の型が、関数の引数の型の部分型かをチェック
+    match &t1.ty.prim {
+        parser::PrimType::Arrow(t_in, t_out) => {
+            if !subtype(&t2.ty, t_in) {
+                return Err(format!(
+                    "関数の引数の型が異なる。{}が必要だが、{}が与えられた",
+                    t_in, t2.ty
+                ));
+            }
+            Ok(TypedExpr {
+                ty: *t_out.clone(),
+                node: Node::App {
+                    expr1: Box::new(t1),
+                    expr2: Box::new(t2),
+                },
+            })
+        }
+        _ => Err("関数型でない値を関数適用している".to_string()),
+    }
 }
 
 /// 修飾子付き値の型付け
 fn typing_qval(expr: &parser::QValExpr, env: &mut TypeEnv, depth: usize) -> TResult {
+    // 値を確保するリージョンがスコープ内にあるかをチェック
+    // 関数などは作成した後に評価されるため、その時点ではリージョンが解放されているかもしれない。
+    // そのため、これらの内側では外側のリージョンに値を確保できない
+    if let Some(r) = &expr.region {
+        match env.regions.iter().find(|(r2, _)| r2 == r) {
+            None => return Err(format!("リージョン\"{}\"はスコープ内にない", r)),
+            Some((_, d)) if *d < env.floor => {
+                return Err(format!(
+                    "関数や!型の値の中で、外側のリージョン\"{}\"に値を確保している",
+                    r
+                ))
+            }
+            _ => (),
+        }
+    }
+
     // プリミティブ型を計算
-    let p = match &expr.val {
-        parser::ValExpr::Bool(_) => parser::PrimType::Bool,
+    let (p, val) = match &expr.val {
+        parser::ValExpr::Bool(b) => (parser::PrimType::Bool, TypedVal::Bool(*b)),
+        parser::ValExpr::Int(n) => (parser::PrimType::Int, TypedVal::Int(*n)),
+        parser::ValExpr::Str(s) => (parser::PrimType::Str, TypedVal::Str(s.clone())),
         parser::ValExpr::Pair(e1, e2) => {
             // 式e1とe2をtypingにより型付け
             let t1 = typing(e1, env, depth)?;
             let t2 = typing(e2, env, depth)?;
 
-            // expr.qualがUnであり、
-            // e1か、e2の型にlinが含まれていた場合、型付けエラー
-            if expr.qual == parser::Qual::Un
-                && (t1.qual == parser::Qual::Lin || t2.qual == parser::Qual::Lin)
-            {
-                return Err("un型のペア内でlin型を利用している".to_string());
+            // e1か、e2の型の修飾子がexpr.qualより制約の強い場合、型付けエラー
+            // 例えば、un型のペア内ではlin型を利用できない
+            for t in [&t1.ty, &t2.ty] {
+                if !t.qual.leq(expr.qual) {
+                    return Err(format!(
+                        "{}型のペア内で{}型を利用している",
+                        expr.qual, t.qual
+                    ));
+                }
             }
 
             // ペア型を返す
-            parser::PrimType::Pair(Box::new(t1), Box::new(t2))
+            (
+                parser::PrimType::Pair(Box::new(t1.ty.clone()), Box::new(t2.ty.clone())),
+                TypedVal::Pair(Box::new(t1), Box::new(t2)),
+            )
+        }
+        parser::ValExpr::With(e1, e2) => {
+            // 加法的ペアの要素はどちらか一方のみが評価されるため、
+            // 同じ型環境でe1とe2を型付けする。
+            // 例えばun型の加法的ペアは複数回射影できるため、
+            // lin型の自由変数をキャプチャできないよう、キャプチャできない型環境を取り除く
+            let env_prev = env.take_uncapturable(expr.qual);
+            let floor = env.set_floor(depth + 1);
+
+            let mut e = env.clone();
+            let t1 = typing(e1, &mut e, depth)?;
+            let t2 = typing(e2, env, depth)?;
+
+            // ペアと同様に、un型の加法的ペア内ではlin型を利用できない
+            for t in [&t1.ty, &t2.ty] {
+                if !t.qual.leq(expr.qual) {
+                    return Err(format!(
+                        "{}型の加法的ペア内で{}型を利用している",
+                        expr.qual, t.qual
+                    ));
+                }
+            }
+
+            // e1とe2の型付け後の型環境は同じかをチェック
+            env.join_aff(&mut e);
+            if e != *env {
+                return Err("<| |>の各要素で消費するlin型の変数が異なる".to_string());
+            }
+
+            // 取り除いた型環境を復元
+            env.set_floor(floor);
+            env.restore(expr.qual, env_prev);
+
+            // 加法的ペア型を返す
+            (
+                parser::PrimType::With(Box::new(t1.ty.clone()), Box::new(t2.ty.clone())),
+                TypedVal::With(Box::new(t1), Box::new(t2)),
+            )
         }
         parser::ValExpr::Fun(e) => {
             // 関数の型付け
 
+            // 引数の型注釈が妥当かをチェック
+            check_wf(&e.ty)
+                .and_then(|_| env.check_regions(&e.ty))
+                .map_err(|msg| format!("引数\"{}\"の型注釈{}が不正。{}", e.var, e.ty, msg))?;
+
             // un型の関数内では、lin型の自由変数をキャプチャできないため
-            // lin用の型環境を置き換え
-            let env_prev = if expr.qual == parser::Qual::Un {
-                Some(mem::take(&mut env.env_lin))
-            } else {
-                None
-            };
+            // キャプチャできない型環境を取り除く
+            let env_prev = env.take_uncapturable(expr.qual);
 
             // depthをインクリメントしてpush
             let mut depth = depth;
@@ -158,47 +1031,145 @@ fn typing_qval(expr: &parser::QValExpr, env: &mut TypeEnv, depth: usize) -> TRes
             })?;
             env.push(depth);
             env.insert(e.var.clone(), e.ty.clone());
+            let floor = env.set_floor(depth);
 
             // 関数中の式を型付け
             let t = typing(&e.expr, env, depth)?;
 
-            // スタックをpopし、popした型環境の中にlin型が含まれていた場合、型付けエラー
-            let (elin, _) = env.pop(depth);
-            for (k, v) in elin.unwrap().iter() {
-                if v.is_some() {
-                    return Err(format!("関数定義内でlin型の変数\"{}\"を消費していない", k));
-                }
-            }
+            // スタックをpopし、popした型環境の中に消費されていないlin型か、
+            // 利用されていないrel型が含まれていた場合、型付けエラー
+            env.pop(depth, "関数定義内")?;
 
-            // lin用の型環境を復元
-            if let Some(ep) = env_prev {
-                env.env_lin = ep;
-            }
+            // 取り除いた型環境を復元
+            env.set_floor(floor);
+            env.restore(expr.qual, env_prev);
 
             // 関数型を返す
-            parser::PrimType::Arrow(Box::new(e.ty.clone()), Box::new(t))
+            (
+                parser::PrimType::Arrow(Box::new(e.ty.clone()), Box::new(t.ty.clone())),
+                TypedVal::Fun {
+                    var: e.var.clone(),
+                    ty: e.ty.clone(),
+                    expr: Box::new(t),
+                },
+            )
         }
     };
 
     // 修飾子付き型を返す
-    Ok(parser::TypeExpr {
-        qual: expr.qual,
-        prim: p,
+    Ok(TypedExpr {
+        node: Node::QVal {
+            qual: expr.qual,
+            region: expr.region.clone(),
+            val,
+        },
+        ty: parser::TypeExpr {
+            qual: expr.qual,
+            region: expr.region.clone(),
+            prim: p,
+        },
     })
 }
 
 /// free式の型付け
 fn typing_free(expr: &parser::FreeExpr, env: &mut TypeEnv, depth: usize) -> TResult {
-    // TODO: ここを実装せよ
-    // todo!()は削除すること
-    todo!()
+    // free文の型は、続けて実行する式の型
+    let free = |e: TypedExpr| TypedExpr {
+        ty: e.ty.clone(),
+        node: Node::Free {
+            var: expr.var.clone(),
+            expr: Box::new(e),
+        },
+    };
+
+    if env.is_frozen(&expr.var) {
+        return Err(format!("借用中の変数\"{}\"をfreeしている", expr.var));
+    }
+
+    // linかaff用の型環境から変数を探し、消費されていなければ消費
+    // freeするとペアの要素やrefの中身も解放されるため、それらも利用せずに解放できなければならない
+    let is_droppable = env.is_droppable(&expr.var);
+    if let Some((q, t)) = env.get_mut(&expr.var) {
+        if let Some(t) = t.as_ref().filter(|_| !is_droppable) {
+            return Err(format!(
+                "利用せずに解放できない値を含む{}型の変数\"{}\"をfreeしている",
+                t, expr.var
+            ));
+        }
+
+        if (q == parser::Qual::Lin || q == parser::Qual::Aff) && t.is_some() {
+            *t = None;
+            return typing(&expr.expr, env, depth).map(free);
+        }
+
+        // ord型の変数は、束縛とは逆順でのみfreeできる
+        if q == parser::Qual::Ord && t.is_some() {
+            env.check_ord(&expr.var)?;
+            if let Some((_, t)) = env.get_mut(&expr.var) {
+                *t = None;
+            }
+            return typing(&expr.expr, env, depth).map(free);
+        }
+    }
+
+    Err(format!(
+        "既にfreeしたか、ord、lin、aff型ではない変数\"{}\"をfreeしている",
+        expr.var
+    ))
+}
+
+/// freeで解放できる値の型かを判定
+///
+/// rel型の値は少なくとも一度利用しなければならないため、利用せずに解放できない。
+/// ファイルハンドルとチャネルの端点はfcloseとcloseで閉じなければならないため、freeで解放できない。
+/// 関数と加法的ペアの型はキャプチャした変数の型を含まないため、
+/// rel型の変数やファイルハンドル、チャネルの端点をキャプチャしうるun、aff型以外のものは解放できないとみなす。
+/// ただし、キャプチャした値が分かる変数は[TypeEnv::is_droppable]で判定する
+fn droppable(t: &parser::TypeExpr) -> bool {
+    match &t.prim {
+        _ if t.qual == parser::Qual::Rel => false,
+        parser::PrimType::Handle | parser::PrimType::Chan(_) => false,
+        parser::PrimType::Pair(t1, t2) => droppable(t1) && droppable(t2),
+        parser::PrimType::Cell(t) | parser::PrimType::Array(t) => droppable(t),
+        parser::PrimType::Arrow(..) | parser::PrimType::With(..) => {
+            matches!(t.qual, parser::Qual::Un | parser::Qual::Aff)
+        }
+        _ => true,
+    }
+}
+
+/// letregion式を抜けた時点の一括解放に、解放を任せられる値の型かを判定
+///
+/// スコープ内のリージョンに確保した値は、修飾子に関わらず一括して解放される。
+/// ただし一括解放はペアの要素などを再帰的に解放しないため、要素はun型か、
+/// それ自体が一括解放に任せられるリージョン内の値でなければならない。
+/// 関数と加法的ペアは、キャプチャした変数を解放できないため任せられない
+fn freed_by_region(t: &parser::TypeExpr, regions: &[(String, usize)]) -> bool {
+    let elem = |t: &parser::TypeExpr| {
+        t.qual == parser::Qual::Un || (t.qual != parser::Qual::Rel && freed_by_region(t, regions))
+    };
+
+    let in_scope = match &t.region {
+        Some(r) => regions.iter().any(|(r2, _)| r2 == r),
+        None => false,
+    };
+    in_scope
+        && match &t.prim {
+            parser::PrimType::Pair(t1, t2) => elem(t1) && elem(t2),
+            parser::PrimType::Cell(t) | parser::PrimType::Array(t) => elem(t),
+            parser::PrimType::Arrow(..)
+            | parser::PrimType::With(..)
+            | parser::PrimType::Handle
+            | parser::PrimType::Chan(_) => false,
+            _ => true,
+        }
 }
 
 /// if式の型付け
 fn typing_if(expr: &parser::IfExpr, env: &mut TypeEnv, depth: usize) -> TResult {
     let t1 = typing(&expr.cond_expr, env, depth)?;
-    // 条件の式の型はbool
-    if t1.prim != parser::PrimType::Bool {
+    // 条件の式の型はboolか、boolへの参照
+    if deref(&t1.ty).prim != parser::PrimType::Bool {
         return Err("ifの条件式がboolでない".to_string());
     }
 
@@ -206,36 +1177,151 @@ fn typing_if(expr: &parser::IfExpr, env: &mut TypeEnv, depth: usize) -> TResult
     let t2 = typing(&expr.then_expr, &mut e, depth)?;
     let t3 = typing(&expr.else_expr, env, depth)?;
 
-    // thenとelse部の型は同じで、
+    // thenとelse部の型を合流でき、
     // thenとelse部評価後の型環境は同じかをチェック
-    if t2 != t3 || e != *env {
-        return Err("ifのthenとelseの式の型が異なる".to_string());
+    env.join_aff(&mut e);
+    env.join_frozen(&mut e);
+    match join(&t2.ty, &t3.ty) {
+        Some(ty) if e == *env => Ok(TypedExpr {
+            ty,
+            node: Node::If {
+                cond_expr: Box::new(t1),
+                then_expr: Box::new(t2),
+                else_expr: Box::new(t3),
+            },
+        }),
+        _ => Err("ifのthenとelseの式の型が異なる".to_string()),
     }
-
-    Ok(t2)
 }
 
 /// split式の型付け
 fn typing_split(expr: &parser::SplitExpr, env: &mut TypeEnv, depth: usize) -> TResult {
-    // TODO: ここを実装せよ
-    // todo!()は削除すること
-    todo!()
+    if expr.left == expr.right {
+        return Err("splitの変数名が同じ".to_string());
+    }
+
+    // 分解する式の型を計算
+    let t1 = typing(&expr.expr, env, depth)?;
+
+    // depthをインクリメントしてpush
+    let mut depth = depth;
+    safe_add(&mut depth, &1, || {
+        "変数スコープのネストが深すぎる".to_string()
+    })?;
+
+    match (&t1.ty.prim, &deref(&t1.ty).prim) {
+        (parser::PrimType::Pair(p1, p2), _) => {
+            env.push(depth);
+            env.insert(expr.left.clone(), *p1.clone());
+            env.insert(expr.right.clone(), *p2.clone());
+        }
+        (parser::PrimType::Ref(_), parser::PrimType::Pair(p1, p2)) => {
+            // ペアへの参照を分解した場合、各要素への参照を束縛
+            let r = |t: &parser::TypeExpr| parser::TypeExpr {
+                qual: parser::Qual::Un,
+                region: None,
+                prim: parser::PrimType::Ref(Box::new(t.clone())),
+            };
+            env.push(depth);
+            env.insert(expr.left.clone(), r(p1));
+            env.insert(expr.right.clone(), r(p2));
+        }
+        _ => {
+            return Err("splitの引数がペア型でない".to_string());
+        }
+    }
+
+    // splitの本体を型付け
+    let ret = typing(&expr.body, env, depth)?;
+
+    // スタックをpopし、popした型環境の中に消費されていないlin型か、
+    // 利用されていないrel型が含まれていた場合、型付けエラー
+    env.pop(depth, "splitの式内")?;
+
+    Ok(TypedExpr {
+        ty: ret.ty.clone(),
+        node: Node::Split {
+            expr: Box::new(t1),
+            left: expr.left.clone(),
+            right: expr.right.clone(),
+            body: Box::new(ret),
+        },
+    })
+}
+
+/// fstとsnd式の型付け
+fn typing_proj(expr: &parser::ProjExpr, env: &mut TypeEnv, depth: usize) -> TResult {
+    // 射影する式の型を計算
+    // lin型の加法的ペアは、ここで消費されるため一度しか射影できない
+    let t = typing(&expr.expr, env, depth)?;
+
+    let ty = match &t.ty.prim {
+        parser::PrimType::With(t1, t2) => match expr.proj {
+            parser::Proj::Fst => *t1.clone(),
+            parser::Proj::Snd => *t2.clone(),
+        },
+        _ => return Err("fstかsndの引数が加法的ペア型でない".to_string()),
+    };
+    Ok(TypedExpr {
+        ty,
+        node: Node::Proj {
+            proj: expr.proj,
+            expr: Box::new(t),
+        },
+    })
 }
 
 /// 変数の型付け
-fn typing_var(expr: &str, env: &mut TypeEnv) -> TResult {
+///
+/// 変数の型と、変数を消費して値を移動するか、消費せずに複製するかを返す
+fn typing_var(expr: &str, env: &mut TypeEnv) -> Result<(parser::TypeExpr, Use), String> {
+    let frozen = env.is_frozen(expr);
+    let outer = matches!(env.find(expr), Some((d, _)) if d < env.floor);
     let ret = env.get_mut(expr);
-    if let Some(it) = ret {
+    if let Some((q, it)) = ret {
         // 定義されている
         if let Some(t) = it {
             // 消費されていない
-            if t.qual == parser::Qual::Lin {
-                // lin型
-                let eret = t.clone();
-                *it = None; // linを消費
-                return Ok(eret);
-            } else {
-                return Ok(t.clone());
+            if outer && has_ref(t) {
+                return Err(format!(
+                    "参照\"{}\"を、関数や!型の値の中でキャプチャできない",
+                    expr
+                ));
+            }
+            if outer && find_region(t, &|_| true).is_some() {
+                return Err(format!(
+                    "リージョン内の値\"{}\"を、関数や!型の値の中でキャプチャできない",
+                    expr
+                ));
+            }
+            if frozen && q != parser::Qual::Rel && q != parser::Qual::Un {
+                return Err(format!("借用中の変数\"{}\"を消費している", expr));
+            }
+
+            // let !式で束縛された変数は、lin型でもun用の型環境にあるため消費しない
+            match q {
+                parser::Qual::Ord => {
+                    // ord型は、束縛とは逆順でのみ消費できる
+                    let eret = t.clone();
+                    env.check_ord(expr)?;
+                    if let Some((_, it)) = env.get_mut(expr) {
+                        *it = None; // ordを消費
+                    }
+                    return Ok((eret, Use::Move));
+                }
+                parser::Qual::Lin | parser::Qual::Aff => {
+                    // linかaff型
+                    let eret = t.clone();
+                    *it = None; // linかaffを消費
+                    return Ok((eret, Use::Move));
+                }
+                parser::Qual::Rel => {
+                    // rel型
+                    let eret = t.clone();
+                    env.mark_used(expr); // relを利用済みとする
+                    return Ok((eret, Use::Copy));
+                }
+                parser::Qual::Un => return Ok((t.clone(), Use::Copy)),
             }
         }
     }
@@ -248,7 +1334,462 @@ fn typing_var(expr: &str, env: &mut TypeEnv) -> TResult {
 
 /// let式の型付け
 fn typing_let(expr: &parser::LetExpr, env: &mut TypeEnv, depth: usize) -> TResult {
-    // TODO: ここを実装せよ
-    // todo!()は削除すること
-    todo!()
+    // 型注釈が妥当かをチェック
+    check_wf(&expr.ty)
+        .and_then(|_| env.check_regions(&expr.ty))
+        .map_err(|msg| format!("変数\"{}\"の型注釈{}が不正。{}", expr.var, expr.ty, msg))?;
+
+    // 関数か加法的ペアを束縛する場合、キャプチャする変数が全て捨てられれば変数も解放できる
+    // 解放できる変数を束縛する場合も、同じ値であるため解放できる
+    let is_droppable = match &*expr.expr1 {
+        parser::Expr::QVal(parser::QValExpr {
+            val: parser::ValExpr::Fun(_) | parser::ValExpr::With(..),
+            ..
+        }) => free_vars(&expr.expr1).iter().all(|v| env.is_discardable(v)),
+        parser::Expr::Var(v) => env.is_droppable(v),
+        _ => false,
+    };
+
+    // 変数に束縛する式の型を計算し、型注釈の部分型かをチェック
+    let t1 = typing(&expr.expr1, env, depth)?;
+    if !subtype(&t1.ty, &expr.ty) {
+        return Err(format!(
+            "変数\"{}\"の型が異なる。{}が必要だが、{}が与えられた",
+            expr.var, expr.ty, t1.ty
+        ));
+    }
+
+    // depthをインクリメントしてpush
+    let mut depth = depth;
+    safe_add(&mut depth, &1, || {
+        "変数スコープのネストが深すぎる".to_string()
+    })?;
+    env.push(depth);
+    env.insert(expr.var.clone(), expr.ty.clone());
+    if is_droppable {
+        env.droppable.push((expr.var.clone(), depth));
+    }
+
+    // let式の本体を型付け
+    let t2 = typing(&expr.expr2, env, depth)?;
+
+    // スタックをpopし、popした型環境の中に消費されていないlin型か、
+    // 利用されていないrel型が含まれていた場合、型付けエラー
+    env.pop(depth, "let式内")?;
+
+    Ok(TypedExpr {
+        ty: t2.ty.clone(),
+        node: Node::Let {
+            var: expr.var.clone(),
+            ty: expr.ty.clone(),
+            expr1: Box::new(t1),
+            expr2: Box::new(t2),
+        },
+    })
+}
+
+/// let !式の型付け
+fn typing_let_bang(expr: &parser::LetBangExpr, env: &mut TypeEnv, depth: usize) -> TResult {
+    // 変数に束縛する式の型を計算し、!型かをチェック
+    let t1 = typing(&expr.expr1, env, depth)?;
+    let t = match &t1.ty.prim {
+        parser::PrimType::Bang(t) => *t.clone(),
+        _ => {
+            return Err(format!(
+                "let !式で束縛する変数\"{}\"の値が!型でない",
+                expr.var
+            ))
+        }
+    };
+
+    // depthをインクリメントしてpush
+    let mut depth = depth;
+    safe_add(&mut depth, &1, || {
+        "変数スコープのネストが深すぎる".to_string()
+    })?;
+    env.push(depth);
+
+    // !型の中身は何度でも取り出せるため、un用の型環境に追加
+    env.insert_un(expr.var.clone(), t);
+
+    // let !式の本体を型付け
+    let t2 = typing(&expr.expr2, env, depth)?;
+
+    // スタックをpopし、popした型環境の中に消費されていないlin型か、
+    // 利用されていないrel型が含まれていた場合、型付けエラー
+    env.pop(depth, "let !式内")?;
+
+    Ok(TypedExpr {
+        ty: t2.ty.clone(),
+        node: Node::LetBang {
+            var: expr.var.clone(),
+            expr1: Box::new(t1),
+            expr2: Box::new(t2),
+        },
+    })
+}
+
+/// promote式の型付け
+fn typing_promote(expr: &parser::PromoteExpr, env: &mut TypeEnv, depth: usize) -> TResult {
+    // !型の値は何度でも取り出せるため、un型以外の自由変数を利用できない
+    // un fnと同様に、キャプチャできない型環境を取り除いて型付け
+    let env_prev = env.take_uncapturable(parser::Qual::Un);
+    let floor = env.set_floor(depth + 1);
+    let t = typing(&expr.expr, env, depth);
+    env.set_floor(floor);
+    env.restore(parser::Qual::Un, env_prev);
+
+    let t = t?;
+    Ok(TypedExpr {
+        ty: parser::TypeExpr {
+            qual: parser::Qual::Un,
+            region: None,
+            prim: parser::PrimType::Bang(Box::new(t.ty.clone())),
+        },
+        node: Node::Promote { expr: Box::new(t) },
+    })
+}
+
+/// new式の型付け
+fn typing_new(expr: &parser::NewExpr, env: &TypeEnv) -> Result<parser::TypeExpr, String> {
+    // 両端点のチャネル型を作成し、妥当かをチェック
+    let chan = |s| parser::TypeExpr {
+        qual: parser::Qual::Lin,
+        region: None,
+        prim: parser::PrimType::Chan(s),
+    };
+    let t1 = chan(expr.session.clone());
+    check_wf(&t1)
+        .and_then(|_| env.check_regions(&t1))
+        .map_err(|msg| format!("newのセッション型{}が不正。{}", expr.session, msg))?;
+
+    // 一方の端点はセッション型の通りに、もう一方の端点は双対のセッション型に従って通信する
+    let t2 = chan(dual(&expr.session));
+    Ok(parser::TypeExpr {
+        qual: parser::Qual::Lin,
+        region: None,
+        prim: parser::PrimType::Pair(Box::new(t1), Box::new(t2)),
+    })
+}
+
+/// send式の型付け
+fn typing_send(expr: &parser::SendExpr, env: &mut TypeEnv, depth: usize) -> TResult {
+    // チャネルと送信する値の型を計算
+    let t1 = typing(&expr.chan, env, depth)?;
+    let t2 = typing(&expr.expr, env, depth)?;
+
+    match &t1.ty.prim {
+        parser::PrimType::Chan(parser::Session::Send(t, s)) => {
+            // 送信する値の型が、セッション型で送信する値の型の部分型かをチェック
+            if !subtype(&t2.ty, t) {
+                return Err(format!(
+                    "送信する値の型が異なる。{}が必要だが、{}が与えられた",
+                    t, t2.ty
+                ));
+            }
+            Ok(TypedExpr {
+                ty: parser::TypeExpr {
+                    qual: t1.ty.qual,
+                    region: None,
+                    prim: parser::PrimType::Chan(*s.clone()),
+                },
+                node: Node::Send {
+                    chan: Box::new(t1),
+                    expr: Box::new(t2),
+                },
+            })
+        }
+        _ => Err(format!("送信できるチャネルでない値{}に送信している", t1.ty)),
+    }
+}
+
+/// recv式の型付け
+fn typing_recv(expr: &parser::RecvExpr, env: &mut TypeEnv, depth: usize) -> TResult {
+    let t1 = typing(&expr.chan, env, depth)?;
+
+    match &t1.ty.prim {
+        parser::PrimType::Chan(parser::Session::Recv(t, s)) => {
+            // 受信した値と残りのチャネルのペアを返す
+            // ペアの修飾子は、両方の要素を含められるものとする
+            let c = parser::TypeExpr {
+                qual: t1.ty.qual,
+                region: None,
+                prim: parser::PrimType::Chan(*s.clone()),
+            };
+            Ok(TypedExpr {
+                ty: parser::TypeExpr {
+                    qual: t.qual.join(c.qual),
+                    region: None,
+                    prim: parser::PrimType::Pair(t.clone(), Box::new(c)),
+                },
+                node: Node::Recv { chan: Box::new(t1) },
+            })
+        }
+        _ => Err(format!(
+            "受信できるチャネルでない値{}から受信している",
+            t1.ty
+        )),
+    }
+}
+
+/// close式の型付け
+fn typing_close(expr: &parser::CloseExpr, env: &mut TypeEnv, depth: usize) -> TResult {
+    let t = typing(&expr.chan, env, depth)?;
+
+    match &t.ty.prim {
+        parser::PrimType::Chan(parser::Session::End) => Ok(TypedExpr {
+            ty: parser::TypeExpr {
+                qual: parser::Qual::Un,
+                region: None,
+                prim: parser::PrimType::Unit,
+            },
+            node: Node::Close { chan: Box::new(t) },
+        }),
+        _ => Err(format!("通信を終えていない値{}を閉じている", t.ty)),
+    }
+}
+
+/// fork式の型付け
+fn typing_fork(expr: &parser::ForkExpr, env: &mut TypeEnv, depth: usize) -> TResult {
+    // 新たなスレッドで評価する式を型付け
+    // スレッドが利用したlin型の変数は、スレッドに移動したものとして消費される
+    // スレッドは後から評価されるため、関数と同様に外側の変数を借用できない
+    let floor = env.set_floor(depth + 1);
+    let t1 = typing(&expr.expr1, env, depth);
+    env.set_floor(floor);
+    let t1 = t1?;
+
+    // スレッドの評価結果は捨てられるため、un unit型でなければならない
+    let unit = parser::TypeExpr {
+        qual: parser::Qual::Un,
+        region: None,
+        prim: parser::PrimType::Unit,
+    };
+    if !subtype(&t1.ty, &unit) {
+        return Err(format!(
+            "forkするスレッドの型が異なる。{}が必要だが、{}が与えられた",
+            unit, t1.ty
+        ));
+    }
+
+    let t2 = typing(&expr.expr2, env, depth)?;
+    Ok(TypedExpr {
+        ty: t2.ty.clone(),
+        node: Node::Fork {
+            expr1: Box::new(t1),
+            expr2: Box::new(t2),
+        },
+    })
+}
+
+/// 借用の型付け
+fn typing_borrow(var: &str, env: &mut TypeEnv) -> Result<parser::TypeExpr, String> {
+    let (d, q) = env
+        .find(var)
+        .ok_or_else(|| format!("\"{}\"という変数は定義されていない", var))?;
+    if d < env.floor {
+        return Err(format!(
+            "関数や!型の値の中で、外側の変数\"{}\"を借用している",
+            var
+        ));
+    }
+
+    let t = match env.get_mut(var) {
+        Some((_, Some(t))) => t.clone(),
+        _ => return Err(format!("利用済みの変数\"{}\"を借用している", var)),
+    };
+
+    // 参照型の変数の借用は、同じ参照とする
+    if let parser::PrimType::Ref(_) = t.prim {
+        return Ok(t);
+    }
+
+    // 消費されうる変数は、借用が終わるまで凍結
+    // rel型とun型の変数は消費されず、評価器でも解放されないため凍結しない
+    if matches!(q, parser::Qual::Ord | parser::Qual::Lin | parser::Qual::Aff) {
+        env.frozen.push((var.to_string(), d));
+    }
+
+    Ok(parser::TypeExpr {
+        qual: parser::Qual::Un,
+        region: None,
+        prim: parser::PrimType::Ref(Box::new(t)),
+    })
+}
+
+/// new式（可変な参照の作成）の型付け
+fn typing_new_ref(expr: &parser::NewRefExpr, env: &mut TypeEnv, depth: usize) -> TResult {
+    let t = typing(&expr.expr, env, depth)?;
+
+    // ref型はlin型とし、ord型の値を格納する場合はord型とする
+    Ok(TypedExpr {
+        ty: parser::TypeExpr {
+            qual: parser::Qual::Lin.join(t.ty.qual),
+            region: None,
+            prim: parser::PrimType::Cell(Box::new(t.ty.clone())),
+        },
+        node: Node::NewRef { expr: Box::new(t) },
+    })
+}
+
+/// swap式の型付け
+fn typing_swap(expr: &parser::SwapExpr, env: &mut TypeEnv, depth: usize) -> TResult {
+    // 参照と格納する値の型を計算
+    let t1 = typing(&expr.cell, env, depth)?;
+    let t2 = typing(&expr.expr, env, depth)?;
+
+    match &t1.ty.prim {
+        parser::PrimType::Cell(t) => {
+            // 格納する値の型が、refの中身の型の部分型かをチェック
+            if !subtype(&t2.ty, t) {
+                return Err(format!(
+                    "refに格納する値の型が異なる。{}が必要だが、{}が与えられた",
+                    t, t2.ty
+                ));
+            }
+
+            // 参照と元の中身のペアを返す
+            let t = *t.clone();
+            Ok(TypedExpr {
+                ty: parser::TypeExpr {
+                    qual: t1.ty.qual.join(t.qual),
+                    region: None,
+                    prim: parser::PrimType::Pair(Box::new(t1.ty.clone()), Box::new(t)),
+                },
+                node: Node::Swap {
+                    cell: Box::new(t1),
+                    expr: Box::new(t2),
+                },
+            })
+        }
+        _ => Err(format!("ref型でない値{}をswapしている", t1.ty)),
+    }
+}
+
+/// letregion式の型付け
+fn typing_letregion(expr: &parser::LetRegionExpr, env: &mut TypeEnv, depth: usize) -> TResult {
+    // 型に現れるリージョンを区別できるよう、同じ名前のリージョンの内側では作成できない
+    if env.regions.iter().any(|(r, _)| *r == expr.region) {
+        return Err(format!(
+            "リージョン\"{}\"の中で、同じ名前のリージョンを作成している",
+            expr.region
+        ));
+    }
+
+    // depthをインクリメントしてpushし、リージョンを作成
+    let mut depth = depth;
+    safe_add(&mut depth, &1, || {
+        "変数スコープのネストが深すぎる".to_string()
+    })?;
+    env.push(depth);
+    env.regions.push((expr.region.clone(), depth));
+
+    // letregion式の本体を型付け
+    let t = typing(&expr.expr, env, depth)?;
+
+    // スタックをpopし、popした型環境の中に消費されていないlin型か、
+    // 利用されていないrel型が含まれていた場合、型付けエラー
+    env.pop(depth, "letregion式内")?;
+    env.regions.pop();
+
+    // リージョン内の値は解放されるため、式の型にリージョンが現れる場合は型付けエラー
+    if find_region(&t.ty, &|r| r == expr.region).is_some() {
+        return Err(format!(
+            "リージョン\"{}\"の外に、リージョン内の値を含む{}型の値が出ている",
+            expr.region, t.ty
+        ));
+    }
+
+    Ok(TypedExpr {
+        ty: t.ty.clone(),
+        node: Node::LetRegion {
+            region: expr.region.clone(),
+            expr: Box::new(t),
+        },
+    })
+}
+
+/// 配列の要素の位置を表す式の型付け
+fn typing_index(expr: &parser::Expr, env: &mut TypeEnv, depth: usize, op: &str) -> TResult {
+    let t = typing(expr, env, depth)?;
+    if t.ty.prim != parser::PrimType::Int {
+        return Err(format!("{}の引数{}がintでない", op, t.ty));
+    }
+    Ok(t)
+}
+
+/// alloc式の型付け
+fn typing_alloc(expr: &parser::AllocExpr, env: &mut TypeEnv, depth: usize) -> TResult {
+    let len = typing_index(&expr.len, env, depth, "alloc")?;
+
+    // 初期値は全ての要素に複製されるため、un型でなければならない
+    let t = typing(&expr.expr, env, depth)?;
+    if t.ty.qual != parser::Qual::Un {
+        return Err(format!("配列の要素の型{}がun型でない", t.ty));
+    }
+
+    Ok(TypedExpr {
+        ty: parser::TypeExpr {
+            qual: parser::Qual::Lin,
+            region: None,
+            prim: parser::PrimType::Array(Box::new(t.ty.clone())),
+        },
+        node: Node::Alloc {
+            len: Box::new(len),
+            expr: Box::new(t),
+        },
+    })
+}
+
+/// get式の型付け
+fn typing_get(expr: &parser::GetExpr, env: &mut TypeEnv, depth: usize) -> TResult {
+    // 配列はここで消費されるため、配列と読み出した要素のペアを返す
+    let t1 = typing(&expr.array, env, depth)?;
+    let index = typing_index(&expr.index, env, depth, "get")?;
+
+    match &t1.ty.prim {
+        parser::PrimType::Array(t) => {
+            let t = *t.clone();
+            Ok(TypedExpr {
+                ty: parser::TypeExpr {
+                    qual: t1.ty.qual,
+                    region: None,
+                    prim: parser::PrimType::Pair(Box::new(t1.ty.clone()), Box::new(t)),
+                },
+                node: Node::Get {
+                    array: Box::new(t1),
+                    index: Box::new(index),
+                },
+            })
+        }
+        _ => Err(format!("array型でない値{}から読み出している", t1.ty)),
+    }
+}
+
+/// set式の型付け
+fn typing_set(expr: &parser::SetExpr, env: &mut TypeEnv, depth: usize) -> TResult {
+    let t1 = typing(&expr.array, env, depth)?;
+    let index = typing_index(&expr.index, env, depth, "set")?;
+    let t2 = typing(&expr.expr, env, depth)?;
+
+    match &t1.ty.prim {
+        parser::PrimType::Array(t) => {
+            // 書き込む値の型が、配列の要素の型の部分型かをチェック
+            if !subtype(&t2.ty, t) {
+                return Err(format!(
+                    "配列に書き込む値の型が異なる。{}が必要だが、{}が与えられた",
+                    t, t2.ty
+                ));
+            }
+            Ok(TypedExpr {
+                ty: t1.ty.clone(),
+                node: Node::Set {
+                    array: Box::new(t1),
+                    index: Box::new(index),
+                    expr: Box::new(t2),
+                },
+            })
+        }
+        _ => Err(format!("array型でない値{}に書き込んでいる", t1.ty)),
+    }
 }
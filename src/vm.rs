@@ -0,0 +1,666 @@
+//! ## スタックマシン
+//!
+//! [crate::bytecode]でコンパイルしたプログラムを実行する。
+//!
+//! 値は評価器と同様にすべてヒープ上に確保し、オペランドスタックと局所変数にはアドレスを置く。
+//! ord、lin、aff型の値は分解した時点で、外部関数に渡した値は渡した時点で解放し、
+//! リージョン内の値はリージョンを抜けた時点で一括して解放するため、
+//! 評価器と同じプログラムを実行した場合は同じヒープの統計情報となる。
+//! ただし、クロージャは環境全体ではなく自由変数のみをキャプチャする。
+//!
+//! 関数呼び出しはフレームを積んで命令の読み出し位置を移すため、Rustの再帰呼び出しを伴わない。
+//! `.linc`ファイルから読み込んだプログラムも実行するため、
+//! 関数や局所変数の番号が範囲外のものなど、不正なバイトコードは評価エラーとする。
+
+use crate::{
+    bytecode::{Func, Op, Program},
+    eval::{self, Addr, HeapStats, RegionStats, Value},
+    externs::{self, ExternVal, Externs},
+    parser,
+};
+
+/// ヒープ上のデータ
+#[derive(Debug)]
+enum Data {
+    Bool(bool),                    // 真偽値
+    Unit,                          // ユニット
+    Handle(u64),                   // ファイルハンドル
+    Int(i64),                      // 整数
+    Str(String),                   // 文字列
+    Pair(Addr, Addr),              // ペア
+    With(Vec<Addr>, usize, usize), // 加法的ペア。キャプチャした値と、各要素の関数の番号
+    Fun(Vec<Addr>, usize),         // 関数（クロージャ）
+    Bang(Vec<Addr>, usize),        // !型の値。取り出す度に関数を呼び出す
+    Extern(usize, Vec<Addr>),      // 外部関数。外部定義の番号と、受け取った引数
+    Ref(Addr),                     // 参照。参照先のアドレスを保持する
+    Cell(Addr),                    // 可変な参照。中身のアドレスを保持する
+    Array(Vec<Addr>),              // 配列。要素のアドレスを保持する
+}
+
+/// ヒープ上のセル
+#[derive(Debug)]
+struct Cell {
+    qual: parser::Qual,
+    data: Data,
+}
+
+/// 修飾子qの値を、分解した時点で解放するかを判定
+fn consumes(q: parser::Qual) -> bool {
+    matches!(q, parser::Qual::Ord | parser::Qual::Lin | parser::Qual::Aff)
+}
+
+/// リージョン
+#[derive(Debug)]
+struct Region {
+    name: String,
+    cells: Vec<Addr>,     // リージョン内に確保したセルのアドレス
+    num_bulk_free: usize, // リージョンを抜けた時点で一括して解放したセルの数
+}
+
+/// ヒープ
+#[derive(Debug, Default)]
+struct Heap {
+    cells: Vec<Option<Cell>>,
+    num_free: usize,
+    regions: Vec<Region>, // 作成したリージョン。作成順に番号を振る
+}
+
+impl Heap {
+    /// セルを確保し、アドレスを返す
+    fn alloc(&mut self, qual: parser::Qual, data: Data) -> Addr {
+        self.cells.push(Some(Cell { qual, data }));
+        self.cells.len() - 1
+    }
+
+    /// セルを取得
+    fn get(&self, addr: Addr) -> Result<&Cell, String> {
+        match self.cells.get(addr) {
+            Some(Some(c)) => Ok(c),
+            _ => Err(format!("解放済みのアドレス{}を参照した", addr)),
+        }
+    }
+
+    /// セルの内容を、書き換えられるよう取得
+    fn get_mut(&mut self, addr: Addr) -> Result<&mut Cell, String> {
+        match self.cells.get_mut(addr) {
+            Some(Some(c)) => Ok(c),
+            _ => Err(format!("解放済みのアドレス{}を書き換えた", addr)),
+        }
+    }
+
+    /// セルを解放
+    fn free(&mut self, addr: Addr) -> Result<(), String> {
+        match self.cells.get_mut(addr) {
+            Some(c @ Some(_)) => {
+                *c = None;
+                self.num_free += 1;
+                Ok(())
+            }
+            _ => Err(format!("解放済みのアドレス{}を解放した", addr)),
+        }
+    }
+
+    /// 分解したセルを、修飾子に応じて解放
+    fn consume(&mut self, addr: Addr) -> Result<(), String> {
+        if consumes(self.get(addr)?.qual) {
+            self.free(addr)?;
+        }
+        Ok(())
+    }
+
+    /// freeしたセルや外部関数に渡したセルを、ペアの要素やrefの中身も含めて修飾子に応じて解放
+    fn release(&mut self, addr: Addr) -> Result<(), String> {
+        match self.get(addr)?.data {
+            Data::Pair(a1, a2) => {
+                self.release(a1)?;
+                self.release(a2)?;
+            }
+            Data::Cell(a) => self.release(a)?,
+            _ => (),
+        }
+        self.consume(addr)
+    }
+
+    /// リージョン内の解放されていないセルを、修飾子に関わらず一括して解放
+    fn free_region(&mut self, region: usize) -> Result<(), String> {
+        let cells = self.regions[region].cells.clone();
+        for addr in cells {
+            if self.cells[addr].is_some() {
+                self.free(addr)?;
+                self.regions[region].num_bulk_free += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// 参照をたどり、参照でない値のアドレスを返す
+    fn deref(&self, addr: Addr) -> Result<Addr, String> {
+        match self.get(addr)?.data {
+            Data::Ref(a) => self.deref(a),
+            _ => Ok(addr),
+        }
+    }
+
+    /// 配列のアドレスから、要素のアドレスを取得
+    fn elems(&mut self, addr: Addr) -> Result<&mut Vec<Addr>, String> {
+        match &mut self.get_mut(addr)?.data {
+            Data::Array(elems) => Ok(elems),
+            _ => Err("配列でない値を読み書きした".to_string()),
+        }
+    }
+
+    /// addrから到達可能なセルのアドレスをreachableに追加
+    fn mark(&self, addr: Addr, reachable: &mut Vec<bool>) {
+        if reachable[addr] {
+            return;
+        }
+        reachable[addr] = true;
+
+        match self.cells[addr].as_ref().map(|c| &c.data) {
+            Some(Data::Pair(a1, a2)) => {
+                self.mark(*a1, reachable);
+                self.mark(*a2, reachable);
+            }
+            Some(Data::Ref(a)) | Some(Data::Cell(a)) => self.mark(*a, reachable),
+            Some(Data::With(addrs, _, _))
+            | Some(Data::Fun(addrs, _))
+            | Some(Data::Bang(addrs, _))
+            | Some(Data::Extern(_, addrs))
+            | Some(Data::Array(addrs)) => {
+                for a in addrs {
+                    self.mark(*a, reachable);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// ヒープの統計情報を計算
+    ///
+    /// rootは実行結果のアドレスで、rootから到達できないord型とlin型のセルはリークとなる
+    fn stats(&self, root: Addr) -> HeapStats {
+        let mut reachable = vec![false; self.cells.len()];
+        self.mark(root, &mut reachable);
+
+        let mut leaked = Vec::new();
+        for (addr, c) in self.cells.iter().enumerate() {
+            if let Some(c) = c {
+                if !reachable[addr] && matches!(c.qual, parser::Qual::Ord | parser::Qual::Lin) {
+                    leaked.push((addr, c.qual));
+                }
+            }
+        }
+
+        let regions = self
+            .regions
+            .iter()
+            .map(|r| {
+                let num_live = r.cells.iter().filter(|a| self.cells[**a].is_some()).count();
+                RegionStats {
+                    name: r.name.clone(),
+                    num_alloc: r.cells.len(),
+                    num_free: r.cells.len() - num_live - r.num_bulk_free,
+                    num_bulk_free: r.num_bulk_free,
+                }
+            })
+            .collect();
+
+        HeapStats {
+            num_alloc: self.cells.len(),
+            num_free: self.num_free,
+            leaked,
+            regions,
+        }
+    }
+}
+
+/// 関数呼び出しのフレーム
+#[derive(Debug)]
+struct Frame {
+    func: usize,
+    pc: usize,                 // 次に実行する命令の位置
+    locals: Vec<Option<Addr>>, // 局所変数。束縛されていないものはNone
+    regions: Vec<usize>,       // 関数内で作成中のリージョンの番号。後のものほど内側
+}
+
+type VResult<T> = Result<T, String>;
+
+/// スタックマシン
+struct Vm<'a> {
+    program: &'a Program,
+    externs: &'a Externs,
+    heap: Heap,
+    stack: Vec<Addr>, // オペランドスタック
+    frames: Vec<Frame>,
+}
+
+impl<'a> Vm<'a> {
+    /// 関数を取得
+    fn func(&self, id: u32) -> VResult<&'a Func> {
+        self.program
+            .funcs
+            .get(id as usize)
+            .ok_or_else(|| format!("不正なバイトコード: 関数fn#{}は存在しない", id))
+    }
+
+    /// 文字列テーブルの文字列を取得
+    fn string(&self, id: u32) -> VResult<&'a str> {
+        match self.program.strings.get(id as usize) {
+            Some(s) => Ok(s),
+            None => Err(format!("不正なバイトコード: 文字列#{}は存在しない", id)),
+        }
+    }
+
+    /// 実行中のフレームを取得
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().unwrap()
+    }
+
+    /// 実行中のフレームの局所変数を取得
+    fn local(&mut self, slot: u32) -> VResult<Addr> {
+        match self.frame().locals.get(slot as usize) {
+            Some(Some(a)) => Ok(*a),
+            Some(None) => Err(format!(
+                "不正なバイトコード: 局所変数{}は束縛されていない",
+                slot
+            )),
+            None => Err(format!("不正なバイトコード: 局所変数{}は存在しない", slot)),
+        }
+    }
+
+    /// オペランドスタックから値を降ろす
+    fn pop(&mut self) -> VResult<Addr> {
+        self.stack
+            .pop()
+            .ok_or_else(|| "不正なバイトコード: オペランドスタックが空".to_string())
+    }
+
+    /// オペランドスタックの上からn番目の値を取得
+    fn peek(&self, n: usize) -> VResult<Addr> {
+        match self.stack.len().checked_sub(n + 1) {
+            Some(i) => Ok(self.stack[i]),
+            None => Err("不正なバイトコード: オペランドスタックが空".to_string()),
+        }
+    }
+
+    /// 関数の呼び出し。キャプチャした値と引数を局所変数に束縛したフレームを積む
+    fn call(&mut self, id: usize, env: &[Addr], arg: Option<Addr>) -> VResult<()> {
+        let func = self.func(id as u32)?;
+        let mut locals = vec![None; func.num_locals as usize];
+        let n = env.len() + arg.is_some() as usize;
+        if locals.len() < n {
+            return Err(format!("不正なバイトコード: fn#{}の局所変数が足りない", id));
+        }
+        for (l, a) in locals.iter_mut().zip(env.iter().copied().chain(arg)) {
+            *l = Some(a);
+        }
+        self.frames.push(Frame {
+            func: id,
+            pc: 0,
+            locals,
+            regions: Vec::new(),
+        });
+        Ok(())
+    }
+
+    /// 関数がキャプチャする値を、実行中のフレームの局所変数から集める
+    fn capture(&mut self, id: u32) -> VResult<Vec<Addr>> {
+        let func = self.func(id)?;
+        func.captures.iter().map(|slot| self.local(*slot)).collect()
+    }
+
+    /// 修飾子qの値を確保し、オペランドスタックに積む
+    fn push(&mut self, qual: parser::Qual, data: Data) {
+        let a = self.heap.alloc(qual, data);
+        self.stack.push(a);
+    }
+
+    /// 配列の位置か長さを表す整数を取得し、範囲内かをチェック
+    ///
+    /// lenがNoneの場合は配列の長さを表すものとし、負でないかのみをチェック
+    fn index(&self, addr: Addr, len: Option<usize>) -> VResult<usize> {
+        let n = match self.heap.get(addr)?.data {
+            Data::Int(n) => n,
+            _ => return Err("配列の位置か長さがintでない".to_string()),
+        };
+        match usize::try_from(n) {
+            Ok(i) if len.is_none_or(|len| i < len) => Ok(i),
+            _ => Err(format!("配列の範囲外の位置{}を指定した", n)),
+        }
+    }
+
+    /// プログラムの最後の関数から戻るまで実行し、実行結果のアドレスを返す
+    fn run(&mut self) -> VResult<Addr> {
+        let program = self.program;
+        loop {
+            let frame = self.frame();
+            let (id, pc) = (frame.func, frame.pc);
+            frame.pc += 1;
+            let op = match program.funcs[id].code.get(pc) {
+                Some(op) => op,
+                None => return Err(format!("不正なバイトコード: fn#{}の末尾を越えた", id)),
+            };
+
+            match op {
+                Op::PushBool(q, b) => self.push(*q, Data::Bool(*b)),
+                Op::PushInt(q, n) => self.push(*q, Data::Int(*n)),
+                Op::PushStr(q, s) => {
+                    let s = self.string(*s)?.to_string();
+                    self.push(*q, Data::Str(s));
+                }
+                Op::MkPair(q) => {
+                    let a2 = self.pop()?;
+                    let a1 = self.pop()?;
+                    self.push(*q, Data::Pair(a1, a2));
+                }
+                Op::MkWith(q, f1, f2) => {
+                    let env = self.capture(*f1)?;
+                    self.push(*q, Data::With(env, *f1 as usize, *f2 as usize));
+                }
+                Op::MkClosure(q, f) => {
+                    let env = self.capture(*f)?;
+                    self.push(*q, Data::Fun(env, *f as usize));
+                }
+                Op::MkBang(f) => {
+                    let env = self.capture(*f)?;
+                    self.push(parser::Qual::Un, Data::Bang(env, *f as usize));
+                }
+                Op::Load(slot) => {
+                    let a = self.local(*slot)?;
+                    self.stack.push(a);
+                }
+                Op::Force(slot) => {
+                    let a = self.local(*slot)?;
+                    let (env, f) = match &self.heap.get(a)?.data {
+                        Data::Bang(env, f) => (env.clone(), *f),
+                        _ => return Err("!型でない値を取り出そうとした".to_string()),
+                    };
+                    self.call(f, &env, None)?;
+                }
+                Op::Store(slot) => {
+                    let a = self.pop()?;
+                    match self.frame().locals.get_mut(*slot as usize) {
+                        Some(l) => *l = Some(a),
+                        None => {
+                            return Err(format!("不正なバイトコード: 局所変数{}は存在しない", slot))
+                        }
+                    }
+                }
+                Op::Borrow => {
+                    // 参照の借用は同じ参照とする
+                    let a = self.pop()?;
+                    if let Data::Ref(_) = self.heap.get(a)?.data {
+                        self.stack.push(a);
+                    } else {
+                        self.push(parser::Qual::Un, Data::Ref(a));
+                    }
+                }
+                Op::Free(slot) => {
+                    // ペアの要素やrefの中身も含めて解放
+                    let a = self.local(*slot)?;
+                    self.heap.release(a)?;
+                }
+                Op::App => {
+                    let arg = self.pop()?;
+                    let f = self.pop()?;
+                    match &self.heap.get(f)?.data {
+                        Data::Fun(env, id) => {
+                            let (env, id) = (env.clone(), *id);
+                            self.heap.consume(f)?;
+                            self.call(id, &env, Some(arg))?;
+                        }
+                        Data::Extern(id, args) => {
+                            let (id, mut args) = (*id, args.clone());
+                            self.heap.consume(f)?;
+                            args.push(arg);
+                            let a = self.apply_extern(id, args)?;
+                            self.stack.push(a);
+                        }
+                        _ => return Err("関数でない値を関数適用した".to_string()),
+                    }
+                }
+                Op::Ret => {
+                    self.frames.pop();
+                    if self.frames.is_empty() {
+                        return self.pop();
+                    }
+                }
+                Op::Fst | Op::Snd => {
+                    let a = self.pop()?;
+                    let (env, f) = match &self.heap.get(a)?.data {
+                        Data::With(env, f1, f2) => {
+                            (env.clone(), if *op == Op::Fst { *f1 } else { *f2 })
+                        }
+                        _ => return Err("fstかsndの引数が加法的ペアでない".to_string()),
+                    };
+                    self.heap.consume(a)?;
+                    self.call(f, &env, None)?;
+                }
+                Op::Split => {
+                    let a = self.pop()?;
+                    let target = self.heap.deref(a)?;
+                    let (mut a1, mut a2) = match self.heap.get(target)?.data {
+                        Data::Pair(a1, a2) => (a1, a2),
+                        _ => return Err("splitの引数がペアでない".to_string()),
+                    };
+                    self.heap.consume(a)?;
+
+                    // ペアへの参照を分解した場合、各要素への参照を積む
+                    if target != a {
+                        a1 = self.heap.alloc(parser::Qual::Un, Data::Ref(a1));
+                        a2 = self.heap.alloc(parser::Qual::Un, Data::Ref(a2));
+                    }
+                    self.stack.push(a1);
+                    self.stack.push(a2);
+                }
+                Op::JmpIf(target) => {
+                    let a = self.pop()?;
+                    let b = match self.heap.get(self.heap.deref(a)?)?.data {
+                        Data::Bool(b) => b,
+                        _ => return Err("ifの条件式がboolでない".to_string()),
+                    };
+                    self.heap.consume(a)?;
+                    if b {
+                        self.frame().pc = *target as usize;
+                    }
+                }
+                Op::Jmp(target) => self.frame().pc = *target as usize,
+                Op::NewRef => {
+                    let a = self.pop()?;
+                    let q = parser::Qual::Lin.join(self.heap.get(a)?.qual);
+                    self.push(q, Data::Cell(a));
+                }
+                Op::Swap => {
+                    let v = self.pop()?;
+                    let c = self.pop()?;
+                    let (q, old) = match self.heap.get(c)? {
+                        Cell {
+                            qual,
+                            data: Data::Cell(old),
+                        } => (*qual, *old),
+                        _ => return Err("refでない値をswapした".to_string()),
+                    };
+
+                    // セルを書き換えて中身を交換し、同じ参照と元の中身のペアを積む
+                    self.heap.get_mut(c)?.data = Data::Cell(v);
+                    let qo = self.heap.get(old)?.qual;
+                    self.push(q.join(qo), Data::Pair(c, old));
+                }
+                Op::CheckLen => {
+                    self.index(self.peek(0)?, None)?;
+                }
+                Op::Alloc => {
+                    // 要素はun型で解放されないため、全ての要素で同じ値を共有する
+                    let v = self.pop()?;
+                    let a = self.pop()?;
+                    let n = self.index(a, None)?;
+                    self.heap.consume(a)?;
+                    let elems = eval::array_elems(v, n)?;
+                    self.push(parser::Qual::Lin, Data::Array(elems));
+                }
+                Op::CheckIndex => {
+                    let len = self.heap.elems(self.peek(1)?)?.len();
+                    self.index(self.peek(0)?, Some(len))?;
+                }
+                Op::Get => {
+                    // 配列は消費せずに、同じ配列と要素のペアを積む
+                    let i = self.pop()?;
+                    let a = self.pop()?;
+                    let len = self.heap.elems(a)?.len();
+                    let n = self.index(i, Some(len))?;
+                    self.heap.consume(i)?;
+                    let v = self.heap.elems(a)?[n];
+                    let q = self.heap.get(a)?.qual;
+                    self.push(q, Data::Pair(a, v));
+                }
+                Op::Set => {
+                    // 配列はlin型で他から参照されないため、コピーせずに書き換える
+                    let v = self.pop()?;
+                    let i = self.pop()?;
+                    let a = self.pop()?;
+                    let len = self.heap.elems(a)?.len();
+                    let n = self.index(i, Some(len))?;
+                    self.heap.consume(i)?;
+                    self.heap.elems(a)?[n] = v;
+                    self.stack.push(a);
+                }
+                Op::RegionNew(s) => {
+                    let name = self.string(*s)?.to_string();
+                    self.heap.regions.push(Region {
+                        name,
+                        cells: Vec::new(),
+                        num_bulk_free: 0,
+                    });
+                    let r = self.heap.regions.len() - 1;
+                    self.frame().regions.push(r);
+                }
+                Op::RegionFree => {
+                    // 実行結果はリージョン外の値のため、リージョン内の値を一括して解放
+                    let r = self.frame().regions.pop().ok_or_else(|| {
+                        "不正なバイトコード: 作成されていないリージョンを解放した".to_string()
+                    })?;
+                    self.heap.free_region(r)?;
+                }
+                Op::InRegion(n) => {
+                    let r = match self.frame().regions.get(*n as usize) {
+                        Some(r) => *r,
+                        None => {
+                            return Err(format!("不正なバイトコード: リージョン{}は存在しない", n))
+                        }
+                    };
+                    let a = self.peek(0)?;
+                    self.heap.regions[r].cells.push(a);
+                }
+            }
+        }
+    }
+
+    /// 外部関数に引数argsを与える
+    ///
+    /// 全ての引数がそろった場合は外部関数を呼び出し、そうでない場合は部分適用した外部関数を返す
+    fn apply_extern(&mut self, id: usize, args: Vec<Addr>) -> VResult<Addr> {
+        let def = &self.externs.defs[id];
+        if args.len() < externs::arity(&def.ty) {
+            let qual = externs::applied(&def.ty, args.len()).qual;
+            return Ok(self.heap.alloc(qual, Data::Extern(id, args)));
+        }
+
+        let f = match &def.val {
+            ExternVal::Fun(f) => f.clone(),
+            ExternVal::Value(_) => return Err(format!("\"{}\"は関数でない", def.name)),
+        };
+
+        // 引数を読み出し、外部関数に渡したものとして解放
+        let mut vals = Vec::new();
+        for a in args {
+            vals.push(self.read(a)?);
+            self.heap.release(a)?;
+        }
+
+        let v = f(vals).map_err(|msg| format!("外部関数\"{}\"のエラー: {}", def.name, msg))?;
+        self.alloc_value(&v)
+    }
+
+    /// 値をヒープ上に確保
+    fn alloc_value(&mut self, v: &Value) -> VResult<Addr> {
+        let (qual, data) = match v {
+            Value::Bool(q, b) => (*q, Data::Bool(*b)),
+            Value::Unit(q) => (*q, Data::Unit),
+            Value::Handle(q, n) => (*q, Data::Handle(*n)),
+            Value::Int(q, n) => (*q, Data::Int(*n)),
+            Value::Str(q, s) => (*q, Data::Str(s.clone())),
+            Value::Pair(q, v1, v2) => {
+                let a1 = self.alloc_value(v1)?;
+                let a2 = self.alloc_value(v2)?;
+                (*q, Data::Pair(a1, a2))
+            }
+            _ => return Err(format!("{}はヒープ上に確保できない", v)),
+        };
+        Ok(self.heap.alloc(qual, data))
+    }
+
+    /// ヒープ上の値を読み出す
+    fn read(&self, addr: Addr) -> VResult<Value> {
+        let c = self.heap.get(addr)?;
+        Ok(match &c.data {
+            Data::Bool(b) => Value::Bool(c.qual, *b),
+            Data::Unit => Value::Unit(c.qual),
+            Data::Handle(n) => Value::Handle(c.qual, *n),
+            Data::Int(n) => Value::Int(c.qual, *n),
+            Data::Str(s) => Value::Str(c.qual, s.clone()),
+            Data::Pair(a1, a2) => {
+                Value::Pair(c.qual, Box::new(self.read(*a1)?), Box::new(self.read(*a2)?))
+            }
+            Data::With(..) => Value::With(c.qual),
+            Data::Fun(_, id) => {
+                let var = self.func(*id as u32)?.var.clone().unwrap_or_default();
+                Value::Fun(c.qual, var)
+            }
+            Data::Bang(..) => Value::Bang(c.qual),
+            Data::Extern(id, _) => Value::Fun(c.qual, self.externs.defs[*id].name.clone()),
+            Data::Ref(a) => Value::Ref(c.qual, Box::new(self.read(*a)?)),
+            Data::Cell(a) => Value::Cell(c.qual, Box::new(self.read(*a)?)),
+            Data::Array(elems) => Value::Array(
+                c.qual,
+                elems
+                    .iter()
+                    .map(|a| self.read(*a))
+                    .collect::<Result<_, _>>()?,
+            ),
+        })
+    }
+}
+
+/// 外部定義を束縛した初期環境でプログラムを実行し、実行結果の値と、実行後のヒープの統計情報を返す
+///
+/// プログラムが利用する外部定義は、名前によりexternsから探す
+pub fn run(program: &Program, externs: &Externs) -> Result<(Value, HeapStats), String> {
+    let mut vm = Vm {
+        program,
+        externs,
+        heap: Heap::default(),
+        stack: Vec::new(),
+        frames: Vec::new(),
+    };
+
+    // 外部定義を、0番の関数の局所変数の先頭に束縛
+    let mut env = Vec::new();
+    for s in program.externs.iter() {
+        let name = vm.string(*s)?;
+        let id = externs
+            .defs
+            .iter()
+            .rposition(|d| d.name == name)
+            .ok_or_else(|| format!("外部定義\"{}\"が登録されていない", name))?;
+        let def = &externs.defs[id];
+        let a = match &def.val {
+            ExternVal::Value(v) => vm.alloc_value(v)?,
+            ExternVal::Fun(_) => vm.heap.alloc(def.ty.qual, Data::Extern(id, vec![])),
+        };
+        env.push(a);
+    }
+
+    vm.call(0, &env, None)?;
+    let addr = vm.run()?;
+    Ok((vm.read(addr)?, vm.heap.stats(addr)))
+}
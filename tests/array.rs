@@ -0,0 +1,29 @@
+//! 配列の検査
+
+use lineartype::{Error, Externs};
+
+/// 長さが上限を超える配列は、メモリを確保せずに評価エラーとなること
+#[test]
+fn huge_length() {
+    let expr =
+        lineartype::parse("let a : lin array un int = alloc un 100000000000 un 0; free a; un true")
+            .unwrap();
+    lineartype::check(&expr).unwrap();
+    let externs = Externs::new();
+    let program = lineartype::compile(&expr, &externs).unwrap();
+
+    let results = [
+        ("eval", lineartype::eval(&expr).map(|_| ())),
+        (
+            "eval_cek",
+            lineartype::eval_cek(&expr, &externs, |_| ()).map(|_| ()),
+        ),
+        ("run", lineartype::run(&program, &externs).map(|_| ())),
+    ];
+    for (name, res) in results {
+        match res {
+            Err(Error::Eval(msg)) => assert!(msg.contains("上限"), "{}: {}", name, msg),
+            res => panic!("{}: 評価エラーとなるべき: {:?}", name, res),
+        }
+    }
+}
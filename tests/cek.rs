@@ -0,0 +1,102 @@
+//! CEK機械の検査
+//!
+//! サンプルファイルの評価結果とヒープの統計情報が評価器と一致することと、
+//! トレースに表示するセルの確保と解放を確かめる。
+
+mod common;
+
+use lineartype::{Error, Externs};
+use std::{fs, path::Path};
+
+/// サンプルファイルをCEK機械で評価した結果を評価器と比較する
+///
+/// 型付けに失敗するか、CEK機械で評価できない場合はNoneを返す
+fn check(path: &Path, externs: &Externs) -> Result<Option<()>, String> {
+    let src = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let Ok(expr) = lineartype::parse(&src) else {
+        return Ok(None);
+    };
+    if lineartype::check_with(&expr, &externs.type_env()).is_err() {
+        return Ok(None);
+    }
+
+    match (
+        lineartype::eval_with(&expr, externs),
+        lineartype::eval_cek(&expr, externs, |_| ()),
+    ) {
+        (Ok((v1, s1)), Ok((v2, s2))) => {
+            if v1.to_string() != v2.to_string() {
+                return Err(format!("評価結果が{}でなく{}", v1, v2));
+            }
+            if s1 != s2 {
+                return Err(format!("ヒープの統計情報が{:?}でなく{:?}", s1, s2));
+            }
+        }
+        // チャネルとスレッドには対応していない
+        (Ok(_), Err(Error::Eval(msg))) if msg.contains("チャネル") => return Ok(None),
+        (Err(Error::Eval(_)), Err(Error::Eval(_))) => (),
+        (r1, r2) => return Err(format!("評価器は{:?}だが、CEK機械は{:?}", r1, r2)),
+    }
+    Ok(Some(()))
+}
+
+/// 型付けに成功するサンプルファイルを評価し、評価結果とヒープの統計情報が評価器と一致すること
+#[test]
+fn codes() {
+    let externs = common::builtins();
+    let mut failures = Vec::new();
+    let mut num_run = 0;
+    for path in common::codes() {
+        match check(&path, &externs) {
+            Ok(Some(())) => num_run += 1,
+            Ok(None) => (),
+            Err(msg) => failures.push(format!("{}: {}", path.display(), msg)),
+        }
+    }
+
+    assert!(num_run > 0);
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+/// トレースに、lin型の変数が指すセルと、セルを消費した時点とfreeで解放した時点が表示されること
+#[test]
+fn trace() {
+    let expr = lineartype::parse(
+        "let x : lin bool = lin true; let y : lin bool = lin false; free y; if x { un true } else { un false }",
+    )
+    .unwrap();
+    lineartype::check(&expr).unwrap();
+
+    let mut states = Vec::new();
+    let (v, stats) =
+        lineartype::eval_cek(&expr, &Externs::new(), |m| states.push(m.to_string())).unwrap();
+    assert_eq!(v.to_string(), "un true");
+    assert_eq!((stats.num_alloc, stats.num_free), (3, 2));
+    assert!(stats.leaked.is_empty());
+
+    // 確保したセルと、変数が指すセル
+    let state = |i: usize| states[i].lines().skip(1).collect::<Vec<_>>();
+    assert_eq!(state(2)[0], "  確保: #0 = lin true");
+    assert_eq!(state(6)[1], "  E: x ↦ #0 = lin true, y ↦ #1 = lin false");
+
+    // freeで解放したセル
+    assert_eq!(
+        state(7)[..3],
+        [
+            "  freeで解放: #1 = lin false（y）",
+            "  C: if x { un true } else { un false }",
+            "  E: x ↦ #0 = lin true, y ↦ #1（解放済み）",
+        ]
+    );
+
+    // if式の条件として消費したセル
+    assert_eq!(
+        state(10)[..3],
+        [
+            "  消費して解放: #0 = lin true（x）",
+            "  C: un true",
+            "  E: x ↦ #0（解放済み）, y ↦ #1（解放済み）",
+        ]
+    );
+    assert_eq!(states.len(), 12);
+}
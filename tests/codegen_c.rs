@@ -0,0 +1,55 @@
+//! C言語へのコード生成の検査
+//!
+//! 型付けに成功すべきサンプルファイルをC言語に変換し、AddressSanitizerを有効にしてccでコンパイルして実行する。
+//! 表示された評価結果が評価器と一致し、リークがなく、AddressSanitizerがエラーを報告しないことを確かめる。
+//! C言語に変換できない機能を利用するサンプルファイルは[UNSUPPORTED]に列挙し、
+//! それ以外のサンプルファイルが全て変換できることを確かめる。
+//! ccが利用できない環境では何もしない。
+
+mod common;
+
+use std::{fs, path::PathBuf, process::Command};
+
+/// C言語に変換できない機能（チャネルとスレッド）を利用するサンプルファイル
+const UNSUPPORTED: [&str; 2] = ["chan_ex1", "chan_ex2"];
+
+#[test]
+fn codegen_c() {
+    if Command::new("cc").arg("--version").output().is_err() {
+        eprintln!("ccが見つからないため、C言語へのコード生成の検査を省略する");
+        return;
+    }
+
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("codegen_c");
+    fs::create_dir_all(&dir).unwrap();
+    let paths: Vec<_> = common::codes()
+        .into_iter()
+        .filter(|p| common::is_ex(p))
+        .collect();
+
+    // C言語のプログラムをコンパイルして実行し、リークがないことも確かめる
+    common::check_backend(
+        &paths,
+        &UNSUPPORTED,
+        lineartype::codegen_c,
+        |sample, code| {
+            let (value, _) = common::expected_value(sample)?;
+            let (c, exe) = (
+                dir.join(format!("{}.c", sample.stem)),
+                dir.join(sample.stem),
+            );
+            fs::write(&c, code).map_err(|e| e.to_string())?;
+            common::run_command(
+                Command::new("cc")
+                    .args(["-std=c99", "-g", "-fsanitize=address,undefined", "-o"])
+                    .args([&exe, &c]),
+            )
+            .map_err(|e| format!("コンパイルに失敗した: {}", e))?;
+
+            let stdout =
+                common::run_command(Command::new(&exe).env("ASAN_OPTIONS", "detect_leaks=1"))?;
+            common::check_value(&stdout, &value)?;
+            common::check_leaked(&stdout, 0)
+        },
+    );
+}
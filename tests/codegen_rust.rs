@@ -0,0 +1,53 @@
+//! Rustへのコード生成の検査
+//!
+//! 型付けに成功すべきサンプルファイルをRustに変換し、rustcでコンパイルして実行する。
+//! 生成したプログラムがコンパイルでき、表示された評価結果が評価器と一致することを確かめる。
+//! Rustに変換できない機能を利用するサンプルファイルは[UNSUPPORTED]に列挙し、
+//! それ以外のサンプルファイルが全て変換できることを確かめる。
+//! rustcが利用できない環境では何もしない。
+
+mod common;
+
+use std::{fs, path::PathBuf, process::Command};
+
+/// Rustに変換できない機能（チャネルとスレッド）を利用するサンプルファイル
+const UNSUPPORTED: [&str; 2] = ["chan_ex1", "chan_ex2"];
+
+#[test]
+fn codegen_rust() {
+    if Command::new("rustc").arg("--version").output().is_err() {
+        eprintln!("rustcが見つからないため、Rustへのコード生成の検査を省略する");
+        return;
+    }
+
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("codegen_rust");
+    fs::create_dir_all(&dir).unwrap();
+    let paths: Vec<_> = common::codes()
+        .into_iter()
+        .filter(|p| common::is_ex(p))
+        .collect();
+
+    // Rustのプログラムを警告なくコンパイルできることも確かめる
+    common::check_backend(
+        &paths,
+        &UNSUPPORTED,
+        lineartype::codegen_rust,
+        |sample, code| {
+            let (value, _) = common::expected_value(sample)?;
+            let (rs, exe) = (
+                dir.join(format!("{}.rs", sample.stem)),
+                dir.join(sample.stem),
+            );
+            fs::write(&rs, code).map_err(|e| e.to_string())?;
+            common::run_command(
+                Command::new("rustc")
+                    .args(["--edition", "2021", "-o"])
+                    .args([&exe, &rs]),
+            )
+            .map_err(|e| format!("コンパイルに失敗したか警告がある: {}", e))?;
+
+            let stdout = common::run_command(&mut Command::new(&exe))?;
+            common::check_value(&stdout, &value)
+        },
+    );
+}
@@ -0,0 +1,104 @@
+//! WebAssemblyへのコード生成の検査
+//!
+//! 型付けに成功すべきサンプルファイルをWATに変換し、watでアセンブルしてwasmiで実行する。
+//! 表示された評価結果とリークしたセルの数、終了コードが、評価器と一致することを確かめる。
+//! WebAssemblyに変換できない機能を利用するサンプルファイルは[UNSUPPORTED]に列挙し、
+//! それ以外のサンプルファイルが全て変換できることを確かめる。
+
+mod common;
+
+use wasmi::{Caller, Engine, Linker, Module, Store};
+
+/// 実行したモジュールの出力と終了コード
+struct Output {
+    stdout: Vec<u8>,
+    status: i32,
+}
+
+/// WASIのfd_writeとproc_exitのみを提供して、モジュールの_startを実行する
+fn run_wasm(wasm: &[u8]) -> Result<Output, String> {
+    let engine = Engine::default();
+    let module = Module::new(&engine, wasm).map_err(|e| e.to_string())?;
+    let mut store = Store::new(&engine, Vec::<u8>::new());
+    let mut linker = <Linker<Vec<u8>>>::new(&engine);
+    linker
+        .func_wrap(
+            "wasi_snapshot_preview1",
+            "fd_write",
+            |mut caller: Caller<'_, Vec<u8>>, fd: i32, iovs: i32, len: i32, nwritten: i32| {
+                let memory = caller.get_export("memory").unwrap().into_memory().unwrap();
+                let mut buf = Vec::new();
+                {
+                    let data = memory.data(&caller);
+                    let word = |a: usize| u32::from_le_bytes(data[a..a + 4].try_into().unwrap());
+                    for i in 0..len as usize {
+                        let iov = iovs as usize + i * 8;
+                        let (p, n) = (word(iov) as usize, word(iov + 4) as usize);
+                        buf.extend_from_slice(&data[p..p + n]);
+                    }
+                }
+                let n = (buf.len() as u32).to_le_bytes();
+                memory.data_mut(&mut caller)[nwritten as usize..][..4].copy_from_slice(&n);
+                if fd == 1 {
+                    caller.data_mut().extend(buf);
+                }
+                0
+            },
+        )
+        .map_err(|e| e.to_string())?;
+    linker
+        .func_wrap(
+            "wasi_snapshot_preview1",
+            "proc_exit",
+            |status: i32| -> Result<(), wasmi::Error> { Err(wasmi::Error::i32_exit(status)) },
+        )
+        .map_err(|e| e.to_string())?;
+
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .and_then(|pre| pre.start(&mut store))
+        .map_err(|e| e.to_string())?;
+    let start = instance
+        .get_typed_func::<(), ()>(&store, "_start")
+        .map_err(|e| e.to_string())?;
+    let status = match start.call(&mut store, ()) {
+        Ok(()) => 0,
+        Err(e) => e.i32_exit_status().ok_or_else(|| e.to_string())?,
+    };
+    Ok(Output {
+        stdout: store.into_data(),
+        status,
+    })
+}
+
+/// WebAssemblyに変換できない機能（チャネルとスレッド）を利用するサンプルファイル
+const UNSUPPORTED: [&str; 2] = ["chan_ex1", "chan_ex2"];
+
+#[test]
+fn codegen_wat() {
+    let paths: Vec<_> = common::codes()
+        .into_iter()
+        .filter(|p| common::is_ex(p))
+        .collect();
+
+    // リークしたセルの数と終了コードも、評価器と一致することを確かめる
+    common::check_backend(
+        &paths,
+        &UNSUPPORTED,
+        lineartype::codegen_wat,
+        |sample, code| {
+            let (value, leaked) = common::expected_value(sample)?;
+            let wasm =
+                wat::parse_str(&code).map_err(|e| format!("WATのアセンブルに失敗した: {}", e))?;
+            let out = run_wasm(&wasm)?;
+            let stdout = String::from_utf8(out.stdout).map_err(|e| e.to_string())?;
+
+            common::check_value(&stdout, &value)?;
+            common::check_leaked(&stdout, leaked)?;
+            if out.status != (leaked > 0) as i32 {
+                return Err(format!("終了コードが{}", out.status));
+            }
+            Ok(())
+        },
+    );
+}
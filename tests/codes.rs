@@ -0,0 +1,151 @@
+//! codes/以下のサンプルファイルの検査
+//!
+//! ex*.linと<機能名>_ex*.linは型付けと評価に成功し、ord、lin型のセルがリークしないこと、
+//! err*.linと<機能名>_err*.linは[ERRORS]に記録した段階で、記録したメッセージを含むエラーとなることを確かめる。
+
+mod common;
+
+use lineartype::Externs;
+use std::{fs, path::Path};
+
+/// 失敗すべきサンプルファイルの名前と、失敗する段階を表すエラーの種類と、エラーメッセージに含まれるべき文字列
+///
+/// パースの誤りなど、意図しない理由で失敗したサンプルファイルを検出するために用いる
+const ERRORS: &[(&str, &str, &str)] = &[
+    ("array_err1", "型付けエラー", "\"a\"という変数は定義されていないか、利用済みか、キャプチャできない"),
+    ("array_err2", "型付けエラー", "let式内でlin型の変数\"a\"を消費していない"),
+    ("array_err3", "型付けエラー", "配列の要素の型lin boolがun型でない"),
+    ("array_err4", "型付けエラー", "$のarray型の修飾子がun。linかordでなければならない"),
+    ("array_err5", "型付けエラー", "配列に書き込む値の型が異なる。un intが必要だが、un boolが与えられた"),
+    ("array_err6", "型付けエラー", "getの引数un boolがintでない"),
+    ("bang_err1", "型付けエラー", "\"x\"という変数は定義されていないか、利用済みか、キャプチャできない"),
+    ("bang_err2", "型付けエラー", "let !式で束縛する変数\"y\"の値が!型でない"),
+    ("borrow_err1", "型付けエラー", "借用中の変数\"x\"を消費している"),
+    ("borrow_err2", "型付けエラー", "let式内で借用した変数\"x\"の参照が、変数のスコープの外に出ている"),
+    ("borrow_err3", "型付けエラー", "参照\"r\"を、関数や!型の値の中でキャプチャできない"),
+    ("borrow_err4", "型付けエラー", "借用中の変数\"x\"をfreeしている"),
+    ("borrow_err5", "型付けエラー", "$.0.0の参照を含む値は送受信できない"),
+    ("borrow_err6", "型付けエラー", "関数や!型の値の中で、外側の変数\"x\"を借用している"),
+    ("chan_err1", "型付けエラー", "splitの式内でlin型の変数\"d\"を消費していない"),
+    ("chan_err2", "型付けエラー", "送信する値の型が異なる。un intが必要だが、un boolが与えられた"),
+    ("chan_err3", "型付けエラー", "\"c\"という変数は定義されていないか、利用済みか、キャプチャできない"),
+    ("chan_err4", "型付けエラー", "lin (lin !un int.end * lin !un int.end)が必要だが、lin (lin !un int.end * lin ?un int.end)が与えられた"),
+    ("chan_err5", "型付けエラー", "通信を終えていない値lin ?un int.endを閉じている"),
+    ("chan_err6", "型付けエラー", "$.0のチャネル型の修飾子がun。linかordでなければならない"),
+    ("chan_err7", "評価エラー", "デッドロックした"),
+    ("chan_err8", "型付けエラー", "利用せずに解放できない値を含むlin !lin bool.end型の変数\"a\"をfreeしている"),
+    ("err1", "型付けエラー", "un型のペア内でlin型を利用している"),
+    ("err2", "型付けエラー", "\"x\"という変数は定義されていないか、利用済みか、キャプチャできない"),
+    ("err3", "型付けエラー", "既にfreeしたか、ord、lin、aff型ではない変数\"x\"をfreeしている"),
+    ("err4", "型付けエラー", "splitの式内でlin型の変数\"y\"を消費していない"),
+    ("err5", "型付けエラー", "ifのthenとelseの式の型が異なる"),
+    ("err6", "型付けエラー", "\"x\"という変数は定義されていないか、利用済みか、キャプチャできない"),
+    ("err7", "型付けエラー", "let式内でlin型の変数\"x\"を消費していない"),
+    ("err8", "型付けエラー", "lin boolが必要だが、lin (lin bool * lin bool)が与えられた"),
+    ("err9", "型付けエラー", "splitの変数名が同じ"),
+    ("extern_err1", "型付けエラー", "\"x\"という変数は定義されていないか、利用済みか、キャプチャできない"),
+    ("fs_err1", "型付けエラー", "let式内でlin型の変数\"h\"を消費していない"),
+    ("fs_err2", "型付けエラー", "\"h\"という変数は定義されていないか、利用済みか、キャプチャできない"),
+    ("fs_err3", "型付けエラー", "利用せずに解放できない値を含むlin handle型の変数\"h\"をfreeしている"),
+    ("fs_err4", "型付けエラー", "利用せずに解放できない値を含むlin (lin handle * un bool)型の変数\"p\"をfreeしている"),
+    ("fs_err5", "型付けエラー", "利用せずに解放できない値を含むlin (un bool -> un unit)型の変数\"f\"をfreeしている"),
+    ("ord_err1", "型付けエラー", "ord型の変数\"y\"を、\"x\"より先に消費しなければならない"),
+    ("ord_err2", "型付けエラー", "既にfreeしたか、ord、lin、aff型ではない変数\"x\"をfreeしている"),
+    ("ord_err3", "型付けエラー", "ord型の変数\"b\"を、\"a\"より先に消費しなければならない"),
+    ("parse_err", "パースエラー", "expected '}', found x"),
+    ("parse_err2", "パースエラー", "lin fn x : ln bool"),
+    ("qual_err1", "型付けエラー", "\"x\"という変数は定義されていないか、利用済みか、キャプチャできない"),
+    ("qual_err2", "型付けエラー", "let式内でrel型の変数\"x\"を一度も利用していない"),
+    ("qual_err3", "型付けエラー", "\"x\"という変数は定義されていないか、利用済みか、キャプチャできない"),
+    ("qual_err4", "型付けエラー", "aff型のペア内でrel型を利用している"),
+    ("qual_err5", "型付けエラー", "利用せずに解放できない値を含むlin (rel bool * un bool)型の変数\"p\"をfreeしている"),
+    ("qual_err6", "型付けエラー", "利用せずに解放できない値を含むlin (un bool -> un bool)型の変数\"f\"をfreeしている"),
+    ("qual_err7", "型付けエラー", "利用せずに解放できない値を含むlin (un bool -> lin bool)型の変数\"f\"をfreeしている"),
+    ("qual_err8", "型付けエラー", "利用せずに解放できない値を含むlin (lin bool & lin bool)型の変数\"g\"をfreeしている"),
+    ("qual_err9", "型付けエラー", "利用せずに解放できない値を含むord (un bool -> ord bool)型の変数\"f\"をfreeしている"),
+    ("ref_err1", "型付けエラー", "let式内でlin型の変数\"r\"を消費していない"),
+    ("ref_err2", "型付けエラー", "\"r\"という変数は定義されていないか、利用済みか、キャプチャできない"),
+    ("ref_err3", "型付けエラー", "refに格納する値の型が異なる。lin boolが必要だが、lin strが与えられた"),
+    ("ref_err4", "型付けエラー", "$のref型の修飾子がun。linかordでなければならない"),
+    ("ref_err5", "型付けエラー", "利用せずに解放できない値を含むlin ref rel bool型の変数\"r\"をfreeしている"),
+    ("ref_err6", "型付けエラー", "splitの式内でlin型の変数\"b\"を消費していない"),
+    ("region_err1", "型付けエラー", "リージョン\"r\"の外に、リージョン内の値を含むun@r bool型の値が出ている"),
+    ("region_err10", "型付けエラー", "let式内でlin型の変数\"f\"を消費していない"),
+    ("region_err2", "型付けエラー", "リージョン\"r\"の外に、リージョン内の値を含むun (un@r bool * un bool)型の値が出ている"),
+    ("region_err3", "型付けエラー", "リージョン\"r\"はスコープ内にない"),
+    ("region_err4", "型付けエラー", "関数や!型の値の中で、外側のリージョン\"r\"に値を確保している"),
+    ("region_err5", "型付けエラー", "リージョン内の値\"x\"を、関数や!型の値の中でキャプチャできない"),
+    ("region_err6", "型付けエラー", "refに格納する値の型が異なる。un boolが必要だが、un@r boolが与えられた"),
+    ("region_err7", "型付けエラー", "リージョン\"r\"の中で、同じ名前のリージョンを作成している"),
+    ("region_err8", "型付けエラー", "変数\"x\"の型が異なる。lin boolが必要だが、lin@r boolが与えられた"),
+    ("region_err9", "型付けエラー", "let式内でlin型の変数\"p\"を消費していない"),
+    ("resolve_err1", "名前解決エラー", "\"c\"、\"z\"という変数は定義されていない"),
+    ("str_err1", "型付けエラー", "let式内でlin型の変数\"s\"を消費していない"),
+    ("str_err2", "型付けエラー", "\"s\"という変数は定義されていないか、利用済みか、キャプチャできない"),
+    ("str_err3", "型付けエラー", "変数\"s\"の型が異なる。un strが必要だが、lin strが与えられた"),
+    ("sub_err1", "型付けエラー", "関数の引数の型が異なる。un boolが必要だが、lin boolが与えられた"),
+    ("sub_err2", "型付けエラー", "un (lin bool -> lin bool)が必要だが、un (un bool -> lin bool)が与えられた"),
+    ("wf_err1", "型付けエラー", "$.0のlin型の値を、un型のペア内に含められない"),
+    ("wf_err2", "型付けエラー", "$.ret.1.1のaff型の値を、un型のペア内に含められない"),
+    ("wf_err3", "型付けエラー", "$.0のlin型の値を、un型のペア内に含められない"),
+    ("wf_err4", "型付けエラー", "$.!.1のlin型の値を、un型のペア内に含められない"),
+    ("with_err1", "型付けエラー", "<| |>の各要素で消費するlin型の変数が異なる"),
+    ("with_err2", "型付けエラー", "\"y\"という変数は定義されていないか、利用済みか、キャプチャできない"),
+];
+
+/// サンプルファイルをパースして型付けし、評価する
+fn run(path: &Path, externs: &Externs) -> Result<(), String> {
+    let src = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let expr = lineartype::parse(&src).map_err(|e| e.to_string())?;
+    lineartype::check_with(&expr, &externs.type_env()).map_err(|e| e.to_string())?;
+    let (_, stats) = lineartype::eval_with(&expr, externs).map_err(|e| e.to_string())?;
+    if !stats.leaked.is_empty() {
+        return Err(format!("リークした: {}", stats));
+    }
+    Ok(())
+}
+
+#[test]
+fn codes() {
+    let externs = common::builtins();
+    let mut failures = Vec::new();
+    let (mut num_ex, mut num_err) = (0, 0);
+    for path in common::codes() {
+        let res = run(&path, &externs);
+        if common::is_err(&path) {
+            num_err += 1;
+            let name = path.file_stem().unwrap().to_str().unwrap();
+            let Some((_, stage, msg)) = ERRORS.iter().find(|(n, _, _)| *n == name) else {
+                failures.push(format!(
+                    "{}: 失敗すべき理由が記録されていない",
+                    path.display()
+                ));
+                continue;
+            };
+            match res {
+                Ok(()) => failures.push(format!("{}: 失敗すべきだが成功した", path.display())),
+                Err(e) if !e.starts_with(stage) || !e.contains(msg) => failures.push(format!(
+                    "{}: {}で\"{}\"を含むエラーとなるべきだが、異なるエラーとなった\n{}",
+                    path.display(),
+                    stage,
+                    msg,
+                    e
+                )),
+                Err(_) => (),
+            }
+        } else if common::is_ex(&path) {
+            num_ex += 1;
+            if let Err(msg) = res {
+                failures.push(format!("{}: {}", path.display(), msg));
+            }
+        }
+    }
+
+    assert!(num_ex > 0 && num_err > 0);
+    assert_eq!(
+        num_err,
+        ERRORS.len(),
+        "存在しないサンプルファイルが記録されている"
+    );
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
@@ -0,0 +1,172 @@
+//! 結合テストで共通に用いる関数
+
+// テストごとに利用する関数が異なるため、未使用の警告を抑制
+#![allow(dead_code)]
+
+use lineartype::{Error, Expr, Externs, HeapStats, Qual, Value};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    thread,
+};
+
+/// main.rsと同じ組み込み関数を登録した外部定義
+pub fn builtins() -> Externs {
+    let ty = lineartype::parse_type("un (lin bool -> lin bool)").unwrap();
+    Externs::new()
+        .func("not", ty, |args| match &args[0] {
+            Value::Bool(_, b) => Ok(Value::Bool(Qual::Lin, !b)),
+            _ => Err("notの引数がboolでない".to_string()),
+        })
+        .extend(lineartype::fs_externs())
+        .extend(lineartype::string_externs())
+}
+
+/// codes/以下のサンプルファイルのパスを、ファイル名の順に返す
+pub fn codes() -> Vec<PathBuf> {
+    let mut paths: Vec<_> = fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/codes"))
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "lin"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// ファイル名の、最後の_より後の部分（ex1、err1など）
+fn kind(path: &Path) -> &str {
+    let stem = path.file_stem().unwrap().to_str().unwrap();
+    stem.rsplit('_').next().unwrap()
+}
+
+/// 型付けと評価に成功すべきサンプルファイルか
+pub fn is_ex(path: &Path) -> bool {
+    kind(path).starts_with("ex")
+}
+
+/// パース、型付け、評価のいずれかで失敗すべきサンプルファイルか
+pub fn is_err(path: &Path) -> bool {
+    kind(path).starts_with("err")
+}
+
+/// バックエンドの検査で、変換した結果とともに実行する処理に渡すサンプルファイルの情報
+pub struct Sample<'a> {
+    pub stem: &'a str, // ファイル名の拡張子を除いた部分
+    pub expr: &'a Expr,
+    pub externs: &'a Externs,
+    pub expected: &'a Result<(Value, HeapStats), Error>, // 評価器による評価結果
+}
+
+/// サンプルファイルごとの検査の結果
+enum Outcome {
+    Checked,     // 変換して実行し、評価器と比較した
+    Skipped,     // 型付けに失敗すべきサンプルファイルで、パースか型付けに失敗した
+    Unsupported, // バックエンドで変換できなかった
+}
+
+/// サンプルファイルをバックエンドで変換し、runで実行して評価器の評価結果と比較する
+///
+/// 型付けに成功すべきサンプルファイルがパースか型付けに失敗した場合と、runがエラーを返した場合は検査の失敗となる。
+/// それ以外のサンプルファイルは、パースか型付けに失敗した場合は検査しない。
+/// translateで変換できなかったサンプルファイルが、unsupportedとちょうど一致することを確かめる。
+/// 外部コマンドの実行に時間がかかるバックエンドがあるため、サンプルファイルごとにスレッドで並列に検査する
+pub fn check_backend<T>(
+    paths: &[PathBuf],
+    unsupported: &[&str],
+    translate: impl Fn(&Expr, &Externs) -> Result<T, Error> + Sync,
+    run: impl Fn(&Sample, T) -> Result<(), String> + Sync,
+) {
+    let externs = builtins();
+    let check = |path: &Path| -> Result<Outcome, String> {
+        let stem = path.file_stem().unwrap().to_str().unwrap();
+        let src = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let typed = lineartype::parse(&src)
+            .and_then(|expr| lineartype::check_with(&expr, &externs.type_env()).map(|_| expr));
+        let expr = match typed {
+            Ok(expr) => expr,
+            Err(e) if is_ex(path) => return Err(e.to_string()),
+            Err(_) => return Ok(Outcome::Skipped),
+        };
+        let out = match translate(&expr, &externs) {
+            Ok(out) => out,
+            Err(_) if unsupported.contains(&stem) => return Ok(Outcome::Unsupported),
+            Err(e) => return Err(e.to_string()),
+        };
+
+        let expected = lineartype::eval_with(&expr, &externs);
+        let sample = Sample {
+            stem,
+            expr: &expr,
+            externs: &externs,
+            expected: &expected,
+        };
+        run(&sample, out)?;
+        Ok(Outcome::Checked)
+    };
+
+    let results: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = paths
+            .iter()
+            .map(|path| scope.spawn(|| check(path)))
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+
+    let mut failures = Vec::new();
+    let mut skipped = Vec::new();
+    let mut num_checked = 0;
+    for (path, res) in paths.iter().zip(results) {
+        match res {
+            Ok(Outcome::Checked) => num_checked += 1,
+            Ok(Outcome::Skipped) => (),
+            Ok(Outcome::Unsupported) => skipped.push(path.file_stem().unwrap().to_str().unwrap()),
+            Err(msg) => failures.push(format!("{}: {}", path.display(), msg)),
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+    assert_eq!(skipped, unsupported);
+    assert!(num_checked > 0);
+}
+
+/// 評価器の評価結果を返す。評価器が評価エラーとなった場合はエラーを返す
+pub fn expected_value(sample: &Sample) -> Result<(String, usize), String> {
+    match sample.expected {
+        Ok((v, stats)) => Ok((v.to_string(), stats.leaked.len())),
+        Err(e) => Err(format!("評価器が評価に失敗した: {}", e)),
+    }
+}
+
+/// コマンドを実行し、正常に終了してエラー出力がない場合は標準出力を返す
+pub fn run_command(cmd: &mut Command) -> Result<String, String> {
+    let out = cmd.output().map_err(|e| e.to_string())?;
+    let stdout = String::from_utf8(out.stdout).map_err(|e| e.to_string())?;
+    if !out.status.success() || !out.stderr.is_empty() {
+        return Err(format!(
+            "終了コード{:?}:\n{}{}",
+            out.status.code(),
+            stdout,
+            String::from_utf8_lossy(&out.stderr)
+        ));
+    }
+    Ok(stdout)
+}
+
+/// 生成したプログラムが表示した評価結果が、評価器の評価結果valueと一致するかを確かめる
+pub fn check_value(stdout: &str, value: &str) -> Result<(), String> {
+    let printed = stdout.lines().skip_while(|l| *l != "評価結果:").nth(1);
+    if printed != Some(value) {
+        return Err(format!("評価結果が{}でなく{:?}", value, printed));
+    }
+    Ok(())
+}
+
+/// 生成したプログラムが表示したリークしたセルの数が、leakedと一致するかを確かめる
+pub fn check_leaked(stdout: &str, leaked: usize) -> Result<(), String> {
+    let printed = stdout.lines().find_map(|l| l.split("リーク: ").nth(1));
+    if printed != Some(leaked.to_string().as_str()) {
+        return Err(format!("リークしたセルの数が{}でなく{:?}", leaked, printed));
+    }
+    Ok(())
+}
@@ -0,0 +1,26 @@
+//! ファイルハンドルの検査
+
+mod common;
+
+use std::fs;
+
+/// open、write、fcloseで書き込んだ内容が、ファイルに書き込まれていること
+///
+/// テストごとに異なるパスに書き込むため、並列に実行しても競合しない
+#[test]
+fn write() {
+    let path = format!("{}/fs_write.txt", env!("CARGO_TARGET_TMPDIR"));
+    let src = format!(
+        r#"let h : lin handle = (open un "{}");
+let h : lin handle = ((write h) un "true\n");
+let h : lin handle = ((write h) lin "false\n");
+(fclose h)"#,
+        path
+    );
+    let expr = lineartype::parse(&src).unwrap();
+    let externs = common::builtins();
+    lineartype::check_with(&expr, &externs.type_env()).unwrap();
+    let (_, stats) = lineartype::eval_with(&expr, &externs).unwrap();
+    assert!(stats.leaked.is_empty(), "リークした: {}", stats);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "true\nfalse\n");
+}
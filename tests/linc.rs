@@ -0,0 +1,38 @@
+//! `.linc`形式の読み書きの検査
+
+mod common;
+
+use lineartype::Program;
+use std::fs;
+
+/// ex*.linと<機能名>_ex*.linのうちコンパイルできるものが、書き出して読み込んでも変わらないこと
+#[test]
+fn roundtrip() {
+    let externs = common::builtins();
+    let mut num = 0;
+    for path in common::codes().into_iter().filter(|p| common::is_ex(p)) {
+        let src = fs::read_to_string(&path).unwrap();
+        let expr = lineartype::parse(&src).unwrap();
+        let Ok(program) = lineartype::compile(&expr, &externs) else {
+            continue;
+        };
+        let read = Program::from_bytes(&program.to_bytes());
+        assert_eq!(read.as_ref(), Ok(&program), "{}", path.display());
+        num += 1;
+    }
+    assert!(num > 0);
+}
+
+/// 命令から確保されうる数を超える局所変数の数を持つlincファイルは、読み込み時に拒否すること
+#[test]
+fn too_many_locals() {
+    let expr = lineartype::parse("let x : lin bool = lin true; x").unwrap();
+    let program = lineartype::compile(&expr, &common::builtins()).unwrap();
+
+    for n in [program.funcs[0].num_locals + 1, u32::MAX] {
+        let mut bad = program.clone();
+        bad.funcs[0].num_locals = n;
+        let err = Program::from_bytes(&bad.to_bytes()).unwrap_err();
+        assert!(err.contains("局所変数の数"), "{}", err);
+    }
+}
@@ -0,0 +1,100 @@
+//! 最適化の検査
+//!
+//! パスごとに、書き換えた式と最適化した式の型、評価結果が変わらないことを確かめる。
+
+use lineartype::{Expr, TypeEnv, TypedExpr};
+use std::fs;
+
+/// 式を最適化し、パスの名前と書き換えた式の列と、最適化した型付き構文木を返す
+///
+/// 最適化した式の評価結果が元の式と一致し、
+/// 最適化した型付き構文木が、型付けをやり直した結果と一致することを確かめる
+fn optimize(expr: &Expr) -> (Vec<String>, TypedExpr) {
+    let env = TypeEnv::new();
+    let mut steps = Vec::new();
+    let typed = lineartype::optimize(expr, &env, |pass, e| {
+        steps.push(format!("({}) {}", pass, e.to_expr()))
+    })
+    .unwrap();
+
+    let optimized = typed.to_expr();
+    let (v1, _) = lineartype::eval(expr).unwrap();
+    let (v2, _) = lineartype::eval(&optimized).unwrap();
+    assert_eq!(v1.to_string(), v2.to_string());
+
+    // 型と変数の参照の移動と複製は、書き換えた式を型付けした結果と一致する
+    let retyped = lineartype::elaborate(&optimized, &env).unwrap();
+    assert_eq!(typed.to_string(), retyped.to_string());
+    (steps, typed)
+}
+
+#[test]
+fn beta() {
+    let src = fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/codes/ex5.lin")).unwrap();
+    let expr = lineartype::parse(&src).unwrap();
+    let (steps, typed) = optimize(&expr);
+    assert_eq!(
+        steps,
+        [
+            "(beta) let x : lin bool = lin true; if x { un <un true, un false> } else { un <un false, un true> }",
+            "(inline) if lin true { un <un true, un false> } else { un <un false, un true> }",
+            "(if-fold) un <un true, un false>",
+        ]
+    );
+    assert_eq!(typed.ty.to_string(), "un (un bool * un bool)");
+}
+
+#[test]
+fn inline() {
+    let expr = lineartype::parse(
+        "let f : lin (lin bool -> lin bool) = lin fn x : lin bool { x }; (f lin true)",
+    )
+    .unwrap();
+    let (steps, typed) = optimize(&expr);
+    assert_eq!(
+        steps,
+        [
+            "(inline) (lin fn x : lin bool { x } lin true)",
+            "(beta) let x : lin bool = lin true; x",
+            "(inline) lin true",
+        ]
+    );
+    assert_eq!(typed.ty.to_string(), "lin bool");
+}
+
+#[test]
+fn if_fold() {
+    let expr = lineartype::parse("if lin true { un false } else { un true }").unwrap();
+    let (steps, typed) = optimize(&expr);
+    assert_eq!(steps, ["(if-fold) un false"]);
+    assert_eq!(typed.ty.to_string(), "un bool");
+}
+
+#[test]
+fn dead_let() {
+    let expr = lineartype::parse("let y : un int = un 1; un true").unwrap();
+    let (steps, typed) = optimize(&expr);
+    assert_eq!(steps, ["(dead-let) un true"]);
+    assert_eq!(typed.ty.to_string(), "un bool");
+
+    // 参照される変数と、lin型の変数は取り除かない
+    let expr = lineartype::parse("let y : un int = un 1; let z : lin bool = lin true; lin <y, z>")
+        .unwrap();
+    assert_eq!(
+        optimize(&expr).0,
+        ["(inline) let y : un int = un 1; lin <y, lin true>"]
+    );
+}
+
+/// 型付けをやり直した結果が型付けエラーとなるパスの書き換えは、取り消されること
+#[test]
+fn rollback() {
+    // 関数を展開するとxをキャプチャする時点がwの消費より前になり、ord型の変数の消費順が変わる
+    let expr = lineartype::parse(
+        "let w : ord bool = ord true; let x : ord bool = ord false; split (ord fn z : ord bool { ord <z, x> } w) as a, b { free b; free a; un true }",
+    )
+    .unwrap();
+    let (steps, typed) = optimize(&expr);
+    assert!(steps.is_empty(), "{:?}", steps);
+    assert_eq!(typed.to_expr().to_string(), expr.to_string());
+}
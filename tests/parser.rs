@@ -0,0 +1,63 @@
+//! パーサの検査
+
+mod common;
+
+use lineartype::Error;
+use std::fs;
+
+/// キーワードは、変数やリージョンの名前にできないこと
+#[test]
+fn keywords() {
+    for kw in [
+        "send",
+        "recv",
+        "close",
+        "fork",
+        "swap",
+        "letregion",
+        "alloc",
+        "get",
+        "set",
+        "new",
+        "let",
+        "lin",
+        "fn",
+        "true",
+        "false",
+    ] {
+        let srcs = [
+            format!("let {} : un bool = un true; un false", kw),
+            format!("split un <un true, un false> as {}, x {{ x }}", kw),
+            format!("un fn {} : un bool {{ un true }}", kw),
+            format!("letregion {} {{ un true }}", kw),
+        ];
+        for src in srcs {
+            assert!(
+                matches!(lineartype::parse(&src), Err(Error::Parse(_))),
+                "パースエラーとなるべき: {}",
+                src
+            );
+        }
+    }
+
+    // キーワードで始まる変数名は利用できる
+    let expr = lineartype::parse("let sender : un bool = un true; sender").unwrap();
+    assert_eq!(expr.to_string(), "let sender : un bool = un true; sender");
+}
+
+/// 式を表示した文字列をパースすると、元の構文木と等しい構文木になること
+#[test]
+fn display_roundtrip() {
+    for path in common::codes() {
+        let src = fs::read_to_string(&path).unwrap();
+        let Ok(expr) = lineartype::parse(&src) else {
+            continue;
+        };
+        assert_eq!(
+            lineartype::parse(&expr.to_string()).unwrap(),
+            expr,
+            "{}",
+            path.display()
+        );
+    }
+}
@@ -0,0 +1,95 @@
+//! 代入による簡約の検査
+//!
+//! 規則ごとに、適用した規則の名前と簡約後の式の列を確かめる。
+
+use lineartype::{Error, Externs};
+
+/// 型付けに成功する式を簡約し、「（規則の名前） 簡約後の式」の列を返す
+fn steps(src: &str) -> Vec<String> {
+    let expr = lineartype::parse(src).unwrap();
+    lineartype::check(&expr).unwrap();
+    let mut steps = Vec::new();
+    lineartype::reduce(&expr, &Externs::new(), |rule, e| {
+        steps.push(format!("({}) {}", rule, e))
+    })
+    .unwrap();
+    steps
+}
+
+#[test]
+fn beta() {
+    assert_eq!(
+        steps("(lin fn x : lin bool { x } lin true)"),
+        ["(beta) lin true"]
+    );
+}
+
+#[test]
+fn if_true() {
+    assert_eq!(
+        steps("if lin true { un false } else { un true }"),
+        ["(if-true) un false"]
+    );
+}
+
+#[test]
+fn if_false() {
+    assert_eq!(
+        steps("if lin false { un false } else { un true }"),
+        ["(if-false) un true"]
+    );
+}
+
+#[test]
+fn split_pair() {
+    assert_eq!(
+        steps("split lin <lin true, un false> as x, y { if x { y } else { un true } }"),
+        [
+            "(split-pair) if lin true { un false } else { un true }",
+            "(if-true) un false",
+        ]
+    );
+}
+
+#[test]
+fn free_value() {
+    assert_eq!(
+        steps("let x : lin bool = lin true; free x; un true"),
+        ["(let) free x; un true", "(free-value) un true"]
+    );
+}
+
+/// 簡約に対応していない機能を利用する式は、パニックせずに評価エラーとなること
+#[test]
+fn unsupported() {
+    let srcs = [
+        (
+            "借用",
+            "let x : un bool = un true; let r : un &un bool = &x; un true",
+        ),
+        (
+            "可変な参照",
+            "let r : lin ref un bool = new un true; free r; un true",
+        ),
+        (
+            "配列",
+            "let a : lin array un int = alloc un 1 un 0; free a; un true",
+        ),
+        (
+            "チャネル",
+            "let p : lin (lin !un bool.end * lin ?un bool.end) = new !un bool.end; \
+             split p as c, d { \
+                 let c : lin end = send c un true; let u : un unit = close c; \
+                 split recv d as b, d { let v : un unit = close d; b } \
+             }",
+        ),
+    ];
+    for (name, src) in srcs {
+        let expr = lineartype::parse(src).unwrap();
+        lineartype::check(&expr).unwrap();
+        match lineartype::reduce(&expr, &Externs::new(), |_, _| ()) {
+            Err(Error::Eval(msg)) => assert!(msg.contains("対応していない"), "{}: {}", name, msg),
+            res => panic!("{}: 評価エラーとなるべき: {:?}", name, res),
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! 名前解決の検査
+
+use lineartype::{Error, TypeEnv};
+
+/// 定義されていない変数は、型付けの前に全てまとめて名前解決エラーとなること
+#[test]
+fn unbound() {
+    let src = "let x : un bool = un true; if y { free z; x } else { let w : un bool = &z; y }";
+    let expected = "\"y\"、\"z\"という変数は定義されていない".to_string();
+    let expr = lineartype::parse(src).unwrap();
+    assert_eq!(
+        lineartype::check(&expr),
+        Err(Error::Resolve(expected.clone()))
+    );
+    assert_eq!(
+        lineartype::elaborate(&expr, &TypeEnv::new()).map(|_| ()),
+        Err(Error::Resolve(expected))
+    );
+
+    // 束縛の外側で参照した変数は定義されていない
+    let expr = lineartype::parse("let x : un bool = (un fn y : un bool { y } un true); y").unwrap();
+    assert_eq!(
+        lineartype::check(&expr),
+        Err(Error::Resolve(
+            "\"y\"という変数は定義されていない".to_string()
+        ))
+    );
+}
+
+/// 型環境に与えた変数は、定義されている変数となること
+#[test]
+fn globals() {
+    let expr = lineartype::parse("let x : un bool = un true; (f x)").unwrap();
+    assert!(matches!(lineartype::check(&expr), Err(Error::Resolve(_))));
+
+    let ty = lineartype::parse_type("un (un bool -> un bool)").unwrap();
+    let env = TypeEnv::builder().bind("f", ty).build();
+    assert_eq!(
+        lineartype::check_with(&expr, &env).map(|t| t.to_string()),
+        Ok("un bool".to_string())
+    );
+}
+
+/// 式を空の型環境で名前解決し、束縛変数をインデックスで表した式を返す
+fn resolve(src: &str) -> Result<String, Error> {
+    let expr = lineartype::parse(src).unwrap();
+    lineartype::resolve(&expr, &TypeEnv::new()).map(|t| t.to_string())
+}
+
+/// 同じ名前の束縛は、内側の束縛を参照すること
+#[test]
+fn shadowing() {
+    assert_eq!(
+        resolve("let x : un bool = un true; let x : un bool = un false; x").unwrap(),
+        "let x : un bool = un true; let x : un bool = un false; #0"
+    );
+    assert_eq!(
+        resolve("let x : un bool = un true; let y : un bool = x; let x : un bool = y; x").unwrap(),
+        "let x : un bool = un true; let y : un bool = #0; let x : un bool = #0; #0"
+    );
+    assert_eq!(
+        resolve("un fn x : un bool { un fn y : un bool { un fn x : un bool { un <x, y> } } }")
+            .unwrap(),
+        "un fn x : un bool { un fn y : un bool { un fn x : un bool { un <#0, #1> } } }"
+    );
+
+    // 束縛した式の中では、外側の同じ名前の束縛を参照する
+    assert_eq!(
+        resolve("let x : aff bool = aff true; let x : aff bool = x; free x; un true").unwrap(),
+        "let x : aff bool = aff true; let x : aff bool = #0; free #0; un true"
+    );
+    assert_eq!(
+        resolve("let x : un bool = un true; let !x = promote x; &x").unwrap(),
+        "let x : un bool = un true; let !x = promote #0; &#0"
+    );
+}
+
+/// split式の本体ではrightが0番、leftが1番となり、外側の束縛はその後に続くこと
+#[test]
+fn split() {
+    assert_eq!(
+        resolve(
+            "let z : un bool = un true; split un <un true, un false> as x, z { un <un <x, z>, z> }"
+        )
+        .unwrap(),
+        "let z : un bool = un true; split un <un true, un false> as x, z { un <un <#1, #0>, #0> }"
+    );
+    assert_eq!(
+        resolve("let y : un bool = un true; split un <un true, un false> as x, z { un <x, y> }")
+            .unwrap(),
+        "let y : un bool = un true; split un <un true, un false> as x, z { un <#1, #2> }"
+    );
+}
+
+/// 型環境に与えた変数は名前のまま残り、定義されていない変数は名前解決エラーとなること
+#[test]
+fn resolve_globals() {
+    let expr = lineartype::parse("let x : un bool = un true; (f x)").unwrap();
+    let ty = lineartype::parse_type("un (un bool -> un bool)").unwrap();
+    let env = TypeEnv::builder().bind("f", ty).build();
+    assert_eq!(
+        lineartype::resolve(&expr, &env).unwrap().to_string(),
+        "let x : un bool = un true; (f #0)"
+    );
+    assert_eq!(
+        lineartype::resolve(&expr, &TypeEnv::new()).map(|t| t.to_string()),
+        Err(Error::Resolve(
+            "\"f\"という変数は定義されていない".to_string()
+        ))
+    );
+}
@@ -0,0 +1,184 @@
+//! 自由変数、代入、α同値の検査
+
+use lineartype::{alpha_eq, free_vars, subst, Expr};
+use std::collections::BTreeSet;
+
+fn parse(src: &str) -> Expr {
+    lineartype::parse(src).unwrap()
+}
+
+fn vars(names: &[&str]) -> BTreeSet<String> {
+    names.iter().map(|v| v.to_string()).collect()
+}
+
+/// 式srcの自由変数varに式valを代入した結果が、式expectedと文字列として一致すること
+fn assert_subst(src: &str, var: &str, val: &str, expected: &str) {
+    let e = subst(&parse(src), var, &parse(val));
+    assert_eq!(e.to_string(), parse(expected).to_string());
+}
+
+#[test]
+fn free_vars_split() {
+    // splitは2つの変数を束縛する
+    let e = parse("split p as a, b { lin <a, c> }");
+    assert_eq!(free_vars(&e), vars(&["c", "p"]));
+}
+
+#[test]
+fn free_vars_shadowing() {
+    // 内側の束縛は外側の束縛を隠蔽し、スコープを出ると外側の変数が自由変数となる
+    let e = parse("lin <let x : lin bool = y; lin fn y : lin bool { (x y) }, y>");
+    assert_eq!(free_vars(&e), vars(&["y"]));
+}
+
+#[test]
+fn subst_renames_capturing_binder() {
+    // 束縛変数yは代入する式の自由変数yを捕獲するため、付け替える
+    assert_subst(
+        "lin fn y : lin bool { (x y) }",
+        "x",
+        "y",
+        "lin fn ya : lin bool { (y ya) }",
+    );
+}
+
+#[test]
+fn subst_renames_capturing_split() {
+    // splitの2つの束縛変数のうち、捕獲する方のみを付け替える
+    assert_subst(
+        "split p as a, b { lin <x, lin <a, b>> }",
+        "x",
+        "(f a)",
+        "split p as aa, b { lin <(f a), lin <aa, b>> }",
+    );
+}
+
+#[test]
+fn subst_renamed_name_avoids_body() {
+    // 付け替えた名前は、本体の自由変数とも衝突しない
+    assert_subst(
+        "lin fn y : lin bool { lin <x, lin <y, ya>> }",
+        "x",
+        "y",
+        "lin fn yb : lin bool { lin <y, lin <yb, ya>> }",
+    );
+}
+
+#[test]
+fn subst_renames_capturing_region() {
+    // letregionのリージョン名rは、代入する値のリージョン名rを捕獲するため、付け替える
+    assert_subst(
+        "letregion r { let z : un@r bool = y; un@r <z, y> }",
+        "y",
+        "un@r true",
+        "letregion ra { let z : un@ra bool = un@r true; un@ra <z, un@r true> }",
+    );
+
+    // 付け替えた名前は、本体の内側のletregionとも衝突しない
+    assert_subst(
+        "letregion r { letregion ra { un@ra <y, un@r true> } }",
+        "y",
+        "un@r true",
+        "letregion rb { letregion ra { un@ra <un@r true, un@rb true> } }",
+    );
+
+    // 代入する箇所がないか、値がリージョン名を含まない場合は付け替えない
+    assert_subst(
+        "letregion r { un@r true }",
+        "y",
+        "un@r true",
+        "letregion r { un@r true }",
+    );
+    assert_subst(
+        "letregion r { un@r <y, y> }",
+        "y",
+        "un true",
+        "letregion r { un@r <un true, un true> }",
+    );
+
+    // 束縛されていないリージョン名と、付け替えた結果はα同値でない
+    let e = subst(
+        &parse("letregion r { un@r <y, y> }"),
+        "y",
+        &parse("un@r true"),
+    );
+    assert!(!alpha_eq(
+        &e,
+        &parse("letregion r { un@r <un@r true, un@r true> }")
+    ));
+}
+
+#[test]
+fn subst_renamed_name_avoids_keywords() {
+    // f、fa〜fmが使われている場合、キーワードのfnを飛ばしてfoに付け替える
+    let names: Vec<String> = std::iter::once("f".to_string())
+        .chain((b'a'..=b'm').map(|c| format!("f{}", c as char)))
+        .collect();
+    let val = names
+        .iter()
+        .skip(1)
+        .fold(names[0].clone(), |acc, v| format!("lin <{}, {}>", acc, v));
+    assert_subst(
+        "let f : un bool = un true; x",
+        "x",
+        &val,
+        &format!("let fo : un bool = un true; {}", val),
+    );
+}
+
+#[test]
+fn subst_shadowing() {
+    // 同じ名前の束縛変数のスコープでは代入しない
+    assert_subst(
+        "lin <x, lin fn x : lin bool { x }>",
+        "x",
+        "lin true",
+        "lin <lin true, lin fn x : lin bool { x }>",
+    );
+    assert_subst(
+        "split x as x, y { lin <x, y> }",
+        "x",
+        "p",
+        "split p as x, y { lin <x, y> }",
+    );
+}
+
+#[test]
+fn alpha_eq_renamed_binders() {
+    let pairs = [
+        ("lin fn x : lin bool { x }", "lin fn y : lin bool { y }"),
+        (
+            "split p as a, b { lin <b, a> }",
+            "split p as c, d { lin <d, c> }",
+        ),
+        (
+            "let !f = promote lin fn x : lin bool { x }; (f lin true)",
+            "let !g = promote lin fn y : lin bool { y }; (g lin true)",
+        ),
+    ];
+    for (e1, e2) in pairs {
+        assert!(alpha_eq(&parse(e1), &parse(e2)), "{} と {}", e1, e2);
+    }
+}
+
+#[test]
+fn alpha_eq_distinguishes_binders() {
+    let pairs = [
+        // 自由変数の名前は区別する
+        ("lin fn x : lin bool { y }", "lin fn x : lin bool { z }"),
+        // 束縛変数と自由変数は区別する
+        ("lin fn x : lin bool { x }", "lin fn y : lin bool { x }"),
+        // splitの2つの束縛変数の順序は区別する
+        (
+            "split p as a, b { lin <a, b> }",
+            "split p as a, b { lin <b, a> }",
+        ),
+        (
+            "let !f = promote lin true; f",
+            "let !g = promote lin true; f",
+        ),
+    ];
+    for (e1, e2) in pairs {
+        assert!(!alpha_eq(&parse(e1), &parse(e2)), "{} と {}", e1, e2);
+    }
+}
@@ -0,0 +1,52 @@
+//! 型環境の検査
+
+use lineartype::{Error, TypeEnv};
+
+/// 型付けに失敗した型環境は書き換えられず、別の式の型付けに再利用できること
+#[test]
+fn reuse_after_error() {
+    let ty = lineartype::parse_type("lin bool").unwrap();
+    let env = TypeEnv::builder().bind("y", ty).build();
+    let before = env.clone();
+
+    let first = lineartype::parse(
+        "let x : lin bool = y; let r : un &lin bool = &x; if x { un true } else { un false }",
+    )
+    .unwrap();
+    assert!(matches!(
+        lineartype::check_with(&first, &env),
+        Err(Error::Typing(_))
+    ));
+    assert_eq!(env, before);
+
+    let second = lineartype::parse(
+        "let r : lin ref lin bool = new lin true; split swap r y as c, b { free c; b }",
+    )
+    .unwrap();
+    assert_eq!(
+        lineartype::check_with(&second, &env).map(|t| t.to_string()),
+        Ok("lin bool".to_string())
+    );
+}
+
+/// 型付けに成功しても型環境に事前に与えた変数は残り、同じ型環境で何度でも型付けできること
+#[test]
+fn reuse_after_success() {
+    let f = lineartype::parse_type("un (un bool -> un bool)").unwrap();
+    let y = lineartype::parse_type("lin bool").unwrap();
+    let env = TypeEnv::builder().bind("f", f).bind("y", y).build();
+    let before = env.clone();
+
+    let expr = lineartype::parse("let x : un bool = (f un true); y").unwrap();
+    for _ in 0..2 {
+        assert_eq!(
+            lineartype::check_with(&expr, &env).map(|t| t.to_string()),
+            Ok("lin bool".to_string())
+        );
+        assert_eq!(
+            lineartype::elaborate(&expr, &env).map(|t| t.ty.to_string()),
+            Ok("lin bool".to_string())
+        );
+    }
+    assert_eq!(env, before);
+}
@@ -0,0 +1,136 @@
+//! 型付き構文木の検査
+//!
+//! 型付き構文木の各式の型が型付けの結果と一致することと、
+//! 変数の参照に付く移動と複製の区別が変数の型の修飾子に従うことを確かめる。
+
+mod common;
+
+use lineartype::{Externs, Node, Qual, TypedExpr, TypedVal, Use};
+use std::fs;
+
+/// 部分式のうち、単独で型付けに成功するものの型が、型付き構文木の型と一致するかを検査する
+///
+/// 外側で束縛された変数やリージョンを参照する部分式は、単独では型付けに失敗するため対象としない
+fn check_types(typed: &TypedExpr, externs: &Externs, failures: &mut Vec<String>) {
+    let expr = typed.to_expr();
+    if let Ok(ty) = lineartype::check_with(&expr, &externs.type_env()) {
+        if ty != typed.ty {
+            failures.push(format!("{}の型が{}でなく{}", expr, ty, typed.ty));
+        }
+    }
+    for e in typed.children() {
+        check_types(e, externs, failures);
+    }
+}
+
+/// 変数の参照の移動と複製の区別を検査する
+///
+/// scopeは束縛された変数名と、let !式で束縛されたか。末尾ほど内側の束縛
+fn check_uses(typed: &TypedExpr, scope: &mut Vec<(String, bool)>, failures: &mut Vec<String>) {
+    let n = scope.len();
+    let mut child = |e: &TypedExpr, vars: &[(&String, bool)], scope: &mut Vec<(String, bool)>| {
+        scope.extend(vars.iter().map(|(v, b)| (v.to_string(), *b)));
+        check_uses(e, scope, failures);
+        scope.truncate(n);
+    };
+
+    match &typed.node {
+        Node::Let {
+            var, expr1, expr2, ..
+        } => {
+            child(expr1, &[], scope);
+            child(expr2, &[(var, false)], scope);
+        }
+        Node::LetBang { var, expr1, expr2 } => {
+            child(expr1, &[], scope);
+            child(expr2, &[(var, true)], scope);
+        }
+        Node::Split {
+            expr,
+            left,
+            right,
+            body,
+        } => {
+            child(expr, &[], scope);
+            child(body, &[(left, false), (right, false)], scope);
+        }
+        Node::QVal {
+            val: TypedVal::Fun { var, expr, .. },
+            ..
+        } => child(expr, &[(var, false)], scope),
+        Node::Var(var, u) => {
+            let bang = scope
+                .iter()
+                .rev()
+                .find(|(v, _)| v == var)
+                .is_some_and(|b| b.1);
+            let expected = if !bang && matches!(typed.ty.qual, Qual::Ord | Qual::Lin | Qual::Aff) {
+                Use::Move
+            } else {
+                Use::Copy
+            };
+            if *u != expected {
+                failures.push(format!(
+                    "{}型の変数{}の参照が{}でなく{}",
+                    typed.ty, var, expected, u
+                ));
+            }
+        }
+        _ => {
+            for e in typed.children() {
+                child(e, &[], scope);
+            }
+        }
+    }
+}
+
+/// サンプルファイルの型付き構文木を検査する
+#[test]
+fn codes() {
+    let externs = common::builtins();
+    let mut failures = Vec::new();
+    let mut num_checked = 0;
+    for path in common::codes().into_iter().filter(|p| common::is_ex(p)) {
+        let src = fs::read_to_string(&path).unwrap();
+        let expr = lineartype::parse(&src).unwrap();
+        let ty = lineartype::check_with(&expr, &externs.type_env()).unwrap();
+        let typed = lineartype::elaborate(&expr, &externs.type_env()).unwrap();
+        num_checked += 1;
+
+        let mut errs = Vec::new();
+        if typed.ty != ty {
+            errs.push(format!("式全体の型が{}でなく{}", ty, typed.ty));
+        }
+        check_types(&typed, &externs, &mut errs);
+        check_uses(&typed, &mut Vec::new(), &mut errs);
+        failures.extend(errs.iter().map(|e| format!("{}: {}", path.display(), e)));
+    }
+
+    assert!(num_checked > 0);
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+/// 修飾子ごとに、変数の参照が移動か複製かを確かめる
+#[test]
+fn uses() {
+    let expr = lineartype::parse(
+        "let r : rel bool = rel true; let u : un bool = un true; let a : aff bool = aff true; \
+         let l : lin bool = lin true; let o : ord bool = ord true; \
+         ord <o, ord <l, ord <a, ord <r, ord <r, ord <u, u>>>>>>",
+    )
+    .unwrap();
+    let typed = lineartype::elaborate(&expr, &lineartype::TypeEnv::new()).unwrap();
+
+    let mut uses = Vec::new();
+    let mut stack = vec![&typed];
+    while let Some(e) = stack.pop() {
+        if let Node::Var(var, u) = &e.node {
+            uses.push(format!("{} {}", var, u));
+        }
+        stack.extend(e.children().into_iter().rev());
+    }
+    assert_eq!(
+        uses,
+        ["o move", "l move", "a move", "r copy", "r copy", "u copy", "u copy"]
+    );
+}
@@ -0,0 +1,37 @@
+//! バイトコードとスタックマシンの検査
+//!
+//! 型付けに成功するサンプルファイルをバイトコードにコンパイルしてスタックマシンで実行し、
+//! 評価結果とヒープの統計情報が評価器と一致することを確かめる。
+//! 評価器で評価エラーとなるサンプルファイルは、スタックマシンでも評価エラーとなることを確かめる。
+//! バイトコードにコンパイルできない機能を利用するサンプルファイルは[UNSUPPORTED]に列挙し、
+//! それ以外の型付けに成功するサンプルファイルが全てコンパイルできることを確かめる。
+
+mod common;
+
+use lineartype::Error;
+
+/// バイトコードにコンパイルできない機能（チャネルとスレッド）を利用するサンプルファイル
+const UNSUPPORTED: [&str; 3] = ["chan_err7", "chan_ex1", "chan_ex2"];
+
+#[test]
+fn vm() {
+    // 型付けに失敗すべきサンプルファイルも、型付けに成功するものは検査する
+    common::check_backend(
+        &common::codes(),
+        &UNSUPPORTED,
+        lineartype::compile,
+        |sample, program| match (sample.expected, lineartype::run(&program, sample.externs)) {
+            (Ok((v1, s1)), Ok((v2, s2))) => {
+                if v1.to_string() != v2.to_string() {
+                    return Err(format!("評価結果が{}でなく{}", v1, v2));
+                }
+                if *s1 != s2 {
+                    return Err(format!("ヒープの統計情報が{:?}でなく{:?}", s1, s2));
+                }
+                Ok(())
+            }
+            (Err(Error::Eval(_)), Err(Error::Eval(_))) => Ok(()),
+            (r1, r2) => Err(format!("評価器は{:?}だが、スタックマシンは{:?}", r1, r2)),
+        },
+    );
+}